@@ -1,7 +1,7 @@
 //! Benchmarks for KVStore operations.
 
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use mini_kvstore_v2::KVStore;
+use mini_kvstore_v2::{KVStore, StoreConfig};
 use std::fs::remove_dir_all;
 
 fn setup_bench_dir(path: &str) {
@@ -79,5 +79,87 @@ fn bench_compaction(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_set, bench_get, bench_compaction);
+fn bench_preallocation(c: &mut Criterion) {
+    // Point BENCH_TMPFS_DIR at a tmpfs mount to isolate allocation overhead.
+    let root = std::env::var("BENCH_TMPFS_DIR").unwrap_or_else(|_| "bench_data".to_string());
+    let mut group = c.benchmark_group("sequential_writes");
+
+    for (name, prealloc) in [
+        ("no_prealloc", None),
+        ("prealloc_16mb", Some(16 * 1024 * 1024)),
+    ] {
+        group.bench_function(name, |b| {
+            let test_dir = format!("{}/prealloc_{}", root, name);
+            setup_bench_dir(&test_dir);
+            let config = StoreConfig {
+                data_path: test_dir.clone().into(),
+                preallocate_segment_bytes: prealloc,
+                ..StoreConfig::default()
+            };
+            let mut store = KVStore::from_config(&config).unwrap();
+            let value = vec![0u8; 256];
+
+            b.iter(|| {
+                for i in 0..1000 {
+                    store.set(&format!("key_{}", i), black_box(&value)).unwrap();
+                }
+            });
+
+            let _ = remove_dir_all(&test_dir);
+        });
+    }
+    group.finish();
+}
+
+fn bench_bulk_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load_10k_sorted");
+    group.sample_size(10);
+
+    group.bench_function("sequential_set", |b| {
+        b.iter_with_setup(
+            || {
+                setup_bench_dir("bench_data/load_set");
+                KVStore::open("bench_data/load_set").unwrap()
+            },
+            |mut store| {
+                for i in 0..10_000 {
+                    store
+                        .set(&format!("key_{:06}", i), format!("value_{}", i).as_bytes())
+                        .unwrap();
+                }
+            },
+        );
+    });
+
+    group.bench_function("bulk_load", |b| {
+        b.iter_with_setup(
+            || {
+                setup_bench_dir("bench_data/load_bulk");
+                KVStore::open("bench_data/load_bulk").unwrap()
+            },
+            |mut store| {
+                store
+                    .bulk_load(
+                        (0..10_000).map(|i| {
+                            (format!("key_{:06}", i), format!("value_{}", i).into_bytes())
+                        }),
+                    )
+                    .unwrap();
+            },
+        );
+    });
+
+    group.finish();
+    let _ = remove_dir_all("bench_data/load_set");
+    let _ = remove_dir_all("bench_data/load_bulk");
+}
+
+criterion_group!(
+    benches,
+    bench_bulk_load,
+    bench_set,
+    bench_get,
+    bench_compaction,
+    bench_preallocation
+);
 criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Read throughput of `SharedKVStore` as concurrent readers are added.
+//!
+//! Gets only take the read lock, so throughput should grow with the reader
+//! count until it reaches the number of cores.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_kvstore_v2::SharedKVStore;
+use std::fs::remove_dir_all;
+
+const KEYS: usize = 1000;
+const READS_PER_TASK: usize = 10_000;
+
+fn bench_concurrent_reads(c: &mut Criterion) {
+    let test_dir = "bench_data/shared_reads";
+    let _ = remove_dir_all(test_dir);
+    std::fs::create_dir_all(test_dir).unwrap();
+
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(cores)
+        .build()
+        .unwrap();
+    let store = SharedKVStore::open(test_dir).unwrap();
+    runtime.block_on(async {
+        for i in 0..KEYS {
+            store.set(&format!("key_{}", i), &[0u8; 100]).await.unwrap();
+        }
+    });
+
+    let mut group = c.benchmark_group("shared_concurrent_reads");
+    let mut readers = 1;
+    while readers <= cores {
+        group.throughput(Throughput::Elements((readers * READS_PER_TASK) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(readers), &readers, |b, &n| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let tasks: Vec<_> = (0..n)
+                        .map(|t| {
+                            let store = store.clone();
+                            tokio::spawn(async move {
+                                for i in 0..READS_PER_TASK {
+                                    let key = format!("key_{}", (i * 7 + t) % KEYS);
+                                    black_box(store.get(&key).await.unwrap());
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                })
+            });
+        });
+        readers *= 2;
+    }
+    group.finish();
+
+    drop(store);
+    let _ = remove_dir_all(test_dir);
+}
+
+criterion_group!(benches, bench_concurrent_reads);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+//! Types shared by the volume servers and the coordinator.
+
+pub mod logging;
+pub mod schemas;
+
+pub use schemas::{KeyMeta, PaginatedList, PaginationCursor, VolumeInfo, VolumeStatus};
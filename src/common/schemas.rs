@@ -0,0 +1,208 @@
+//! Wire formats exchanged between volumes and the coordinator.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Whether a volume is taking requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeStatus {
+    #[default]
+    Online,
+    Offline,
+    /// Being decommissioned: still serves reads but takes no new writes.
+    Draining,
+}
+
+impl VolumeStatus {
+    /// Whether new blobs may be placed on the volume.
+    pub fn accepts_writes(self) -> bool {
+        self == VolumeStatus::Online
+    }
+
+    /// Whether the volume's copies can be read from.
+    pub fn serves_reads(self) -> bool {
+        matches!(self, VolumeStatus::Online | VolumeStatus::Draining)
+    }
+}
+
+impl fmt::Display for VolumeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VolumeStatus::Online => "Online",
+            VolumeStatus::Offline => "Offline",
+            VolumeStatus::Draining => "Draining",
+        })
+    }
+}
+
+/// A volume as the coordinator sees it; also the body of a heartbeat.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeInfo {
+    pub id: String,
+    /// Base URL clients send blob requests to.
+    pub url: String,
+    pub status: VolumeStatus,
+    /// The volume's hard quota; 0 when it has none.
+    pub capacity_bytes: u64,
+    /// Live bytes stored on the volume.
+    pub used_bytes: u64,
+    pub num_keys: usize,
+    /// Unix time of the last heartbeat the coordinator received; 0 if none.
+    pub last_heartbeat_secs: u64,
+}
+
+/// What the coordinator knows about one blob key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyMeta {
+    pub key: String,
+    /// Ids of the volumes holding a copy, in the order they reported it.
+    pub replicas: Vec<String>,
+}
+
+/// Where a paginated listing stopped. Opaque to clients: its string form is
+/// URL-safe base64 of the JSON, passed back as `?cursor=` for the next page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaginationCursor {
+    /// Last key of the previous page; the next page starts after it.
+    pub last_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_id: Option<usize>,
+}
+
+impl PaginationCursor {
+    pub fn after(last_key: impl Into<String>) -> Self {
+        Self {
+            last_key: last_key.into(),
+            ..Self::default()
+        }
+    }
+}
+
+impl fmt::Display for PaginationCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_vec(self).map_err(|_| fmt::Error)?;
+        f.write_str(&URL_SAFE_NO_PAD.encode(json))
+    }
+}
+
+impl FromStr for PaginationCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|e| format!("invalid cursor: {}", e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("invalid cursor: {}", e))
+    }
+}
+
+/// One page of a listing. `next_cursor` is absent on the last page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaginatedList<T> {
+    pub items: Vec<T>,
+    #[serde(default, with = "cursor_string")]
+    pub next_cursor: Option<PaginationCursor>,
+    /// Items across all pages, when cheap to know.
+    #[serde(default)]
+    pub total_count: Option<usize>,
+}
+
+impl<T> PaginatedList<T> {
+    /// The page of at most `page_size` items of `items` (sorted by `key`)
+    /// that follow `after`. `page_size` is at least 1.
+    pub fn page(
+        items: Vec<T>,
+        after: Option<&PaginationCursor>,
+        page_size: usize,
+        key: impl Fn(&T) -> &str,
+    ) -> Self {
+        let total_count = items.len();
+        let page_size = page_size.max(1);
+        let mut page: Vec<T> = items
+            .into_iter()
+            .filter(|item| after.map_or(true, |c| key(item) > c.last_key.as_str()))
+            .take(page_size + 1)
+            .collect();
+        let next_cursor = if page.len() > page_size {
+            page.truncate(page_size);
+            page.last().map(|item| PaginationCursor::after(key(item)))
+        } else {
+            None
+        };
+        Self {
+            items: page,
+            next_cursor,
+            total_count: Some(total_count),
+        }
+    }
+}
+
+/// Serializes `Option<PaginationCursor>` as its opaque string form.
+mod cursor_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        cursor: &Option<PaginationCursor>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match cursor {
+            Some(cursor) => serializer.serialize_some(&cursor.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PaginationCursor>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| s.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination_cursor_round_trips() {
+        let cursor = PaginationCursor {
+            last_key: "photos/2024/ü?&=.jpg".to_string(),
+            volume_id: Some("vol-1".to_string()),
+            segment_id: Some(7),
+        };
+        let encoded = cursor.to_string();
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(PaginationCursor::from_str(&encoded), Ok(cursor));
+        assert!("not a cursor!".parse::<PaginationCursor>().is_err());
+    }
+
+    #[test]
+    fn test_paging_visits_every_item_once() {
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{:03}", i)).collect();
+        let mut seen = Vec::new();
+        let mut cursor: Option<PaginationCursor> = None;
+        let mut pages = 0;
+        loop {
+            let page = PaginatedList::page(keys.clone(), cursor.as_ref(), 50, |k| k.as_str());
+            assert_eq!(page.total_count, Some(200));
+            seen.extend(page.items);
+            pages += 1;
+            // Clients only ever hold the string form.
+            match page.next_cursor {
+                Some(next) => cursor = Some(next.to_string().parse().unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 4);
+        assert_eq!(seen, keys);
+    }
+}
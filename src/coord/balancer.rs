@@ -0,0 +1,151 @@
+//! Write placement across registered volumes.
+
+use crate::common::VolumeInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Load figures a volume reports to the coordinator.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VolumeStats {
+    pub volume_id: String,
+    /// Live bytes stored on the volume.
+    pub current_bytes: u64,
+    pub num_keys: usize,
+    /// Recent requests per second.
+    pub rps: f64,
+}
+
+/// A volume known to the coordinator, with its routing settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredVolume {
+    #[serde(flatten)]
+    pub info: VolumeInfo,
+    /// Relative share of writes under [`WeightedRoundRobin`]; at least 1.
+    pub weight: u32,
+    /// Recent requests per second, as last reported.
+    pub rps: f64,
+}
+
+/// Picks the volume a new write goes to.
+pub trait Balancer: Send {
+    /// Index into `volumes` of the chosen volume, or `None` if it is empty.
+    fn select(&mut self, volumes: &[RegisteredVolume]) -> Option<usize>;
+}
+
+/// Sends every write to the volume holding the fewest bytes. Ties go to the
+/// volume listed first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LeastUsedBalancer;
+
+impl Balancer for LeastUsedBalancer {
+    fn select(&mut self, volumes: &[RegisteredVolume]) -> Option<usize> {
+        volumes
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, volume)| volume.info.used_bytes)
+            .map(|(i, _)| i)
+    }
+}
+
+/// Smooth weighted round robin: over any `sum(weights)` consecutive writes,
+/// each volume gets `weight` of them, interleaved rather than in bursts.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedRoundRobin {
+    /// Running score per volume id.
+    current: HashMap<String, i64>,
+}
+
+impl Balancer for WeightedRoundRobin {
+    fn select(&mut self, volumes: &[RegisteredVolume]) -> Option<usize> {
+        let total: i64 = volumes.iter().map(|v| i64::from(v.weight.max(1))).sum();
+        self.current
+            .retain(|id, _| volumes.iter().any(|v| &v.info.id == id));
+        let mut best: Option<(usize, i64)> = None;
+        for (i, volume) in volumes.iter().enumerate() {
+            let score = self.current.entry(volume.info.id.clone()).or_default();
+            *score += i64::from(volume.weight.max(1));
+            if best.map_or(true, |(_, top)| *score > top) {
+                best = Some((i, *score));
+            }
+        }
+        let (chosen, _) = best?;
+        if let Some(score) = self.current.get_mut(&volumes[chosen].info.id) {
+            *score -= total;
+        }
+        Some(chosen)
+    }
+}
+
+/// Balancer strategy, as named by the `COORD_BALANCER` variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BalancerKind {
+    #[default]
+    LeastUsed,
+    RoundRobin,
+}
+
+impl BalancerKind {
+    pub fn build(self) -> Box<dyn Balancer> {
+        match self {
+            BalancerKind::LeastUsed => Box::new(LeastUsedBalancer),
+            BalancerKind::RoundRobin => Box::new(WeightedRoundRobin::default()),
+        }
+    }
+}
+
+impl FromStr for BalancerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "least_used" => Ok(BalancerKind::LeastUsed),
+            "round_robin" => Ok(BalancerKind::RoundRobin),
+            other => Err(format!(
+                "unknown balancer {:?}, expected least_used or round_robin",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(id: &str, weight: u32, used_bytes: u64) -> RegisteredVolume {
+        RegisteredVolume {
+            info: VolumeInfo {
+                id: id.to_string(),
+                url: format!("http://{}", id),
+                used_bytes,
+                ..VolumeInfo::default()
+            },
+            weight,
+            rps: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_weighted_round_robin_spreads_by_weight() {
+        let volumes = [volume("a", 3, 0), volume("b", 1, 0), volume("c", 1, 0)];
+        let mut balancer = WeightedRoundRobin::default();
+        let picks: Vec<usize> = (0..10)
+            .map(|_| balancer.select(&volumes).unwrap())
+            .collect();
+
+        assert_eq!(picks.iter().filter(|&&i| i == 0).count(), 6);
+        assert_eq!(picks.iter().filter(|&&i| i == 1).count(), 2);
+        assert_eq!(picks.iter().filter(|&&i| i == 2).count(), 2);
+        // Interleaved: the heavy volume never takes more than two in a row.
+        assert!(picks.windows(3).all(|w| w != [0, 0, 0]));
+    }
+
+    #[test]
+    fn test_balancer_kind_parses_env_names() {
+        assert_eq!("least_used".parse(), Ok(BalancerKind::LeastUsed));
+        assert_eq!("round_robin".parse(), Ok(BalancerKind::RoundRobin));
+        assert!("random".parse::<BalancerKind>().is_err());
+        assert_eq!(LeastUsedBalancer.select(&[]), None);
+    }
+}
@@ -0,0 +1,520 @@
+//! HTTP handlers for the coordinator.
+
+use crate::common::{PaginationCursor, VolumeInfo};
+use crate::coord::balancer::VolumeStats;
+use crate::coord::registry::Coordinator;
+use crate::store::error::StoreError;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Page size of `GET /keys` when no `limit` is given.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Shared application state.
+#[derive(Clone)]
+pub struct CoordState {
+    pub coordinator: Arc<Mutex<Coordinator>>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.into(),
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct RegisterRequest {
+    volume_id: String,
+    url: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Body of `PUT /volumes/:id/stats`; the id comes from the path.
+#[derive(Deserialize)]
+struct StatsUpdate {
+    current_bytes: u64,
+    #[serde(default)]
+    num_keys: usize,
+    #[serde(default)]
+    rps: f64,
+}
+
+/// Body of `POST /keys/:key/replicas`.
+#[derive(Deserialize)]
+struct ReplicaReport {
+    volume_id: String,
+}
+
+/// A key listed by `GET /health/under-replicated`.
+#[derive(Serialize)]
+struct UnderReplicatedKey {
+    key: String,
+    replicas: Vec<String>,
+    /// The replicas on online volumes.
+    live_replicas: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct UnderReplicatedResponse {
+    replication_factor: usize,
+    keys: Vec<UnderReplicatedKey>,
+}
+
+#[derive(Deserialize)]
+struct ListKeysParams {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RouteParams {
+    op: String,
+}
+
+#[derive(Serialize)]
+struct RouteResponse {
+    volume_id: String,
+    volume_url: String,
+}
+
+async fn health_check() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "healthy" }))
+}
+
+async fn under_replicated(State(state): State<CoordState>) -> impl IntoResponse {
+    let coordinator = state.coordinator.lock().unwrap();
+    let keys = coordinator
+        .under_replicated()
+        .into_iter()
+        .map(|(meta, live)| UnderReplicatedKey {
+            key: meta.key.clone(),
+            replicas: meta.replicas.clone(),
+            live_replicas: live.into_iter().map(String::from).collect(),
+        })
+        .collect();
+    Json(UnderReplicatedResponse {
+        replication_factor: coordinator.replication_factor(),
+        keys,
+    })
+}
+
+fn store_error(err: StoreError) -> Response {
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+async fn list_volumes(State(state): State<CoordState>) -> impl IntoResponse {
+    Json(state.coordinator.lock().unwrap().volumes().to_vec())
+}
+
+async fn get_volume(State(state): State<CoordState>, Path(volume_id): Path<String>) -> Response {
+    match state.coordinator.lock().unwrap().volume(&volume_id) {
+        Some(volume) => Json(volume.clone()).into_response(),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            format!("Volume {} is not registered", volume_id),
+        ),
+    }
+}
+
+async fn register_volume(
+    State(state): State<CoordState>,
+    Json(request): Json<RegisterRequest>,
+) -> Response {
+    match state.coordinator.lock().unwrap().register(
+        &request.volume_id,
+        request.url,
+        request.weight,
+    ) {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(e) => store_error(e),
+    }
+}
+
+async fn volume_heartbeat(
+    State(state): State<CoordState>,
+    Path(volume_id): Path<String>,
+    Json(info): Json<VolumeInfo>,
+) -> Response {
+    if info.id != volume_id {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Heartbeat for {} sent to /volumes/{}", info.id, volume_id),
+        );
+    }
+    match state.coordinator.lock().unwrap().heartbeat(info) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => store_error(e),
+    }
+}
+
+async fn update_volume_stats(
+    State(state): State<CoordState>,
+    Path(volume_id): Path<String>,
+    Json(update): Json<StatsUpdate>,
+) -> Response {
+    let stats = VolumeStats {
+        volume_id,
+        current_bytes: update.current_bytes,
+        num_keys: update.num_keys,
+        rps: update.rps,
+    };
+    let volume_id = stats.volume_id.clone();
+    match state.coordinator.lock().unwrap().update_stats(stats) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => error_response(
+            StatusCode::NOT_FOUND,
+            format!("Volume {} is not registered", volume_id),
+        ),
+        Err(e) => store_error(e),
+    }
+}
+
+/// One page of the known keys and their replicas; pass `next_cursor` back
+/// as `?cursor=` for the next.
+async fn list_keys(
+    State(state): State<CoordState>,
+    Query(params): Query<ListKeysParams>,
+) -> Response {
+    let cursor = match params.cursor.as_deref().map(PaginationCursor::from_str) {
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+        Some(Ok(cursor)) => Some(cursor),
+        None => None,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let page = state
+        .coordinator
+        .lock()
+        .unwrap()
+        .keys_paginated(cursor.as_ref(), limit);
+    Json(page).into_response()
+}
+
+async fn get_key(State(state): State<CoordState>, Path(key): Path<String>) -> Response {
+    match state.coordinator.lock().unwrap().key(&key) {
+        Some(meta) => Json(meta.clone()).into_response(),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            format!("Key {} has no replicas", key),
+        ),
+    }
+}
+
+async fn add_key_replica(
+    State(state): State<CoordState>,
+    Path(key): Path<String>,
+    Json(report): Json<ReplicaReport>,
+) -> Response {
+    match state
+        .coordinator
+        .lock()
+        .unwrap()
+        .add_replica(&key, &report.volume_id)
+    {
+        Ok(Some(meta)) => Json(meta.clone()).into_response(),
+        Ok(None) => error_response(
+            StatusCode::NOT_FOUND,
+            format!("Volume {} is not registered", report.volume_id),
+        ),
+        Err(e) => store_error(e),
+    }
+}
+
+async fn route(State(state): State<CoordState>, Query(params): Query<RouteParams>) -> Response {
+    if params.op != "write" {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported op {:?}; only write is routed", params.op),
+        );
+    }
+    let mut coordinator = state.coordinator.lock().unwrap();
+    match coordinator.route_write() {
+        Some(volume) => Json(RouteResponse {
+            volume_id: volume.info.id.clone(),
+            volume_url: volume.info.url.clone(),
+        })
+        .into_response(),
+        None => error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No registered volume accepts writes",
+        ),
+    }
+}
+
+/// Create the coordinator router.
+pub fn create_router(coordinator: Arc<Mutex<Coordinator>>) -> Router {
+    let state = CoordState { coordinator };
+
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/health/under-replicated", get(under_replicated))
+        .route("/volumes", get(list_volumes).post(register_volume))
+        .route("/volumes/:id", get(get_volume))
+        .route("/volumes/:id/stats", put(update_volume_stats))
+        .route("/volumes/:id/heartbeat", post(volume_heartbeat))
+        .route("/keys", get(list_keys))
+        .route("/keys/:key", get(get_key))
+        .route("/keys/:key/replicas", post(add_key_replica))
+        .route("/route", get(route))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{KeyMeta, PaginatedList, VolumeStatus};
+    use crate::coord::balancer::BalancerKind;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_route_write_picks_least_used_volume() {
+        let coordinator = Arc::new(Mutex::new(Coordinator::new(BalancerKind::LeastUsed)));
+        let app = create_router(coordinator);
+
+        let (status, _) = send(&app, "GET", "/route?op=write", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        for (id, bytes) in [("vol-a", 5_000), ("vol-b", 1_000), ("vol-c", 9_000)] {
+            let register = format!(r#"{{"volume_id":"{}","url":"http://{}:9002"}}"#, id, id);
+            assert_eq!(
+                send(&app, "POST", "/volumes", &register).await.0,
+                StatusCode::CREATED
+            );
+            let stats = format!(r#"{{"current_bytes":{},"num_keys":10,"rps":1.5}}"#, bytes);
+            let uri = format!("/volumes/{}/stats", id);
+            assert_eq!(
+                send(&app, "PUT", &uri, &stats).await.0,
+                StatusCode::NO_CONTENT
+            );
+        }
+
+        for _ in 0..5 {
+            let (status, body) = send(&app, "GET", "/route?op=write", "").await;
+            assert_eq!(status, StatusCode::OK);
+            let route: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(route["volume_id"], "vol-b");
+            assert_eq!(route["volume_url"], "http://vol-b:9002");
+        }
+
+        // Once vol-b fills up, vol-a becomes the least used.
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/volumes/vol-b/stats",
+            r#"{"current_bytes":20000}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = send(&app, "GET", "/route?op=write", "").await;
+        assert!(body.contains(r#""volume_id":"vol-a""#), "{}", body);
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/volumes/vol-z/stats",
+            r#"{"current_bytes":1}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "GET", "/route?op=read", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_key_replicas_are_deduplicated() {
+        let coordinator = Arc::new(Mutex::new(Coordinator::new(BalancerKind::LeastUsed)));
+        let app = create_router(coordinator);
+        for id in ["vol-a", "vol-b"] {
+            let register = format!(r#"{{"volume_id":"{}","url":"http://{}:9002"}}"#, id, id);
+            send(&app, "POST", "/volumes", &register).await;
+        }
+
+        let (status, _) = send(&app, "GET", "/keys/photo.jpg", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        for volume in ["vol-a", "vol-b", "vol-a"] {
+            let report = format!(r#"{{"volume_id":"{}"}}"#, volume);
+            let (status, _) = send(&app, "POST", "/keys/photo.jpg/replicas", &report).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/keys/photo.jpg/replicas",
+            r#"{"volume_id":"vol-z"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(&app, "GET", "/keys/photo.jpg", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let meta: KeyMeta = serde_json::from_str(&body).unwrap();
+        assert_eq!(meta.key, "photo.jpg");
+        assert_eq!(meta.replicas, ["vol-a", "vol-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_pages_through_every_key() {
+        let coordinator = Arc::new(Mutex::new(Coordinator::new(BalancerKind::LeastUsed)));
+        {
+            let mut coordinator = coordinator.lock().unwrap();
+            coordinator.register("vol-a", "http://vol-a", 1).unwrap();
+            for i in 0..25 {
+                coordinator
+                    .add_replica(&format!("key{:02}", i), "vol-a")
+                    .unwrap();
+            }
+        }
+        let app = create_router(coordinator);
+
+        let mut seen = Vec::new();
+        let mut uri = "/keys?limit=10".to_string();
+        loop {
+            let (status, body) = send(&app, "GET", &uri, "").await;
+            assert_eq!(status, StatusCode::OK);
+            let page: PaginatedList<KeyMeta> = serde_json::from_str(&body).unwrap();
+            assert!(page.items.len() <= 10);
+            assert_eq!(page.total_count, Some(25));
+            assert!(page.items.iter().all(|meta| meta.replicas == ["vol-a"]));
+            seen.extend(page.items.into_iter().map(|meta| meta.key));
+            match page.next_cursor {
+                Some(cursor) => uri = format!("/keys?limit=10&cursor={}", cursor),
+                None => break,
+            }
+        }
+        let expected: Vec<String> = (0..25).map(|i| format!("key{:02}", i)).collect();
+        assert_eq!(seen, expected);
+
+        let (status, _) = send(&app, "GET", "/keys?cursor=%21%21", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_under_replicated_keys_skip_offline_volumes() {
+        let coordinator = Arc::new(Mutex::new(
+            Coordinator::new(BalancerKind::LeastUsed).with_replication_factor(2),
+        ));
+        let app = create_router(coordinator.clone());
+        {
+            let mut coordinator = coordinator.lock().unwrap();
+            for id in ["vol-a", "vol-b"] {
+                coordinator
+                    .register(id, format!("http://{}", id), 1)
+                    .unwrap();
+                coordinator.add_replica("photo.jpg", id).unwrap();
+            }
+            coordinator.add_replica("notes.txt", "vol-a").unwrap();
+        }
+
+        let (status, body) = send(&app, "GET", "/health/under-replicated", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["replication_factor"], 2);
+        assert_eq!(report["keys"].as_array().unwrap().len(), 1);
+        assert_eq!(report["keys"][0]["key"], "notes.txt");
+
+        // vol-b goes down: photo.jpg is left with one live copy.
+        coordinator
+            .lock()
+            .unwrap()
+            .heartbeat(VolumeInfo {
+                id: "vol-b".to_string(),
+                url: "http://vol-b".to_string(),
+                status: VolumeStatus::Offline,
+                ..VolumeInfo::default()
+            })
+            .unwrap();
+        let (_, body) = send(&app, "GET", "/health/under-replicated", "").await;
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let keys = report["keys"].as_array().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1]["key"], "photo.jpg");
+        assert_eq!(keys[1]["replicas"], serde_json::json!(["vol-a", "vol-b"]));
+        assert_eq!(keys[1]["live_replicas"], serde_json::json!(["vol-a"]));
+    }
+
+    #[tokio::test]
+    async fn test_draining_volume_serves_reads_but_takes_no_writes() {
+        let coordinator = Arc::new(Mutex::new(Coordinator::new(BalancerKind::LeastUsed)));
+        let app = create_router(coordinator.clone());
+        for id in ["vol-a", "vol-b"] {
+            let register = format!(r#"{{"volume_id":"{}","url":"http://{}"}}"#, id, id);
+            send(&app, "POST", "/volumes", &register).await;
+        }
+        send(
+            &app,
+            "PUT",
+            "/volumes/vol-b/stats",
+            r#"{"current_bytes":9000}"#,
+        )
+        .await;
+        let body = r#"{"volume_id":"vol-a"}"#;
+        send(&app, "POST", "/keys/photo.jpg/replicas", body).await;
+
+        // vol-a is the least used, but once draining it gets no writes.
+        coordinator
+            .lock()
+            .unwrap()
+            .heartbeat(VolumeInfo {
+                id: "vol-a".to_string(),
+                url: "http://vol-a".to_string(),
+                status: VolumeStatus::Draining,
+                ..VolumeInfo::default()
+            })
+            .unwrap();
+        for _ in 0..3 {
+            let (_, body) = send(&app, "GET", "/route?op=write", "").await;
+            assert!(body.contains(r#""volume_id":"vol-b""#), "{}", body);
+        }
+        let coord = coordinator.lock().unwrap();
+        let readable: Vec<&str> = coord
+            .read_replicas("photo.jpg")
+            .iter()
+            .map(|v| v.info.id.as_str())
+            .collect();
+        assert_eq!(readable, ["vol-a"]);
+        assert_eq!(VolumeStatus::Draining.to_string(), "Draining");
+    }
+}
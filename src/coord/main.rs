@@ -0,0 +1,43 @@
+// mini-kvstore-v2/src/coord/main.rs
+//! Coordinator binary entrypoint.
+
+use mini_kvstore_v2::common::logging::init_stderr_logger;
+use mini_kvstore_v2::coord::handlers::create_router;
+use mini_kvstore_v2::coord::{BalancerKind, Coordinator};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    init_stderr_logger(log::LevelFilter::Info);
+    let port: u16 = std::env::var("PORT")
+        .unwrap_or_else(|_| "9000".to_string())
+        .parse()
+        .unwrap_or(9000);
+    let balancer: BalancerKind = match std::env::var("COORD_BALANCER") {
+        Ok(name) => name.parse()?,
+        Err(_) => BalancerKind::default(),
+    };
+
+    let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "coord_data".to_string());
+    let replication_factor: usize = std::env::var("REPLICATION_FACTOR")
+        .ok()
+        .and_then(|rf| rf.parse().ok())
+        .unwrap_or(1);
+
+    let bind_addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    println!("Starting coordinator:");
+    println!("  balancer  = {:?}", balancer);
+    println!("  data_dir  = {}", data_dir);
+    println!("  replication_factor = {}", replication_factor);
+    println!("  bind_addr = {}", bind_addr);
+
+    let coordinator = Arc::new(Mutex::new(
+        Coordinator::open(data_dir, balancer)?.with_replication_factor(replication_factor),
+    ));
+    let listener = tokio::net::TcpListener::bind(bind_addr).await?;
+    axum::serve(listener, create_router(coordinator)).await?;
+
+    Ok(())
+}
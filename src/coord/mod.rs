@@ -0,0 +1,8 @@
+pub mod balancer;
+pub mod handlers;
+pub mod registry;
+
+pub use balancer::{
+    Balancer, BalancerKind, LeastUsedBalancer, RegisteredVolume, VolumeStats, WeightedRoundRobin,
+};
+pub use registry::Coordinator;
@@ -0,0 +1,259 @@
+//! The coordinator's view of the volumes and where writes go.
+
+use crate::common::{KeyMeta, PaginatedList, PaginationCursor, VolumeInfo, VolumeStatus};
+use crate::coord::balancer::{Balancer, BalancerKind, RegisteredVolume, VolumeStats};
+use crate::store::error::{Result, StoreError};
+use crate::store::KVStore;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Key prefix of the persisted volume records.
+const VOLUME_PREFIX: &str = "volume:";
+/// Key prefix of the persisted blob key records.
+const KEY_PREFIX: &str = "key:";
+
+/// Registered volumes, in registration order, the blob keys they hold, and
+/// the placement strategy.
+pub struct Coordinator {
+    volumes: Vec<RegisteredVolume>,
+    keys: BTreeMap<String, KeyMeta>,
+    /// Copies every key should have on online volumes.
+    replication_factor: usize,
+    balancer: Box<dyn Balancer>,
+    /// Where the latest record of each volume is kept, if anywhere.
+    store: Option<KVStore>,
+}
+
+impl Coordinator {
+    /// A coordinator that keeps its volume list in memory only.
+    pub fn new(kind: BalancerKind) -> Self {
+        Self {
+            volumes: Vec::new(),
+            keys: BTreeMap::new(),
+            replication_factor: 1,
+            balancer: kind.build(),
+            store: None,
+        }
+    }
+
+    /// A coordinator that persists every volume and key update in a store at
+    /// `dir`, and starts from the volumes recorded there, ordered by id.
+    pub fn open(dir: impl AsRef<Path>, kind: BalancerKind) -> Result<Self> {
+        let store = KVStore::open(dir)?;
+        let volumes = Self::load_records(&store, VOLUME_PREFIX)?;
+        let keys = Self::load_records::<KeyMeta>(&store, KEY_PREFIX)?
+            .into_iter()
+            .map(|meta| (meta.key.clone(), meta))
+            .collect();
+        Ok(Self {
+            volumes,
+            keys,
+            replication_factor: 1,
+            balancer: kind.build(),
+            store: Some(store),
+        })
+    }
+
+    /// Expect `replication_factor` copies of every key; at least 1.
+    pub fn with_replication_factor(mut self, replication_factor: usize) -> Self {
+        self.replication_factor = replication_factor.max(1);
+        self
+    }
+
+    pub fn replication_factor(&self) -> usize {
+        self.replication_factor
+    }
+
+    /// Adds a volume, or updates the URL and weight of a known one. Its
+    /// load starts at zero until it reports.
+    pub fn register(&mut self, volume_id: &str, url: impl Into<String>, weight: u32) -> Result<()> {
+        let url = url.into();
+        let i = match self.position(volume_id) {
+            Some(i) => {
+                self.volumes[i].info.url = url;
+                self.volumes[i].weight = weight.max(1);
+                i
+            },
+            None => {
+                self.volumes.push(RegisteredVolume {
+                    info: VolumeInfo {
+                        id: volume_id.to_string(),
+                        url,
+                        ..VolumeInfo::default()
+                    },
+                    weight: weight.max(1),
+                    rps: 0.0,
+                });
+                self.volumes.len() - 1
+            },
+        };
+        self.persist(i)
+    }
+
+    /// Replaces the load figures of a registered volume. Returns `false` if
+    /// the volume is unknown.
+    pub fn update_stats(&mut self, stats: VolumeStats) -> Result<bool> {
+        let Some(i) = self.position(&stats.volume_id) else {
+            return Ok(false);
+        };
+        let volume = &mut self.volumes[i];
+        volume.info.used_bytes = stats.current_bytes;
+        volume.info.num_keys = stats.num_keys;
+        volume.rps = stats.rps;
+        self.persist(i).map(|_| true)
+    }
+
+    /// Records a heartbeat, registering the volume with weight 1 if it is
+    /// new. The timestamp is the coordinator's clock, not the volume's.
+    pub fn heartbeat(&mut self, mut info: VolumeInfo) -> Result<()> {
+        info.last_heartbeat_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let i = match self.position(&info.id) {
+            Some(i) => {
+                self.volumes[i].info = info;
+                i
+            },
+            None => {
+                self.volumes.push(RegisteredVolume {
+                    info,
+                    weight: 1,
+                    rps: 0.0,
+                });
+                self.volumes.len() - 1
+            },
+        };
+        self.persist(i)
+    }
+
+    /// The volume the next write should go to, among those accepting
+    /// writes; draining and offline volumes are skipped.
+    pub fn route_write(&mut self) -> Option<&RegisteredVolume> {
+        let writable: Vec<RegisteredVolume> = self
+            .volumes
+            .iter()
+            .filter(|v| v.info.status.accepts_writes())
+            .cloned()
+            .collect();
+        let i = self.balancer.select(&writable)?;
+        self.volume(&writable[i].info.id)
+    }
+
+    pub fn volume(&self, volume_id: &str) -> Option<&RegisteredVolume> {
+        self.volumes.iter().find(|v| v.info.id == volume_id)
+    }
+
+    pub fn volumes(&self) -> &[RegisteredVolume] {
+        &self.volumes
+    }
+
+    /// Records that `volume_id` holds a copy of `key`; reporting the same
+    /// copy again changes nothing. Returns `None` if the volume is unknown.
+    pub fn add_replica(&mut self, key: &str, volume_id: &str) -> Result<Option<&KeyMeta>> {
+        if self.position(volume_id).is_none() {
+            return Ok(None);
+        }
+        let meta = self.keys.entry(key.to_string()).or_insert_with(|| KeyMeta {
+            key: key.to_string(),
+            replicas: Vec::new(),
+        });
+        if !meta.replicas.iter().any(|id| id == volume_id) {
+            meta.replicas.push(volume_id.to_string());
+            if let Some(store) = self.store.as_mut() {
+                store.set(&format!("{}{}", KEY_PREFIX, key), &to_json(meta)?)?;
+            }
+        }
+        Ok(self.keys.get(key))
+    }
+
+    pub fn key(&self, key: &str) -> Option<&KeyMeta> {
+        self.keys.get(key)
+    }
+
+    /// The page of at most `page_size` known keys, in key order, that
+    /// follow `after`.
+    pub fn keys_paginated(
+        &self,
+        after: Option<&PaginationCursor>,
+        page_size: usize,
+    ) -> PaginatedList<KeyMeta> {
+        let keys = self.keys.values().cloned().collect();
+        PaginatedList::page(keys, after, page_size, |meta| &meta.key)
+    }
+
+    /// Registered volumes `key` can be read from, in replica order. Draining
+    /// volumes are included; offline ones are not.
+    pub fn read_replicas(&self, key: &str) -> Vec<&RegisteredVolume> {
+        self.keys.get(key).map_or_else(Vec::new, |meta| {
+            meta.replicas
+                .iter()
+                .filter_map(|id| self.volume(id))
+                .filter(|v| v.info.status.serves_reads())
+                .collect()
+        })
+    }
+
+    /// Keys with fewer than `replication_factor` replicas on registered,
+    /// online volumes, in key order, each with those live replicas.
+    pub fn under_replicated(&self) -> Vec<(&KeyMeta, Vec<&str>)> {
+        self.keys
+            .values()
+            .filter_map(|meta| {
+                let live: Vec<&str> = meta
+                    .replicas
+                    .iter()
+                    .filter(|id| {
+                        self.volume(id)
+                            .is_some_and(|v| v.info.status == VolumeStatus::Online)
+                    })
+                    .map(String::as_str)
+                    .collect();
+                (live.len() < self.replication_factor).then_some((meta, live))
+            })
+            .collect()
+    }
+
+    /// Every record stored under `prefix`, in key order.
+    fn load_records<T: serde::de::DeserializeOwned>(
+        store: &KVStore,
+        prefix: &str,
+    ) -> Result<Vec<T>> {
+        let mut records = Vec::new();
+        for key in store.members_with_prefix(prefix) {
+            let Some(bytes) = store.get(&key)? else {
+                continue;
+            };
+            let record = serde_json::from_slice(&bytes)
+                .map_err(|e| StoreError::CorruptedData(format!("Record {}: {}", key, e)))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn position(&self, volume_id: &str) -> Option<usize> {
+        self.volumes.iter().position(|v| v.info.id == volume_id)
+    }
+
+    fn persist(&mut self, i: usize) -> Result<()> {
+        let Some(store) = self.store.as_mut() else {
+            return Ok(());
+        };
+        let volume = &self.volumes[i];
+        store.set(
+            &format!("{}{}", VOLUME_PREFIX, volume.info.id),
+            &to_json(volume)?,
+        )
+    }
+}
+
+fn to_json(record: &impl serde::Serialize) -> Result<Vec<u8>> {
+    serde_json::to_vec(record).map_err(|e| StoreError::Io(std::io::Error::other(e)))
+}
+
+impl Default for Coordinator {
+    fn default() -> Self {
+        Self::new(BalancerKind::default())
+    }
+}
@@ -1,5 +1,35 @@
 mod store;
-pub use store::stats::StoreStats;
+#[cfg(feature = "async")]
+pub use store::actor::{Command, KVStoreActor, KVStoreHandle};
+#[cfg(feature = "async")]
+pub use store::async_store::AsyncKVStore;
+pub use store::batch::{BatchOp, WriteBatch};
+pub use store::cache::{LfuCache, LruBlockCache, ValueCache};
+pub use store::codec::{BincodeCodec, JsonCodec, RawCodec, RecordCodec, TypedKVStore};
+pub use store::compaction::{CompactionEstimate, CompactionStats};
+pub use store::compaction_journal::{CompactionJournal, CompactionSummary, JournalAction};
+pub use store::compress::{Compression, Compressor, LZ4Compressor, NullCompressor, ZstdCompressor};
+pub use store::config::{CachePolicy, FsyncPolicy, StoreConfig};
+pub use store::engine::{BulkLoadStats, CheckpointInfo, KeyDescription, ReplayProgressCallback};
+pub use store::error::{ErrorSeverity, StoreError};
+pub use store::index::Index;
+pub use store::key_lock::KeyGuard;
+pub use store::manifest::{Manifest, SegmentEntry, SegmentState};
+pub use store::migration::migrate_segment_names;
+pub use store::record::{Record, RecordHeader};
+pub use store::replication::{
+    lsn, lsn_position, ChangeRecord, ReplicationMessage, ReplicationReceiver, ReplicationRecord,
+    ReplicationStream,
+};
+pub use store::segment::{
+    parse_segment_file_name, segment_file_name, Segment, SegmentRecordIter, Verification,
+};
+#[cfg(feature = "async")]
+pub use store::shared::SharedKVStore;
+pub use store::stats::{OpStats, ReplayStats, StoreStats};
+pub use store::validator::{DefaultKeyValidator, KeyValidator};
 pub use store::KVStore;
 
+pub mod common;
+pub mod coord;
 pub mod volume;
@@ -1,8 +1,14 @@
-use mini_kvstore_v2::KVStore;
+use mini_kvstore_v2::common::logging::init_stderr_logger;
+use mini_kvstore_v2::{KVStore, ReplayStats};
 use std::io::{self, Write};
 
+/// Stores with more segments than this show a progress bar while loading.
+const PROGRESS_MIN_SEGMENTS: usize = 5;
+
 fn main() {
-    let mut kv = KVStore::open("db").expect("failed to open db");
+    init_stderr_logger(log::LevelFilter::Warn);
+    let mut kv = KVStore::open_with_progress("db", Some(Box::new(print_replay_progress)))
+        .expect("failed to open db");
 
     println!("mini-kvstore-v2 (type help for instructions)");
 
@@ -56,11 +62,102 @@ fn main() {
             },
 
             "compact" => match kv.compact() {
-                Ok(()) => println!("Compaction finished"),
+                Ok(stats) => println!(
+                    "Compaction finished: {} -> {} bytes in {} ms",
+                    stats.bytes_read, stats.bytes_written, stats.elapsed_ms
+                ),
                 Err(e) => println!("Compaction error: {}", e),
             },
 
-            "stats" => println!("{:?}", kv.stats()),
+            "stats" => {
+                println!("{:?}", kv.stats());
+                let est = kv.compaction_estimate();
+                println!(
+                    "Compaction estimate: live={} bytes, reclaimable={} bytes, segments {} -> {}, records={} ({} tombstones)",
+                    est.live_bytes,
+                    est.dead_bytes,
+                    est.segments_before,
+                    est.estimated_segments_after,
+                    est.records_before,
+                    est.tombstones_before
+                );
+            },
+            "export-ndjson" => {
+                let path = parts.next().unwrap_or("");
+                if path.is_empty() {
+                    println!("Usage: export-ndjson <output_file>");
+                    continue;
+                }
+                match kv.export_all_segments_ndjson(path) {
+                    Ok(n) => println!("Exported {} records to {}", n, path),
+                    Err(e) => println!("Export error: {}", e),
+                }
+            },
+            "export" => {
+                let path = parts.next().unwrap_or("");
+                if path.is_empty() {
+                    println!("Usage: export <file>");
+                    continue;
+                }
+                match kv.export(path) {
+                    Ok(n) => println!("Exported {} pairs to {}", n, path),
+                    Err(e) => println!("Export error: {}", e),
+                }
+            },
+            "import" => {
+                let path = parts.next().unwrap_or("");
+                if path.is_empty() {
+                    println!("Usage: import <file>");
+                    continue;
+                }
+                match kv.import(path) {
+                    Ok(n) => println!("Imported {} pairs from {}", n, path),
+                    Err(e) => println!("Import error: {}", e),
+                }
+            },
+            "segment-dump" => {
+                let Some(id) = parts.next().and_then(|id| id.parse().ok()) else {
+                    println!("Usage: segment-dump <segment_id>");
+                    continue;
+                };
+                match kv.iter_segment(id) {
+                    Ok(records) => {
+                        for record in records {
+                            match record {
+                                Ok((offset, key, Some(value))) => {
+                                    println!("  @{} {} = {} bytes", offset, key, value.len())
+                                },
+                                Ok((offset, key, None)) => {
+                                    println!("  @{} {} (deleted)", offset, key)
+                                },
+                                Err(e) => {
+                                    println!("Read error: {}", e);
+                                    break;
+                                },
+                            }
+                        }
+                    },
+                    Err(e) => println!("Error: {}", e),
+                }
+            },
+            "describe" => {
+                let key = parts.next().unwrap_or("");
+                match kv.describe_key(key) {
+                    Ok(Some(d)) => {
+                        println!("  segment:   {} @ {}", d.segment_id, d.offset);
+                        println!("  value_len: {} bytes", d.value_len);
+                        println!("  checksum:  {:08x}", d.checksum);
+                        if let Some(secs) = d.last_modified_secs {
+                            println!("  segment last written: {} (unix)", secs);
+                        }
+                        if d.is_tombstone {
+                            println!("  (tombstone)");
+                        }
+                    },
+                    Ok(None) => println!("Key not found"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            },
             "help" => print_help(),
             "quit" | "exit" => break,
             other => println!("Unknown command: {}", other),
@@ -68,6 +165,25 @@ fn main() {
     }
 }
 
+fn print_replay_progress(progress: ReplayStats) {
+    if progress.total_segments <= PROGRESS_MIN_SEGMENTS {
+        return;
+    }
+    const WIDTH: usize = 30;
+    let filled = WIDTH * progress.segment / progress.total_segments;
+    eprint!(
+        "\rLoading [{}{}] {}/{} segments, {} records",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        progress.segment,
+        progress.total_segments,
+        progress.records_replayed
+    );
+    if progress.segment == progress.total_segments {
+        eprintln!();
+    }
+}
+
 fn print_help() {
     println!("Available commands:");
     println!("  set <key> <value>");
@@ -76,6 +192,11 @@ fn print_help() {
     println!("  list");
     println!("  compact");
     println!("  stats");
+    println!("  export <file>");
+    println!("  import <file>");
+    println!("  export-ndjson <output_file>");
+    println!("  segment-dump <segment_id>");
+    println!("  describe <key>");
     println!("  help");
     println!("  quit / exit");
 }
@@ -1,9 +1,29 @@
+#[cfg(feature = "async")]
+pub mod actor;
+#[cfg(feature = "async")]
+pub mod async_store;
+mod background_sync;
+pub mod batch;
+pub mod cache;
+pub mod codec;
 pub mod compaction;
+pub mod compaction_journal;
+pub mod compress;
 pub mod config;
 pub mod engine;
 pub mod error;
+pub mod file_utils;
 pub mod index;
+pub mod key_lock;
+pub mod manifest;
+pub mod migration;
+pub mod record;
+pub mod replication;
+pub mod retention;
 pub mod segment;
+#[cfg(feature = "async")]
+pub mod shared;
 pub mod stats;
+pub mod validator;
 
 pub use engine::KVStore;
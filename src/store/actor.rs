@@ -0,0 +1,122 @@
+//! Single-writer actor owning a KVStore.
+
+use crate::store::compaction::CompactionStats;
+use crate::store::error::{Result, StoreError};
+use crate::store::KVStore;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tokio::sync::{mpsc, oneshot};
+
+/// Commands queued before senders start waiting for the actor.
+const COMMAND_QUEUE_CAPACITY: usize = 256;
+
+/// A request to the actor, carrying the channel its result is sent back on.
+pub enum Command {
+    Set {
+        key: String,
+        value: Vec<u8>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Get {
+        key: String,
+        reply: oneshot::Sender<Result<Option<Vec<u8>>>>,
+    },
+    Delete {
+        key: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Compact {
+        reply: oneshot::Sender<Result<CompactionStats>>,
+    },
+}
+
+/// Owns a `KVStore` and applies commands to it one at a time on a dedicated
+/// thread, so callers share it without a lock.
+pub struct KVStoreActor {
+    store: KVStore,
+    receiver: mpsc::Receiver<Command>,
+}
+
+impl KVStoreActor {
+    /// Moves `store` onto a new thread and returns a handle for sending it
+    /// commands. The thread exits, handing back the store, once every handle
+    /// has been dropped.
+    pub fn spawn(store: KVStore) -> (KVStoreHandle, JoinHandle<KVStore>) {
+        let (sender, receiver) = mpsc::channel(COMMAND_QUEUE_CAPACITY);
+        let actor = Self { store, receiver };
+        let thread = thread::Builder::new()
+            .name("kvstore-actor".to_string())
+            .spawn(move || actor.run())
+            .expect("failed to spawn store actor thread");
+        let handle = KVStoreHandle {
+            sender: Arc::new(sender),
+        };
+        (handle, thread)
+    }
+
+    fn run(mut self) -> KVStore {
+        while let Some(command) = self.receiver.blocking_recv() {
+            // A caller that gave up waiting has dropped its receiver; that's fine.
+            match command {
+                Command::Set { key, value, reply } => {
+                    let _ = reply.send(self.store.set(&key, &value));
+                },
+                Command::Get { key, reply } => {
+                    let _ = reply.send(self.store.get(&key));
+                },
+                Command::Delete { key, reply } => {
+                    let _ = reply.send(self.store.delete(&key));
+                },
+                Command::Compact { reply } => {
+                    let _ = reply.send(self.store.compact());
+                },
+            }
+        }
+        self.store
+    }
+}
+
+/// Cheaply clonable handle for sending commands to a [`KVStoreActor`].
+#[derive(Clone)]
+pub struct KVStoreHandle {
+    sender: Arc<mpsc::Sender<Command>>,
+}
+
+impl KVStoreHandle {
+    pub async fn set(&self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let (key, value) = (key.into(), value.into());
+        self.request(|reply| Command::Set { key, value, reply })
+            .await
+    }
+
+    pub async fn get(&self, key: impl Into<String>) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.request(|reply| Command::Get { key, reply }).await
+    }
+
+    pub async fn delete(&self, key: impl Into<String>) -> Result<()> {
+        let key = key.into();
+        self.request(|reply| Command::Delete { key, reply }).await
+    }
+
+    pub async fn compact(&self) -> Result<CompactionStats> {
+        self.request(|reply| Command::Compact { reply }).await
+    }
+
+    /// Sends the command built by `make` and waits for the actor's reply.
+    async fn request<T>(
+        &self,
+        make: impl FnOnce(oneshot::Sender<Result<T>>) -> Command,
+    ) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(make(reply))
+            .await
+            .map_err(|_| actor_gone())?;
+        response.await.map_err(|_| actor_gone())?
+    }
+}
+
+fn actor_gone() -> StoreError {
+    StoreError::Io(std::io::Error::other("store actor has shut down"))
+}
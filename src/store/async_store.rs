@@ -0,0 +1,67 @@
+//! Async facade over the blocking KVStore.
+
+use crate::store::compaction::CompactionStats;
+use crate::store::error::{Result, StoreError};
+use crate::store::KVStore;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Cheaply clonable async handle to a `KVStore`.
+///
+/// Every operation runs on tokio's blocking pool so disk I/O never stalls
+/// the async runtime.
+#[derive(Clone)]
+pub struct AsyncKVStore {
+    inner: Arc<Mutex<KVStore>>,
+}
+
+impl AsyncKVStore {
+    /// Opens the store at `dir` (blocking) and wraps it.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Ok(Self::new(KVStore::open(dir)?))
+    }
+
+    /// Wraps an already-open store.
+    pub fn new(store: KVStore) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    pub async fn get(&self, key: impl Into<String>) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.run(move |store| store.get(&key)).await
+    }
+
+    pub async fn set(&self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let key = key.into();
+        let value = value.into();
+        self.run(move |store| store.set(&key, &value)).await
+    }
+
+    pub async fn delete(&self, key: impl Into<String>) -> Result<()> {
+        let key = key.into();
+        self.run(move |store| store.delete(&key)).await
+    }
+
+    pub async fn compact(&self) -> Result<CompactionStats> {
+        self.run(|store| store.compact()).await
+    }
+
+    /// Runs `f` against the store on the blocking pool.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut KVStore) -> Result<T> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || {
+            let mut store = inner
+                .lock()
+                .map_err(|_| StoreError::Io(std::io::Error::other("store mutex poisoned")))?;
+            f(&mut store)
+        })
+        .await
+        .map_err(|e| StoreError::Io(std::io::Error::other(e)))?
+    }
+}
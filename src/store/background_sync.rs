@@ -0,0 +1,98 @@
+//! Timer-driven fsync of the active segment for `FsyncPolicy::Interval`.
+
+use std::fs::File;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Background thread that fsyncs the current active segment file every
+/// interval. The store swaps the file in and out as segments rotate; the
+/// thread stops, and is joined, when this is dropped.
+#[derive(Debug)]
+pub(crate) struct BackgroundSync {
+    /// A handle to the active segment, shared with the thread. Held across
+    /// each fsync, so a rotation waits for one in progress to finish.
+    file: Arc<Mutex<Option<File>>>,
+    syncs: Arc<AtomicU64>,
+    failures: Arc<AtomicU64>,
+    /// The first fsync error not yet reported to the store.
+    error: Arc<Mutex<Option<io::Error>>>,
+    /// Dropping this wakes the thread and tells it to exit.
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundSync {
+    pub(crate) fn start(interval: Duration, file: Option<File>) -> Self {
+        let file = Arc::new(Mutex::new(file));
+        let syncs = Arc::new(AtomicU64::new(0));
+        let failures = Arc::new(AtomicU64::new(0));
+        let error = Arc::new(Mutex::new(None));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = {
+            let file = Arc::clone(&file);
+            let syncs = Arc::clone(&syncs);
+            let failures = Arc::clone(&failures);
+            let error = Arc::clone(&error);
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let file = file.lock().unwrap();
+                    let Some(file) = file.as_ref() else {
+                        continue;
+                    };
+                    match file.sync_data() {
+                        Ok(()) => {
+                            syncs.fetch_add(1, Ordering::Relaxed);
+                        },
+                        Err(e) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                            error.lock().unwrap().get_or_insert(e);
+                        },
+                    }
+                }
+            })
+        };
+        Self {
+            file,
+            syncs,
+            failures,
+            error,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Points the thread at a new active segment, or at nothing while none
+    /// is open.
+    pub(crate) fn set_file(&self, file: Option<File>) {
+        *self.file.lock().unwrap() = file;
+    }
+
+    /// Number of fsyncs completed so far.
+    pub(crate) fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+
+    /// Number of fsyncs that failed so far.
+    pub(crate) fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// The first fsync error since the last call, if any. Later errors
+    /// before it is taken are only counted.
+    pub(crate) fn take_error(&self) -> Option<io::Error> {
+        self.error.lock().unwrap().take()
+    }
+}
+
+impl Drop for BackgroundSync {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
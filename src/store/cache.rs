@@ -0,0 +1,446 @@
+//! Block cache for segment reads.
+
+use crate::store::config::CachePolicy;
+use bytes::Bytes;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// Default size of a cached block.
+pub const DEFAULT_BLOCK_SIZE: u64 = 4096;
+
+/// Cache key: `(segment_id, block_offset)`.
+pub type BlockKey = (usize, u64);
+
+/// A byte-bounded cache of segment blocks, whatever its eviction policy.
+pub trait ValueCache: std::fmt::Debug + Send + Sync {
+    /// Looks up a block, counting a hit or miss.
+    fn get(&mut self, key: &BlockKey) -> Option<&Bytes>;
+    /// Inserts a block, evicting others to make room. Blocks larger than
+    /// the whole cache are not stored.
+    fn insert(&mut self, key: BlockKey, value: Bytes);
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn block_size(&self) -> u64;
+    fn capacity_bytes(&self) -> u64;
+    fn hits(&self) -> u64;
+    fn misses(&self) -> u64;
+    /// Drops every cached block of a segment, e.g. after it is deleted.
+    fn invalidate_segment(&mut self, segment_id: usize);
+}
+
+/// Builds the block cache selected by `policy`.
+pub fn new_block_cache(
+    policy: CachePolicy,
+    capacity_bytes: u64,
+    block_size: u64,
+) -> Box<dyn ValueCache> {
+    match policy {
+        CachePolicy::Lru => Box::new(LruBlockCache::new(capacity_bytes, block_size)),
+        CachePolicy::Lfu => Box::new(LfuCache::new(capacity_bytes, block_size)),
+    }
+}
+
+/// Least-recently-used cache of fixed-size segment blocks, bounded by bytes.
+#[derive(Debug)]
+pub struct LruBlockCache {
+    capacity_bytes: u64,
+    block_size: u64,
+    used_bytes: u64,
+    /// Block data plus the tick of its last access.
+    entries: HashMap<BlockKey, (Bytes, u64)>,
+    /// Access tick -> key, oldest first.
+    recency: BTreeMap<u64, BlockKey>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl LruBlockCache {
+    pub fn new(capacity_bytes: u64, block_size: u64) -> Self {
+        Self {
+            capacity_bytes,
+            block_size: block_size.max(1),
+            used_bytes: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Looks up a block, marking it most recently used.
+    pub fn get(&mut self, key: &BlockKey) -> Option<Bytes> {
+        self.touch(key).cloned()
+    }
+
+    fn touch(&mut self, key: &BlockKey) -> Option<&Bytes> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(key) {
+            Some((data, last)) => {
+                self.recency.remove(last);
+                self.recency.insert(tick, *key);
+                *last = tick;
+                self.hits += 1;
+                Some(data)
+            },
+            None => {
+                self.misses += 1;
+                None
+            },
+        }
+    }
+
+    /// Inserts a block, evicting least recently used blocks to make room.
+    /// Blocks larger than the whole cache are not stored.
+    pub fn insert(&mut self, key: BlockKey, data: Bytes) {
+        let size = data.len() as u64;
+        if size > self.capacity_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.used_bytes + size > self.capacity_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.used_bytes -= evicted.len() as u64;
+            }
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key);
+        self.entries.insert(key, (data, self.tick));
+        self.used_bytes += size;
+    }
+
+    /// Drops a single block.
+    pub fn remove(&mut self, key: &BlockKey) {
+        if let Some((data, tick)) = self.entries.remove(key) {
+            self.recency.remove(&tick);
+            self.used_bytes -= data.len() as u64;
+        }
+    }
+
+    /// Drops every cached block of a segment, e.g. after it is deleted.
+    pub fn invalidate_segment(&mut self, segment_id: usize) {
+        let keys: Vec<BlockKey> = self
+            .entries
+            .keys()
+            .filter(|(seg, _)| *seg == segment_id)
+            .copied()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+}
+
+impl ValueCache for LruBlockCache {
+    fn get(&mut self, key: &BlockKey) -> Option<&Bytes> {
+        self.touch(key)
+    }
+
+    fn insert(&mut self, key: BlockKey, value: Bytes) {
+        LruBlockCache::insert(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes
+    }
+
+    fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    fn invalidate_segment(&mut self, segment_id: usize) {
+        LruBlockCache::invalidate_segment(self, segment_id)
+    }
+}
+
+/// Least-frequently-used cache bounded by bytes. Evicts the entry with the
+/// fewest accesses, and among those the one touched longest ago.
+#[derive(Debug)]
+pub struct LfuCache<K = BlockKey, V = Bytes> {
+    capacity_bytes: u64,
+    block_size: u64,
+    used_bytes: u64,
+    /// Value, access count and tick of the last access.
+    entries: HashMap<K, (V, u64, u64)>,
+    /// Min-heap of `(count, tick, key)`. Entries go stale when their key is
+    /// accessed again or removed, and are skipped when popped.
+    heap: BinaryHeap<Reverse<(u64, u64, K)>>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K, V> LfuCache<K, V>
+where
+    K: Hash + Ord + Clone,
+    V: AsRef<[u8]>,
+{
+    pub fn new(capacity_bytes: u64, block_size: u64) -> Self {
+        Self {
+            capacity_bytes,
+            block_size: block_size.max(1),
+            used_bytes: 0,
+            entries: HashMap::new(),
+            heap: BinaryHeap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Looks up a value, bumping its access count.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let tick = self.tick;
+        let Some((_, count, last)) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        *count += 1;
+        *last = tick;
+        self.heap.push(Reverse((*count, tick, key.clone())));
+        self.hits += 1;
+        self.compact_heap();
+        self.entries.get(key).map(|(value, _, _)| value)
+    }
+
+    /// Inserts a value with an access count of one, evicting the least
+    /// frequently used entries to make room. Values larger than the whole
+    /// cache are not stored.
+    pub fn insert(&mut self, key: K, value: V) {
+        let size = value.as_ref().len() as u64;
+        if size > self.capacity_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.used_bytes + size > self.capacity_bytes {
+            let Some(Reverse((count, tick, victim))) = self.heap.pop() else {
+                break;
+            };
+            if self.is_current(&victim, count, tick) {
+                self.remove(&victim);
+            }
+        }
+
+        self.tick += 1;
+        self.heap.push(Reverse((1, self.tick, key.clone())));
+        self.entries.insert(key, (value, 1, self.tick));
+        self.used_bytes += size;
+    }
+
+    /// Drops a single entry. Its heap slot is left to go stale.
+    pub fn remove(&mut self, key: &K) {
+        if let Some((value, _, _)) = self.entries.remove(key) {
+            self.used_bytes -= value.as_ref().len() as u64;
+        }
+    }
+
+    /// Keeps only the entries whose key satisfies `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let doomed: Vec<K> = self.entries.keys().filter(|k| !keep(k)).cloned().collect();
+        for key in doomed {
+            self.remove(&key);
+        }
+        self.compact_heap();
+    }
+
+    fn is_current(&self, key: &K, count: u64, tick: u64) -> bool {
+        matches!(self.entries.get(key), Some((_, c, t)) if *c == count && *t == tick)
+    }
+
+    /// Rebuilds the heap from the live entries once stale slots dominate.
+    fn compact_heap(&mut self) {
+        if self.heap.len() <= 2 * self.entries.len() + 16 {
+            return;
+        }
+        self.heap = self
+            .entries
+            .iter()
+            .map(|(key, (_, count, tick))| Reverse((*count, *tick, key.clone())))
+            .collect();
+    }
+}
+
+impl ValueCache for LfuCache<BlockKey, Bytes> {
+    fn get(&mut self, key: &BlockKey) -> Option<&Bytes> {
+        LfuCache::get(self, key)
+    }
+
+    fn insert(&mut self, key: BlockKey, value: Bytes) {
+        LfuCache::insert(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes
+    }
+
+    fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    fn invalidate_segment(&mut self, segment_id: usize) {
+        self.retain(|(seg, _)| *seg != segment_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruBlockCache::new(8, 4);
+        cache.insert((1, 0), Bytes::from_static(b"aaaa"));
+        cache.insert((1, 4), Bytes::from_static(b"bbbb"));
+
+        // Touch the first block so the second becomes the eviction candidate.
+        assert!(cache.get(&(1, 0)).is_some());
+        cache.insert((1, 8), Bytes::from_static(b"cccc"));
+
+        assert!(cache.get(&(1, 4)).is_none());
+        assert_eq!(cache.get(&(1, 0)).unwrap(), Bytes::from_static(b"aaaa"));
+        assert_eq!(cache.get(&(1, 8)).unwrap(), Bytes::from_static(b"cccc"));
+        assert_eq!(cache.used_bytes(), 8);
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_frequently_used() {
+        let mut cache: LfuCache = LfuCache::new(12, 4);
+        cache.insert((1, 0), Bytes::from_static(b"aaaa"));
+        cache.insert((1, 4), Bytes::from_static(b"bbbb"));
+        cache.insert((1, 8), Bytes::from_static(b"cccc"));
+
+        // a: 3 accesses, b: 1, c: 2. b goes first even though it was inserted
+        // after a.
+        for _ in 0..2 {
+            assert!(cache.get(&(1, 0)).is_some());
+        }
+        assert!(cache.get(&(1, 8)).is_some());
+        cache.insert((1, 12), Bytes::from_static(b"dddd"));
+        assert!(cache.get(&(1, 4)).is_none());
+
+        // d (count 1) is now the rarest.
+        cache.insert((1, 16), Bytes::from_static(b"eeee"));
+        assert!(cache.get(&(1, 12)).is_none());
+        assert_eq!(cache.get(&(1, 0)), Some(&Bytes::from_static(b"aaaa")));
+        assert_eq!(cache.get(&(1, 8)), Some(&Bytes::from_static(b"cccc")));
+        assert_eq!(cache.get(&(1, 16)), Some(&Bytes::from_static(b"eeee")));
+        assert_eq!(cache.used_bytes(), 12);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn test_lfu_breaks_ties_by_recency() {
+        let mut cache: LfuCache = LfuCache::new(8, 4);
+        cache.insert((1, 0), Bytes::from_static(b"aaaa"));
+        cache.insert((1, 4), Bytes::from_static(b"bbbb"));
+        assert!(cache.get(&(1, 4)).is_some());
+        assert!(cache.get(&(1, 0)).is_some());
+
+        // Both have two accesses; b's last one is older.
+        cache.insert((1, 8), Bytes::from_static(b"cccc"));
+        assert!(cache.get(&(1, 4)).is_none());
+        assert!(cache.get(&(1, 0)).is_some());
+    }
+
+    #[test]
+    fn test_policies_share_the_value_cache_interface() {
+        for policy in [CachePolicy::Lru, CachePolicy::Lfu] {
+            let mut cache = new_block_cache(policy, 8, 4);
+            cache.insert((1, 0), Bytes::from_static(b"aaaa"));
+            cache.insert((2, 0), Bytes::from_static(b"bbbb"));
+            cache.invalidate_segment(1);
+            assert!(cache.get(&(1, 0)).is_none());
+            assert_eq!(cache.get(&(2, 0)), Some(&Bytes::from_static(b"bbbb")));
+            assert_eq!((cache.len(), cache.hits(), cache.misses()), (1, 1, 1));
+        }
+    }
+}
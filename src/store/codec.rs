@@ -0,0 +1,174 @@
+//! Pluggable serialization of typed keys and values.
+
+use crate::store::error::{Result, StoreError};
+use crate::store::KVStore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Converts typed keys and values to and from the bytes the store holds.
+pub trait RecordCodec {
+    type Key;
+    type Value;
+
+    fn encode_key(k: &Self::Key) -> Vec<u8>;
+    fn decode_key(b: &[u8]) -> Result<Self::Key>;
+    fn encode_value(v: &Self::Value) -> Vec<u8>;
+    fn decode_value(b: &[u8]) -> Result<Self::Value>;
+}
+
+/// Identity codec over raw bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawCodec;
+
+impl RecordCodec for RawCodec {
+    type Key = Vec<u8>;
+    type Value = Vec<u8>;
+
+    fn encode_key(k: &Vec<u8>) -> Vec<u8> {
+        k.clone()
+    }
+
+    fn decode_key(b: &[u8]) -> Result<Vec<u8>> {
+        Ok(b.to_vec())
+    }
+
+    fn encode_value(v: &Vec<u8>) -> Vec<u8> {
+        v.clone()
+    }
+
+    fn decode_value(b: &[u8]) -> Result<Vec<u8>> {
+        Ok(b.to_vec())
+    }
+}
+
+/// Encodes keys and values as JSON via `serde_json`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec<K, V>(PhantomData<(K, V)>);
+
+impl<K, V> RecordCodec for JsonCodec<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    type Key = K;
+    type Value = V;
+
+    fn encode_key(k: &K) -> Vec<u8> {
+        // Serializing plain data types to JSON doesn't fail.
+        serde_json::to_vec(k).expect("JSON key encoding failed")
+    }
+
+    fn decode_key(b: &[u8]) -> Result<K> {
+        serde_json::from_slice(b).map_err(|e| StoreError::CorruptedData(format!("json key: {}", e)))
+    }
+
+    fn encode_value(v: &V) -> Vec<u8> {
+        serde_json::to_vec(v).expect("JSON value encoding failed")
+    }
+
+    fn decode_value(b: &[u8]) -> Result<V> {
+        serde_json::from_slice(b)
+            .map_err(|e| StoreError::CorruptedData(format!("json value: {}", e)))
+    }
+}
+
+/// Encodes keys and values with `bincode`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec<K, V>(PhantomData<(K, V)>);
+
+impl<K, V> RecordCodec for BincodeCodec<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    type Key = K;
+    type Value = V;
+
+    fn encode_key(k: &K) -> Vec<u8> {
+        bincode::serialize(k).expect("bincode key encoding failed")
+    }
+
+    fn decode_key(b: &[u8]) -> Result<K> {
+        bincode::deserialize(b)
+            .map_err(|e| StoreError::CorruptedData(format!("bincode key: {}", e)))
+    }
+
+    fn encode_value(v: &V) -> Vec<u8> {
+        bincode::serialize(v).expect("bincode value encoding failed")
+    }
+
+    fn decode_value(b: &[u8]) -> Result<V> {
+        bincode::deserialize(b)
+            .map_err(|e| StoreError::CorruptedData(format!("bincode value: {}", e)))
+    }
+}
+
+/// A [`KVStore`] whose keys and values are typed through codec `C`.
+#[derive(Debug)]
+pub struct TypedKVStore<C: RecordCodec> {
+    store: KVStore,
+    _codec: PhantomData<C>,
+}
+
+impl<C: RecordCodec> TypedKVStore<C> {
+    pub fn new(store: KVStore) -> Self {
+        Self {
+            store,
+            _codec: PhantomData,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Ok(Self::new(KVStore::open(dir)?))
+    }
+
+    pub fn set(&mut self, key: &C::Key, value: &C::Value) -> Result<()> {
+        self.store
+            .set_bytes_key(&C::encode_key(key), &C::encode_value(value))
+    }
+
+    pub fn get(&self, key: &C::Key) -> Result<Option<C::Value>> {
+        self.store
+            .get_bytes_key(&C::encode_key(key))?
+            .map(|bytes| C::decode_value(&bytes))
+            .transpose()
+    }
+
+    pub fn delete(&mut self, key: &C::Key) -> Result<()> {
+        self.store.delete_bytes_key(&C::encode_key(key))
+    }
+
+    /// Decodes every key in the store.
+    pub fn keys(&self) -> Result<Vec<C::Key>> {
+        self.store
+            .list_bytes_keys()
+            .iter()
+            .map(|key| C::decode_key(key))
+            .collect()
+    }
+
+    pub fn inner(&self) -> &KVStore {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> KVStore {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bincode_codec_round_trip() {
+        type Codec = BincodeCodec<u32, (String, Vec<u8>)>;
+        let value = ("blob".to_string(), vec![1, 2, 3]);
+        let encoded = Codec::encode_value(&value);
+        assert_eq!(Codec::decode_value(&encoded).unwrap(), value);
+        assert_eq!(Codec::decode_key(&Codec::encode_key(&7)).unwrap(), 7);
+        assert!(Codec::decode_value(&encoded[..2]).is_err());
+    }
+}
@@ -1,50 +1,464 @@
 //! Manual log compaction logic.
 
 use super::error::{Result, StoreError};
+use crate::store::compaction_journal::{CompactionJournal, JournalAction};
+use crate::store::engine::KeyedRecord;
+use crate::store::manifest::SegmentState;
+use crate::store::segment;
 use crate::store::KVStore;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
 
-/// Performs manual compaction.
-/// Clears all old segments, then asks the KVStore to create a fresh one.
-pub fn compact(store: &mut KVStore) -> Result<()> {
-    let volume_dir = store.base_dir();
-    let segments = find_all_segments(&volume_dir)?;
-
-    for seg_path in segments {
-        if let Err(e) = fs::remove_file(&seg_path) {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                return Err(StoreError::CompactionFailed(format!(
-                    "Failed to remove old segment {}: {}",
-                    seg_path.display(),
-                    e
-                )));
+/// Dry-run report of what a compaction would reclaim.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionEstimate {
+    /// Bytes of records still referenced by live keys.
+    pub live_bytes: u64,
+    /// Bytes held by overwritten or deleted records.
+    pub dead_bytes: u64,
+    /// Number of segment files on disk right now.
+    pub segments_before: usize,
+    /// Number of segment files expected once compaction has run.
+    pub estimated_segments_after: usize,
+    /// Records across those segments, tombstones included.
+    pub records_before: usize,
+    /// Tombstones across those segments; compaction drops them all.
+    pub tombstones_before: usize,
+}
+
+/// Estimates the payoff of a compaction without rewriting anything.
+pub fn estimate(store: &KVStore) -> CompactionEstimate {
+    let segments = store.segment_ids();
+    let disk_bytes = segment_bytes(store, &segments);
+    let live_bytes = store.live_record_bytes();
+    let (records_before, tombstones_before) = segment_record_counts(store, &segments);
+
+    CompactionEstimate {
+        live_bytes,
+        dead_bytes: disk_bytes.saturating_sub(live_bytes),
+        segments_before: segments.len(),
+        // Live records are rewritten into a single fresh segment.
+        estimated_segments_after: 1,
+        records_before,
+        tombstones_before,
+    }
+}
+
+/// What a compaction run did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Size of the segments that were replaced.
+    pub bytes_read: u64,
+    /// Size of the live records rewritten into the new segment.
+    pub bytes_written: u64,
+    pub elapsed_ms: u64,
+    /// `bytes_written` averaged over the whole run, in bytes per second.
+    pub avg_write_rate_bps: u64,
+}
+
+/// Performs manual compaction at full speed.
+pub fn compact(store: &mut KVStore) -> Result<CompactionStats> {
+    run(store, None)
+}
+
+/// Like [`compact`], but sleeps as needed to keep the rewrite at or below
+/// `max_bytes_per_sec`. A limit of 0 means unthrottled.
+pub fn compact_with_throttle(
+    store: &mut KVStore,
+    max_bytes_per_sec: u64,
+) -> Result<CompactionStats> {
+    run(store, Some(max_bytes_per_sec).filter(|&rate| rate > 0))
+}
+
+/// Rewrites the live records into a fresh segment, then removes every older one.
+///
+/// The inputs are marked `Compacting` in the manifest up front and only
+/// switched to `Deleted` once the live data is written and synced, so a crash
+/// mid-compaction leaves duplicate records behind rather than losing any.
+fn run(store: &mut KVStore, max_bytes_per_sec: Option<u64>) -> Result<CompactionStats> {
+    let started = Instant::now();
+    let segments = store.segment_ids();
+    let bytes_read = segment_bytes(store, &segments);
+    let mut journal = open_journal(store)?;
+    if let Some(journal) = journal.as_mut() {
+        for &id in &segments {
+            journal_segment_read(store, journal, id)?;
+            // Only live values are carried over; every tombstone is dropped.
+            for (key, _) in store
+                .read_segment_records(id)?
+                .into_iter()
+                .filter(|(_, value)| value.is_none())
+            {
+                let bytes = segment::Segment::tombstone_size(key.len() as u64);
+                journal.record(JournalAction::SkipTombstone, id, Some(&key), bytes)?;
             }
         }
     }
 
-    // Recreate a fresh active segment for further writes
+    store.update_manifest(|manifest| {
+        for id in &segments {
+            manifest.set_state(*id, SegmentState::Compacting);
+        }
+    })?;
     store.reset_active_segment()?;
+    let throttle = max_bytes_per_sec.map(|rate| Throttle::new(rate, started));
+    let output = store.active_segment_id();
+    let mut written = 0;
+    let bytes_written = store.write_live_records(&mut |key, len| {
+        written += len;
+        if let Some(throttle) = &throttle {
+            throttle.pace(written);
+        }
+        match journal.as_mut() {
+            Some(journal) => journal.record(JournalAction::WriteKey, output, Some(key), len),
+            None => Ok(()),
+        }
+    })?;
 
-    Ok(())
+    retire_segments(store, &segments, journal.as_mut())?;
+    if let Some(journal) = journal.as_mut() {
+        journal.flush()?;
+    }
+    // Only live values are rewritten, so no tombstones survive.
+    let tombstones = store.tombstone_count();
+    store.record_rewrite(0, tombstones, 0);
+
+    let elapsed = started.elapsed();
+    Ok(CompactionStats {
+        bytes_read,
+        bytes_written,
+        elapsed_ms: elapsed.as_millis() as u64,
+        avg_write_rate_bps: (bytes_written as f64 / elapsed.as_secs_f64().max(1e-9)) as u64,
+    })
 }
 
-fn find_all_segments(dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>> {
-    let mut segments = Vec::new();
+/// Like [`compact`], but trusts only the segment files: the live records are
+/// worked out by replaying every segment in order, ignoring the in-memory
+/// values and index, and once they are rewritten the index is rebuilt by
+/// replaying the new segment. Repairs an in-memory state that has drifted
+/// from disk instead of baking the drift into the output.
+pub fn compact_from_disk(store: &mut KVStore) -> Result<CompactionStats> {
+    let live = store.live_records_on_disk()?;
+    store.replace_values(live);
+    let stats = run(store, None)?;
+    store.reindex_active_segment()?;
+    Ok(stats)
+}
 
-    for entry in fs::read_dir(dir)
-        .map_err(|e| StoreError::CompactionFailed(format!("Failed to read directory: {}", e)))?
-    {
-        let entry = entry
-            .map_err(|e| StoreError::CompactionFailed(format!("Failed to read entry: {}", e)))?;
-        let path = entry.path();
+/// Subdirectory of the store where merge output is staged before the swap.
+const MERGE_TMP_DIR: &str = "merge.tmp";
 
-        if let Some(name) = path.file_name() {
-            let name = name.to_string_lossy();
-            if name.starts_with("segment-") && name.ends_with(".dat") {
-                segments.push(path);
+/// Merges two segments into one, keeping only the latest record per key.
+///
+/// The sorted output replaces the newer of the two segments, so it keeps that
+/// segment's place in replay order, and the older one is deleted. Records of
+/// the older segment that a segment in between overwrites are dropped, which
+/// makes this safe for non-adjacent segments too. Tombstones are kept, since
+/// even older segments may still hold the keys they delete.
+pub fn merge_segments(store: &mut KVStore, seg_a: usize, seg_b: usize) -> Result<()> {
+    let (older, newer) = (seg_a.min(seg_b) as u64, seg_a.max(seg_b) as u64);
+    if older == newer {
+        return Err(StoreError::CompactionFailed(format!(
+            "Cannot merge segment {} with itself",
+            older
+        )));
+    }
+    let ids = store.segment_ids();
+    for id in [older, newer] {
+        if !ids.contains(&id) {
+            return Err(StoreError::CompactionFailed(format!(
+                "Segment {} does not exist",
+                id
+            )));
+        }
+        if id == store.active_segment_id() {
+            return Err(StoreError::CompactionFailed(format!(
+                "Cannot merge the active segment {}",
+                id
+            )));
+        }
+    }
+
+    let mut journal = open_journal(store)?;
+    let older_records = store.read_segment_records(older)?;
+    let newer_records = store.read_segment_records(newer)?;
+    let tombstones_in = older_records
+        .iter()
+        .chain(&newer_records)
+        .filter(|(_, value)| value.is_none())
+        .count();
+
+    let mut merged: BTreeMap<Vec<u8>, Option<Vec<u8>>> = older_records.into_iter().collect();
+    let mut overwritten = HashSet::new();
+    let between: Vec<u64> = ids
+        .iter()
+        .copied()
+        .filter(|&id| id > older && id < newer)
+        .collect();
+    for &id in &between {
+        overwritten.extend(store.read_segment_records(id)?.into_iter().map(|(k, _)| k));
+    }
+    if let Some(journal) = journal.as_mut() {
+        for id in [older].iter().chain(&between).chain([newer].iter()) {
+            journal_segment_read(store, journal, *id)?;
+        }
+    }
+    merged.retain(|key, _| !overwritten.contains(key));
+    merged.extend(newer_records);
+    let tombstones_out = merged.values().filter(|value| value.is_none()).count();
+
+    // Until the older segment is gone, replaying it before the merged output
+    // still yields the right values, so a crash in between loses nothing.
+    let (locations, written) = rewrite_segment(store, newer, &merged)?;
+    if let Some(journal) = journal.as_mut() {
+        journal_rewrite(journal, newer, &locations, written)?;
+    }
+    store.set_segment_size(newer, Some(written));
+    store.relocate_records(&[older, newer], newer, locations);
+    store.record_rewrite(written, tombstones_in, tombstones_out);
+    retire_segments(store, &[older], journal.as_mut())?;
+    match journal.as_mut() {
+        Some(journal) => journal.flush(),
+        None => Ok(()),
+    }
+}
+
+/// Rewrites only the segments holding records of keys under `prefix`,
+/// dropping the records of those keys that are no longer live: overwritten
+/// values, deleted values and the tombstones themselves. Other records, and
+/// segments without such keys, are left as they are.
+///
+/// There is no standing per-segment key index; the candidates are found by
+/// scanning the segments. The active segment is sealed first if it holds any
+/// of the keys, so the tombstones of a fresh `delete_prefix` are reclaimed too.
+pub fn compact_prefix(store: &mut KVStore, prefix: &[u8]) -> Result<()> {
+    let active = store.active_segment_id();
+    if has_prefix_records(&store.read_segment_records(active)?, prefix) {
+        store.reset_active_segment()?;
+    }
+    let active = store.active_segment_id();
+    let mut journal = open_journal(store)?;
+
+    // Oldest first: by the time a tombstone is dropped, the older records it
+    // shadows are already gone, so a crash part-way can't resurrect a key.
+    for id in store.segment_ids() {
+        if id == active {
+            continue;
+        }
+        let records = store.read_segment_records(id)?;
+        if !has_prefix_records(&records, prefix) {
+            continue;
+        }
+        if let Some(journal) = journal.as_mut() {
+            journal_segment_read(store, journal, id)?;
+            for (key, _) in records
+                .iter()
+                .filter(|(key, value)| key.starts_with(prefix) && value.is_none())
+            {
+                let bytes = segment::Segment::tombstone_size(key.len() as u64);
+                journal.record(JournalAction::SkipTombstone, id, Some(key), bytes)?;
             }
         }
+        let tombstones_in = records.iter().filter(|(_, value)| value.is_none()).count();
+        // Walk backwards so only the last record of each prefixed key counts.
+        let mut seen = HashSet::new();
+        let mut kept: Vec<KeyedRecord> = records
+            .into_iter()
+            .rev()
+            .filter(|(key, value)| {
+                !key.starts_with(prefix)
+                    || (seen.insert(key.clone())
+                        && value.is_some()
+                        && store.index_points_into(key, id))
+            })
+            .collect();
+        kept.reverse();
+        let tombstones_out = kept.iter().filter(|(_, value)| value.is_none()).count();
+
+        if kept.is_empty() {
+            store.relocate_records(&[id], id, Vec::new());
+            store.record_rewrite(0, tombstones_in, 0);
+            retire_segments(store, &[id], journal.as_mut())?;
+            continue;
+        }
+        let (locations, written) = rewrite_segment(store, id, kept.iter().map(|(k, v)| (k, v)))?;
+        if let Some(journal) = journal.as_mut() {
+            journal_rewrite(journal, id, &locations, written)?;
+        }
+        store.set_segment_size(id, Some(written));
+        store.relocate_records(&[id], id, locations);
+        store.record_rewrite(written, tombstones_in, tombstones_out);
+    }
+    match journal.as_mut() {
+        Some(journal) => journal.flush(),
+        None => Ok(()),
+    }
+}
+
+fn has_prefix_records(records: &[KeyedRecord], prefix: &[u8]) -> bool {
+    records.iter().any(|(key, _)| key.starts_with(prefix))
+}
+
+/// `(key, offset, len)` of a value record in a rewritten segment.
+type RecordLocation = (Vec<u8>, u64, u64);
+
+/// Writes `records` in order as a sealed segment that atomically replaces
+/// segment `id`. Returns the `(key, offset, len)` of every value record and
+/// the bytes written, footer included.
+fn rewrite_segment<'a>(
+    store: &KVStore,
+    id: u64,
+    records: impl IntoIterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)>,
+) -> Result<(Vec<RecordLocation>, u64)> {
+    // Stage the output next to the segments so the rename below is atomic.
+    let path = store.segment_path(id);
+    let tmp_dir = store.base_dir().join(MERGE_TMP_DIR);
+    let tmp_path = tmp_dir.join(path.file_name().unwrap_or_default());
+    let merge_err = |what: &str, e: std::io::Error| {
+        StoreError::CompactionFailed(format!("Failed to {} merged segment: {}", what, e))
+    };
+    fs::create_dir_all(&tmp_dir).map_err(|e| merge_err("stage", e))?;
+    let file = KVStore::segment_file_options(store.config())
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .map_err(|e| merge_err("create", e))?;
+    let mut writer = BufWriter::new(file);
+    let mut locations = Vec::new();
+    let mut offset = 0;
+    for (key, value) in records {
+        let len = store
+            .encode_record(&mut writer, key, value.as_deref())
+            .map_err(|e| merge_err("write", e))?;
+        if value.is_some() {
+            locations.push((key.clone(), offset, len));
+        }
+        offset += len;
+    }
+    writer.flush().map_err(|e| merge_err("write", e))?;
+    writer
+        .get_ref()
+        .sync_all()
+        .map_err(|e| merge_err("sync", e))?;
+    drop(writer);
+    offset += segment::write_footer(&tmp_path).map_err(|e| match e {
+        StoreError::Io(e) | StoreError::IoWithPath { source: e, .. } => merge_err("seal", e),
+        other => other,
+    })?;
+
+    fs::rename(&tmp_path, &path).map_err(|e| merge_err("install", e))?;
+    let _ = fs::remove_dir(&tmp_dir);
+    Ok((locations, offset))
+}
+
+/// Marks `ids` deleted in the manifest, removes their files, then drops them
+/// from the manifest. A crash in between leaves `Deleted` entries that the
+/// next open cleans up.
+fn retire_segments(
+    store: &mut KVStore,
+    ids: &[u64],
+    journal: Option<&mut CompactionJournal>,
+) -> Result<()> {
+    if let Some(journal) = journal {
+        for &id in ids {
+            let bytes = segment_bytes(store, &[id]);
+            journal.record(JournalAction::DeleteOldSegment, id, None, bytes)?;
+        }
+    }
+    store.update_manifest(|manifest| {
+        for id in ids {
+            manifest.set_state(*id, SegmentState::Deleted);
+        }
+    })?;
+    let dir = store.base_dir();
+    for id in ids {
+        KVStore::remove_segment_file(&dir, *id)?;
+        store.set_segment_size(*id, None);
     }
+    store.update_manifest(|manifest| {
+        for id in ids {
+            manifest.remove(*id);
+        }
+    })?;
+    store.refresh_oldest_segment_id();
+    Ok(())
+}
 
-    Ok(segments)
+/// The store's compaction journal, if `enable_compaction_journal` is set.
+fn open_journal(store: &KVStore) -> Result<Option<CompactionJournal>> {
+    if !store.config().enable_compaction_journal {
+        return Ok(None);
+    }
+    CompactionJournal::open(&store.base_dir()).map(Some)
+}
+
+fn journal_segment_read(store: &KVStore, journal: &mut CompactionJournal, id: u64) -> Result<()> {
+    let bytes = segment_bytes(store, &[id]);
+    journal.record(JournalAction::ReadSegment, id, None, bytes)
+}
+
+/// Logs the value records of a rewritten segment `id`, then its install.
+fn journal_rewrite(
+    journal: &mut CompactionJournal,
+    id: u64,
+    locations: &[RecordLocation],
+    written: u64,
+) -> Result<()> {
+    for (key, _, len) in locations {
+        journal.record(JournalAction::WriteKey, id, Some(key), *len)?;
+    }
+    journal.record(JournalAction::RenameNewSegment, id, None, written)
+}
+
+/// Total on-disk size of the given segments.
+fn segment_bytes(store: &KVStore, ids: &[u64]) -> u64 {
+    ids.iter()
+        .filter_map(|id| fs::metadata(store.segment_path(*id)).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Total records and tombstones in segments `ids`, counted from their
+/// headers. Segments that cannot be read are left out, as in
+/// [`segment_bytes`].
+fn segment_record_counts(store: &KVStore, ids: &[u64]) -> (usize, usize) {
+    ids.iter()
+        .filter_map(|&id| {
+            let mut segment =
+                segment::Segment::open_read_only_path(store.segment_path(id), id as usize).ok()?;
+            let records = segment.record_count().ok()?;
+            let tombstones = segment.tombstone_count().ok()?;
+            Some((records, tombstones))
+        })
+        .fold((0, 0), |(r, t), (records, tombstones)| {
+            (r + records, t + tombstones)
+        })
+}
+
+/// Paces writes so the cumulative byte count never runs ahead of the rate.
+struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+}
+
+impl Throttle {
+    /// Sleeping for less than this is mostly syscall overhead; let it accrue.
+    const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+    fn new(bytes_per_sec: u64, started: Instant) -> Self {
+        Self {
+            bytes_per_sec,
+            started,
+        }
+    }
+
+    fn pace(&self, written: u64) {
+        let due = Duration::from_secs_f64(written as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if due >= elapsed + Self::MIN_SLEEP {
+            std::thread::sleep(due - elapsed);
+        }
+    }
 }
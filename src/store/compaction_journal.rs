@@ -0,0 +1,170 @@
+//! Append-only log of what compaction did, for post-mortems.
+//!
+//! With `StoreConfig::enable_compaction_journal` set, every compaction
+//! appends one JSON object per action to `{base_dir}/compaction.log`:
+//! `{"ts":1760000000000,"action":"write_key","segment_id":7,"key":"user:1","bytes":42}`.
+//! `ts` is milliseconds since the Unix epoch and `bytes` is the on-disk size
+//! of the segment or record acted on; `key` is left out for segment actions.
+
+use crate::store::error::{Result, StoreError};
+use crate::store::file_utils::retry_on_interrupt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const COMPACTION_LOG_FILE: &str = "compaction.log";
+
+/// What a journal line records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalAction {
+    /// An input segment was taken into the compaction.
+    ReadSegment,
+    /// A live value was written to the output.
+    WriteKey,
+    /// A tombstone was dropped instead of being carried over.
+    SkipTombstone,
+    /// A replaced segment was removed.
+    DeleteOldSegment,
+    /// A rewritten segment was moved into place.
+    RenameNewSegment,
+}
+
+/// One line of the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub ts: u64,
+    pub action: JournalAction,
+    pub segment_id: u64,
+    /// The key, for record actions; converted lossily if not UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub bytes: u64,
+}
+
+/// Totals over every entry of a journal; see [`CompactionJournal::summarize`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionSummary {
+    pub segments_read: usize,
+    pub bytes_read: u64,
+    pub keys_written: usize,
+    pub bytes_written: u64,
+    pub tombstones_skipped: usize,
+    pub segments_deleted: usize,
+    pub segments_renamed: usize,
+    /// Timestamps of the first and last entries, if there are any.
+    pub first_ts: Option<u64>,
+    pub last_ts: Option<u64>,
+}
+
+/// Writer for `compaction.log`. Record entries are buffered, but every
+/// segment action is synced to disk as soon as it is recorded, so the
+/// journal names a segment before compaction deletes or replaces it.
+pub struct CompactionJournal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl CompactionJournal {
+    /// Opens `dir/compaction.log` for appending, creating it if needed.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(COMPACTION_LOG_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(StoreError::io_at(&path))?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends one entry, stamped with the current time.
+    pub fn record(
+        &mut self,
+        action: JournalAction,
+        segment_id: u64,
+        key: Option<&[u8]>,
+        bytes: u64,
+    ) -> Result<()> {
+        let entry = JournalEntry {
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            action,
+            segment_id,
+            key: key.map(|key| String::from_utf8_lossy(key).into_owned()),
+            bytes,
+        };
+        serde_json::to_writer(&mut self.writer, &entry).map_err(std::io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        match action {
+            JournalAction::WriteKey | JournalAction::SkipTombstone => Ok(()),
+            JournalAction::ReadSegment
+            | JournalAction::DeleteOldSegment
+            | JournalAction::RenameNewSegment => self.sync(),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().map_err(StoreError::io_at(&self.path))
+    }
+
+    /// Flushes pending entries and fsyncs the log.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        retry_on_interrupt(|| self.writer.get_ref().sync_data())
+            .map_err(StoreError::io_at(&self.path))
+    }
+
+    /// Every entry in the log, oldest first, including ones written by
+    /// earlier runs. Flushes pending entries first.
+    pub fn entries(&mut self) -> Result<Vec<JournalEntry>> {
+        self.flush()?;
+        let text = fs::read_to_string(&self.path).map_err(StoreError::io_at(&self.path))?;
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    StoreError::CorruptedData(format!(
+                        "Line {} of {}: {}",
+                        n + 1,
+                        self.path.display(),
+                        e
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Parses the log and adds up what it records.
+    pub fn summarize(&mut self) -> Result<CompactionSummary> {
+        let mut summary = CompactionSummary::default();
+        for entry in self.entries()? {
+            match entry.action {
+                JournalAction::ReadSegment => {
+                    summary.segments_read += 1;
+                    summary.bytes_read += entry.bytes;
+                },
+                JournalAction::WriteKey => {
+                    summary.keys_written += 1;
+                    summary.bytes_written += entry.bytes;
+                },
+                JournalAction::SkipTombstone => summary.tombstones_skipped += 1,
+                JournalAction::DeleteOldSegment => summary.segments_deleted += 1,
+                JournalAction::RenameNewSegment => summary.segments_renamed += 1,
+            }
+            summary.first_ts.get_or_insert(entry.ts);
+            summary.last_ts = Some(entry.ts);
+        }
+        Ok(summary)
+    }
+}
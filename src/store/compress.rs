@@ -0,0 +1,123 @@
+//! Pluggable value compression for segment records.
+
+use crate::store::error::{Result, StoreError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Compresses record values on write and restores them on read.
+///
+/// `id` is stored in the record header and must fit in 4 bits (0..=15).
+pub trait Compressor: Send + Sync + fmt::Debug {
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn id(&self) -> u8;
+}
+
+/// Compression selected in [`StoreConfig`](crate::store::config::StoreConfig).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Lz4,
+    Zstd {
+        level: i32,
+    },
+    /// Any other [`Compressor`]. Not serializable.
+    #[serde(skip)]
+    Custom(Arc<dyn Compressor>),
+}
+
+impl Compression {
+    pub fn compressor(&self) -> Arc<dyn Compressor> {
+        match self {
+            Compression::Lz4 => Arc::new(LZ4Compressor),
+            Compression::Zstd { level } => Arc::new(ZstdCompressor { level: *level }),
+            Compression::Custom(compressor) => Arc::clone(compressor),
+        }
+    }
+}
+
+/// Stores values as-is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullCompressor;
+
+impl Compressor for NullCompressor {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn id(&self) -> u8 {
+        0
+    }
+}
+
+/// LZ4 block compression via `lz4_flex`, with the uncompressed size prepended.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LZ4Compressor;
+
+impl Compressor for LZ4Compressor {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        lz4_flex::compress_prepend_size(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        lz4_flex::decompress_size_prepended(data)
+            .map_err(|e| StoreError::CorruptedData(format!("lz4: {}", e)))
+    }
+
+    fn id(&self) -> u8 {
+        1
+    }
+}
+
+/// Zstandard compression at the given level.
+#[derive(Debug, Clone, Copy)]
+pub struct ZstdCompressor {
+    pub level: i32,
+}
+
+impl Default for ZstdCompressor {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+impl Compressor for ZstdCompressor {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        // Compressing an in-memory buffer only fails on allocation failure.
+        zstd::bulk::compress(data, self.level).expect("zstd compression failed")
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        zstd::decode_all(data).map_err(|e| StoreError::CorruptedData(format!("zstd: {}", e)))
+    }
+
+    fn id(&self) -> u8 {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressors_round_trip() {
+        let data = b"abcabcabcabcabcabcabcabcabcabcabcabc".repeat(32);
+        let compressors: [&dyn Compressor; 3] =
+            [&NullCompressor, &LZ4Compressor, &ZstdCompressor::default()];
+        for compressor in compressors {
+            let packed = compressor.compress(&data);
+            assert_eq!(compressor.decompress(&packed).unwrap(), data);
+            if compressor.id() != 0 {
+                assert!(packed.len() < data.len());
+            }
+        }
+    }
+}
@@ -1,14 +1,22 @@
 #![allow(dead_code)]
 //! Store configuration options for mini-kvstore-v2.
 
+use crate::store::compress::{Compression, Compressor, NullCompressor};
+use crate::store::validator::KeyValidator;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 /// Policy for how fsync is handled. Controls data durability.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum FsyncPolicy {
     /// Fsync after every write for maximum safety.
     #[default]
     Always,
-    /// Fsync periodically at intervals.
+    /// Fsync the active segment every `StoreConfig::fsync_interval`, from a
+    /// background thread.
     Interval,
     /// Never fsync (fast, not durable).
     Never,
@@ -25,27 +33,135 @@ pub fn as_str(&self) -> &'static str {
     }
 }
 
+/// Eviction policy of the segment block cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CachePolicy {
+    /// Evict the block read longest ago.
+    #[default]
+    Lru,
+    /// Evict the block read least often; suits skewed, CDN-style reads.
+    Lfu,
+}
+
 /// Complete store configuration with typical options.
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StoreConfig {
     pub fsync_policy: FsyncPolicy,
+    /// Time between background fsyncs under [`FsyncPolicy::Interval`].
+    pub fsync_interval: Duration,
+    /// Size in bytes at which the active segment is sealed and a new one
+    /// started. A write is never split, so a segment may overshoot it.
     pub max_segment_size: u64,
     pub enable_checksums: bool,
-    pub data_path: String,
+    /// Directory holding the segments and manifest.
+    pub data_path: PathBuf,
     pub cache_segments: usize,
+    /// Emit a `log` record for every set, get, delete, compaction and
+    /// segment rotation: `debug` for writes, `trace` for reads.
     pub verbose_logging: bool,
+    /// Bytes to reserve on disk for each new segment, reducing fragmentation.
+    pub preallocate_segment_bytes: Option<u64>,
+    /// Name new segments `segment-{id:010}-{unix_ts}.dat` instead of
+    /// `segment-{id:010}.dat`, to see when each was created.
+    pub timestamp_segment_names: bool,
+    /// Start a new segment once the active one holds this many records, so
+    /// workloads of small records don't pile thousands into one segment.
+    pub max_records_per_segment: Option<usize>,
+    /// Start a new segment once the active one holds this many tombstones,
+    /// leaving the deletes in a sealed segment ready for compaction.
+    pub max_tombstones_before_rotation: Option<usize>,
+    /// Capacity of the segment block cache in bytes.
+    pub block_cache_bytes: u64,
+    /// Eviction policy of the segment block cache.
+    pub cache_policy: CachePolicy,
+    /// Optional hook that rejects or normalizes keys on `set`/`get`/`delete`.
+    #[serde(skip)]
+    pub key_validator: Option<Arc<dyn KeyValidator>>,
+    /// Reject `set` with `WriteThrottled` while the active segment is nearly
+    /// full and a compaction is pending.
+    pub enable_write_throttling: bool,
+    /// Fraction of `max_segment_size` above which writes are throttled.
+    pub throttle_threshold: f64,
+    /// Share of the segment files' bytes that must be dead (overwritten,
+    /// deleted or footers) for a segment rotation to flag a pending
+    /// compaction.
+    pub compaction_dead_ratio: f64,
+    /// Compression for values larger than `compression_threshold_bytes`;
+    /// compressed records must be read back with the same algorithm.
+    pub compression: Option<Compression>,
+    /// Values up to this size are always stored uncompressed.
+    pub compression_threshold_bytes: usize,
+    /// Pad every record to a multiple of 8 bytes so records start on
+    /// aligned offsets, at the cost of up to 7 bytes each. Segments written
+    /// with and without it read back alike.
+    pub align_records: bool,
+    /// Cap on compaction write bandwidth; `None` compacts at full speed.
+    pub compaction_max_bytes_per_sec: Option<u64>,
+    /// Log every compaction action to `compaction.log` in the store
+    /// directory; see [`CompactionJournal`](crate::store::compaction_journal::CompactionJournal).
+    pub enable_compaction_journal: bool,
+    /// Cap on the estimated index memory; new keys are rejected with
+    /// `StoreFull` once it is reached.
+    pub max_index_memory_bytes: Option<usize>,
+    /// Budget for the total size of live values. A `set` that goes over it
+    /// tombstones the least recently written keys until the store fits.
+    pub max_total_bytes: Option<u64>,
+    /// Hold an exclusive lock on a `LOCK` file in the data directory while
+    /// the store is open, so a second writer fails with `AlreadyLocked`.
+    pub lock_data_dir: bool,
+    /// Permission bits for the data directory when `open` creates it, e.g.
+    /// `0o700`. `None` uses the process umask as before.
+    #[cfg(unix)]
+    pub dir_mode: Option<u32>,
+    /// Permission bits for segment files when they are created.
+    #[cfg(unix)]
+    pub file_mode: Option<u32>,
+    /// Time every `get`, `set` and `delete` for [`KVStore::op_stats`].
+    ///
+    /// [`KVStore::op_stats`]: crate::KVStore::op_stats
+    pub collect_timings: bool,
+    /// Open without writing anything to the data directory: no lock, no
+    /// active segment, no manifest repair. Writes fail with `ReadOnly`. For
+    /// read-only mounts such as backup snapshots.
+    pub read_only: bool,
 }
 
 impl Default for StoreConfig {
     fn default() -> Self {
         Self {
             fsync_policy: FsyncPolicy::default(),
+            fsync_interval: Duration::from_secs(1),
             max_segment_size: 16 * 1024 * 1024, // 16 MB
             enable_checksums: true,
-            data_path: "data".to_string(),
+            data_path: PathBuf::from("data"),
             cache_segments: 4,
             verbose_logging: false,
+            preallocate_segment_bytes: None,
+            timestamp_segment_names: false,
+            max_records_per_segment: None,
+            max_tombstones_before_rotation: None,
+            block_cache_bytes: 32 * 1024 * 1024, // 32 MB
+            cache_policy: CachePolicy::Lru,
+            key_validator: None,
+            enable_write_throttling: false,
+            throttle_threshold: 0.9,
+            compaction_dead_ratio: 0.5,
+            compression: None,
+            compression_threshold_bytes: 1024,
+            align_records: false,
+            compaction_max_bytes_per_sec: None,
+            enable_compaction_journal: false,
+            max_index_memory_bytes: None,
+            max_total_bytes: None,
+            lock_data_dir: true,
+            #[cfg(unix)]
+            dir_mode: None,
+            #[cfg(unix)]
+            file_mode: None,
+            collect_timings: false,
+            read_only: false,
         }
     }
 }
@@ -56,11 +172,60 @@ impl StoreConfig {
     pub fn test_config() -> Self {
         Self {
             fsync_policy: FsyncPolicy::Never,
+            fsync_interval: Duration::from_secs(1),
             max_segment_size: 512 * 1024,
             enable_checksums: false,
-            data_path: "tests_data/temp".to_string(),
+            data_path: PathBuf::from("tests_data/temp"),
             cache_segments: 1,
             verbose_logging: false,
+            preallocate_segment_bytes: None,
+            timestamp_segment_names: false,
+            max_records_per_segment: None,
+            max_tombstones_before_rotation: None,
+            block_cache_bytes: 1024 * 1024,
+            cache_policy: CachePolicy::Lru,
+            key_validator: None,
+            enable_write_throttling: false,
+            throttle_threshold: 0.9,
+            compaction_dead_ratio: 0.5,
+            compression: None,
+            compression_threshold_bytes: 1024,
+            align_records: false,
+            compaction_max_bytes_per_sec: None,
+            enable_compaction_journal: false,
+            max_index_memory_bytes: None,
+            max_total_bytes: None,
+            lock_data_dir: true,
+            #[cfg(unix)]
+            dir_mode: None,
+            #[cfg(unix)]
+            file_mode: None,
+            collect_timings: false,
+            read_only: false,
+        }
+    }
+
+    pub fn with_data_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.data_path = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// `data_path` as a string, for logging and display.
+    ///
+    /// # Panics
+    ///
+    /// If the path is not valid UTF-8.
+    pub fn data_path_str(&self) -> &str {
+        self.data_path
+            .to_str()
+            .expect("data_path is not valid UTF-8")
+    }
+
+    /// The compressor selected by `compression`, or [`NullCompressor`].
+    pub fn compressor(&self) -> Arc<dyn Compressor> {
+        match &self.compression {
+            Some(compression) => compression.compressor(),
+            None => Arc::new(NullCompressor),
         }
     }
 
@@ -72,9 +237,19 @@ pub fn summary(&self) -> String {
             self.fsync_policy.as_str(),
             self.max_segment_size,
             self.enable_checksums,
-            self.data_path,
+            self.data_path.display(),
             self.cache_segments,
             self.verbose_logging
         )
     }
+
+    /// Parses a config from JSON; missing fields take their default values.
+    pub fn from_json_str(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    /// Serializes the config to pretty-printed JSON.
+    pub fn to_json_str(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
 }
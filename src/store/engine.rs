@@ -1,267 +1,2305 @@
 // mini-kvstore-v2/src/store/engine.rs
+use crate::store::background_sync::BackgroundSync;
+use crate::store::batch::{BatchOp, WriteBatch};
+use crate::store::cache::{self, ValueCache, DEFAULT_BLOCK_SIZE};
+use crate::store::compaction::{CompactionEstimate, CompactionStats};
+use crate::store::compress::Compressor;
+use crate::store::config::{FsyncPolicy, StoreConfig};
 use crate::store::error::{Result, StoreError};
-use crate::store::stats::StoreStats;
-use std::collections::HashMap;
+use crate::store::file_utils::{self, retry_on_interrupt};
+use crate::store::index::{hash_table_bytes, Index};
+use crate::store::key_lock::{KeyGuard, KeyLocks};
+use crate::store::manifest::{Manifest, MANIFEST_FILE};
+use crate::store::record::{self, Record, FOOTER_SIZE, HEADER_SIZE};
+use crate::store::replication::{self, ChangeRecord, RecordStream, ReplicationRecord};
+use crate::store::retention::WriteOrder;
+use crate::store::segment::{
+    self, parse_segment_file_name, segment_file_name, segment_file_path, Segment,
+    SegmentRecordIter, Verification,
+};
+use crate::store::stats::{Op, OpStats, OpTimings, ReplayStats, StoreStats};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-const SEGMENT_PREFIX: &str = "segment-";
-const SEGMENT_SUFFIX: &str = ".dat";
+const LOCK_FILE: &str = "LOCK";
+const CHECKPOINT_FILE: &str = "CHECKPOINT";
+/// Present only between a clean close and the next writable open.
+const CLEAN_SHUTDOWN_FILE: &str = "CLEAN_SHUTDOWN";
+const CHECKPOINT_TMP_FILE: &str = "CHECKPOINT.tmp";
+/// Back-off suggested to writers rejected by the throttle.
+const THROTTLE_DELAY_MS: u64 = 100;
+/// First bytes of a file written by [`KVStore::export`].
+const EXPORT_MAGIC: &[u8; 8] = b"MKVEXP01";
+
+/// Called by [`KVStore::open_with_progress`] after each replayed segment.
+pub type ReplayProgressCallback = Box<dyn Fn(ReplayStats) + Send>;
+
+/// Summary of a [`KVStore::bulk_load`] run.
+#[derive(Debug, Clone, Default)]
+pub struct BulkLoadStats {
+    /// Records written.
+    pub records: usize,
+    /// Bytes appended to the segment.
+    pub bytes_written: u64,
+    /// Segment the records were written to.
+    pub segment_id: u64,
+    pub elapsed: Duration,
+}
+
+/// Where the log stood when [`KVStore::checkpoint`] sealed the active
+/// segment. Also written to `{base_dir}/CHECKPOINT` as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointInfo {
+    /// The segment that was sealed.
+    pub segment_id: usize,
+    /// Length of the sealed segment's records, excluding its footer.
+    pub byte_offset: u64,
+    /// LSN just past the last record covered by the checkpoint.
+    pub lsn: u64,
+    pub timestamp: SystemTime,
+}
+
+/// Where a key's latest record lives and what its header says, from
+/// [`KVStore::describe_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDescription {
+    pub key: String,
+    pub segment_id: usize,
+    /// Offset of the record's header within the segment.
+    pub offset: u64,
+    /// Length of the value as stored, i.e. after any compression.
+    pub value_len: u64,
+    /// CRC32 of the key and uncompressed value, from the record header.
+    pub checksum: u32,
+    /// Unix time the holding segment was last written to. Records carry no
+    /// timestamp of their own, so this is an upper bound.
+    pub last_modified_secs: Option<u64>,
+    pub is_tombstone: bool,
+}
+
+/// Value of a record, either straight from the segment file or already
+/// decompressed. Returned by [`KVStore::get_reader`].
+enum ValueReader {
+    Raw(std::io::Take<BufReader<File>>),
+    Decoded(Cursor<Vec<u8>>),
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ValueReader::Raw(reader) => reader.read(buf),
+            ValueReader::Decoded(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// `(key, value or None for a tombstone)`, as read back from a segment.
+pub(crate) type KeyedRecord = (Vec<u8>, Option<Vec<u8>>);
 
-#[derive(Debug)]
 pub struct KVStore {
     pub base_dir: PathBuf,
-    values: HashMap<String, Vec<u8>>,
+    /// Live values keyed by raw key bytes; keys need not be UTF-8.
+    values: HashMap<Vec<u8>, Vec<u8>>,
+    /// Where each live key's latest record sits on disk.
+    index: Index,
 
     // segment bookkeeping
     active_segment_id: u64,
     active_writer: Option<BufWriter<File>>,
+    /// Bytes appended to the active segment since it was opened.
+    active_segment_len: u64,
+    /// Records, and of those tombstones, in the active segment; checked
+    /// against the config's rotation limits after every write.
+    records_in_active_segment: usize,
+    tombstones_in_active_segment: usize,
+    pending_compaction: bool,
+    /// Bytes appended to segments since open, seeded with the segments'
+    /// sizes at open. Feeds write amplification.
+    segment_bytes_written: u64,
+    /// Tombstone records in the live segments.
+    tombstone_count: usize,
+    /// File size of every live segment except the active one, kept up to
+    /// date on rotation and compaction for [`KVStore::stats_cached`].
+    segment_sizes: HashMap<u64, u64>,
+    /// Lowest live segment id: the first replayed at open, then the first
+    /// left after each compaction.
+    oldest_segment_id_loaded: usize,
+    /// Write recency of live keys; only kept when `max_total_bytes` is set.
+    write_order: Option<WriteOrder>,
+
+    /// Per-key mutexes handed out by [`KVStore::lock_key`].
+    key_locks: Arc<KeyLocks>,
+    /// Latency accumulator; only kept when `collect_timings` is set.
+    timings: Option<OpTimings>,
+
+    /// Block cache shared by segment reads that go to disk, except the
+    /// verifying ones, which must see what is on disk now.
+    block_cache: Mutex<Box<dyn ValueCache>>,
+    /// Resolved from `config.compression` at open.
+    compressor: Arc<dyn Compressor>,
+    /// Segments to replay and their states, mirrored to `MANIFEST`.
+    manifest: Manifest,
+    /// Exclusively locked `LOCK` file, released on drop.
+    lock_file: Option<File>,
+    read_only: bool,
+    /// Whether the previous writer left a clean-shutdown marker.
+    clean_shutdown: bool,
+    /// Set once `close` or `drop` has sealed the store.
+    closed: bool,
+    /// Fsyncs the active segment on a timer under `FsyncPolicy::Interval`.
+    background_sync: Option<BackgroundSync>,
+    /// Fsyncs done by writes under `FsyncPolicy::Always`.
+    inline_fsyncs: u64,
+    config: StoreConfig,
 }
 
 impl KVStore {
     /// Open the store and replay all segment files to rebuild in-memory index.
+    /// Uses the default [`StoreConfig`] with `data_path` set to `dir`.
     pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
-        let base_dir = dir.as_ref().to_path_buf();
-        if !base_dir.exists() {
-            fs::create_dir_all(&base_dir).map_err(StoreError::Io)?;
+        Self::open_with_progress(dir, None)
+    }
+
+    /// Like [`open`](Self::open), calling `progress` after each segment is
+    /// replayed, e.g. to show a progress bar while a large store loads.
+    pub fn open_with_progress<P: AsRef<Path>>(
+        dir: P,
+        progress: Option<ReplayProgressCallback>,
+    ) -> Result<Self> {
+        let config = StoreConfig {
+            data_path: dir.as_ref().to_path_buf(),
+            ..StoreConfig::default()
+        };
+        Self::open_with_config(config.data_path.clone(), config, progress.as_deref())
+    }
+
+    /// Open the store at `config.data_path` with the given settings.
+    pub fn from_config(config: &StoreConfig) -> Result<Self> {
+        Self::open_with_config(config.data_path.clone(), config.clone(), None)
+    }
+
+    /// Open an existing store for reads only. No lock is taken and no active
+    /// segment is created, so this works alongside a writer holding the
+    /// directory; writes and compaction fail with `ReadOnly`.
+    pub fn open_read_only<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let config = StoreConfig {
+            data_path: dir.as_ref().to_path_buf(),
+            read_only: true,
+            ..StoreConfig::default()
+        };
+        Self::open_with_config(dir.as_ref().to_path_buf(), config, None)
+    }
+
+    fn open_with_config(
+        base_dir: PathBuf,
+        config: StoreConfig,
+        progress: Option<&(dyn Fn(ReplayStats) + Send)>,
+    ) -> Result<Self> {
+        let read_only = config.read_only;
+        if !base_dir.exists() && !read_only {
+            Self::create_data_dir(&base_dir, &config)?;
         }
+        let lock_file = if config.lock_data_dir && !read_only {
+            Some(Self::lock_data_dir(&base_dir)?)
+        } else {
+            None
+        };
 
-        // 1) find existing segment files
-        let mut segment_paths: Vec<(u64, PathBuf)> = Vec::new();
-        for entry in fs::read_dir(&base_dir)
-            .map_err(|e| StoreError::Io(std::io::Error::other(format!("read_dir: {}", e))))?
-        {
-            let entry = entry.map_err(|e| {
-                StoreError::Io(std::io::Error::other(format!("read_dir entry: {}", e)))
-            })?;
-            let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with(SEGMENT_PREFIX) && name.ends_with(SEGMENT_SUFFIX) {
-                    // parse id
-                    let id_str = &name[SEGMENT_PREFIX.len()..name.len() - SEGMENT_SUFFIX.len()];
-                    if let Ok(id) = id_str.parse::<u64>() {
-                        segment_paths.push((id, path));
-                    }
-                }
+        // 1) load the manifest and repair it against the segment files on disk
+        let mut manifest = Self::load_manifest(&base_dir)?;
+        if !read_only {
+            for id in manifest.deleted_segment_ids() {
+                Self::remove_segment_file(&base_dir, id)?;
+                manifest.remove(id);
             }
         }
 
-        // sort ascending by id
-        segment_paths.sort_by_key(|(id, _)| *id);
+        // A store with data but no marker wasn't closed cleanly; its last
+        // segment may end in a partial record.
+        let marker = base_dir.join(CLEAN_SHUTDOWN_FILE);
+        let clean_shutdown = manifest.live_segment_ids().is_empty() || marker.exists();
+        if !clean_shutdown {
+            log::warn!(
+                "{} was not shut down cleanly; consider running verify()",
+                base_dir.display()
+            );
+        }
+        if !read_only {
+            match fs::remove_file(&marker) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(StoreError::io_at(&marker)(e));
+                },
+                _ => {},
+            }
+        }
 
         // 2) replay segments
-        let mut values: HashMap<String, Vec<u8>> = HashMap::new();
-        for (_id, path) in &segment_paths {
-            Self::replay_segment(path, &mut values)?;
+        let mut values: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut index = Index::new();
+        let compressor = config.compressor();
+        let live_ids = manifest.live_segment_ids();
+        let mut segment_bytes_written = 0;
+        let mut tombstone_count = 0;
+        let mut segment_sizes = HashMap::new();
+        let mut replay = ReplayStats {
+            total_segments: live_ids.len(),
+            ..ReplayStats::default()
+        };
+        for id in &live_ids {
+            let path = manifest.segment_path(&base_dir, *id);
+            let (records, tombstones) =
+                Self::replay_segment(*id, &path, &mut values, &mut index, &*compressor)?;
+            tombstone_count += tombstones;
+            let size = fs::metadata(&path).map_err(StoreError::Io)?.len();
+            segment_bytes_written += size;
+            segment_sizes.insert(*id, size);
+            if let Some(progress) = progress {
+                replay.segment += 1;
+                replay.records_replayed += records;
+                replay.bytes_read += size;
+                progress(replay);
+            }
         }
 
         // 3) determine next segment id and open active segment for append
-        let active_segment_id = segment_paths.last().map(|(id, _)| *id).unwrap_or(0);
-        let next_id = active_segment_id + 1;
-        let active_path = base_dir.join(format!("{}{}{}", SEGMENT_PREFIX, next_id, SEGMENT_SUFFIX));
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&active_path)
-            .map_err(StoreError::Io)?;
-        let writer = BufWriter::new(file);
+        let last_segment_id = live_ids.last().copied().unwrap_or(0);
+        let (active_segment_id, active_writer) = if read_only {
+            (last_segment_id, None)
+        } else {
+            let next_id = last_segment_id + 1;
+            let active_name = Self::new_segment_name(next_id, &config);
+            let writer = Self::open_segment_writer(&base_dir.join(&active_name), &config)?;
+            manifest.push_active(next_id);
+            manifest.set_path(next_id, active_name);
+            manifest.save(&base_dir)?;
+            (next_id, Some(writer))
+        };
+
+        let oldest_segment_id_loaded =
+            live_ids.first().copied().unwrap_or(active_segment_id) as usize;
+
+        let background_sync = match (&config.fsync_policy, &active_writer) {
+            (FsyncPolicy::Interval, Some(writer)) => Some(BackgroundSync::start(
+                config.fsync_interval,
+                Some(writer.get_ref().try_clone().map_err(StoreError::Io)?),
+            )),
+            _ => None,
+        };
+
+        let write_order = config
+            .max_total_bytes
+            .map(|_| WriteOrder::from_index(&index, &values));
 
         Ok(Self {
             base_dir,
             values,
-            active_segment_id: next_id,
-            active_writer: Some(writer),
+            index,
+            active_segment_id,
+            active_writer,
+            active_segment_len: 0,
+            records_in_active_segment: 0,
+            tombstones_in_active_segment: 0,
+            pending_compaction: false,
+            segment_bytes_written,
+            tombstone_count,
+            segment_sizes,
+            oldest_segment_id_loaded,
+            write_order,
+            key_locks: Arc::default(),
+            timings: config.collect_timings.then(OpTimings::default),
+            block_cache: Mutex::new(cache::new_block_cache(
+                config.cache_policy,
+                config.block_cache_bytes,
+                DEFAULT_BLOCK_SIZE,
+            )),
+            compressor,
+            manifest,
+            lock_file,
+            read_only,
+            clean_shutdown,
+            closed: false,
+            background_sync,
+            inline_fsyncs: 0,
+            config,
         })
     }
 
-    /// Replay a single segment file into the provided values map.
-    fn replay_segment(path: &Path, values: &mut HashMap<String, Vec<u8>>) -> Result<()> {
-        let file = File::open(path).map_err(|e| {
-            StoreError::CorruptedData(format!("Failed to open segment {}: {}", path.display(), e))
-        })?;
-        let mut reader = BufReader::new(file);
-
-        loop {
-            // Read opcode (1 byte)
-            let mut op_buf = [0u8; 1];
-            if reader.read_exact(&mut op_buf).is_err() {
-                // EOF -> done
-                break;
+    /// Load `dir/MANIFEST` and reconcile it with the segment files present,
+    /// warning about anything that had to be repaired. A missing or unreadable
+    /// manifest is rebuilt from the directory listing.
+    fn load_manifest(dir: &Path) -> Result<Manifest> {
+        let files = Self::segment_files(dir)?;
+        let on_disk: Vec<u64> = files.iter().map(|(id, _)| *id).collect();
+        let (mut manifest, problem) = match Manifest::load(dir) {
+            Ok(Some(manifest)) => (manifest, None),
+            Ok(None) if on_disk.is_empty() => (Manifest::default(), None),
+            Ok(None) => (Manifest::default(), Some("no manifest".to_string())),
+            Err(StoreError::CorruptedData(msg)) => (Manifest::default(), Some(msg)),
+            Err(e) => return Err(e),
+        };
+        let fixes = manifest.reconcile(&on_disk);
+        // Follow files renamed since the manifest was written, e.g. by
+        // `migrate_segment_names`.
+        for (id, path) in &files {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                manifest.set_path(*id, name);
             }
-            let op = op_buf[0];
+        }
+        match problem {
+            Some(problem) => log::warn!(
+                "{} in {}, rebuilt from {} segment files",
+                problem,
+                dir.display(),
+                on_disk.len()
+            ),
+            None => {
+                for fix in fixes {
+                    log::warn!("manifest in {}: {}", dir.display(), fix);
+                }
+            },
+        }
+        Ok(manifest)
+    }
 
-            // Read key length (u32 LE)
-            let mut len_buf = [0u8; 4];
-            reader.read_exact(&mut len_buf).map_err(|e| {
-                StoreError::CorruptedData(format!(
-                    "Failed to read key length in {}: {}",
-                    path.display(),
-                    e
-                ))
-            })?;
-            let key_len = u32::from_le_bytes(len_buf) as usize;
-
-            // Read key bytes
-            let mut key_bytes = vec![0u8; key_len];
-            reader.read_exact(&mut key_bytes).map_err(|e| {
-                StoreError::CorruptedData(format!(
-                    "Failed to read key in {}: {}",
+    /// Apply `update` to the manifest and persist it.
+    pub(crate) fn update_manifest(&mut self, update: impl FnOnce(&mut Manifest)) -> Result<()> {
+        update(&mut self.manifest);
+        self.manifest.save(&self.base_dir)
+    }
+
+    /// The segments this store replays, and their states.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Remove a segment file, tolerating one that is already gone.
+    pub(crate) fn remove_segment_file(dir: &Path, id: u64) -> Result<()> {
+        let path = segment_file_path(dir, id);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(StoreError::CompactionFailed(format!(
+                    "Failed to remove old segment {}: {}",
                     path.display(),
                     e
-                ))
-            })?;
-            let key = String::from_utf8(key_bytes).map_err(|e| {
-                StoreError::CorruptedData(format!("Invalid UTF-8 key in {}: {}", path.display(), e))
-            })?;
+                )))
+            },
+            _ => Ok(()),
+        }
+    }
 
-            match op {
-                0 => {
-                    // set: read value length and bytes
-                    reader.read_exact(&mut len_buf).map_err(|e| {
-                        StoreError::CorruptedData(format!(
-                            "Failed to read val len in {}: {}",
-                            path.display(),
-                            e
-                        ))
-                    })?;
-                    let val_len = u32::from_le_bytes(len_buf) as usize;
-                    let mut val_bytes = vec![0u8; val_len];
-                    reader.read_exact(&mut val_bytes).map_err(|e| {
-                        StoreError::CorruptedData(format!(
-                            "Failed to read val in {}: {}",
-                            path.display(),
-                            e
-                        ))
-                    })?;
-                    values.insert(key, val_bytes);
+    /// Open an independent store rooted at `{base_dir}/{sub}`, with this
+    /// store's settings but its own segments, index and lock. `sub` must be a
+    /// relative path that stays inside `base_dir`.
+    pub fn open_substore<P: AsRef<Path>>(&self, sub: P) -> Result<KVStore> {
+        let sub = sub.as_ref();
+        let nested = sub.components().count() > 0
+            && sub
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !nested {
+            return Err(StoreError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid substore path {}", sub.display()),
+            )));
+        }
+        let base_dir = self.base_dir.join(sub);
+        let config = StoreConfig {
+            data_path: base_dir.clone(),
+            ..self.config.clone()
+        };
+        Self::open_with_config(base_dir, config, None)
+    }
+
+    /// Names of the immediate sub-directories that hold a store, i.e. contain
+    /// a `MANIFEST` or at least one `.dat` file. Sorted.
+    pub fn list_substores(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.base_dir).map_err(StoreError::io_at(&self.base_dir))? {
+            let entry = entry.map_err(StoreError::io_at(&self.base_dir))?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let path = entry.path();
+            let is_store = fs::read_dir(&path)
+                .map_err(StoreError::io_at(&path))?
+                .filter_map(|e| e.ok())
+                .any(|e| {
+                    let name = e.file_name();
+                    let name = name.to_string_lossy();
+                    name == MANIFEST_FILE || parse_segment_file_name(&name).is_some()
+                });
+            if is_store {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Take an exclusive advisory lock on `dir/LOCK`, recording our pid in it.
+    fn lock_data_dir(dir: &Path) -> Result<File> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(StoreError::io_at(&path))?;
+        if let Err(e) = file.try_lock_exclusive() {
+            return Err(if e.kind() == fs2::lock_contended_error().kind() {
+                StoreError::AlreadyLocked(dir.to_path_buf())
+            } else {
+                StoreError::Io(e)
+            });
+        }
+        file.set_len(0)
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .map_err(StoreError::io_at(&path))?;
+        Ok(file)
+    }
+
+    /// Fails with `ReadOnly` if the store was opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Segment files in `dir`, sorted ascending by id.
+    fn segment_files(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+        let mut segment_paths: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(dir).map_err(StoreError::io_at(dir))? {
+            let entry = entry.map_err(StoreError::io_at(dir))?;
+            let path = entry.path();
+            // Both `segment-{id:010}[-{unix_ts}].dat` and the older unpadded
+            // `segment-{id}.dat` are accepted.
+            if let Some(id) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(parse_segment_file_name)
+            {
+                segment_paths.push((id, path));
+            }
+        }
+
+        // sort ascending by id
+        segment_paths.sort_by_key(|(id, _)| *id);
+        Ok(segment_paths)
+    }
+
+    /// Create the data directory, with `config.dir_mode` if set.
+    fn create_data_dir(dir: &Path, config: &StoreConfig) -> Result<()> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = config.dir_mode {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = config;
+        builder.create(dir).map_err(StoreError::io_at(dir))
+    }
+
+    /// Options for creating a segment file, with `config.file_mode` if set.
+    pub(crate) fn segment_file_options(config: &StoreConfig) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options.create(true);
+        #[cfg(unix)]
+        if let Some(mode) = config.file_mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = config;
+        options
+    }
+
+    /// Open a segment file for appending, pre-allocating space if configured.
+    fn open_segment_writer(path: &Path, config: &StoreConfig) -> Result<BufWriter<File>> {
+        let file = Self::segment_file_options(config)
+            .append(true)
+            .open(path)
+            .map_err(StoreError::io_at(path))?;
+        if let Some(size) = config.preallocate_segment_bytes {
+            segment::preallocate_file(&file, size)?;
+        }
+        Ok(BufWriter::new(file))
+    }
+
+    /// Replay a single segment file into the provided values map and index.
+    /// Returns the number of records it holds and how many are tombstones.
+    fn replay_segment(
+        segment_id: u64,
+        path: &Path,
+        values: &mut HashMap<Vec<u8>, Vec<u8>>,
+        index: &mut Index,
+        compressor: &dyn Compressor,
+    ) -> Result<(usize, usize)> {
+        let file = File::open(path).map_err(|e| {
+            StoreError::CorruptedData(format!("Failed to open segment {}: {}", path.display(), e))
+        })?;
+        let mut reader = BufReader::new(file);
+        let mut offset: u64 = 0;
+        let mut records = 0;
+        let mut tombstones = 0;
+
+        while let Some((record, header)) =
+            Record::read_from(&mut reader, compressor, segment_id, offset).map_err(|e| match e {
+                StoreError::CorruptedData(msg) => {
+                    StoreError::CorruptedData(format!("{} in {}", msg, path.display()))
                 },
-                1 => {
-                    // delete
-                    values.remove(&key);
+                other => other,
+            })?
+        {
+            // Keys are opaque bytes; only text-facing APIs care about UTF-8.
+            let key = record.key;
+            match record.value {
+                Some(value) => {
+                    index.insert(
+                        key.clone(),
+                        segment_id as usize,
+                        offset,
+                        header.record_len(),
+                    );
+                    values.insert(key, value);
                 },
-                other => {
-                    return Err(StoreError::CorruptedData(format!(
-                        "Unknown opcode {} in segment {}",
-                        other,
-                        path.display()
-                    )));
+                None => {
+                    index.remove(&key);
+                    values.remove(&key);
+                    tombstones += 1;
                 },
             }
+            offset += header.record_len();
+            records += 1;
         }
 
-        Ok(())
+        Ok((records, tombstones))
     }
 
     /// Append a set operation to the active segment and update in-memory index.
     pub fn set(&mut self, key: &str, value: &[u8]) -> Result<()> {
-        // write entry: op(1) = 0, key_len(u32), key, val_len(u32), val
+        self.set_bytes_key(key.as_bytes(), value)
+    }
+
+    /// Like [`set`](Self::set), but the key may be any bytes, including
+    /// NULs and sequences that aren't valid UTF-8.
+    ///
+    /// The key validator only sees keys that are valid UTF-8.
+    pub fn set_bytes_key(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_evicting(key, value).map(|_| ())
+    }
+
+    /// Add `key` as a member with no value, for using the store as a set.
+    /// Stored as a zero-length value, so `get` returns `Some(vec![])`.
+    pub fn add(&mut self, key: &str) -> Result<()> {
+        self.set(key, b"")
+    }
+
+    /// Delete every live key starting with `prefix`. Returns how many were
+    /// deleted.
+    pub fn delete_prefix(&mut self, prefix: &str) -> Result<usize> {
+        self.check_writable()?;
+        let keys: Vec<Vec<u8>> = self
+            .values
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_bytes()))
+            .cloned()
+            .collect();
+        for key in &keys {
+            self.delete_bytes_key(key)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Like [`set`](Self::set), and returns the keys tombstoned to bring the
+    /// store back under `max_total_bytes`, oldest first.
+    pub fn set_with_eviction(&mut self, key: &str, value: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.set_evicting(key.as_bytes(), value)
+    }
+
+    fn set_evicting(&mut self, key: &[u8], value: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let started = self.start_timer();
+        let result = self.set_evicting_untimed(key, value);
+        self.record_timing(Op::Set, started);
+        if self.config.verbose_logging && result.is_ok() {
+            log::debug!(
+                "set key={} bytes={} segment={}",
+                String::from_utf8_lossy(key),
+                value.len(),
+                self.active_segment_id
+            );
+        }
+        result
+    }
+
+    fn set_evicting_untimed(&mut self, key: &[u8], value: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.check_writable()?;
+        self.check_throttle()?;
+        let key = self.validate_key_bytes(key)?;
+        self.check_index_memory(&key)?;
+        self.append(&key, Some(value))?;
+        self.evict_over_budget(&HashSet::from([&key[..]]))
+    }
+
+    /// Lock `key` of a store shared between threads, for a read-modify-write
+    /// cycle that doesn't block access to other keys. The store mutex is only
+    /// taken briefly, here and for each operation on the returned guard.
+    pub fn lock_key<'a>(store: &'a Mutex<KVStore>, key: &str) -> KeyGuard<'a> {
+        KeyGuard::new(store, key)
+    }
+
+    pub(crate) fn key_locks(&self) -> Arc<KeyLocks> {
+        Arc::clone(&self.key_locks)
+    }
+
+    /// Start time for an operation, if timings are being collected.
+    fn start_timer(&self) -> Option<Instant> {
+        self.timings.as_ref().map(|_| Instant::now())
+    }
+
+    fn record_timing(&self, op: Op, started: Option<Instant>) {
+        if let (Some(timings), Some(started)) = (&self.timings, started) {
+            timings.record(op, started);
+        }
+    }
+
+    /// Counts and total latency of `get`, `set` and `delete` since open.
+    /// All zero unless `StoreConfig::collect_timings` is set.
+    pub fn op_stats(&self) -> OpStats {
+        self.timings
+            .as_ref()
+            .map(OpTimings::snapshot)
+            .unwrap_or_default()
+    }
+
+    /// Tombstone the least recently written keys until live values fit in
+    /// `max_total_bytes`. The keys in `keep`, just written, are never evicted.
+    fn evict_over_budget(&mut self, keep: &HashSet<&[u8]>) -> Result<Vec<Vec<u8>>> {
+        let mut evicted = Vec::new();
+        let Some(max) = self.config.max_total_bytes else {
+            return Ok(evicted);
+        };
+        while let Some(order) = &self.write_order {
+            if order.total_bytes() <= max {
+                break;
+            }
+            let Some(oldest) = order.oldest().filter(|k| !keep.contains(k)) else {
+                break;
+            };
+            let oldest = oldest.to_vec();
+            self.append(&oldest, None)?;
+            evicted.push(oldest);
+        }
+        Ok(evicted)
+    }
+
+    /// Append a delete operation to the active segment and update in-memory index.
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        self.delete_bytes_key(key.as_bytes())
+    }
+
+    /// Byte-key counterpart of [`delete`](Self::delete).
+    pub fn delete_bytes_key(&mut self, key: &[u8]) -> Result<()> {
+        let started = self.start_timer();
+        let result = self.check_writable().and_then(|_| {
+            let key = self.validate_key_bytes(key)?;
+            self.append(&key, None)
+        });
+        self.record_timing(Op::Delete, started);
+        if self.config.verbose_logging && result.is_ok() {
+            log::debug!("delete key={}", String::from_utf8_lossy(key));
+        }
+        result
+    }
+
+    /// Write a set (`Some(value)`) or tombstone record for an already
+    /// validated key, flush it and update the in-memory state.
+    fn append(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        self.check_background_sync()?;
         let writer = self
             .active_writer
             .as_mut()
             .ok_or_else(|| StoreError::Io(std::io::Error::other("Active writer missing")))?;
 
-        // Build buffers
-        let key_bytes = key.as_bytes();
-        let key_len = (key_bytes.len() as u32).to_le_bytes();
-        let val_len = (value.len() as u32).to_le_bytes();
-
-        writer.write_all(&[0u8]).map_err(StoreError::Io)?;
-        writer.write_all(&key_len).map_err(StoreError::Io)?;
-        writer.write_all(key_bytes).map_err(StoreError::Io)?;
-        writer.write_all(&val_len).map_err(StoreError::Io)?;
-        writer.write_all(value).map_err(StoreError::Io)?;
+        let offset = self.active_segment_len;
+        let written = record::write_record(
+            writer,
+            key,
+            value,
+            &*self.compressor,
+            self.config.compression_threshold_bytes,
+            self.config.align_records,
+        )
+        .map_err(StoreError::Io)?;
         writer.flush().map_err(StoreError::Io)?;
+        self.sync_if_always()?;
+        self.active_segment_len += written;
+        self.segment_bytes_written += written;
+        self.records_in_active_segment += 1;
+        self.tombstones_in_active_segment += usize::from(value.is_none());
 
         // update in-memory
-        self.values.insert(key.to_string(), value.to_vec());
+        match value {
+            Some(value) => {
+                self.index.insert(
+                    key.to_vec(),
+                    self.active_segment_id as usize,
+                    offset,
+                    written,
+                );
+                self.values.insert(key.to_vec(), value.to_vec());
+                if let Some(order) = &mut self.write_order {
+                    order.record_write(key, value.len() as u64);
+                }
+            },
+            None => {
+                self.index.remove(key);
+                self.values.remove(key);
+                self.tombstone_count += 1;
+                if let Some(order) = &mut self.write_order {
+                    order.record_delete(key);
+                }
+            },
+        }
+        self.rotate_if_full()
+    }
+
+    /// Fsync the active segment after a flushed write under
+    /// `FsyncPolicy::Always`; the other policies leave it to the background
+    /// thread or the OS.
+    fn sync_if_always(&mut self) -> Result<()> {
+        if !matches!(self.config.fsync_policy, FsyncPolicy::Always) {
+            return Ok(());
+        }
+        if let Some(writer) = self.active_writer.as_ref() {
+            retry_on_interrupt(|| writer.get_ref().sync_data()).map_err(StoreError::Io)?;
+            self.inline_fsyncs += 1;
+        }
         Ok(())
     }
 
-    /// Append a delete operation to the active segment and update in-memory index.
-    pub fn delete(&mut self, key: &str) -> Result<()> {
+    /// Report a background fsync failure that happened since the last write,
+    /// so the caller learns earlier writes may not be durable.
+    fn check_background_sync(&self) -> Result<()> {
+        match self
+            .background_sync
+            .as_ref()
+            .and_then(|sync| sync.take_error())
+        {
+            Some(e) => Err(StoreError::Io(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Start a new segment once the active one reaches `max_segment_size`
+    /// bytes, `max_records_per_segment` records or
+    /// `max_tombstones_before_rotation` tombstones, whichever comes first.
+    fn rotate_if_full(&mut self) -> Result<()> {
+        let reached = |limit: Option<usize>, count: usize| limit.is_some_and(|max| count >= max);
+        if self.active_segment_len >= self.config.max_segment_size
+            || reached(
+                self.config.max_records_per_segment,
+                self.records_in_active_segment,
+            )
+            || reached(
+                self.config.max_tombstones_before_rotation,
+                self.tombstones_in_active_segment,
+            )
+        {
+            self.reset_active_segment()?;
+            self.flag_compaction_if_due();
+        }
+        Ok(())
+    }
+
+    /// Flag a pending compaction once dead bytes make up more than
+    /// `compaction_dead_ratio` of the segment files. Run after a rotation
+    /// rather than per write, since the estimate scans segment headers.
+    fn flag_compaction_if_due(&mut self) {
+        let estimate = self.compaction_estimate();
+        let total = estimate.live_bytes + estimate.dead_bytes;
+        if total > 0
+            && estimate.dead_bytes as f64 > total as f64 * self.config.compaction_dead_ratio
+        {
+            self.pending_compaction = true;
+        }
+    }
+
+    /// Append every operation in `batch` to the active segment with a single flush,
+    /// then apply them to the in-memory index in order.
+    ///
+    /// Checked like [`set`](Self::set): the whole batch is refused while
+    /// writes are throttled or if its new keys would take the index past
+    /// `max_index_memory_bytes`, and older keys are evicted afterwards to
+    /// stay under `max_total_bytes`.
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        self.check_writable()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.check_throttle()?;
+        let batch = self.validate_batch(batch)?;
+        self.check_batch_index_memory(&batch)?;
+        self.check_background_sync()?;
         let writer = self
             .active_writer
             .as_mut()
             .ok_or_else(|| StoreError::Io(std::io::Error::other("Active writer missing")))?;
 
-        let key_bytes = key.as_bytes();
-        let key_len = (key_bytes.len() as u32).to_le_bytes();
-
-        writer.write_all(&[1u8]).map_err(StoreError::Io)?;
-        writer.write_all(&key_len).map_err(StoreError::Io)?;
-        writer.write_all(key_bytes).map_err(StoreError::Io)?;
+        let mut written = 0u64;
+        let mut locations = Vec::with_capacity(batch.len());
+        for op in batch.ops() {
+            let (key, value) = match op {
+                BatchOp::Set { key, value } => (key, Some(value.as_slice())),
+                BatchOp::Delete { key } => (key, None),
+            };
+            let offset = self.active_segment_len + written;
+            let len = record::write_record(
+                writer,
+                key.as_bytes(),
+                value,
+                &*self.compressor,
+                self.config.compression_threshold_bytes,
+                self.config.align_records,
+            )
+            .map_err(StoreError::Io)?;
+            locations.push((offset, len));
+            written += len;
+        }
         writer.flush().map_err(StoreError::Io)?;
+        self.sync_if_always()?;
+        self.active_segment_len += written;
+        self.segment_bytes_written += written;
+        self.records_in_active_segment += batch.len();
+
+        let segment_id = self.active_segment_id as usize;
+        for (op, (offset, len)) in batch.ops().iter().zip(locations) {
+            match op {
+                BatchOp::Set { key, value } => {
+                    if let Some(order) = &mut self.write_order {
+                        order.record_write(key.as_bytes(), value.len() as u64);
+                    }
+                    let key = key.as_bytes().to_vec();
+                    self.index.insert(key.clone(), segment_id, offset, len);
+                    self.values.insert(key, value.clone());
+                },
+                BatchOp::Delete { key } => {
+                    self.tombstone_count += 1;
+                    self.tombstones_in_active_segment += 1;
+                    if let Some(order) = &mut self.write_order {
+                        order.record_delete(key.as_bytes());
+                    }
+                    self.index.remove(key.as_bytes());
+                    self.values.remove(key.as_bytes());
+                },
+            }
+        }
+        // A batch is never split across segments, so it may overshoot.
+        self.rotate_if_full()?;
+
+        let written: HashSet<&[u8]> = batch
+            .ops()
+            .iter()
+            .filter_map(|op| match op {
+                BatchOp::Set { key, .. } => Some(key.as_bytes()),
+                BatchOp::Delete { .. } => None,
+            })
+            .collect();
+        self.evict_over_budget(&written).map(|_| ())
+    }
+
+    /// Set every entry through one [`WriteBatch`]: a single append and flush,
+    /// then one fsync of the active segment unless the policy is
+    /// `FsyncPolicy::Never`; under `FsyncPolicy::Interval` it doesn't wait for
+    /// the timer. Later entries for the same key win. Returns the number of
+    /// entries written.
+    pub fn set_many(
+        &mut self,
+        entries: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> Result<usize> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in entries {
+            batch.set(key, value);
+        }
+        let count = batch.len();
+        self.write_batch(batch)?;
+        // `write_batch` already synced under `Always`.
+        if count > 0 && matches!(self.config.fsync_policy, FsyncPolicy::Interval) {
+            if let Some(writer) = self.active_writer.as_ref() {
+                retry_on_interrupt(|| writer.get_ref().sync_data()).map_err(StoreError::Io)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Load key-sorted pairs straight into a fresh segment, then rebuild the
+    /// in-memory index from that segment in one pass.
+    ///
+    /// Keys must arrive in non-decreasing order; an out-of-order key aborts the
+    /// load with `StoreError::UnsortedInput`. Otherwise checked like
+    /// [`write_batch`](Self::write_batch): a rejected key, a load that would
+    /// take the index past `max_index_memory_bytes` or a write error aborts
+    /// it too, and any abort discards everything written by this call.
+    pub fn bulk_load<I>(&mut self, iter: I) -> Result<BulkLoadStats>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        self.check_writable()?;
+        self.check_throttle()?;
+        self.check_background_sync()?;
+        let started = Instant::now();
+        self.reset_active_segment()?;
+        let segment_id = self.active_segment_id;
+        let path = self.segment_path(segment_id);
+
+        let (records, bytes_written) = match self.write_sorted(iter) {
+            Ok(written) => written,
+            Err(e) => {
+                self.discard_active_segment(&path)?;
+                return Err(e);
+            },
+        };
+        self.sync_if_always()?;
+        self.active_segment_len = bytes_written;
+        self.segment_bytes_written += bytes_written;
+        self.records_in_active_segment = records;
+        Self::replay_segment(
+            segment_id,
+            &path,
+            &mut self.values,
+            &mut self.index,
+            &*self.compressor,
+        )?;
+        if self.write_order.is_some() {
+            self.write_order = Some(WriteOrder::from_index(&self.index, &self.values));
+        }
+        // Like a batch, the load is never split across segments.
+        self.rotate_if_full()?;
+
+        if self.config.max_total_bytes.is_some() {
+            let loaded: Vec<Vec<u8>> = self
+                .index
+                .iter()
+                .filter(|(_, &(seg, _, _))| seg as u64 == segment_id)
+                .map(|(key, _)| key.clone())
+                .collect();
+            self.evict_over_budget(&loaded.iter().map(Vec::as_slice).collect())?;
+        }
+
+        Ok(BulkLoadStats {
+            records,
+            bytes_written,
+            segment_id,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Write the records of [`bulk_load`](Self::bulk_load) to the active
+    /// segment and flush them. Returns the records and bytes written; on
+    /// error the caller discards the segment.
+    fn write_sorted<I>(&mut self, iter: I) -> Result<(usize, u64)>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        let mut records = 0;
+        let mut bytes_written = 0;
+        let mut new_keys = 0;
+        let mut new_key_bytes = 0;
+        let mut previous: Option<String> = None;
+        for (key, value) in iter {
+            let key = self.validate_key(&key)?.into_owned();
+            if let Some(prev) = &previous {
+                if key < *prev {
+                    return Err(StoreError::UnsortedInput {
+                        previous: prev.clone(),
+                        key,
+                    });
+                }
+            }
+            if let Some(max) = self.config.max_index_memory_bytes {
+                if previous.as_ref() != Some(&key) && !self.index.contains(key.as_bytes()) {
+                    new_keys += 1;
+                    new_key_bytes += key.len();
+                    if self.index.memory_estimate_after(new_keys, new_key_bytes) > max {
+                        return Err(StoreError::StoreFull);
+                    }
+                }
+            }
 
-        self.values.remove(key);
+            let writer = self
+                .active_writer
+                .as_mut()
+                .ok_or_else(|| StoreError::Io(std::io::Error::other("Active writer missing")))?;
+            bytes_written += record::write_record(
+                writer,
+                key.as_bytes(),
+                Some(&value),
+                &*self.compressor,
+                self.config.compression_threshold_bytes,
+                self.config.align_records,
+            )
+            .map_err(StoreError::Io)?;
+            records += 1;
+            previous = Some(key);
+        }
+
+        if let Some(writer) = self.active_writer.as_mut() {
+            writer.flush().map_err(StoreError::Io)?;
+        }
+        Ok((records, bytes_written))
+    }
+
+    /// Drop everything buffered or written to the active segment.
+    fn discard_active_segment(&mut self, path: &Path) -> Result<()> {
+        // Throw the buffer away rather than flush it, which may be what failed.
+        if let Some(writer) = self.active_writer.take() {
+            let (file, _) = writer.into_parts();
+            self.active_writer = Some(BufWriter::new(file));
+        }
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_len(0))
+            .map_err(StoreError::io_at(path))?;
+        self.active_segment_len = 0;
+        Ok(())
+    }
+
+    /// Refuse writes while the active segment is past the throttle threshold
+    /// and a compaction is pending, so callers back off instead of piling on.
+    fn check_throttle(&self) -> Result<()> {
+        if !self.config.enable_write_throttling || !self.pending_compaction {
+            return Ok(());
+        }
+        let limit = self.config.max_segment_size as f64 * self.config.throttle_threshold;
+        if self.active_segment_len as f64 > limit {
+            return Err(StoreError::WriteThrottled {
+                suggested_delay_ms: THROTTLE_DELAY_MS,
+            });
+        }
         Ok(())
     }
 
+    /// Refuse new keys once the index has grown to `max_index_memory_bytes`.
+    /// Overwriting an existing key doesn't grow the index and is always allowed.
+    fn check_index_memory(&self, key: &[u8]) -> Result<()> {
+        if let Some(max) = self.config.max_index_memory_bytes {
+            if !self.index.contains(key) && self.index.memory_estimate_bytes() >= max {
+                return Err(StoreError::StoreFull);
+            }
+        }
+        Ok(())
+    }
+
+    /// Batch counterpart of [`check_index_memory`](Self::check_index_memory):
+    /// refuse the batch if the keys it sets that aren't indexed yet would
+    /// take the index past `max_index_memory_bytes`.
+    fn check_batch_index_memory(&self, batch: &WriteBatch) -> Result<()> {
+        let Some(max) = self.config.max_index_memory_bytes else {
+            return Ok(());
+        };
+        let new_keys: HashSet<&[u8]> = batch
+            .ops()
+            .iter()
+            .filter_map(|op| match op {
+                BatchOp::Set { key, .. } if !self.index.contains(key.as_bytes()) => {
+                    Some(key.as_bytes())
+                },
+                _ => None,
+            })
+            .collect();
+        if new_keys.is_empty() {
+            return Ok(());
+        }
+        let new_key_bytes = new_keys.iter().map(|key| key.len()).sum();
+        if self
+            .index
+            .memory_estimate_after(new_keys.len(), new_key_bytes)
+            > max
+        {
+            return Err(StoreError::StoreFull);
+        }
+        Ok(())
+    }
+
+    /// Approximate heap bytes held by the in-memory state: every live key
+    /// and value, the value map's table, and the index.
+    pub fn memory_estimate(&self) -> usize {
+        let data: usize = self
+            .values
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        let slot = 2 * std::mem::size_of::<Vec<u8>>();
+        data + hash_table_bytes(self.values.capacity(), slot) + self.index.memory_estimate_bytes()
+    }
+
+    /// Releases the spare capacity the value map and index keep after many
+    /// deletes. Their tables never shrink on their own.
+    pub fn shrink_to_fit(&mut self) {
+        self.values.shrink_to_fit();
+        self.index.shrink_to_fit();
+    }
+
+    /// Flag (or clear) that a compaction is due. Set by segment rotations
+    /// once enough of the log is dead, and cleared by `compact`.
+    pub fn set_pending_compaction(&mut self, pending: bool) {
+        self.pending_compaction = pending;
+    }
+
+    pub fn pending_compaction(&self) -> bool {
+        self.pending_compaction
+    }
+
+    pub(crate) fn segment_path(&self, id: u64) -> PathBuf {
+        self.manifest.segment_path(&self.base_dir, id)
+    }
+
+    /// File name for a segment created now, timestamped if the config asks.
+    fn new_segment_name(id: u64, config: &StoreConfig) -> String {
+        let unix_ts = config.timestamp_segment_names.then(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+        segment_file_name(id, unix_ts)
+    }
+
+    /// Tombstone every key in one buffered write with a single flush.
+    /// Returns how many of the keys were actually present.
+    pub fn delete_batch(&mut self, keys: &[&str]) -> Result<usize> {
+        let mut batch = WriteBatch::new();
+        let mut present = HashSet::new();
+        for key in keys {
+            let key = self.validate_key(key)?;
+            if self.values.contains_key(key.as_bytes()) {
+                present.insert(key.to_string());
+            }
+            batch.delete(key.into_owned());
+        }
+        self.write_batch(batch)?;
+        Ok(present.len())
+    }
+
+    /// Move the value of `from` to `to`, writing `to` and tombstoning `from`
+    /// in one buffered write with a single flush. An existing `to` is
+    /// overwritten. Returns `false`, writing nothing, if `from` is absent.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<bool> {
+        self.check_writable()?;
+        let from = self.validate_key(from)?.into_owned();
+        let to = self.validate_key(to)?.into_owned();
+        let Some(value) = self.values.get(from.as_bytes()).cloned() else {
+            return Ok(false);
+        };
+        if from == to {
+            return Ok(true);
+        }
+        let mut batch = WriteBatch::new();
+        batch.set(to, value).delete(from);
+        self.write_batch(batch)?;
+        Ok(true)
+    }
+
     pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        Ok(self.values.get(key).cloned())
+        self.get_bytes_key(key.as_bytes())
+    }
+
+    /// Like [`get`](Self::get), but a key that is absent (never set, or
+    /// deleted) is `Err(KeyNotFound)` rather than `Ok(None)`.
+    pub fn get_strict(&self, key: &str) -> Result<Vec<u8>> {
+        self.get(key)?
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))
+    }
+
+    /// Whether `key` has a live value, without copying it.
+    pub fn contains_key(&self, key: &str) -> Result<bool> {
+        self.validate_key_bytes(key.as_bytes())
+            .map(|key| self.values.contains_key(key.as_ref()))
+    }
+
+    /// Byte-key counterpart of [`get`](Self::get).
+    pub fn get_bytes_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let started = self.start_timer();
+        let result = self
+            .validate_key_bytes(key)
+            .map(|key| self.values.get(key.as_ref()).cloned());
+        self.record_timing(Op::Get, started);
+        if self.config.verbose_logging {
+            if let Ok(value) = &result {
+                log::trace!(
+                    "get key={} bytes={}",
+                    String::from_utf8_lossy(key),
+                    value.as_ref().map_or(0, Vec::len)
+                );
+            }
+        }
+        result
+    }
+
+    /// Run the configured key validator, if any.
+    fn validate_key<'a>(&self, key: &'a str) -> Result<Cow<'a, str>> {
+        match &self.config.key_validator {
+            Some(validator) => validator.validate(key),
+            None => Ok(Cow::Borrowed(key)),
+        }
+    }
+
+    /// Run the key validator on keys that are valid UTF-8; other byte keys
+    /// pass through unchanged.
+    fn validate_key_bytes<'a>(&self, key: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let Ok(text) = std::str::from_utf8(key) else {
+            return Ok(Cow::Borrowed(key));
+        };
+        Ok(match self.validate_key(text)? {
+            Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+            Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+        })
     }
 
+    /// Validate (and possibly normalize) every key in a batch.
+    fn validate_batch(&self, batch: WriteBatch) -> Result<WriteBatch> {
+        if self.config.key_validator.is_none() {
+            return Ok(batch);
+        }
+        let mut validated = WriteBatch::new();
+        for op in batch.ops() {
+            match op {
+                BatchOp::Set { key, value } => {
+                    validated.set(self.validate_key(key)?.into_owned(), value.clone());
+                },
+                BatchOp::Delete { key } => {
+                    validated.delete(self.validate_key(key)?.into_owned());
+                },
+            }
+        }
+        Ok(validated)
+    }
+
+    /// Live keys as text. Keys that aren't valid UTF-8 are converted lossily;
+    /// use [`list_bytes_keys`](Self::list_bytes_keys) to get them exactly.
     pub fn list_keys(&self) -> Vec<String> {
+        self.values
+            .keys()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect()
+    }
+
+    pub fn list_bytes_keys(&self) -> Vec<Vec<u8>> {
         self.values.keys().cloned().collect()
     }
 
+    /// Where the latest record of `key` lives, as `(segment_id, offset, len)`;
+    /// `len` covers the whole record, header included. `None` for absent or
+    /// deleted keys.
+    pub fn locate(&self, key: &str) -> Option<(usize, u64, u64)> {
+        self.index.get(key.as_bytes()).copied()
+    }
+
+    /// Location and header details of the latest record of `key`, read
+    /// from disk without loading the value. `None` for absent or deleted keys.
+    pub fn describe_key(&self, key: &str) -> Result<Option<KeyDescription>> {
+        let Some(&(segment_id, offset, _)) = self.index.get(key.as_bytes()) else {
+            return Ok(None);
+        };
+        let path = self.segment_path(segment_id as u64);
+        let header = self
+            .open_segment(segment_id)?
+            .read_header_at_cached(offset, &mut **self.block_cache())?;
+        let last_modified_secs = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        Ok(Some(KeyDescription {
+            key: key.to_string(),
+            segment_id,
+            offset,
+            value_len: u64::from(header.value_len),
+            checksum: header.checksum,
+            last_modified_secs,
+            is_tombstone: header.is_tombstone(),
+        }))
+    }
+
+    /// Like [`get`](Self::get), but reads the value back from its segment
+    /// and verifies its checksum instead of trusting the in-memory copy, so
+    /// on-disk damage since the store was opened is reported as
+    /// `ChecksumMismatch`.
+    pub fn get_verified(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(&(segment_id, offset, _)) = self.index.get(key.as_bytes()) else {
+            return Ok(None);
+        };
+        let path = self.segment_path(segment_id as u64);
+        let file = File::open(&path).map_err(StoreError::io_at(&path))?;
+        let mut reader = BufReader::new(file);
+        retry_on_interrupt(|| reader.seek(SeekFrom::Start(offset)))
+            .map_err(StoreError::io_at(&path))?;
+        let (record, _) =
+            Record::read_from(&mut reader, &*self.compressor, segment_id as u64, offset)?
+                .ok_or_else(|| {
+                    StoreError::CorruptedData(format!(
+                        "No record for {} at offset {} of {}",
+                        key,
+                        offset,
+                        path.display()
+                    ))
+                })?;
+        Ok(record.value)
+    }
+
+    /// A reader over the value of `key`, streamed from its segment rather than
+    /// copied, for values too big to hold twice. Raw values are read straight
+    /// from the file and their checksum is not verified; compressed ones are
+    /// decompressed (and verified) up front, through the block cache. `None`
+    /// for absent or deleted keys.
+    pub fn get_reader(&self, key: &str) -> Result<Option<impl Read>> {
+        let Some(&(segment_id, offset, _)) = self.index.get(key.as_bytes()) else {
+            return Ok(None);
+        };
+        let path = self.segment_path(segment_id as u64);
+        let corrupted = || {
+            StoreError::CorruptedData(format!(
+                "No record for {} at offset {} of {}",
+                key,
+                offset,
+                path.display()
+            ))
+        };
+        let mut segment = self.open_segment(segment_id)?;
+        let header = segment.read_header_at_cached(offset, &mut **self.block_cache())?;
+        if header.is_tombstone() || header.key_len as usize != key.len() {
+            return Err(corrupted());
+        }
+        if header.is_compressed() {
+            let (_, value, _) = segment
+                .read_record_at_cached(offset, &mut **self.block_cache())?
+                .ok_or_else(corrupted)?;
+            return Ok(Some(ValueReader::Decoded(Cursor::new(
+                value.unwrap_or_default(),
+            ))));
+        }
+        let value_offset = offset + HEADER_SIZE as u64 + u64::from(header.key_len);
+        let mut reader = BufReader::new(File::open(&path).map_err(StoreError::io_at(&path))?);
+        retry_on_interrupt(|| reader.seek(SeekFrom::Start(value_offset)))
+            .map_err(StoreError::io_at(&path))?;
+        Ok(Some(ValueReader::Raw(
+            reader.take(u64::from(header.value_len)),
+        )))
+    }
+
+    /// Copy of the index as `(key, segment_id, offset, len)` per live key,
+    /// sorted by key. Non-UTF-8 keys are converted lossily.
+    pub fn index_snapshot(&self) -> Vec<(String, usize, u64, u64)> {
+        let mut entries: Vec<_> = self
+            .index
+            .iter()
+            .map(|(key, (seg, offset, len))| {
+                (
+                    String::from_utf8_lossy(key).into_owned(),
+                    *seg,
+                    *offset,
+                    *len,
+                )
+            })
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    /// Live keys starting with `prefix`, sorted. Scans every key.
+    pub fn members_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut members: Vec<String> = self
+            .values
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_bytes()))
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect();
+        members.sort_unstable();
+        members
+    }
+
+    /// Number of live keys starting with `prefix`, without collecting them.
+    /// O(n): keys are kept in a hash map, so this scans them all.
+    pub fn scan_count(&self, prefix: &str) -> usize {
+        self.index
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_bytes()))
+            .count()
+    }
+
+    /// Total size of the values of live keys starting with `prefix`, as
+    /// stored (after compression, plus any alignment padding), taken from the index without reading
+    /// them. O(n), like [`scan_count`](Self::scan_count).
+    pub fn value_bytes_for_prefix(&self, prefix: &str) -> u64 {
+        self.index
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix.as_bytes()))
+            .map(|(key, &(_, _, len))| len - HEADER_SIZE as u64 - key.len() as u64)
+            .sum()
+    }
+
+    /// The smallest live key, comparing raw bytes. O(n): keys are kept in a
+    /// hash map, so this scans them all.
+    pub fn first_key(&self) -> Option<String> {
+        self.values
+            .keys()
+            .min()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+    }
+
+    /// The largest live key, comparing raw bytes. O(n), like
+    /// [`first_key`](Self::first_key).
+    pub fn last_key(&self) -> Option<String> {
+        self.values
+            .keys()
+            .max()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+    }
+
     /// Create a fresh active segment. Used after compaction to start a new file.
     pub fn reset_active_segment(&mut self) -> Result<()> {
-        // Close current writer by dropping it
-        self.active_writer = None;
+        self.check_writable()?;
+        self.seal_active_segment()?;
 
         // increment id and create new file
         self.active_segment_id = self
             .active_segment_id
             .checked_add(1)
             .ok_or_else(|| StoreError::Io(std::io::Error::other("segment id overflow")))?;
-        let path = self.base_dir.join(format!(
-            "{}{}{}",
-            SEGMENT_PREFIX, self.active_segment_id, SEGMENT_SUFFIX
-        ));
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&path)
-            .map_err(StoreError::Io)?;
-        self.active_writer = Some(BufWriter::new(file));
+        let id = self.active_segment_id;
+        let name = Self::new_segment_name(id, &self.config);
+        let writer = Self::open_segment_writer(&self.base_dir.join(&name), &self.config)?;
+        if let Some(sync) = &self.background_sync {
+            sync.set_file(Some(writer.get_ref().try_clone().map_err(StoreError::Io)?));
+        }
+        self.active_writer = Some(writer);
+        self.active_segment_len = 0;
+        self.records_in_active_segment = 0;
+        self.tombstones_in_active_segment = 0;
+        self.update_manifest(|manifest| {
+            manifest.push_active(id);
+            manifest.set_path(id, name);
+        })?;
+        if self.config.verbose_logging {
+            log::debug!("rotated to segment {}", id);
+        }
+        Ok(())
+    }
+
+    /// Make everything written so far durable and immutable: flush and fsync
+    /// the active segment, footer it with its CRC and start a new segment.
+    /// The returned position is also saved to `{base_dir}/CHECKPOINT`.
+    pub fn checkpoint(&mut self) -> Result<CheckpointInfo> {
+        self.check_writable()?;
+        if let Some(writer) = self.active_writer.as_mut() {
+            writer.flush().map_err(StoreError::Io)?;
+            retry_on_interrupt(|| writer.get_ref().sync_all())?;
+        }
+        let info = CheckpointInfo {
+            segment_id: self.active_segment_id as usize,
+            byte_offset: self.active_segment_len,
+            lsn: self.next_lsn(),
+            timestamp: SystemTime::now(),
+        };
+        self.reset_active_segment()?;
+
+        let tmp_path = self.base_dir.join(CHECKPOINT_TMP_FILE);
+        let json = serde_json::to_vec_pretty(&info)
+            .map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
+        File::create(&tmp_path)
+            .and_then(|mut file| {
+                retry_on_interrupt(|| file.write_all(&json))?;
+                retry_on_interrupt(|| file.sync_all())
+            })
+            .map_err(StoreError::io_at(&tmp_path))?;
+        let path = self.base_dir.join(CHECKPOINT_FILE);
+        fs::rename(&tmp_path, &path).map_err(StoreError::io_at(&path))?;
+        Ok(info)
+    }
+
+    /// Whether the previous session closed the store cleanly. `false` means
+    /// it likely crashed, and [`verify`](Self::verify) is worth running.
+    /// A store opened read-only next to a live writer also reports `false`.
+    pub fn was_clean_shutdown(&self) -> bool {
+        self.clean_shutdown
+    }
+
+    /// Seal the active segment and mark the shutdown as clean, reporting any
+    /// failure. Dropping the store does the same but can only log errors.
+    pub fn close(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        if self.closed || self.read_only {
+            return Ok(());
+        }
+        // The next open starts a fresh active segment, so this one is done.
+        self.seal_active_segment()?;
+        let marker = self.base_dir.join(CLEAN_SHUTDOWN_FILE);
+        File::create(&marker)
+            .and_then(|file| retry_on_interrupt(|| file.sync_all()))
+            .map_err(StoreError::io_at(&marker))?;
+        self.closed = true;
+        self.check_background_sync()
+    }
+
+    /// The settings this store was opened with.
+    pub fn config(&self) -> &StoreConfig {
+        &self.config
+    }
+
+    /// Flush and close the active writer and footer the segment with its CRC.
+    fn seal_active_segment(&mut self) -> Result<()> {
+        let Some(mut writer) = self.active_writer.take() else {
+            return Ok(());
+        };
+        if let Some(sync) = &self.background_sync {
+            sync.set_file(None);
+        }
+        writer.flush().map_err(StoreError::Io)?;
+        drop(writer);
+        let path = self.segment_path(self.active_segment_id);
+        let mut segment = Segment::open_path(path.clone(), self.active_segment_id as usize)?;
+        if !segment.is_sealed() {
+            segment.close()?;
+            self.segment_bytes_written += FOOTER_SIZE as u64;
+        }
+        let size = fs::metadata(&path).map_err(StoreError::io_at(&path))?.len();
+        self.segment_sizes.insert(self.active_segment_id, size);
         Ok(())
     }
 
+    fn block_cache(&self) -> std::sync::MutexGuard<'_, Box<dyn ValueCache>> {
+        self.block_cache
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Opens segment `id` for reading, without write access when the store
+    /// is read-only.
+    fn open_segment(&self, id: usize) -> Result<Segment> {
+        let path = self.segment_path(id as u64);
+        let segment = if self.read_only {
+            Segment::open_read_only_path(path, id)?
+        } else {
+            Segment::open_path(path, id)?
+        };
+        Ok(segment
+            .with_compressor(self.compressor.clone())
+            .with_record_alignment(self.config.align_records))
+    }
+
+    /// Check every live segment, using the footer CRC of sealed segments and
+    /// per-record checksums for the rest. Fails on the first damaged segment.
+    pub fn verify(&self) -> Result<Vec<(u64, Verification)>> {
+        self.segment_ids()
+            .into_iter()
+            .map(|id| {
+                let mut segment = self.open_segment(id as usize)?;
+                Ok((id, segment.verify()?))
+            })
+            .collect()
+    }
+
+    /// Records of segment `seg_id` in file order, for inspecting a single
+    /// segment. Fails with `SegmentNotFound` unless the manifest lists it.
+    pub fn iter_segment(&mut self, seg_id: usize) -> Result<SegmentRecordIter<'_>> {
+        if !self.segment_ids().contains(&(seg_id as u64)) {
+            return Err(StoreError::SegmentNotFound(seg_id));
+        }
+        if let Some(writer) = self.active_writer.as_mut() {
+            writer.flush().map_err(StoreError::Io)?;
+        }
+        self.open_segment(seg_id)?.into_records()
+    }
+
     /// Returns base dir (clone)
     pub fn base_dir(&self) -> PathBuf {
         self.base_dir.clone()
     }
 
-    /// Simple stats view
+    /// Fsyncs writes have done themselves under `FsyncPolicy::Always`, one
+    /// per write or batch; always 0 under the other policies.
+    pub fn inline_fsyncs(&self) -> u64 {
+        self.inline_fsyncs
+    }
+
+    /// Fsyncs the background thread has completed under
+    /// `FsyncPolicy::Interval`; always 0 under the other policies.
+    pub fn background_fsyncs(&self) -> u64 {
+        self.background_sync.as_ref().map_or(0, |sync| sync.syncs())
+    }
+
+    /// Simple stats view. `on_disk_bytes` comes from listing the data
+    /// directory, so it also counts segment files the store doesn't track.
     pub fn stats(&self) -> StoreStats {
-        // Count segments by scanning dir (cheap)
-        let num_segments = match fs::read_dir(&self.base_dir) {
-            Ok(rd) => rd
-                .filter_map(|r| r.ok())
-                .filter(|e| {
-                    e.file_name()
-                        .to_str()
-                        .map(|n| n.starts_with(SEGMENT_PREFIX) && n.ends_with(SEGMENT_SUFFIX))
-                        .unwrap_or(false)
-                })
-                .count(),
-            Err(_) => 0,
+        self.stats_with_disk_bytes(self.disk_usage().unwrap_or(0))
+    }
+
+    /// Like [`stats`](Self::stats), but without touching the filesystem:
+    /// `on_disk_bytes` is the segment sizes as tracked by the store. Cheap
+    /// enough for every health check.
+    pub fn stats_cached(&self) -> StoreStats {
+        let active = match self.active_writer {
+            Some(_) => self.active_segment_len,
+            None => 0,
         };
+        self.stats_with_disk_bytes(self.segment_sizes.values().sum::<u64>() + active)
+    }
 
+    fn stats_with_disk_bytes(&self, on_disk_bytes: u64) -> StoreStats {
+        let num_segments = self.manifest.live_segment_ids().len();
+        let total_bytes = self.values.values().map(|v| v.len() as u64).sum::<u64>();
+        let ratio = |bytes: u64| {
+            if total_bytes == 0 {
+                0.0
+            } else {
+                bytes as f64 / total_bytes as f64
+            }
+        };
+
+        let cache = self.block_cache();
         StoreStats {
             num_keys: self.values.len(),
             num_segments,
-            total_bytes: self.values.values().map(|v| v.len() as u64).sum::<u64>(),
+            total_bytes,
+            on_disk_bytes,
+            write_amplification: ratio(self.segment_bytes_written),
+            space_amplification: ratio(on_disk_bytes),
+            tombstone_count: self.tombstone_count,
             active_segment_id: self.active_segment_id as usize,
-            oldest_segment_id: 0, // could be improved by reading min id
+            oldest_segment_id: self.oldest_segment_id_loaded,
+            active_lsn: self.next_lsn(),
+            cache_hits: cache.hits(),
+            cache_misses: cache.misses(),
+            index_memory_bytes: self.index.memory_estimate_bytes(),
+            eintr_retries: file_utils::eintr_retries(),
+            failed_syncs: self
+                .background_sync
+                .as_ref()
+                .map_or(0, |sync| sync.failures()),
+        }
+    }
+
+    /// Sum of the sizes of every `segment-*.dat` file in the data directory,
+    /// i.e. what `du` would report for the segments, dead records included.
+    pub fn disk_usage(&self) -> std::io::Result<u64> {
+        let mut total = 0;
+        for entry in fs::read_dir(&self.base_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if parse_segment_file_name(&name).is_some() {
+                total += entry.metadata()?.len();
+            }
+        }
+        Ok(total)
+    }
+
+    /// Total on-disk bytes of the records backing the live keys, as stored:
+    /// compressed and padded if they were written that way.
+    pub(crate) fn live_record_bytes(&self) -> u64 {
+        self.index.iter().map(|(_, &(_, _, len))| len).sum()
+    }
+
+    /// Dump every segment, oldest first, to `output_file` as newline-delimited
+    /// JSON (see [`Segment::export_ndjson`]). Returns the number of records.
+    pub fn export_all_segments_ndjson<P: AsRef<Path>>(&self, output_file: P) -> Result<usize> {
+        let output_file = output_file.as_ref();
+        let mut out =
+            BufWriter::new(File::create(output_file).map_err(StoreError::io_at(output_file))?);
+        let mut count = 0;
+        for id in self.segment_ids() {
+            let mut segment = self.open_segment(id as usize)?;
+            count += segment.export_ndjson(&mut out)?;
+        }
+        out.flush().map_err(StoreError::Io)?;
+        Ok(count)
+    }
+
+    /// Write every live pair to `output_file`, sorted by key, for backup or
+    /// moving to another store: an 8-byte magic followed by
+    /// `key_len(u32 LE) | key | value_len(u32 LE) | value` per pair. Values
+    /// are written uncompressed. Returns the number of pairs.
+    pub fn export<P: AsRef<Path>>(&self, output_file: P) -> Result<usize> {
+        let output_file = output_file.as_ref();
+        let mut out =
+            BufWriter::new(File::create(output_file).map_err(StoreError::io_at(output_file))?);
+        let mut pairs: Vec<_> = self.values.iter().collect();
+        pairs.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let write = |out: &mut BufWriter<File>| -> std::io::Result<()> {
+            retry_on_interrupt(|| out.write_all(EXPORT_MAGIC))?;
+            for (key, value) in &pairs {
+                for bytes in [key.as_slice(), value.as_slice()] {
+                    let len = u32::try_from(bytes.len()).map_err(std::io::Error::other)?;
+                    retry_on_interrupt(|| out.write_all(&len.to_le_bytes()))?;
+                    retry_on_interrupt(|| out.write_all(bytes))?;
+                }
+            }
+            out.flush()
+        };
+        write(&mut out).map_err(StoreError::io_at(output_file))?;
+        Ok(pairs.len())
+    }
+
+    /// Set every pair from a file written by [`export`](Self::export),
+    /// overwriting keys that already exist. Returns the number of pairs.
+    /// Pairs before a truncated or corrupt tail are kept.
+    pub fn import<P: AsRef<Path>>(&mut self, input_file: P) -> Result<usize> {
+        self.check_writable()?;
+        let input_file = input_file.as_ref();
+        let file = File::open(input_file).map_err(StoreError::io_at(input_file))?;
+        let mut input = BufReader::new(file);
+        let truncated =
+            || StoreError::CorruptedData(format!("Truncated export file {}", input_file.display()));
+
+        let mut magic = [0u8; EXPORT_MAGIC.len()];
+        retry_on_interrupt(|| input.read_exact(&mut magic)).map_err(|_| truncated())?;
+        if &magic != EXPORT_MAGIC {
+            return Err(StoreError::CorruptedData(format!(
+                "{} is not an export file",
+                input_file.display()
+            )));
+        }
+
+        let mut count = 0;
+        loop {
+            let mut len = [0u8; 4];
+            match retry_on_interrupt(|| input.read_exact(&mut len)) {
+                Ok(()) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(StoreError::io_at(input_file)(e)),
+            }
+            let mut key = vec![0u8; u32::from_le_bytes(len) as usize];
+            retry_on_interrupt(|| input.read_exact(&mut key)).map_err(|_| truncated())?;
+            retry_on_interrupt(|| input.read_exact(&mut len)).map_err(|_| truncated())?;
+            let mut value = vec![0u8; u32::from_le_bytes(len) as usize];
+            retry_on_interrupt(|| input.read_exact(&mut value)).map_err(|_| truncated())?;
+            self.set_bytes_key(&key, &value)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reports how much space a compaction would reclaim, without running it.
+    pub fn compaction_estimate(&self) -> CompactionEstimate {
+        super::compaction::estimate(self)
+    }
+
+    /// Reclaim the space of deleted and overwritten keys under `prefix`
+    /// without a full compaction; see
+    /// [`compaction::compact_prefix`](super::compaction::compact_prefix).
+    pub fn compact_prefix(&mut self, prefix: &str) -> Result<()> {
+        self.check_writable()?;
+        super::compaction::compact_prefix(self, prefix.as_bytes())
+    }
+
+    /// High-level convenience to trigger compaction using compaction.rs.
+    /// Honours `StoreConfig::compaction_max_bytes_per_sec` when set.
+    pub fn compact(&mut self) -> Result<CompactionStats> {
+        self.check_writable()?;
+        let stats = match self.config.compaction_max_bytes_per_sec {
+            Some(rate) => super::compaction::compact_with_throttle(self, rate)?,
+            None => super::compaction::compact(self)?,
+        };
+        self.pending_compaction = false;
+        self.log_compaction(&stats);
+        Ok(stats)
+    }
+
+    /// Compact from the segment files alone, rebuilding the in-memory state
+    /// from the output. See
+    /// [`compaction::compact_from_disk`](super::compaction::compact_from_disk).
+    pub fn compact_from_disk(&mut self) -> Result<CompactionStats> {
+        self.check_writable()?;
+        let stats = super::compaction::compact_from_disk(self)?;
+        self.pending_compaction = false;
+        self.log_compaction(&stats);
+        Ok(stats)
+    }
+
+    /// Compact, keeping the rewrite at or below `max_bytes_per_sec`.
+    pub fn compact_with_throttle(&mut self, max_bytes_per_sec: u64) -> Result<CompactionStats> {
+        self.check_writable()?;
+        let stats = super::compaction::compact_with_throttle(self, max_bytes_per_sec)?;
+        self.pending_compaction = false;
+        self.log_compaction(&stats);
+        Ok(stats)
+    }
+
+    fn log_compaction(&self, stats: &CompactionStats) {
+        if self.config.verbose_logging {
+            log::debug!(
+                "compacted read={} bytes written={} bytes in {} ms",
+                stats.bytes_read,
+                stats.bytes_written,
+                stats.elapsed_ms
+            );
+        }
+    }
+
+    /// Merge two segments into one, keeping only the latest record per key.
+    /// See [`compaction::merge_segments`](super::compaction::merge_segments).
+    pub fn merge_segments(&mut self, seg_a: usize, seg_b: usize) -> Result<()> {
+        self.check_writable()?;
+        super::compaction::merge_segments(self, seg_a, seg_b)
+    }
+
+    /// Every record written at or after `offset` in segment `seg_id`, in log
+    /// order, for shipping to a standby that applies them with
+    /// [`apply_record`](Self::apply_record). Segments removed by compaction
+    /// are no longer available; resume from a position still in the log.
+    pub fn records_since(
+        &mut self,
+        seg_id: u64,
+        offset: u64,
+    ) -> Result<impl Iterator<Item = Result<ReplicationRecord>>> {
+        if let Some(writer) = self.active_writer.as_mut() {
+            writer.flush().map_err(StoreError::Io)?;
+        }
+        let pending = self
+            .segment_ids()
+            .into_iter()
+            .filter(|&id| id >= seg_id)
+            .map(|id| (id, if id == seg_id { offset } else { 0 }))
+            .collect();
+        Ok(RecordStream::new(
+            self.base_dir.clone(),
+            self.compressor.clone(),
+            pending,
+        ))
+    }
+
+    /// Every change written after the record at `since_lsn` (see
+    /// [`replication::lsn`]), in log order. Segments the store creates start
+    /// at id 1, so passing 0 returns the whole log.
+    ///
+    /// The changes are read up front, so any read error is returned here
+    /// rather than part-way through iteration.
+    ///
+    /// Keys are reported as text, so a record with a non-UTF-8 key fails
+    /// with `InvalidUtf8Key`; use [`records_since`](Self::records_since) to
+    /// follow stores holding binary keys.
+    pub fn tail(&self, since_lsn: u64) -> Result<impl Iterator<Item = ChangeRecord>> {
+        let (since_segment, since_offset) = replication::lsn_position(since_lsn);
+        let mut changes = Vec::new();
+        for id in self
+            .segment_ids()
+            .into_iter()
+            .filter(|&id| id >= since_segment)
+        {
+            let mut segment = self.open_segment(id as usize)?;
+            let mut offset = if id == since_segment { since_offset } else { 0 };
+            let mut cache = self.block_cache();
+            while let Some((key, value, next)) =
+                segment.read_record_at_cached(offset, &mut **cache)?
+            {
+                let lsn = replication::lsn(id, offset);
+                if lsn > since_lsn {
+                    changes.push(ChangeRecord { lsn, key, value });
+                }
+                offset = next;
+            }
+        }
+        Ok(changes.into_iter())
+    }
+
+    /// Apply a record read from another store's [`records_since`](Self::records_since)
+    /// to the active segment and in-memory state, as the primary did.
+    ///
+    /// The primary already validated the key and admitted the write, so the
+    /// key validator, throttling and index cap are bypassed. A record whose
+    /// effect is already in place (same value, or a tombstone for an absent
+    /// key) is skipped, so re-applying part of a stream is harmless.
+    pub fn apply_record(&mut self, record: &ReplicationRecord) -> Result<()> {
+        self.apply_replicated(&record.key, record.value.as_deref())
+    }
+
+    /// Apply a change shipped by a primary's [`ReplicationStream`](replication::ReplicationStream),
+    /// with the same rules as [`apply_record`](Self::apply_record).
+    pub fn apply_change(&mut self, change: &ChangeRecord) -> Result<()> {
+        self.apply_replicated(change.key.as_bytes(), change.value.as_deref())
+    }
+
+    fn apply_replicated(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        self.check_writable()?;
+        if self.values.get(key).map(Vec::as_slice) == value {
+            return Ok(());
+        }
+        self.append(key, value)
+    }
+
+    /// LSN the next record appended to the active segment will get.
+    pub fn next_lsn(&self) -> u64 {
+        replication::lsn(self.active_segment_id, self.active_segment_len)
+    }
+
+    /// Id of the segment currently being appended to.
+    pub fn active_segment_id(&self) -> u64 {
+        self.active_segment_id
+    }
+
+    /// Records the size of sealed segment `id` after it was rewritten, or
+    /// `None` once its file is removed.
+    pub(crate) fn set_segment_size(&mut self, id: u64, size: Option<u64>) {
+        match size {
+            Some(size) => self.segment_sizes.insert(id, size),
+            None => self.segment_sizes.remove(&id),
+        };
+    }
+
+    /// Moves `oldest_segment_id` up to the first segment left once a
+    /// compaction has retired older ones.
+    pub(crate) fn refresh_oldest_segment_id(&mut self) {
+        if let Some(&oldest) = self.segment_ids().first() {
+            self.oldest_segment_id_loaded = oldest as usize;
+        }
+    }
+
+    /// Ids of the segments the manifest lists as holding data, ascending.
+    /// Includes the active segment.
+    pub fn segment_ids(&self) -> Vec<u64> {
+        self.manifest.live_segment_ids()
+    }
+
+    /// Every record in segment `id`, in file order; `None` values are tombstones.
+    pub(crate) fn read_segment_records(&self, id: u64) -> Result<Vec<KeyedRecord>> {
+        let path = self.segment_path(id);
+        let mut reader = BufReader::new(File::open(&path).map_err(StoreError::io_at(&path))?);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, header)) =
+            Record::read_from(&mut reader, &*self.compressor, id, offset)?
+        {
+            records.push((record.key, record.value));
+            offset += header.record_len();
+        }
+        Ok(records)
+    }
+
+    /// The live value of every key according to the segment files alone,
+    /// replayed in order.
+    pub(crate) fn live_records_on_disk(&self) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+        let mut live = HashMap::new();
+        for id in self.segment_ids() {
+            for (key, value) in self.read_segment_records(id)? {
+                match value {
+                    Some(value) => live.insert(key, value),
+                    None => live.remove(&key),
+                };
+            }
+        }
+        Ok(live)
+    }
+
+    /// Replace the live values wholesale. The index is emptied, to be rebuilt
+    /// by whatever rewrites the records next.
+    pub(crate) fn replace_values(&mut self, values: HashMap<Vec<u8>, Vec<u8>>) {
+        self.values = values;
+        self.index.clear();
+    }
+
+    /// Rebuild the values and index by replaying the active segment, for
+    /// when it is known to hold every live record.
+    pub(crate) fn reindex_active_segment(&mut self) -> Result<()> {
+        self.values.clear();
+        self.index.clear();
+        let path = self.segment_path(self.active_segment_id);
+        Self::replay_segment(
+            self.active_segment_id,
+            &path,
+            &mut self.values,
+            &mut self.index,
+            &*self.compressor,
+        )?;
+        if self.write_order.is_some() {
+            self.write_order = Some(WriteOrder::from_index(&self.index, &self.values));
+        }
+        Ok(())
+    }
+
+    /// Encode a record with the store's compression settings.
+    pub(crate) fn encode_record<W: Write>(
+        &self,
+        writer: &mut W,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> std::io::Result<u64> {
+        record::write_record(
+            writer,
+            key,
+            value,
+            &*self.compressor,
+            self.config.compression_threshold_bytes,
+            self.config.align_records,
+        )
+    }
+
+    /// Account for segments rewritten outside the active writer: `written`
+    /// bytes of new segment data, `tombstones_removed` tombstones in the
+    /// replaced segments and `tombstones_added` in their replacement.
+    pub(crate) fn record_rewrite(
+        &mut self,
+        written: u64,
+        tombstones_removed: usize,
+        tombstones_added: usize,
+    ) {
+        self.segment_bytes_written += written;
+        self.tombstone_count =
+            self.tombstone_count.saturating_sub(tombstones_removed) + tombstones_added;
+    }
+
+    /// Whether the latest record of live `key` is in segment `id`.
+    pub(crate) fn index_points_into(&self, key: &[u8], id: u64) -> bool {
+        self.index
+            .get(key)
+            .is_some_and(|(seg, _, _)| *seg as u64 == id)
+    }
+
+    pub(crate) fn tombstone_count(&self) -> usize {
+        self.tombstone_count
+    }
+
+    /// Point keys whose latest record lived in one of `replaced` at their new
+    /// `(offset, len)` in segment `into`, and drop cached blocks of all of them.
+    pub(crate) fn relocate_records(
+        &mut self,
+        replaced: &[u64],
+        into: u64,
+        locations: Vec<(Vec<u8>, u64, u64)>,
+    ) {
+        for (key, offset, len) in locations {
+            let moved = self
+                .index
+                .get(&key)
+                .is_some_and(|(seg, _, _)| replaced.contains(&(*seg as u64)));
+            if moved {
+                self.index.insert(key, into as usize, offset, len);
+            }
+        }
+        for id in replaced {
+            self.block_cache().invalidate_segment(*id as usize);
+        }
+    }
+
+    /// Append every live record to the active segment in key order and fsync
+    /// it, calling `on_write` with each record's key and length as it goes;
+    /// an error from `on_write` stops the rewrite. Returns the bytes written.
+    pub(crate) fn write_live_records(
+        &mut self,
+        on_write: &mut dyn FnMut(&[u8], u64) -> Result<()>,
+    ) -> Result<u64> {
+        let writer = self
+            .active_writer
+            .as_mut()
+            .ok_or_else(|| StoreError::Io(std::io::Error::other("Active writer missing")))?;
+
+        let segment_id = self.active_segment_id as usize;
+        let mut keys: Vec<&Vec<u8>> = self.values.keys().collect();
+        keys.sort_unstable();
+        let mut written = 0u64;
+        for key in keys {
+            let offset = self.active_segment_len + written;
+            let len = record::write_record(
+                writer,
+                key,
+                Some(&self.values[key]),
+                &*self.compressor,
+                self.config.compression_threshold_bytes,
+                self.config.align_records,
+            )
+            .map_err(StoreError::Io)?;
+            self.index.insert(key.clone(), segment_id, offset, len);
+            written += len;
+            on_write(key, len)?;
+        }
+        writer.flush().map_err(StoreError::Io)?;
+        retry_on_interrupt(|| writer.get_ref().sync_all()).map_err(StoreError::Io)?;
+
+        self.active_segment_len += written;
+        self.segment_bytes_written += written;
+        self.records_in_active_segment += self.values.len();
+        Ok(written)
+    }
+}
+
+/// One line for log messages, e.g.
+///
+/// ```text
+/// KVStore { dir: "data", keys: 1024, segments: 3 (active: segment-0000000007.dat), size: 4.21 MB }
+/// ```
+///
+/// The alternate form (`{:#}`) prints the directory followed by the full
+/// [`StoreStats`] report.
+impl fmt::Display for KVStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats();
+        if f.alternate() {
+            writeln!(f, "KVStore at {}", self.base_dir.display())?;
+            return write!(f, "{}", stats);
         }
+        write!(
+            f,
+            "KVStore {{ dir: {:?}, keys: {}, segments: {} (active: {}), size: {:.2} MB }}",
+            self.base_dir.display().to_string(),
+            stats.num_keys,
+            stats.num_segments,
+            self.manifest.entry(self.active_segment_id).map_or_else(
+                || segment_file_name(self.active_segment_id, None),
+                |e| e.path.clone()
+            ),
+            stats.total_mb()
+        )
     }
+}
+
+/// Same summary as `Display`; internal state such as cached values and the
+/// index is deliberately left out.
+impl fmt::Debug for KVStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Drop for KVStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            log::warn!(
+                "failed to close segment {} cleanly: {}",
+                self.active_segment_id,
+                e
+            );
+        }
+        if let Some(lock_file) = &self.lock_file {
+            let _ = FileExt::unlock(lock_file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_policy_fsyncs_in_background() {
+        let dir = "tests_data/background_fsync";
+        let _ = fs::remove_dir_all(dir);
+
+        let config = StoreConfig {
+            data_path: dir.into(),
+            fsync_policy: FsyncPolicy::Interval,
+            fsync_interval: Duration::from_millis(10),
+            ..StoreConfig::default()
+        };
+        let mut store = KVStore::from_config(&config).unwrap();
+        store.set("before", b"rotation").unwrap();
+        store.reset_active_segment().unwrap();
+        store.set("after", b"rotation").unwrap();
+
+        let syncs = || store.background_fsyncs();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while syncs() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(syncs() >= 2);
+        let reader = KVStore::open_read_only(dir).unwrap();
+        assert_eq!(reader.get("after").unwrap(), Some(b"rotation".to_vec()));
+
+        // Dropping the store joins the thread instead of leaving it running.
+        drop(store);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_failed_background_fsync_is_returned_by_next_write() {
+        let dir = "tests_data/background_fsync_error";
+        let _ = fs::remove_dir_all(dir);
+
+        let config = StoreConfig {
+            data_path: dir.into(),
+            fsync_policy: FsyncPolicy::Interval,
+            fsync_interval: Duration::from_millis(10),
+            ..StoreConfig::default()
+        };
+        let mut store = KVStore::from_config(&config).unwrap();
+        // fsync on a character device fails with EINVAL.
+        let sync = store.background_sync.as_ref().unwrap();
+        sync.set_file(Some(File::open("/dev/null").unwrap()));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while store.stats_cached().failed_syncs == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(store.stats_cached().failed_syncs >= 1);
+
+        assert!(matches!(store.set("k", b"v"), Err(StoreError::Io(_))));
+        assert_eq!(store.get("k").unwrap(), None);
+
+        drop(store);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_compact_from_disk_repairs_drifted_state() {
+        let dir = "tests_data/compact_from_disk";
+        let _ = fs::remove_dir_all(dir);
+
+        let mut store = KVStore::open(dir).unwrap();
+        store.set("a", b"1").unwrap();
+        store.set("b", b"2").unwrap();
+        store.reset_active_segment().unwrap();
+        store.set("b", b"3").unwrap();
+        store.set("c", b"4").unwrap();
+        store.delete("a").unwrap();
+
+        // Drift: a deleted key comes back, a value changes, another key
+        // loses its index entry and a key that was never written appears.
+        store.values.insert(b"a".to_vec(), b"stale".to_vec());
+        store.values.insert(b"b".to_vec(), b"wrong".to_vec());
+        store.index.remove(b"c");
+        store.values.insert(b"ghost".to_vec(), b"x".to_vec());
+
+        store.compact_from_disk().unwrap();
+
+        let expected = vec![
+            ("b".to_string(), Some(b"3".to_vec())),
+            ("c".to_string(), Some(b"4".to_vec())),
+        ];
+        let check = |store: &KVStore| {
+            let mut keys = store.list_keys();
+            keys.sort();
+            let found: Vec<_> = keys
+                .into_iter()
+                .map(|k| {
+                    let value = store.get(&k).unwrap();
+                    (k, value)
+                })
+                .collect();
+            assert_eq!(found, expected);
+            assert_eq!(store.index.len(), 2);
+        };
+        check(&store);
+        // Everything was rewritten into the one segment left.
+        assert_eq!(store.segment_ids(), vec![store.active_segment_id]);
+        assert_eq!(
+            store.locate("c").map(|(seg, _, _)| seg as u64),
+            Some(store.active_segment_id)
+        );
 
-    /// High-level convenience to trigger compaction using compaction.rs
-    pub fn compact(&mut self) -> Result<()> {
-        // Delegates to compaction module which will remove old segments and then
-        // call reset_active_segment() to prepare a fresh one.
-        super::compaction::compact(self)
+        drop(store);
+        check(&KVStore::open(dir).unwrap());
+        let _ = fs::remove_dir_all(dir);
     }
 }
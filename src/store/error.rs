@@ -1,18 +1,228 @@
 use std::io;
+use std::path::{Path, PathBuf};
+
+/// How a caller should react to a [`StoreError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSeverity {
+    /// The operation may succeed if retried as-is, possibly after a delay.
+    Transient,
+    /// The request was rejected, but the store is healthy.
+    Recoverable,
+    /// The store or its files are damaged; retrying will not help.
+    Fatal,
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum StoreError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
 
-    #[error("Key not found")]
-    KeyNotFound,
+    #[error("IO error at {}: {source}", .path.display())]
+    IoWithPath {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Key not found: {0}")]
+    KeyNotFound(String),
+
+    #[error("Segment {0} not found")]
+    SegmentNotFound(usize),
 
     #[error("Corrupted data: {0}")]
     CorruptedData(String),
 
+    #[error("Checksum mismatch in segment {segment_id} at offset {offset}")]
+    ChecksumMismatch { segment_id: u64, offset: u64 },
+
+    #[error("Invalid UTF-8 key in segment {segment_id} at offset {offset}")]
+    InvalidUtf8Key { segment_id: u64, offset: u64 },
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("Unsorted input: key {key:?} arrived after {previous:?}")]
+    UnsortedInput { previous: String, key: String },
+
+    #[error("Write throttled, retry in {suggested_delay_ms} ms")]
+    WriteThrottled { suggested_delay_ms: u64 },
+
+    #[error("Data directory {} is locked by another process", .0.display())]
+    AlreadyLocked(PathBuf),
+
+    #[error("Store is open read-only")]
+    ReadOnly,
+
+    #[error("Range {start}..{end} is outside the {size}-byte value")]
+    RangeNotSatisfiable { start: u64, end: u64, size: u64 },
+
+    #[error("Store is full")]
+    StoreFull,
+
+    #[error("Precondition failed for {key}: expected etag {expected}, found {}", .actual.as_deref().unwrap_or("none"))]
+    PreconditionFailed {
+        key: String,
+        expected: String,
+        actual: Option<String>,
+    },
+
     #[error("Compaction failed: {0}")]
     CompactionFailed(String),
 }
 
+impl StoreError {
+    /// Adapter for `map_err` that records which file or directory an I/O
+    /// error came from.
+    pub(crate) fn io_at(path: &Path) -> impl FnOnce(io::Error) -> StoreError {
+        let path = path.to_path_buf();
+        move |source| StoreError::IoWithPath { path, source }
+    }
+
+    /// Classify the error for retry decisions. I/O errors are transient
+    /// only for `WouldBlock`, `TimedOut` and `Interrupted`.
+    pub fn severity(&self) -> ErrorSeverity {
+        match self {
+            StoreError::Io(e) | StoreError::IoWithPath { source: e, .. } => match e.kind() {
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted => ErrorSeverity::Transient,
+                _ => ErrorSeverity::Fatal,
+            },
+            StoreError::WriteThrottled { .. } | StoreError::AlreadyLocked(_) => {
+                ErrorSeverity::Transient
+            },
+            StoreError::KeyNotFound(_)
+            | StoreError::SegmentNotFound(_)
+            | StoreError::InvalidUtf8Key { .. }
+            | StoreError::InvalidKey(_)
+            | StoreError::UnsortedInput { .. }
+            | StoreError::ReadOnly
+            | StoreError::RangeNotSatisfiable { .. }
+            | StoreError::PreconditionFailed { .. }
+            | StoreError::StoreFull => ErrorSeverity::Recoverable,
+            StoreError::CorruptedData(_)
+            | StoreError::ChecksumMismatch { .. }
+            | StoreError::CompactionFailed(_) => ErrorSeverity::Fatal,
+        }
+    }
+
+    /// Whether the store can keep serving after this error.
+    pub fn is_recoverable(&self) -> bool {
+        self.severity() != ErrorSeverity::Fatal
+    }
+
+    /// The underlying I/O error, with or without path context.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            StoreError::Io(e) | StoreError::IoWithPath { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, StoreError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_separates_retryable_from_fatal() {
+        let cases = [
+            (
+                StoreError::Io(io::ErrorKind::WouldBlock.into()),
+                ErrorSeverity::Transient,
+            ),
+            (
+                StoreError::Io(io::ErrorKind::TimedOut.into()),
+                ErrorSeverity::Transient,
+            ),
+            (
+                StoreError::IoWithPath {
+                    path: PathBuf::from("segment-1.dat"),
+                    source: io::ErrorKind::Interrupted.into(),
+                },
+                ErrorSeverity::Transient,
+            ),
+            (
+                StoreError::Io(io::ErrorKind::PermissionDenied.into()),
+                ErrorSeverity::Fatal,
+            ),
+            (
+                StoreError::WriteThrottled {
+                    suggested_delay_ms: 100,
+                },
+                ErrorSeverity::Transient,
+            ),
+            (
+                StoreError::AlreadyLocked(PathBuf::from("data")),
+                ErrorSeverity::Transient,
+            ),
+            (
+                StoreError::KeyNotFound("k".into()),
+                ErrorSeverity::Recoverable,
+            ),
+            (
+                StoreError::InvalidUtf8Key {
+                    segment_id: 1,
+                    offset: 0,
+                },
+                ErrorSeverity::Recoverable,
+            ),
+            (
+                StoreError::InvalidKey("".into()),
+                ErrorSeverity::Recoverable,
+            ),
+            (
+                StoreError::UnsortedInput {
+                    previous: "b".into(),
+                    key: "a".into(),
+                },
+                ErrorSeverity::Recoverable,
+            ),
+            (StoreError::ReadOnly, ErrorSeverity::Recoverable),
+            (
+                StoreError::RangeNotSatisfiable {
+                    start: 5,
+                    end: 10,
+                    size: 3,
+                },
+                ErrorSeverity::Recoverable,
+            ),
+            (StoreError::StoreFull, ErrorSeverity::Recoverable),
+            (
+                StoreError::PreconditionFailed {
+                    key: "k".into(),
+                    expected: "*".into(),
+                    actual: None,
+                },
+                ErrorSeverity::Recoverable,
+            ),
+            (
+                StoreError::CorruptedData("bad".into()),
+                ErrorSeverity::Fatal,
+            ),
+            (
+                StoreError::ChecksumMismatch {
+                    segment_id: 1,
+                    offset: 0,
+                },
+                ErrorSeverity::Fatal,
+            ),
+            (
+                StoreError::CompactionFailed("merge".into()),
+                ErrorSeverity::Fatal,
+            ),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(err.severity(), expected, "{:?}", err);
+            assert_eq!(
+                err.is_recoverable(),
+                expected != ErrorSeverity::Fatal,
+                "{:?}",
+                err
+            );
+        }
+    }
+}
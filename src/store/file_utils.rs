@@ -0,0 +1,113 @@
+//! Helpers shared by the segment and engine file I/O.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Attempts [`retry_on_interrupt`] makes before giving up on an
+/// `Interrupted` error.
+pub const MAX_RETRIES: u32 = 10;
+
+/// Retries after `Interrupted`, process-wide; see [`eintr_retries`].
+static EINTR_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Runs `f`, running it again while it fails with `ErrorKind::Interrupted`
+/// (a signal arrived mid-syscall), up to [`MAX_RETRIES`] attempts. Other
+/// errors, and the last `Interrupted`, are returned as they are.
+///
+/// Only wrap operations that are safe to repeat from the start: a seek to
+/// an absolute position, a sync, or a `read_exact`/`write_all`, which std
+/// already restarts internally and so never fail half-done with
+/// `Interrupted`.
+pub fn retry_on_interrupt<F, T>(mut f: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<T>,
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && attempt < MAX_RETRIES => {
+                EINTR_RETRIES.fetch_add(1, Ordering::Relaxed);
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// How many times [`retry_on_interrupt`] has retried since the process
+/// started, across all stores.
+pub fn eintr_retries() -> u64 {
+    EINTR_RETRIES.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Fails the first `interrupts` writes with `Interrupted`.
+    struct FlakyWriter {
+        interrupts: usize,
+        written: Vec<u8>,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.interrupts > 0 {
+                self.interrupts -= 1;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry_on_interrupt_retries_then_gives_up() {
+        let mut writer = FlakyWriter {
+            interrupts: 2,
+            written: Vec::new(),
+        };
+        let mut attempts = 0;
+        let before = eintr_retries();
+        let n = retry_on_interrupt(|| {
+            attempts += 1;
+            writer.write(b"record")
+        })
+        .unwrap();
+
+        assert_eq!(n, 6);
+        assert_eq!(attempts, 3);
+        assert_eq!(writer.written, b"record");
+        // Nothing else in the test suite is interrupted, so the delta is ours.
+        assert_eq!(eintr_retries() - before, 2);
+
+        // A writer that never stops being interrupted is given up on.
+        let mut writer = FlakyWriter {
+            interrupts: usize::MAX,
+            written: Vec::new(),
+        };
+        let mut attempts = 0;
+        let err = retry_on_interrupt(|| {
+            attempts += 1;
+            writer.write(b"x")
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(attempts, MAX_RETRIES);
+
+        // Other errors are not retried.
+        let mut attempts = 0;
+        let err = retry_on_interrupt(|| -> io::Result<()> {
+            attempts += 1;
+            Err(io::ErrorKind::NotFound.into())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+}
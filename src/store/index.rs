@@ -1,27 +1,40 @@
 //! In-memory index for KVStore.
-// Unused code annotated for Clippy compliance.
 
-#[allow(dead_code)]
+use std::collections::HashMap;
+use std::mem::size_of;
+
+/// Location of a key's latest record: `(segment_id, offset, length)`.
+pub type Location = (usize, u64, u64);
+
+#[derive(Debug)]
 pub struct Index {
-    /// Map: key -> (segment_id, offset, length)
-    map: std::collections::HashMap<String, (usize, u64, u64)>,
+    /// Map: key bytes -> (segment_id, offset, length). Keys are arbitrary
+    /// bytes; callers that need text view them with `String::from_utf8_lossy`.
+    map: HashMap<Vec<u8>, Location>,
+    /// Sum of the lengths of all keys in `map`, i.e. their heap footprint.
+    key_bytes: usize,
 }
 
-#[allow(dead_code)]
 impl Index {
     pub fn new() -> Self {
         Self {
-            map: std::collections::HashMap::new(),
+            map: HashMap::new(),
+            key_bytes: 0,
         }
     }
-    pub fn insert(&mut self, key: String, seg_id: usize, offset: u64, len: u64) {
-        self.map.insert(key, (seg_id, offset, len));
+    pub fn insert(&mut self, key: Vec<u8>, seg_id: usize, offset: u64, len: u64) {
+        let key_len = key.len();
+        if self.map.insert(key, (seg_id, offset, len)).is_none() {
+            self.key_bytes += key_len;
+        }
     }
-    pub fn get(&self, key: &str) -> Option<&(usize, u64, u64)> {
+    pub fn get(&self, key: &[u8]) -> Option<&Location> {
         self.map.get(key)
     }
-    pub fn remove(&mut self, key: &str) -> Option<(usize, u64, u64)> {
-        self.map.remove(key)
+    pub fn remove(&mut self, key: &[u8]) -> Option<Location> {
+        let removed = self.map.remove_entry(key)?;
+        self.key_bytes -= removed.0.len();
+        Some(removed.1)
     }
     pub fn len(&self) -> usize {
         self.map.len()
@@ -29,15 +42,61 @@ pub fn len(&self) -> usize {
     pub fn is_empty(&self) -> bool {
         self.map.is_empty()
     }
-    pub fn keys(&self) -> impl Iterator<Item = &String> {
+    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
         self.map.keys()
     }
-    pub fn contains(&self, key: &str) -> bool {
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Location)> {
+        self.map.iter()
+    }
+    pub fn contains(&self, key: &[u8]) -> bool {
         self.map.contains_key(key)
     }
     pub fn clear(&mut self) {
         self.map.clear();
+        self.key_bytes = 0;
+    }
+
+    /// Approximate heap bytes held by the index: the key bytes plus the hash
+    /// table itself.
+    ///
+    /// The table is sized from the map's capacity rather than its length, since
+    /// that is what is actually allocated: one `(Vec<u8>, Location)` slot and
+    /// one control byte per bucket, with buckets kept at most 7/8 full and
+    /// rounded up to a power of two.
+    pub fn memory_estimate_bytes(&self) -> usize {
+        let slot = size_of::<Vec<u8>>() + size_of::<Location>();
+        hash_table_bytes(self.map.capacity(), slot) + self.key_bytes
+    }
+
+    /// What [`memory_estimate_bytes`](Self::memory_estimate_bytes) would
+    /// report after inserting `new_keys` keys, not yet in the index, that
+    /// add up to `new_key_bytes`.
+    pub fn memory_estimate_after(&self, new_keys: usize, new_key_bytes: usize) -> usize {
+        let slot = size_of::<Vec<u8>>() + size_of::<Location>();
+        let capacity = self.map.capacity().max(self.map.len() + new_keys);
+        hash_table_bytes(capacity, slot) + self.key_bytes + new_key_bytes
+    }
+
+    /// Releases spare hash table capacity, e.g. after many removals.
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+}
+
+/// Approximate heap bytes of a `HashMap` table with `capacity` and
+/// `slot`-byte entries, excluding anything the entries point to.
+pub(crate) fn hash_table_bytes(capacity: usize, slot: usize) -> usize {
+    if capacity == 0 {
+        return 0;
     }
+    let buckets = if capacity < 8 {
+        (capacity + 1).next_power_of_two()
+    } else {
+        (capacity * 8 / 7).next_power_of_two()
+    };
+    // Trailing control bytes mirror the first group for SIMD probing.
+    const GROUP_WIDTH: usize = 16;
+    buckets * (slot + 1) + GROUP_WIDTH
 }
 
 impl Default for Index {
@@ -0,0 +1,82 @@
+//! Per-key locks for read-modify-write cycles on a shared store.
+
+use crate::store::error::Result;
+use crate::store::KVStore;
+use dashmap::DashMap;
+use parking_lot::{ArcMutexGuard, Mutex as KeyMutex, RawMutex};
+use std::sync::{Arc, Mutex};
+
+/// Registry of per-key mutexes. Entries exist only while some thread holds
+/// or waits for the key.
+#[derive(Debug, Default)]
+pub(crate) struct KeyLocks {
+    locks: DashMap<String, Arc<KeyMutex<()>>>,
+}
+
+impl KeyLocks {
+    fn acquire(&self, key: &str) -> ArcMutexGuard<RawMutex, ()> {
+        let lock = self.locks.entry(key.to_string()).or_default().clone();
+        lock.lock_arc()
+    }
+
+    /// Drops the entry for `key` once nobody else references it. The shard
+    /// lock held by `remove_if` keeps new waiters from cloning it meanwhile.
+    fn release(&self, key: &str) {
+        self.locks
+            .remove_if(key, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+/// Exclusive hold on one key of a shared store, from [`KVStore::lock_key`].
+///
+/// Other threads calling `lock_key` for the same key block until the guard
+/// is dropped; other keys are unaffected. The store-wide mutex is only taken
+/// for the duration of each `get`, `set` or `delete`.
+pub struct KeyGuard<'a> {
+    store: &'a Mutex<KVStore>,
+    locks: Arc<KeyLocks>,
+    key: String,
+    held: Option<ArcMutexGuard<RawMutex, ()>>,
+}
+
+impl<'a> KeyGuard<'a> {
+    pub(crate) fn new(store: &'a Mutex<KVStore>, key: &str) -> Self {
+        let locks = store.lock().unwrap().key_locks();
+        let held = Some(locks.acquire(key));
+        Self {
+            store,
+            locks,
+            key: key.to_string(),
+            held,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn get(&self) -> Result<Option<Vec<u8>>> {
+        self.store.lock().unwrap().get(&self.key)
+    }
+
+    pub fn set(&self, value: &[u8]) -> Result<()> {
+        self.store.lock().unwrap().set(&self.key, value)
+    }
+
+    pub fn delete(&self) -> Result<()> {
+        self.store.lock().unwrap().delete(&self.key)
+    }
+}
+
+impl std::fmt::Debug for KeyGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyGuard").field("key", &self.key).finish()
+    }
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.held.take());
+        self.locks.release(&self.key);
+    }
+}
@@ -0,0 +1,198 @@
+//! Segment manifest for mini-kvstore-v2.
+//!
+//! `{base_dir}/MANIFEST` is a JSON list of every segment the store knows about
+//! and what state it is in. It is the source of truth for which segments to
+//! replay; the directory listing is only used to repair it.
+
+use crate::store::error::{Result, StoreError};
+use crate::store::segment::segment_file_name;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+const MANIFEST_TMP_FILE: &str = ".MANIFEST.tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentState {
+    /// The segment currently being appended to.
+    Active,
+    /// A sealed segment holding live records.
+    Immutable,
+    /// An input of a compaction that hasn't finished yet.
+    Compacting,
+    /// Superseded; the file is removed once the manifest records this.
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentEntry {
+    pub id: u64,
+    /// File name relative to the data directory.
+    pub path: String,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: u64,
+    pub state: SegmentState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub segments: Vec<SegmentEntry>,
+    pub active_id: u64,
+}
+
+impl Manifest {
+    /// Reads `dir/MANIFEST`, or `None` if there isn't one yet.
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let bytes = match fs::read(dir.join(MANIFEST_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(StoreError::Io(e)),
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| StoreError::CorruptedData(format!("Unreadable manifest: {}", e)))
+    }
+
+    /// Writes the manifest to `.MANIFEST.tmp`, syncs it and renames it over
+    /// `MANIFEST`, so readers only ever see a complete manifest.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(MANIFEST_TMP_FILE);
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+
+    /// Brings the manifest in line with the segment ids found on disk and
+    /// returns a description of each fix, for logging.
+    ///
+    /// Files the manifest doesn't know about are adopted as immutable, entries
+    /// whose file is gone are dropped, and unfinished compactions are rolled
+    /// back to immutable. `Deleted` entries are left for the caller to clean up.
+    pub fn reconcile(&mut self, on_disk: &[u64]) -> Vec<String> {
+        let mut fixes = Vec::new();
+        self.segments.retain(|entry| {
+            let present = on_disk.contains(&entry.id);
+            if !present && entry.state != SegmentState::Deleted {
+                fixes.push(format!("segment {} is listed but missing", entry.id));
+            }
+            present
+        });
+        for entry in &mut self.segments {
+            if entry.state == SegmentState::Compacting {
+                fixes.push(format!("segment {} was mid-compaction", entry.id));
+                entry.state = SegmentState::Immutable;
+            }
+        }
+        for &id in on_disk {
+            if self.entry(id).is_none() {
+                fixes.push(format!("segment {} is on disk but not listed", id));
+                self.segments
+                    .push(SegmentEntry::new(id, SegmentState::Immutable));
+            }
+        }
+        self.segments.sort_by_key(|entry| entry.id);
+        fixes
+    }
+
+    pub fn entry(&self, id: u64) -> Option<&SegmentEntry> {
+        self.segments.iter().find(|entry| entry.id == id)
+    }
+
+    /// Where segment `id` lives under `dir`: the recorded file name, or the
+    /// default one for a segment the manifest doesn't list.
+    pub fn segment_path(&self, dir: &Path, id: u64) -> PathBuf {
+        match self.entry(id) {
+            Some(entry) => dir.join(&entry.path),
+            None => dir.join(segment_file_name(id, None)),
+        }
+    }
+
+    /// Records the file name of segment `id`, e.g. after it was renamed.
+    pub fn set_path(&mut self, id: u64, path: impl Into<String>) {
+        if let Some(entry) = self.segments.iter_mut().find(|entry| entry.id == id) {
+            entry.path = path.into();
+        }
+    }
+
+    /// Ids of segments holding data to replay, ascending.
+    pub fn live_segment_ids(&self) -> Vec<u64> {
+        self.segments
+            .iter()
+            .filter(|entry| entry.state != SegmentState::Deleted)
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    /// Ids of segments marked `Deleted` whose files may still exist.
+    pub fn deleted_segment_ids(&self) -> Vec<u64> {
+        self.segments
+            .iter()
+            .filter(|entry| entry.state == SegmentState::Deleted)
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    /// Seals the current active segment and makes `id` the new one.
+    pub fn push_active(&mut self, id: u64) {
+        for entry in &mut self.segments {
+            if entry.state == SegmentState::Active {
+                entry.state = SegmentState::Immutable;
+            }
+        }
+        self.segments
+            .push(SegmentEntry::new(id, SegmentState::Active));
+        self.active_id = id;
+    }
+
+    pub fn set_state(&mut self, id: u64, state: SegmentState) {
+        if let Some(entry) = self.segments.iter_mut().find(|entry| entry.id == id) {
+            entry.state = state;
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.segments.retain(|entry| entry.id != id);
+    }
+}
+
+impl SegmentEntry {
+    fn new(id: u64, state: SegmentState) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        Self {
+            id,
+            path: segment_file_name(id, None),
+            created_at,
+            state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconcile_adopts_and_drops_segments() {
+        let mut manifest = Manifest::default();
+        manifest.push_active(1);
+        manifest.push_active(2);
+        manifest.push_active(3);
+        manifest.set_state(1, SegmentState::Compacting);
+
+        let fixes = manifest.reconcile(&[1, 3, 4]);
+        assert_eq!(fixes.len(), 3);
+        assert_eq!(manifest.live_segment_ids(), vec![1, 3, 4]);
+        assert_eq!(manifest.entry(1).unwrap().state, SegmentState::Immutable);
+        assert_eq!(manifest.entry(4).unwrap().state, SegmentState::Immutable);
+    }
+}
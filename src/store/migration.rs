@@ -0,0 +1,49 @@
+//! One-off upgrades of a data directory written by an older version.
+
+use crate::store::error::{Result, StoreError};
+use crate::store::manifest::Manifest;
+use crate::store::segment::{parse_segment_file_name, segment_file_name};
+use std::fs;
+use std::path::Path;
+
+/// Renames unpadded `segment-{id}.dat` files in `dir` to the zero-padded
+/// `segment-{id:010}.dat` form and points the manifest at the new names.
+/// Returns how many files were renamed.
+///
+/// Stores open either form, so this is only needed for tools that list
+/// segments by name. Run it while no store has `dir` open.
+pub fn migrate_segment_names(dir: &Path) -> Result<usize> {
+    let mut renamed = Vec::new();
+    for entry in fs::read_dir(dir).map_err(StoreError::io_at(dir))? {
+        let path = entry.map_err(StoreError::io_at(dir))?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(id) = parse_segment_file_name(name) else {
+            continue;
+        };
+        let new_name = segment_file_name(id, None);
+        if name != format!("segment-{}.dat", id) || name == new_name {
+            continue;
+        }
+        let new_path = dir.join(&new_name);
+        if new_path.exists() {
+            return Err(StoreError::CorruptedData(format!(
+                "Both {} and {} exist for segment {}",
+                name, new_name, id
+            )));
+        }
+        fs::rename(&path, &new_path).map_err(StoreError::io_at(&path))?;
+        renamed.push((id, new_name));
+    }
+
+    if !renamed.is_empty() {
+        if let Some(mut manifest) = Manifest::load(dir)? {
+            for (id, name) in &renamed {
+                manifest.set_path(*id, name.as_str());
+            }
+            manifest.save(dir)?;
+        }
+    }
+    Ok(renamed.len())
+}
@@ -0,0 +1,385 @@
+//! On-disk record layout shared by the engine and [`Segment`](super::segment::Segment).
+//!
+//! Every record is a fixed 13-byte header followed by the key and the stored
+//! (possibly compressed) value:
+//! `flags(1) | key_len(u32 LE) | value_len(u32 LE) | checksum(u32 LE) | key | value`.
+//!
+//! Bit 0 of `flags` marks a tombstone, which carries no value. Bit 1 marks a
+//! compressed value, in which case bits 4..8 hold the id of the [`Compressor`]
+//! that encoded it; unflagged values are stored raw and read back regardless
+//! of configuration. The checksum is the CRC32 of the key followed by the
+//! *uncompressed* value, so decoding with the wrong compressor is caught as a
+//! checksum mismatch.
+//!
+//! Bit 3 marks a padded record: zero bytes follow the value up to the next
+//! multiple of [`RECORD_ALIGNMENT`] bytes, counted from the start of the
+//! record, so a segment written only with padded records keeps every record
+//! on an aligned offset. Readers skip the padding; the checksum doesn't cover
+//! it.
+//!
+//! A sealed segment may end in a footer: a header with bit 2 set, no key, an
+//! 8-byte value holding the length of the records before it, and the CRC32 of
+//! those record bytes in the checksum field. Readers treat it as end of input.
+
+use crate::store::compress::Compressor;
+use crate::store::error::{Result, StoreError};
+use std::io::{self, Read, Write};
+
+/// Size of the fixed record header in bytes.
+pub const HEADER_SIZE: usize = 13;
+
+/// Flag bit marking a tombstone.
+pub const TOMBSTONE_MARKER: u8 = 0x01;
+/// Flag bit marking a compressed value.
+pub const COMPRESSED: u8 = 0x02;
+/// Flag bit marking a segment footer.
+pub const FOOTER: u8 = 0x04;
+/// Size of a segment footer: a header plus the `u64` records length.
+pub const FOOTER_SIZE: usize = HEADER_SIZE + 8;
+/// Flag bit marking a record padded to [`RECORD_ALIGNMENT`].
+pub const PADDED: u8 = 0x08;
+const COMPRESSOR_SHIFT: u8 = 4;
+
+/// Boundary padded records are rounded up to, in bytes.
+pub const RECORD_ALIGNMENT: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub flags: u8,
+    pub key_len: u32,
+    /// Length of the value as stored, i.e. after compression.
+    pub value_len: u32,
+    pub checksum: u32,
+}
+
+impl RecordHeader {
+    /// Header of an uncompressed, unpadded set record of `key` and `value`,
+    /// checksum included. Callers adjust `flags` and `value_len` for
+    /// tombstones and compressed values.
+    pub fn compute(key: &[u8], value: &[u8]) -> Self {
+        Self {
+            flags: 0,
+            key_len: key.len() as u32,
+            value_len: value.len() as u32,
+            checksum: checksum(key, value),
+        }
+    }
+
+    /// Whether `key` and the uncompressed `value` (empty for a tombstone)
+    /// match the stored checksum.
+    pub fn verify(&self, key: &[u8], value: &[u8]) -> bool {
+        checksum(key, value) == self.checksum
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0] = self.flags;
+        buf[1..5].copy_from_slice(&self.key_len.to_le_bytes());
+        buf[5..9].copy_from_slice(&self.value_len.to_le_bytes());
+        buf[9..13].copy_from_slice(&self.checksum.to_le_bytes());
+        buf
+    }
+
+    /// Parses a header. Every flag bit has a meaning in this version; older
+    /// versions reject [`PADDED`] as unknown.
+    pub fn decode(buf: &[u8; HEADER_SIZE]) -> Result<Self> {
+        let flags = buf[0];
+        Ok(Self {
+            flags,
+            key_len: u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]),
+            value_len: u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]),
+            checksum: u32::from_le_bytes([buf[9], buf[10], buf[11], buf[12]]),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+
+    /// Reads the next header, or `None` if `reader` is already at end of file
+    /// or at a segment footer. A header cut short is reported as `CorruptedData`.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut buf = [0u8; HEADER_SIZE];
+        let mut filled = 0;
+        while filled < HEADER_SIZE {
+            match reader.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(StoreError::CorruptedData(format!(
+                        "Truncated record header ({} of {} bytes)",
+                        filled, HEADER_SIZE
+                    )))
+                },
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(StoreError::Io(e)),
+            }
+        }
+        let header = Self::decode(&buf)?;
+        Ok((!header.is_footer()).then_some(header))
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.flags & TOMBSTONE_MARKER != 0
+    }
+
+    pub fn is_footer(&self) -> bool {
+        self.flags & FOOTER != 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & COMPRESSED != 0
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.flags & PADDED != 0
+    }
+
+    /// Id of the compressor a compressed value was written with.
+    pub fn compressor_id(&self) -> u8 {
+        self.flags >> COMPRESSOR_SHIFT
+    }
+
+    /// Total on-disk size of the record, header and padding included.
+    pub fn record_len(&self) -> u64 {
+        self.unpadded_len() + self.padding()
+    }
+
+    /// Zero bytes after the value; non-zero only for padded records.
+    pub fn padding(&self) -> u64 {
+        if !self.is_padded() {
+            return 0;
+        }
+        (RECORD_ALIGNMENT - self.unpadded_len() % RECORD_ALIGNMENT) % RECORD_ALIGNMENT
+    }
+
+    fn unpadded_len(&self) -> u64 {
+        HEADER_SIZE as u64 + self.key_len as u64 + self.value_len as u64
+    }
+}
+
+/// CRC32 over the key followed by the uncompressed value.
+pub fn checksum(key: &[u8], value: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize()
+}
+
+/// A decoded record: a key and its value, or `None` for a tombstone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl Record {
+    pub fn set(key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            value: Some(value.into()),
+        }
+    }
+
+    pub fn tombstone(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            value: None,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_none()
+    }
+
+    /// Serializes the record, compressing the value with `compressor` when
+    /// that makes it smaller. Returns the number of bytes written.
+    pub fn write_to<W: Write>(
+        &self,
+        writer: &mut W,
+        compressor: &dyn Compressor,
+    ) -> io::Result<u64> {
+        write_record(
+            writer,
+            &self.key,
+            self.value.as_deref(),
+            compressor,
+            0,
+            false,
+        )
+    }
+
+    /// Reads and verifies the record at `offset`, returning it with its header,
+    /// or `None` at end of file.
+    pub fn read_from<R: Read>(
+        reader: &mut R,
+        compressor: &dyn Compressor,
+        segment_id: u64,
+        offset: u64,
+    ) -> Result<Option<(Self, RecordHeader)>> {
+        let Some(header) = RecordHeader::read_from(reader)? else {
+            return Ok(None);
+        };
+        let mut key = vec![0u8; header.key_len as usize];
+        let mut stored = vec![0u8; header.value_len as usize];
+        let mut padding = [0u8; RECORD_ALIGNMENT as usize];
+        reader
+            .read_exact(&mut key)
+            .and_then(|_| reader.read_exact(&mut stored))
+            .and_then(|_| reader.read_exact(&mut padding[..header.padding() as usize]))
+            .map_err(|e| {
+                StoreError::CorruptedData(format!(
+                    "Failed to read record at offset {}: {}",
+                    offset, e
+                ))
+            })?;
+        let value = decode_value(&header, &key, &stored, compressor, segment_id, offset)?;
+        Ok(Some((Self { key, value }, header)))
+    }
+}
+
+/// Writes a set (`Some(value)`) or tombstone (`None`) record for borrowed
+/// data without building a [`Record`]. Returns the number of bytes written.
+///
+/// Values longer than `compress_threshold` are compressed and flagged, unless
+/// `compressor` is the null one or compression wouldn't save any space. With
+/// `align`, the record is flagged [`PADDED`] and padded to [`RECORD_ALIGNMENT`].
+pub fn write_record<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    value: Option<&[u8]>,
+    compressor: &dyn Compressor,
+    compress_threshold: usize,
+    align: bool,
+) -> io::Result<u64> {
+    let (mut header, stored) = match value {
+        Some(value) => {
+            let compressed = (compressor.id() != 0 && value.len() > compress_threshold)
+                .then(|| compressor.compress(value))
+                .filter(|c| c.len() < value.len());
+            let flags = match compressed {
+                Some(_) => COMPRESSED | compressor.id() << COMPRESSOR_SHIFT,
+                None => 0,
+            };
+            let stored = compressed.unwrap_or_else(|| value.to_vec());
+            let header = RecordHeader {
+                flags,
+                value_len: stored.len() as u32,
+                ..RecordHeader::compute(key, value)
+            };
+            (header, stored)
+        },
+        None => {
+            let header = RecordHeader {
+                flags: TOMBSTONE_MARKER,
+                ..RecordHeader::compute(key, &[])
+            };
+            (header, Vec::new())
+        },
+    };
+    if align {
+        header.flags |= PADDED;
+    }
+    header.write_to(writer)?;
+    writer.write_all(key)?;
+    writer.write_all(&stored)?;
+    writer.write_all(&[0u8; RECORD_ALIGNMENT as usize][..header.padding() as usize])?;
+    Ok(header.record_len())
+}
+
+/// Restores a record's value (`None` for a tombstone) and verifies its checksum.
+///
+/// A compressed value written by a different compressor, or one that fails to
+/// decompress, is reported as a checksum mismatch too: either way the bytes
+/// can't be turned back into what was written.
+pub fn decode_value(
+    header: &RecordHeader,
+    key: &[u8],
+    stored: &[u8],
+    compressor: &dyn Compressor,
+    segment_id: u64,
+    offset: u64,
+) -> Result<Option<Vec<u8>>> {
+    let mismatch = || StoreError::ChecksumMismatch { segment_id, offset };
+    if header.is_tombstone() {
+        return if header.verify(key, &[]) {
+            Ok(None)
+        } else {
+            Err(mismatch())
+        };
+    }
+    let value = if header.is_compressed() {
+        if header.compressor_id() != compressor.id() {
+            return Err(mismatch());
+        }
+        compressor.decompress(stored).map_err(|_| mismatch())?
+    } else {
+        stored.to_vec()
+    };
+    if !header.verify(key, &value) {
+        return Err(mismatch());
+    }
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::compress::{LZ4Compressor, NullCompressor};
+
+    #[test]
+    fn test_record_round_trip() {
+        let records = [
+            Record::set("user", "Alice"),
+            Record::tombstone("user"),
+            Record::set("empty", ""),
+        ];
+        for compressor in [&NullCompressor as &dyn Compressor, &LZ4Compressor] {
+            let mut buf = Vec::new();
+            for record in &records {
+                record.write_to(&mut buf, compressor).unwrap();
+            }
+
+            let mut reader = &buf[..];
+            let mut offset = 0;
+            for expected in &records {
+                let (record, header) = Record::read_from(&mut reader, compressor, 1, offset)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(&record, expected);
+                assert_eq!(header.is_tombstone(), expected.is_tombstone());
+                offset += header.record_len();
+            }
+            assert_eq!(offset, buf.len() as u64);
+            assert!(Record::read_from(&mut reader, compressor, 1, offset)
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_header_verify_detects_changed_key() {
+        let header = RecordHeader::compute(b"user:1", b"Alice");
+        assert_eq!(header.checksum, checksum(b"user:1", b"Alice"));
+        assert_eq!((header.key_len, header.value_len), (6, 5));
+        assert!(header.verify(b"user:1", b"Alice"));
+
+        let mut key = b"user:1".to_vec();
+        key[5] ^= 0x01;
+        assert!(!header.verify(&key, b"Alice"));
+        assert!(!header.verify(b"user:1", b"Alicf"));
+    }
+
+    #[test]
+    fn test_truncated_header_is_corruption() {
+        let mut buf = Vec::new();
+        Record::set("k", "v")
+            .write_to(&mut buf, &NullCompressor)
+            .unwrap();
+        let mut reader = &buf[..HEADER_SIZE - 1];
+        assert!(matches!(
+            RecordHeader::read_from(&mut reader),
+            Err(StoreError::CorruptedData(_))
+        ));
+    }
+}
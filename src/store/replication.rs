@@ -0,0 +1,346 @@
+//! Changefeed of raw records for shipping a store's log to a standby.
+
+use crate::store::compress::Compressor;
+use crate::store::error::{Result, StoreError};
+use crate::store::record::Record;
+use crate::store::segment::segment_file_path;
+use crate::store::KVStore;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Bits of an LSN holding the offset within the segment; the segment id
+/// takes the rest.
+const LSN_OFFSET_BITS: u32 = 40;
+
+/// Largest frame accepted off the wire, so a corrupt length can't trigger a
+/// huge allocation.
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// Frame tags of the replication protocol.
+const FRAME_CHANGE: u8 = 1;
+const FRAME_CATCHUP: u8 = 2;
+
+/// Log sequence number of the record at `offset` in segment `segment_id`.
+///
+/// LSNs order records by log position and stay valid across reopens, but not
+/// across a compaction that rewrites the segment. Offsets must be below 1 TiB.
+pub fn lsn(segment_id: u64, offset: u64) -> u64 {
+    segment_id << LSN_OFFSET_BITS | offset
+}
+
+/// Splits an LSN back into `(segment_id, offset)`.
+pub fn lsn_position(lsn: u64) -> (u64, u64) {
+    (lsn >> LSN_OFFSET_BITS, lsn & ((1 << LSN_OFFSET_BITS) - 1))
+}
+
+/// A change delivered by [`KVStore::tail`](crate::store::KVStore::tail).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub lsn: u64,
+    pub key: String,
+    /// `None` for a tombstone.
+    pub value: Option<Vec<u8>>,
+}
+
+/// One record from the log, with the position it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationRecord {
+    pub segment_id: u64,
+    pub offset: u64,
+    /// On-disk length of the record; `offset + len` is where to resume.
+    pub len: u64,
+    pub key: Vec<u8>,
+    /// `None` for a tombstone.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Iterator over the records of a list of segments, in log order.
+pub(crate) struct RecordStream {
+    base_dir: PathBuf,
+    compressor: Arc<dyn Compressor>,
+    /// Segments still to read, with the offset to start each at.
+    pending: VecDeque<(u64, u64)>,
+    current: Option<(u64, BufReader<File>, u64)>,
+}
+
+impl RecordStream {
+    pub(crate) fn new(
+        base_dir: PathBuf,
+        compressor: Arc<dyn Compressor>,
+        pending: VecDeque<(u64, u64)>,
+    ) -> Self {
+        Self {
+            base_dir,
+            compressor,
+            pending,
+            current: None,
+        }
+    }
+
+    fn open_segment(&self, segment_id: u64, offset: u64) -> Result<BufReader<File>> {
+        let path = segment_file_path(&self.base_dir, segment_id);
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(BufReader::new(file))
+    }
+
+    fn next_record(&mut self) -> Result<Option<ReplicationRecord>> {
+        loop {
+            let Some((segment_id, reader, offset)) = self.current.as_mut() else {
+                let Some((segment_id, offset)) = self.pending.pop_front() else {
+                    return Ok(None);
+                };
+                let reader = self.open_segment(segment_id, offset)?;
+                self.current = Some((segment_id, reader, offset));
+                continue;
+            };
+            match Record::read_from(reader, &*self.compressor, *segment_id, *offset)? {
+                Some((record, header)) => {
+                    let replicated = ReplicationRecord {
+                        segment_id: *segment_id,
+                        offset: *offset,
+                        len: header.record_len(),
+                        key: record.key,
+                        value: record.value,
+                    };
+                    *offset += header.record_len();
+                    return Ok(Some(replicated));
+                },
+                None => self.current = None,
+            }
+        }
+    }
+}
+
+impl Iterator for RecordStream {
+    type Item = Result<ReplicationRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                // Don't keep yielding the same error.
+                self.pending.clear();
+                self.current = None;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// A message of the replication protocol. Every frame is a `u32` LE length
+/// followed by that many bytes: a tag, then for a change `lsn u64 | flag u8
+/// (1 = set) | key_len u32 | key | value`, or for a catchup `since_lsn u64`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationMessage {
+    /// Primary to secondary: a write to apply.
+    Change(ChangeRecord),
+    /// Secondary to primary: resend every change after `since_lsn`.
+    Catchup { since_lsn: u64 },
+}
+
+impl ReplicationMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            ReplicationMessage::Change(change) => {
+                body.push(FRAME_CHANGE);
+                body.extend_from_slice(&change.lsn.to_le_bytes());
+                body.push(change.value.is_some() as u8);
+                body.extend_from_slice(&(change.key.len() as u32).to_le_bytes());
+                body.extend_from_slice(change.key.as_bytes());
+                body.extend_from_slice(change.value.as_deref().unwrap_or_default());
+            },
+            ReplicationMessage::Catchup { since_lsn } => {
+                body.push(FRAME_CATCHUP);
+                body.extend_from_slice(&since_lsn.to_le_bytes());
+            },
+        }
+        let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        frame
+    }
+
+    fn decode(body: &[u8]) -> Result<Self> {
+        let bad = |what: &str| StoreError::CorruptedData(format!("replication frame: {}", what));
+        let u64_at = |at: usize| -> Result<u64> {
+            let bytes = body.get(at..at + 8).ok_or_else(|| bad("truncated"))?;
+            Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+        };
+        match body.first() {
+            Some(&FRAME_CHANGE) => {
+                let lsn = u64_at(1)?;
+                let is_set = *body.get(9).ok_or_else(|| bad("truncated"))? == 1;
+                let key_len = body.get(10..14).ok_or_else(|| bad("truncated"))?;
+                let key_end =
+                    14 + u32::from_le_bytes(key_len.try_into().expect("4 bytes")) as usize;
+                let key = body.get(14..key_end).ok_or_else(|| bad("truncated key"))?;
+                let key = String::from_utf8(key.to_vec()).map_err(|_| bad("non-UTF-8 key"))?;
+                Ok(ReplicationMessage::Change(ChangeRecord {
+                    lsn,
+                    key,
+                    value: is_set.then(|| body[key_end..].to_vec()),
+                }))
+            },
+            Some(&FRAME_CATCHUP) => Ok(ReplicationMessage::Catchup {
+                since_lsn: u64_at(1)?,
+            }),
+            _ => Err(bad("unknown tag")),
+        }
+    }
+
+    /// Writes one frame.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(&self.encode())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads one frame, or `None` if the peer closed the connection cleanly.
+    pub fn read_from(mut reader: impl Read) -> Result<Option<Self>> {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(StoreError::Io(e)),
+        }
+        let len = u32::from_le_bytes(len);
+        if len > MAX_FRAME_LEN {
+            return Err(StoreError::CorruptedData(format!(
+                "replication frame of {} bytes",
+                len
+            )));
+        }
+        let mut body = vec![0u8; len as usize];
+        reader.read_exact(&mut body)?;
+        Self::decode(&body).map(Some)
+    }
+}
+
+/// Primary side of a replication connection: ships changes to a secondary
+/// and hears its catchup requests.
+#[derive(Debug)]
+pub struct ReplicationStream {
+    stream: TcpStream,
+}
+
+impl ReplicationStream {
+    pub fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream })
+    }
+
+    pub fn send(&self, change: &ChangeRecord) -> Result<()> {
+        ReplicationMessage::Change(change.clone()).write_to(&self.stream)
+    }
+
+    /// Sends every change `store` holds after `since_lsn` and returns the LSN
+    /// of the last one sent, or `since_lsn` if there were none.
+    pub fn send_since(&self, store: &KVStore, since_lsn: u64) -> Result<u64> {
+        let mut last = since_lsn;
+        for change in store.tail(since_lsn)? {
+            last = change.lsn;
+            self.send(&change)?;
+        }
+        Ok(last)
+    }
+
+    /// Blocks for the secondary's next catchup request; `None` once it has
+    /// disconnected.
+    pub fn next_catchup(&self) -> Result<Option<u64>> {
+        loop {
+            match ReplicationMessage::read_from(&self.stream)? {
+                Some(ReplicationMessage::Catchup { since_lsn }) => return Ok(Some(since_lsn)),
+                // A secondary has no business sending changes upstream.
+                Some(ReplicationMessage::Change(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Secondary side of a replication connection: receives changes from a
+/// primary and applies them to a local store.
+///
+/// Reads and writes go through `&TcpStream`, so one thread can sit in
+/// [`recv`](Self::recv) while another sends a catchup request.
+#[derive(Debug)]
+pub struct ReplicationReceiver {
+    stream: TcpStream,
+}
+
+impl ReplicationReceiver {
+    pub fn new(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.stream.peer_addr()?)
+    }
+
+    /// Blocks for the next change; `None` once the primary has disconnected.
+    pub fn recv(&self) -> Result<Option<ChangeRecord>> {
+        loop {
+            match ReplicationMessage::read_from(&self.stream)? {
+                Some(ReplicationMessage::Change(change)) => return Ok(Some(change)),
+                Some(ReplicationMessage::Catchup { .. }) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Asks the primary to resend every change after `since_lsn`.
+    pub fn request_catchup(&self, since_lsn: u64) -> Result<()> {
+        ReplicationMessage::Catchup { since_lsn }.write_to(&self.stream)
+    }
+
+    /// Applies changes to `store` until the primary disconnects, returning
+    /// how many were received.
+    pub fn apply_all(&self, store: &mut KVStore) -> Result<usize> {
+        let mut applied = 0;
+        while let Some(change) = self.recv()? {
+            store.apply_change(&change)?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_round_trip_through_frames() {
+        let messages = [
+            ReplicationMessage::Change(ChangeRecord {
+                lsn: lsn(3, 128),
+                key: "key".to_string(),
+                value: Some(b"value".to_vec()),
+            }),
+            ReplicationMessage::Change(ChangeRecord {
+                lsn: lsn(3, 160),
+                key: "key".to_string(),
+                value: None,
+            }),
+            ReplicationMessage::Catchup { since_lsn: 42 },
+        ];
+        let mut wire = Vec::new();
+        for message in &messages {
+            message.write_to(&mut wire).unwrap();
+        }
+        let mut reader = wire.as_slice();
+        for message in &messages {
+            assert_eq!(
+                ReplicationMessage::read_from(&mut reader).unwrap().as_ref(),
+                Some(message)
+            );
+        }
+        assert_eq!(ReplicationMessage::read_from(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,57 @@
+//! Write-recency tracking for the `max_total_bytes` retention budget.
+
+use crate::store::index::Index;
+use std::collections::{BTreeMap, HashMap};
+
+/// Live keys ordered by when they were last written, with the total size of
+/// their values.
+#[derive(Debug, Default)]
+pub(crate) struct WriteOrder {
+    next_seq: u64,
+    /// Write sequence number -> key, oldest first.
+    by_seq: BTreeMap<u64, Vec<u8>>,
+    /// Key -> (sequence number of its latest write, value length).
+    by_key: HashMap<Vec<u8>, (u64, u64)>,
+    total_bytes: u64,
+}
+
+impl WriteOrder {
+    /// Seeds the order from the log position of each key's latest record.
+    pub(crate) fn from_index(index: &Index, values: &HashMap<Vec<u8>, Vec<u8>>) -> Self {
+        let mut keys: Vec<_> = index.iter().collect();
+        keys.sort_unstable_by_key(|(_, (seg, offset, _))| (*seg, *offset));
+        let mut order = Self::default();
+        for (key, _) in keys {
+            if let Some(value) = values.get(key) {
+                order.record_write(key, value.len() as u64);
+            }
+        }
+        order
+    }
+
+    /// Marks `key` as the most recently written, holding `len` value bytes.
+    pub(crate) fn record_write(&mut self, key: &[u8], len: u64) {
+        self.record_delete(key);
+        self.by_seq.insert(self.next_seq, key.to_vec());
+        self.by_key.insert(key.to_vec(), (self.next_seq, len));
+        self.next_seq += 1;
+        self.total_bytes += len;
+    }
+
+    pub(crate) fn record_delete(&mut self, key: &[u8]) {
+        if let Some((seq, len)) = self.by_key.remove(key) {
+            self.by_seq.remove(&seq);
+            self.total_bytes -= len;
+        }
+    }
+
+    /// The least recently written key.
+    pub(crate) fn oldest(&self) -> Option<&[u8]> {
+        self.by_seq.values().next().map(Vec::as_slice)
+    }
+
+    /// Sum of the lengths of all live values.
+    pub(crate) fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+}
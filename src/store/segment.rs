@@ -1,54 +1,1068 @@
 #![allow(dead_code)]
-#![allow(unused_imports)]
 //! Segment logic for mini-kvstore-v2.
+//!
+//! A segment is an append-only file of records; see [`crate::store::record`]
+//! for the layout.
 
-use crate::store::error::Result;
+use crate::store::cache::ValueCache;
+use crate::store::compress::{Compressor, NullCompressor};
+use crate::store::error::{Result, StoreError};
+use crate::store::file_utils::retry_on_interrupt;
+use crate::store::record::{self, Record, RecordHeader, FOOTER, FOOTER_SIZE, HEADER_SIZE};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use bytes::Bytes;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub type SegmentReadResult = Result<Option<(String, Option<Vec<u8>>)>>;
+/// `(key, value or None for a tombstone, offset of the next record)`.
+pub type SegmentReadResult = Result<Option<(String, Option<Vec<u8>>, u64)>>;
+
+/// `(offset, key, value or None for a tombstone)`, as yielded by [`SegmentRecordIter`].
+pub type ScannedRecord = (u64, String, Option<Vec<u8>>);
 
 const SEGMENT_SIZE_LIMIT: u64 = 1024 * 1024;
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".dat";
+
+/// File name of segment `id`: `segment-{id:010}.dat`, so names sort like
+/// ids, or `segment-{id:010}-{unix_ts}.dat` when a creation time is given.
+pub fn segment_file_name(id: u64, unix_ts: Option<u64>) -> String {
+    match unix_ts {
+        Some(ts) => format!("{}{:010}-{}{}", SEGMENT_PREFIX, id, ts, SEGMENT_SUFFIX),
+        None => format!("{}{:010}{}", SEGMENT_PREFIX, id, SEGMENT_SUFFIX),
+    }
+}
+
+/// The id of a segment file named by [`segment_file_name`] or in the older
+/// unpadded `segment-{id}.dat` form; `None` for any other file.
+pub fn parse_segment_file_name(name: &str) -> Option<u64> {
+    let stem = name
+        .strip_prefix(SEGMENT_PREFIX)?
+        .strip_suffix(SEGMENT_SUFFIX)?;
+    let (id, unix_ts) = match stem.split_once('-') {
+        Some((id, ts)) => (id, Some(ts)),
+        None => (stem, None),
+    };
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(id) || !unix_ts.map_or(true, is_number) {
+        return None;
+    }
+    id.parse().ok()
+}
+
+/// The file of segment `id` in `dir`, whichever form it is named in, or
+/// the path [`segment_file_name`] gives if there is no such file yet.
+pub fn segment_file_path(dir: &Path, id: u64) -> PathBuf {
+    let found = std::fs::read_dir(dir).ok().and_then(|entries| {
+        entries.filter_map(|entry| entry.ok()).find_map(|entry| {
+            let path = entry.path();
+            let name = path.file_name()?.to_str()?;
+            (parse_segment_file_name(name) == Some(id)).then_some(path)
+        })
+    });
+    found.unwrap_or_else(|| dir.join(segment_file_name(id, None)))
+}
+
+/// How [`Segment::verify`] established that a segment is intact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The footer's CRC matched the record bytes.
+    Footer,
+    /// No footer; this many records had their checksums checked.
+    Records(usize),
+}
+
+/// CRC32 of the first `len` bytes of `file`.
+fn crc_of_prefix(file: &mut File, len: u64) -> Result<u32> {
+    retry_on_interrupt(|| file.seek(SeekFrom::Start(0)))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut reader = file.take(len);
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// The `(records_len, crc)` of `file`'s footer, if it ends in one.
+fn read_footer(file: &mut File, file_len: u64) -> Result<Option<(u64, u32)>> {
+    if file_len < FOOTER_SIZE as u64 {
+        return Ok(None);
+    }
+    let records_len = file_len - FOOTER_SIZE as u64;
+    retry_on_interrupt(|| file.seek(SeekFrom::Start(records_len)))?;
+    let mut buf = [0u8; FOOTER_SIZE];
+    retry_on_interrupt(|| file.read_exact(&mut buf))?;
+    let Ok(header) = RecordHeader::decode(buf[..HEADER_SIZE].try_into().expect("header slice"))
+    else {
+        return Ok(None);
+    };
+    let covered = u64::from_le_bytes(buf[HEADER_SIZE..].try_into().expect("8 bytes"));
+    let is_footer = header.flags == FOOTER
+        && header.key_len == 0
+        && header.value_len == 8
+        && covered == records_len;
+    Ok(is_footer.then_some((records_len, header.checksum)))
+}
+
+/// Seals the segment file at `path` by appending a footer with the CRC of
+/// its records, and syncs it. Returns the bytes appended; a segment that
+/// already has a footer is left alone.
+pub fn write_footer(path: &Path) -> Result<u64> {
+    let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    if read_footer(&mut file, len)?.is_some() {
+        return Ok(0);
+    }
+    append_footer(&mut file, len)?;
+    Ok(FOOTER_SIZE as u64)
+}
+
+/// Appends the footer covering the first `len` bytes of `file`, which must
+/// be exactly its record bytes, and syncs it. Returns the CRC.
+fn append_footer(file: &mut File, len: u64) -> Result<u32> {
+    let checksum = crc_of_prefix(file, len)?;
+    let header = RecordHeader {
+        flags: FOOTER,
+        key_len: 0,
+        value_len: 8,
+        checksum,
+    };
+    let mut footer = header.encode().to_vec();
+    footer.extend_from_slice(&len.to_le_bytes());
+    retry_on_interrupt(|| file.write_all(&footer))?;
+    retry_on_interrupt(|| file.sync_all())?;
+    Ok(checksum)
+}
+
+/// Reserves `size` bytes of disk space for `file` without changing its
+/// apparent length, so appends and replay are unaffected.
+///
+/// Uses `fallocate(FALLOC_FL_KEEP_SIZE)` on Linux and `F_PREALLOCATE` on
+/// macOS. Other platforms (including Windows, where `SetEndOfFile` would
+/// grow the logical length and expose zeroed bytes to replay) are a no-op,
+/// as are file systems that don't support reservation.
+pub fn preallocate_file(file: &File, size: u64) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the fd is owned by `file` and stays valid for the call.
+        let ret = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                size as libc::off_t,
+            )
+        };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(StoreError::Io(err));
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATEALL,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: size as libc::off_t,
+            fst_bytesalloc: 0,
+        };
+        // SAFETY: the fd is owned by `file` and `store` outlives the call.
+        let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) };
+        if ret == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ENOTSUP) {
+                return Err(StoreError::Io(err));
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = (file, size);
+
+    Ok(())
+}
+
+/// One line of [`Segment::export_ndjson`] output.
+#[derive(Serialize)]
+struct NdjsonRecord<'a> {
+    op: &'static str,
+    key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_b64: Option<String>,
+    /// CRC32 of the key and value, as `0x`-prefixed hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
 
 pub struct Segment {
     pub path: std::path::PathBuf,
     pub id: usize,
+    file: File,
+    /// Length of the record bytes, excluding any footer.
+    len: u64,
+    /// CRC from the footer of a sealed segment.
+    footer_crc: Option<u32>,
+    compressor: Arc<dyn Compressor>,
+    /// Pad appended records; see [`with_record_alignment`](Self::with_record_alignment).
+    align_records: bool,
 }
 
 impl Segment {
-    /// Opens a segment (stub implementation).
+    /// Opens (or creates) the segment with the given id inside `dir`.
     pub fn open(dir: &std::path::Path, id: usize) -> Result<Self> {
+        Self::open_path(segment_file_path(dir, id as u64), id)
+    }
+
+    /// Opens (or creates) segment `id` at `path`.
+    pub(crate) fn open_path(path: PathBuf, id: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        Self::from_file(path, id, file)
+    }
+
+    /// Opens an existing segment without write access, e.g. on a read-only
+    /// mount. Appending to it fails.
+    pub fn open_read_only(dir: &std::path::Path, id: usize) -> Result<Self> {
+        Self::open_read_only_path(segment_file_path(dir, id as u64), id)
+    }
+
+    pub(crate) fn open_read_only_path(path: PathBuf, id: usize) -> Result<Self> {
+        let file = File::open(&path)?;
+        Self::from_file(path, id, file)
+    }
+
+    fn from_file(path: PathBuf, id: usize, mut file: File) -> Result<Self> {
+        let file_len = file.metadata()?.len();
+        let (len, footer_crc) = match read_footer(&mut file, file_len)? {
+            Some((records_len, crc)) => (records_len, Some(crc)),
+            None => (file_len, None),
+        };
         Ok(Segment {
-            path: dir.join(format!("segment-{:04}.dat", id)),
+            path,
             id,
+            file,
+            len,
+            footer_crc,
+            compressor: Arc::new(NullCompressor),
+            align_records: false,
         })
     }
 
-    /// Appends a key-value pair to the segment (stub).
-    pub fn append(&mut self, _key: &[u8], _value: &[u8]) -> Result<u64> {
-        Ok(0)
+    /// Uses `compressor` for values appended to and read from this segment.
+    /// Appended values are compressed whenever that makes them smaller.
+    pub fn with_compressor(mut self, compressor: Arc<dyn Compressor>) -> Self {
+        self.compressor = compressor;
+        self
+    }
+
+    /// Pads each record appended from now on to a multiple of
+    /// [`RECORD_ALIGNMENT`](record::RECORD_ALIGNMENT) bytes, so in a segment
+    /// written this way from the start every record offset is aligned. Reads
+    /// handle padded and unpadded records either way.
+    pub fn with_record_alignment(mut self, align: bool) -> Self {
+        self.align_records = align;
+        self
+    }
+
+    /// Appends a key-value pair and returns the offset of the new record.
+    pub fn append(&mut self, key: &[u8], value: &[u8]) -> Result<u64> {
+        self.write_record(key, Some(value))
     }
 
     /// Appends a tombstone (delete marker) for a key.
-    pub fn append_tombstone(&mut self, _key: &[u8]) -> Result<u64> {
-        Ok(0)
+    pub fn append_tombstone(&mut self, key: &[u8]) -> Result<u64> {
+        self.write_record(key, None)
+    }
+
+    fn write_record(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<u64> {
+        // Assemble the record first so it reaches the file in a single write.
+        let mut buf = Vec::new();
+        record::write_record(
+            &mut buf,
+            key,
+            value,
+            &*self.compressor,
+            0,
+            self.align_records,
+        )?;
+        if self.footer_crc.take().is_some() {
+            // Appending reopens a sealed segment: drop its footer first.
+            self.file.set_len(self.len)?;
+        }
+        let offset = self.len;
+        retry_on_interrupt(|| self.file.write_all(&buf))?;
+        self.len += buf.len() as u64;
+        Ok(offset)
+    }
+
+    /// Reserves `size` bytes on disk for this segment; see [`preallocate_file`].
+    pub fn preallocate(&mut self, size: u64) -> Result<()> {
+        preallocate_file(&self.file, size)
+    }
+
+    /// Seals the segment: syncs the records, truncates the file to them,
+    /// which also releases space reserved by [`preallocate`](Self::preallocate)
+    /// past the end, and appends a fresh CRC footer.
+    pub fn close(&mut self) -> Result<()> {
+        self.file.flush()?;
+        retry_on_interrupt(|| self.file.sync_all())?;
+        self.file.set_len(self.len)?;
+        self.footer_crc = Some(append_footer(&mut self.file, self.len)?);
+        Ok(())
+    }
+
+    /// Cuts the segment back to its first `offset` bytes, e.g. to drop a
+    /// partial trailing record, and syncs it. `offset` should be a record
+    /// boundary. A footer goes too, leaving the segment unsealed.
+    pub fn truncate_to(&mut self, offset: u64) -> Result<()> {
+        if offset > self.len {
+            return Err(StoreError::CorruptedData(format!(
+                "Cannot truncate segment {} to {} bytes; it has {}",
+                self.id, offset, self.len
+            )));
+        }
+        self.file.set_len(offset)?;
+        retry_on_interrupt(|| self.file.sync_all())?;
+        self.len = offset;
+        self.footer_crc = None;
+        Ok(())
+    }
+
+    /// Whether the segment ends in a footer.
+    pub fn is_sealed(&self) -> bool {
+        self.footer_crc.is_some()
     }
 
-    /// Checks if the segment is full (stub).
+    /// Checks if the segment has reached its size limit.
     pub fn is_full(&self) -> bool {
-        false
+        self.len >= SEGMENT_SIZE_LIMIT
+    }
+
+    /// Length of the segment in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Current position of the underlying file cursor.
+    pub fn current_offset(&mut self) -> Result<u64> {
+        Ok(self.file.stream_position()?)
+    }
+
+    /// Reads the record at `offset`, returning it along with the offset of the
+    /// record that follows. Returns `None` at end of segment.
+    pub fn read_record_at(&mut self, offset: u64) -> SegmentReadResult {
+        if offset >= self.len {
+            return Ok(None);
+        }
+        self.check_bounds(offset, HEADER_SIZE as u64)?;
+        retry_on_interrupt(|| self.file.seek(SeekFrom::Start(offset)))?;
+
+        let header = RecordHeader::read_from(&mut self.file)?
+            .ok_or_else(|| StoreError::CorruptedData(format!("No record at offset {}", offset)))?;
+        self.check_bounds(offset, header.record_len())?;
+
+        let mut key = vec![0u8; header.key_len as usize];
+        retry_on_interrupt(|| self.file.read_exact(&mut key))?;
+        let mut stored = vec![0u8; header.value_len as usize];
+        retry_on_interrupt(|| self.file.read_exact(&mut stored))?;
+        self.finish_record(offset, &header, key, &stored)
+    }
+
+    /// Verifies and decodes a record whose bytes have been read.
+    fn finish_record(
+        &self,
+        offset: u64,
+        header: &RecordHeader,
+        key: Vec<u8>,
+        stored: &[u8],
+    ) -> SegmentReadResult {
+        let value = record::decode_value(
+            header,
+            &key,
+            stored,
+            &*self.compressor,
+            self.id as u64,
+            offset,
+        )?;
+        let key = String::from_utf8(key).map_err(|_| StoreError::InvalidUtf8Key {
+            segment_id: self.id as u64,
+            offset,
+        })?;
+        Ok(Some((key, value, offset + header.record_len())))
+    }
+
+    /// Fails with `CorruptedData` if `len` bytes at `offset` run past the end.
+    fn check_bounds(&self, offset: u64, len: u64) -> Result<()> {
+        if offset + len > self.len {
+            return Err(StoreError::CorruptedData(format!(
+                "Truncated record at offset {} in {}",
+                offset,
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes every record in the segment to `writer` as newline-delimited JSON:
+    /// `{"op":"set","key":..,"value_b64":..,"checksum":"0x.."}` for sets and
+    /// `{"op":"del","key":..}` for tombstones. Returns the number of records.
+    pub fn export_ndjson(&mut self, writer: &mut impl Write) -> Result<usize> {
+        let mut offset = 0;
+        let mut count = 0;
+        while let Some((key, value, next)) = self.read_record_at(offset)? {
+            let record = match &value {
+                Some(v) => NdjsonRecord {
+                    op: "set",
+                    key: &key,
+                    value_b64: Some(BASE64.encode(v)),
+                    checksum: Some(format!("0x{:08x}", record::checksum(key.as_bytes(), v))),
+                },
+                None => NdjsonRecord {
+                    op: "del",
+                    key: &key,
+                    value_b64: None,
+                    checksum: None,
+                },
+            };
+            serde_json::to_writer(&mut *writer, &record).map_err(std::io::Error::from)?;
+            retry_on_interrupt(|| writer.write_all(b"\n"))?;
+            count += 1;
+            offset = next;
+        }
+        Ok(count)
+    }
+
+    /// Like [`Segment::read_record_at`], but serves the bytes from `cache` where
+    /// possible and fills it with any blocks read from disk.
+    ///
+    /// Only complete blocks are cached; the partial block at the tail of a
+    /// segment that is still being appended is always read from the file.
+    pub fn read_record_at_cached(
+        &mut self,
+        offset: u64,
+        cache: &mut dyn ValueCache,
+    ) -> SegmentReadResult {
+        if offset >= self.len {
+            return Ok(None);
+        }
+
+        let header = self.read_header_at_cached(offset, cache)?;
+        let key_pos = offset + HEADER_SIZE as u64;
+        let key = self.read_bytes_cached(key_pos, header.key_len as u64, cache)?;
+        let stored = self.read_bytes_cached(
+            key_pos + header.key_len as u64,
+            header.value_len as u64,
+            cache,
+        )?;
+        self.finish_record(offset, &header, key, &stored)
+    }
+
+    /// Reads just the header of the record at `offset` through `cache`.
+    pub fn read_header_at_cached(
+        &mut self,
+        offset: u64,
+        cache: &mut dyn ValueCache,
+    ) -> Result<RecordHeader> {
+        let header = self.read_bytes_cached(offset, HEADER_SIZE as u64, cache)?;
+        let header = RecordHeader::decode(header[..].try_into().expect("header-sized read"))?;
+        if header.is_footer() {
+            return Err(StoreError::CorruptedData(format!(
+                "No record at offset {}",
+                offset
+            )));
+        }
+        self.check_bounds(offset, header.record_len())?;
+        Ok(header)
+    }
+
+    /// Reads `len` bytes at `offset`, assembling them from cached blocks.
+    fn read_bytes_cached(
+        &mut self,
+        offset: u64,
+        len: u64,
+        cache: &mut dyn ValueCache,
+    ) -> Result<Vec<u8>> {
+        self.check_bounds(offset, len)?;
+
+        let block_size = cache.block_size();
+        let mut out = Vec::with_capacity(len as usize);
+        let mut pos = offset;
+        while pos < offset + len {
+            let block_offset = pos - pos % block_size;
+            let block = match cache.get(&(self.id, block_offset)).cloned() {
+                Some(block) => block,
+                None => {
+                    let block_len = block_size.min(self.len - block_offset);
+                    let mut buf = vec![0u8; block_len as usize];
+                    retry_on_interrupt(|| self.file.seek(SeekFrom::Start(block_offset)))?;
+                    retry_on_interrupt(|| self.file.read_exact(&mut buf))?;
+                    let block = Bytes::from(buf);
+                    if block_len == block_size {
+                        cache.insert((self.id, block_offset), block.clone());
+                    }
+                    block
+                },
+            };
+            let start = (pos - block_offset) as usize;
+            let end = ((offset + len - block_offset) as usize).min(block.len());
+            out.extend_from_slice(&block[start..end]);
+            pos = block_offset + end as u64;
+        }
+        Ok(out)
+    }
+
+    /// Iterates over the records from `start_offset`, which must be a record
+    /// boundary, to the end of the segment.
+    pub fn scan_from_offset(&mut self, start_offset: u64) -> Result<SegmentRecordIter<'_>> {
+        retry_on_interrupt(|| self.file.seek(SeekFrom::Start(start_offset)))?;
+        Ok(SegmentRecordIter {
+            segment: SegmentHandle::Borrowed(self),
+            offset: start_offset,
+            done: false,
+        })
     }
 
-    /// Reads a key/value record at the given offset (stub).
-    pub fn read_record_at(&mut self, _offset: u64) -> SegmentReadResult {
-        Ok(None)
+    /// Like [`scan_from_offset`](Self::scan_from_offset) from the start, but
+    /// the iterator takes ownership of the segment.
+    pub fn into_records(mut self) -> Result<SegmentRecordIter<'static>> {
+        retry_on_interrupt(|| self.file.seek(SeekFrom::Start(0)))?;
+        Ok(SegmentRecordIter {
+            segment: SegmentHandle::Owned(Box::new(self)),
+            offset: 0,
+            done: false,
+        })
+    }
+
+    /// Checks the segment's integrity: against its footer CRC when it has
+    /// one, which is a single sequential read, or else record by record.
+    ///
+    /// A footer mismatch falls back to the record checks to report which
+    /// record is damaged; if they all pass, the footer itself is reported.
+    pub fn verify(&mut self) -> Result<Verification> {
+        if let Some(expected) = self.footer_crc {
+            if crc_of_prefix(&mut self.file, self.len)? == expected {
+                return Ok(Verification::Footer);
+            }
+        }
+        retry_on_interrupt(|| self.file.seek(SeekFrom::Start(0)))?;
+        let mut reader = BufReader::new((&self.file).take(self.len));
+        let mut offset = 0;
+        let mut records = 0;
+        while let Some((_, header)) =
+            Record::read_from(&mut reader, &*self.compressor, self.id as u64, offset)?
+        {
+            offset += header.record_len();
+            records += 1;
+        }
+        if self.footer_crc.is_some() {
+            return Err(StoreError::CorruptedData(format!(
+                "Footer CRC mismatch in segment {}",
+                self.id
+            )));
+        }
+        Ok(Verification::Records(records))
+    }
+
+    /// Number of records in the segment, tombstones included. Reads only
+    /// the record headers and seeks past keys and values.
+    pub fn record_count(&mut self) -> Result<usize> {
+        self.count_records().map(|(records, _)| records)
+    }
+
+    /// Number of tombstone records in the segment; see
+    /// [`record_count`](Self::record_count).
+    pub fn tombstone_count(&mut self) -> Result<usize> {
+        self.count_records().map(|(_, tombstones)| tombstones)
+    }
+
+    /// Number of records that are not tombstones. Some may still be
+    /// overwritten by later segments.
+    pub fn live_record_count(&mut self) -> Result<usize> {
+        self.count_records()
+            .map(|(records, tombstones)| records - tombstones)
+    }
+
+    /// Counts records and tombstones with one pass over the headers.
+    fn count_records(&mut self) -> Result<(usize, usize)> {
+        retry_on_interrupt(|| self.file.seek(SeekFrom::Start(0)))?;
+        let mut reader = BufReader::new((&self.file).take(self.len));
+        let mut offset = 0;
+        let (mut records, mut tombstones) = (0, 0);
+        while offset < self.len {
+            let Some(header) = RecordHeader::read_from(&mut reader)? else {
+                break;
+            };
+            let next = offset + header.record_len();
+            if next > self.len {
+                return Err(StoreError::CorruptedData(format!(
+                    "Record at offset {} in segment {} runs past the end",
+                    offset, self.id
+                )));
+            }
+            reader.seek_relative((next - offset - HEADER_SIZE as u64) as i64)?;
+            offset = next;
+            records += 1;
+            if header.is_tombstone() {
+                tombstones += 1;
+            }
+        }
+        Ok((records, tombstones))
     }
 
-    /// Reads a value at a given offset (stub).
-    pub fn read_value_at(&mut self, _offset: u64) -> Result<Option<Vec<u8>>> {
-        Ok(None)
+    /// Reads a value at a given offset; `None` for tombstones or past the end.
+    pub fn read_value_at(&mut self, offset: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.read_record_at(offset)?.and_then(|(_, value, _)| value))
     }
 
-    /// Computes the record size for a key/value (stub).
-    pub fn record_size(_key_len: u64, _value_len: u64) -> u64 {
-        0
+    /// Computes the on-disk size of an uncompressed set record.
+    pub fn record_size(key_len: u64, value_len: u64) -> u64 {
+        HEADER_SIZE as u64 + key_len + value_len
+    }
+
+    /// Computes the on-disk size of a tombstone record.
+    pub fn tombstone_size(key_len: u64) -> u64 {
+        HEADER_SIZE as u64 + key_len
+    }
+}
+
+/// Records of a segment in file order; see [`Segment::scan_from_offset`].
+/// Stops after the first error.
+pub struct SegmentRecordIter<'a> {
+    segment: SegmentHandle<'a>,
+    offset: u64,
+    done: bool,
+}
+
+enum SegmentHandle<'a> {
+    Borrowed(&'a mut Segment),
+    Owned(Box<Segment>),
+}
+
+impl SegmentHandle<'_> {
+    fn get(&mut self) -> &mut Segment {
+        match self {
+            SegmentHandle::Borrowed(segment) => segment,
+            SegmentHandle::Owned(segment) => segment,
+        }
+    }
+}
+
+impl Iterator for SegmentRecordIter<'_> {
+    type Item = Result<ScannedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.segment.get().read_record_at(self.offset) {
+            Ok(Some((key, value, next))) => {
+                let offset = std::mem::replace(&mut self.offset, next);
+                Some(Ok((offset, key, value)))
+            },
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::cache::LruBlockCache;
+
+    #[test]
+    fn test_segment_file_names_sort_and_parse() {
+        assert_eq!(segment_file_name(7, None), "segment-0000000007.dat");
+        assert_eq!(
+            segment_file_name(12, Some(1_700_000_000)),
+            "segment-0000000012-1700000000.dat"
+        );
+        assert!(segment_file_name(9, None) < segment_file_name(10, None));
+
+        assert_eq!(parse_segment_file_name("segment-0000000007.dat"), Some(7));
+        assert_eq!(
+            parse_segment_file_name("segment-0000000012-1700000000.dat"),
+            Some(12)
+        );
+        assert_eq!(parse_segment_file_name("segment-3.dat"), Some(3));
+        for other in [
+            "segment-.dat",
+            "segment-+3.dat",
+            "segment-3-.dat",
+            "segment-3-x.dat",
+            "MANIFEST",
+        ] {
+            assert_eq!(parse_segment_file_name(other), None, "{}", other);
+        }
+    }
+
+    #[test]
+    fn test_chained_reads_land_at_eof() {
+        let dir = std::path::Path::new("tests_data/segment_chained_reads");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        segment.append(b"a", b"first").unwrap();
+        segment.append_tombstone(b"b").unwrap();
+        segment.append(b"c", b"third value").unwrap();
+
+        let mut offset = 0;
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let (key, value, next) = segment.read_record_at(offset).unwrap().unwrap();
+            seen.push((key, value));
+            offset = next;
+            assert_eq!(segment.current_offset().unwrap(), next);
+        }
+
+        assert_eq!(
+            seen,
+            vec![
+                ("a".to_string(), Some(b"first".to_vec())),
+                ("b".to_string(), None),
+                ("c".to_string(), Some(b"third value".to_vec())),
+            ]
+        );
+        assert_eq!(offset, segment.len());
+        assert_eq!(offset, std::fs::metadata(&segment.path).unwrap().len());
+        assert!(segment.read_record_at(offset).unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_scan_from_offset_yields_tail() {
+        let dir = std::path::Path::new("tests_data/segment_scan_from_offset");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..100 {
+            offsets.push(
+                segment
+                    .append(format!("key_{}", i).as_bytes(), b"value")
+                    .unwrap(),
+            );
+        }
+
+        let scanned: Vec<_> = segment
+            .scan_from_offset(offsets[50])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(scanned.len(), 50);
+        assert_eq!(scanned[0].0, offsets[50]);
+        assert_eq!(scanned[0].1, "key_50");
+        for (record, i) in scanned.iter().zip(50..) {
+            assert_eq!(record.0, offsets[i]);
+            assert_eq!(record.2.as_deref(), Some(&b"value"[..]));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_cached_reads_skip_io_on_second_pass() {
+        let dir = std::path::Path::new("tests_data/segment_cached_reads");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        // 24-byte records so every block is complete and therefore cacheable.
+        for i in 0..3 {
+            segment
+                .append(format!("k{:02}", i).as_bytes(), b"vvvvvvvv")
+                .unwrap();
+        }
+        let mut cache = LruBlockCache::new(1024, 8);
+
+        let mut first_pass = Vec::new();
+        let mut offset = 0;
+        while let Some((key, value, next)) =
+            segment.read_record_at_cached(offset, &mut cache).unwrap()
+        {
+            first_pass.push((key, value));
+            offset = next;
+        }
+        assert_eq!(first_pass.len(), 3);
+        let misses = cache.misses();
+
+        // Wipe the file behind the segment's back: any real I/O now fails.
+        OpenOptions::new()
+            .write(true)
+            .open(&segment.path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert!(segment.read_record_at(0).is_err());
+
+        let mut second_pass = Vec::new();
+        let mut offset = 0;
+        while let Some((key, value, next)) =
+            segment.read_record_at_cached(offset, &mut cache).unwrap()
+        {
+            second_pass.push((key, value));
+            offset = next;
+        }
+        assert_eq!(second_pass, first_pass);
+        assert_eq!(cache.misses(), misses);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_truncate_to_drops_trailing_records() {
+        let dir = std::path::Path::new("tests_data/segment_truncate_to");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        segment.append(b"first", b"kept").unwrap();
+        let second = segment.append(b"second", b"dropped").unwrap();
+        segment.close().unwrap();
+        assert!(segment.truncate_to(segment.len() + 1).is_err());
+
+        segment.truncate_to(second).unwrap();
+        assert_eq!(segment.len(), second);
+        assert!(!segment.is_sealed());
+        assert_eq!(std::fs::metadata(&segment.path).unwrap().len(), second);
+
+        let mut reopened = Segment::open(dir, 1).unwrap();
+        let records: Vec<_> = reopened
+            .scan_from_offset(0)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(records, [(0, "first".to_string(), Some(b"kept".to_vec()))]);
+        // Appends continue from the cut.
+        assert_eq!(reopened.append(b"third", b"v").unwrap(), second);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_record_counts_include_tombstones() {
+        let dir = std::path::Path::new("tests_data/segment_record_counts");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        for i in 0..50 {
+            segment
+                .append(format!("key{}", i).as_bytes(), &vec![b'v'; i])
+                .unwrap();
+        }
+        for i in 0..20 {
+            segment
+                .append_tombstone(format!("key{}", i).as_bytes())
+                .unwrap();
+        }
+        assert_eq!(segment.record_count().unwrap(), 70);
+        assert_eq!(segment.tombstone_count().unwrap(), 20);
+        assert_eq!(segment.live_record_count().unwrap(), 50);
+
+        // Sealing adds a footer, which is not a record.
+        segment.close().unwrap();
+        let mut sealed = Segment::open_read_only(dir, 1).unwrap();
+        assert!(sealed.is_sealed());
+        assert_eq!(sealed.record_count().unwrap(), 70);
+        assert_eq!(sealed.tombstone_count().unwrap(), 20);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_aligned_records_start_on_8_byte_offsets() {
+        let dir = std::path::Path::new("tests_data/segment_aligned_records");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap().with_record_alignment(true);
+        let mut offsets = Vec::new();
+        for i in 0..20 {
+            let key = format!("key{}", i);
+            let offset = if i % 5 == 4 {
+                segment.append_tombstone(key.as_bytes()).unwrap()
+            } else {
+                segment.append(key.as_bytes(), &vec![b'v'; i]).unwrap()
+            };
+            offsets.push(offset);
+        }
+        assert!(offsets.iter().all(|offset| offset % 8 == 0));
+        assert_eq!(segment.len() % 8, 0);
+
+        for (i, &offset) in offsets.iter().enumerate() {
+            let (key, value, next) = segment.read_record_at(offset).unwrap().unwrap();
+            assert_eq!(key, format!("key{}", i));
+            let expected = (i % 5 != 4).then(|| vec![b'v'; i]);
+            assert_eq!(value, expected);
+            assert_eq!(next, offsets.get(i + 1).copied().unwrap_or(segment.len()));
+        }
+        assert_eq!(segment.record_count().unwrap(), 20);
+        assert_eq!(segment.tombstone_count().unwrap(), 4);
+
+        // Sealed and reopened without the option, the padding still reads back.
+        segment.close().unwrap();
+        let mut reopened = Segment::open_read_only(dir, 1).unwrap();
+        assert_eq!(reopened.verify().unwrap(), Verification::Footer);
+        let scanned: Vec<u64> = reopened
+            .scan_from_offset(0)
+            .unwrap()
+            .map(|record| record.unwrap().0)
+            .collect();
+        assert_eq!(scanned, offsets);
+        let raw = std::fs::read(&reopened.path).unwrap();
+        let mut reader = &raw[..reopened.len() as usize];
+        let mut offset = 0;
+        while let Some((_, header)) =
+            Record::read_from(&mut reader, &NullCompressor, 1, offset).unwrap()
+        {
+            assert!(header.is_padded());
+            offset += header.record_len();
+        }
+        assert_eq!(offset, reopened.len());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_segment_round_trips_records_and_tombstones() {
+        use crate::store::compress::ZstdCompressor;
+        use crate::store::record::{Record, TOMBSTONE_MARKER};
+
+        let dir = std::path::Path::new("tests_data/segment_record_round_trip");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let records = vec![
+            Record::set("alpha", "first value"),
+            Record::tombstone("alpha"),
+            Record::set("beta", vec![0u8; 512]),
+            Record::tombstone("gamma"),
+        ];
+        let mut segment = Segment::open(dir, 1)
+            .unwrap()
+            .with_compressor(Arc::new(ZstdCompressor::default()));
+        let mut offsets = Vec::new();
+        for record in &records {
+            let offset = match &record.value {
+                Some(value) => segment.append(&record.key, value).unwrap(),
+                None => segment.append_tombstone(&record.key).unwrap(),
+            };
+            offsets.push(offset);
+        }
+
+        // Records written by `Segment` parse with `Record::read_from`, and vice versa.
+        let raw = std::fs::read(&segment.path).unwrap();
+        let mut reader = &raw[..];
+        for (record, offset) in records.iter().zip(&offsets) {
+            let (read, header) =
+                Record::read_from(&mut reader, &ZstdCompressor::default(), 1, *offset)
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(&read, record);
+            assert_eq!(header.flags & TOMBSTONE_MARKER != 0, record.is_tombstone());
+        }
+        for (record, offset) in records.iter().zip(&offsets) {
+            let (key, value, _) = segment.read_record_at(*offset).unwrap().unwrap();
+            assert_eq!(key.as_bytes(), &record.key[..]);
+            assert_eq!(value, record.value);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_preallocated_segment_reads_back() {
+        let dir = std::path::Path::new("tests_data/segment_preallocate");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        segment.preallocate(1024 * 1024).unwrap();
+        assert_eq!(std::fs::metadata(&segment.path).unwrap().len(), 0);
+
+        let first = segment.append(b"key", b"value").unwrap();
+        let second = segment.append(b"other", b"data").unwrap();
+        assert_eq!(
+            segment.read_value_at(first).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            segment.read_value_at(second).unwrap(),
+            Some(b"data".to_vec())
+        );
+        assert_eq!(
+            std::fs::metadata(&segment.path).unwrap().len(),
+            segment.len()
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_close_truncates_preallocated_space() {
+        let dir = std::path::Path::new("tests_data/segment_close");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut segment = Segment::open(dir, 1).unwrap();
+        segment.preallocate(1024 * 1024).unwrap();
+        let value = [b'v'; 100];
+        segment.append(b"key", &value).unwrap();
+        assert!(!segment.is_sealed());
+        segment.close().unwrap();
+
+        let records = Segment::record_size(3, 100);
+        assert!(segment.is_sealed());
+        assert_eq!(segment.len(), records);
+        assert_eq!(
+            std::fs::metadata(&segment.path).unwrap().len(),
+            records + FOOTER_SIZE as u64
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // 512-byte blocks; far below the 1 MB that was reserved.
+            let blocks = std::fs::metadata(&segment.path).unwrap().blocks();
+            assert!(blocks * 512 < 1024 * 1024, "{} blocks still held", blocks);
+        }
+
+        let mut reopened = Segment::open(dir, 1).unwrap();
+        assert!(reopened.is_sealed());
+        assert_eq!(reopened.verify().unwrap(), Verification::Footer);
+        assert_eq!(reopened.read_value_at(0).unwrap(), Some(value.to_vec()));
+
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
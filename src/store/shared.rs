@@ -0,0 +1,69 @@
+//! Async handle that lets reads of a KVStore run concurrently.
+
+use crate::store::error::{Result, StoreError};
+use crate::store::KVStore;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Cheaply clonable async handle to a `KVStore` behind a read-write lock.
+///
+/// Unlike [`AsyncKVStore`](crate::AsyncKVStore), whose mutex serializes
+/// everything, any number of `get`s proceed in parallel; they read the
+/// in-memory map and never touch the disk. Writes take the lock exclusively
+/// and run on tokio's blocking pool.
+#[derive(Clone)]
+pub struct SharedKVStore {
+    inner: Arc<RwLock<KVStore>>,
+}
+
+impl SharedKVStore {
+    /// Opens the store at `dir` (blocking) and wraps it.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Ok(Self::new(KVStore::open(dir)?))
+    }
+
+    /// Wraps an already-open store.
+    pub fn new(store: KVStore) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.read().await.get(key)
+    }
+
+    pub async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        let (key, value) = (key.to_string(), value.to_vec());
+        self.write(move |store| store.set(&key, &value)).await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.write(move |store| store.delete(&key)).await
+    }
+
+    /// Compacts if there is anything to reclaim. Whether there is gets
+    /// decided under the read lock, so reads carry on meanwhile; only the
+    /// rewrite itself shuts them out.
+    pub async fn compact(&self) -> Result<()> {
+        let estimate = self.inner.read().await.compaction_estimate();
+        if estimate.dead_bytes == 0 {
+            return Ok(());
+        }
+        self.write(|store| store.compact().map(|_| ())).await
+    }
+
+    /// Runs `f` with the write lock held, on the blocking pool.
+    async fn write<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut KVStore) -> Result<T> + Send + 'static,
+    {
+        let mut store = Arc::clone(&self.inner).write_owned().await;
+        tokio::task::spawn_blocking(move || f(&mut store))
+            .await
+            .map_err(|e| StoreError::Io(std::io::Error::other(e)))?
+    }
+}
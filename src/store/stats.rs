@@ -1,12 +1,35 @@
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct StoreStats {
     pub num_keys: usize,
     pub num_segments: usize,
+    /// Logical bytes of live values.
     pub total_bytes: u64,
+    /// Sum of the sizes of the segment files on disk.
+    pub on_disk_bytes: u64,
+    /// Bytes written to segments, compaction included, per live byte.
+    pub write_amplification: f64,
+    /// `on_disk_bytes` per live byte.
+    pub space_amplification: f64,
+    /// Tombstone records still held by the segments.
+    pub tombstone_count: usize,
     pub active_segment_id: usize,
     pub oldest_segment_id: usize,
+    /// LSN the next write will get; see [`crate::lsn`].
+    pub active_lsn: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Estimated heap bytes held by the key index.
+    pub index_memory_bytes: usize,
+    /// I/O calls retried after `Interrupted`, by every store in the process;
+    /// see [`retry_on_interrupt`](crate::store::file_utils::retry_on_interrupt).
+    pub eintr_retries: u64,
+    /// Background fsyncs that failed under `FsyncPolicy::Interval`.
+    pub failed_syncs: u64,
 }
 
 impl StoreStats {
@@ -19,6 +42,135 @@ pub fn total_mb(&self) -> f64 {
     pub fn total_kb(&self) -> f64 {
         self.total_bytes as f64 / 1024.0
     }
+
+    /// Fold `other` into `self`: counters are summed, amplification ratios
+    /// are weighted by live bytes, the active segment id and LSN become the
+    /// highest seen and the oldest segment id the lowest. Stats with no segments don't pull the
+    /// oldest id down to 0.
+    pub fn merge(&mut self, other: &StoreStats) {
+        let written = self.write_amplification * self.total_bytes as f64
+            + other.write_amplification * other.total_bytes as f64;
+        if other.num_segments > 0 {
+            self.oldest_segment_id = if self.num_segments == 0 {
+                other.oldest_segment_id
+            } else {
+                self.oldest_segment_id.min(other.oldest_segment_id)
+            };
+        }
+        self.active_segment_id = self.active_segment_id.max(other.active_segment_id);
+        self.active_lsn = self.active_lsn.max(other.active_lsn);
+        self.num_keys += other.num_keys;
+        self.num_segments += other.num_segments;
+        self.total_bytes += other.total_bytes;
+        self.on_disk_bytes += other.on_disk_bytes;
+        self.tombstone_count += other.tombstone_count;
+        if self.total_bytes > 0 {
+            self.write_amplification = written / self.total_bytes as f64;
+            self.space_amplification = self.on_disk_bytes as f64 / self.total_bytes as f64;
+        }
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.index_memory_bytes += other.index_memory_bytes;
+        // Process-wide already; summing would count retries twice.
+        self.eintr_retries = self.eintr_retries.max(other.eintr_retries);
+        self.failed_syncs += other.failed_syncs;
+    }
+
+    /// Merge every stats in `iter` into one cluster-wide view.
+    pub fn sum<'a, I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = &'a StoreStats>,
+    {
+        iter.into_iter().fold(Self::new(), |mut acc, stats| {
+            acc.merge(stats);
+            acc
+        })
+    }
+}
+
+/// Progress of replaying the segments on open, reported after each segment
+/// to the callback given to [`KVStore::open_with_progress`].
+///
+/// [`KVStore::open_with_progress`]: crate::KVStore::open_with_progress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// 1-based position of the segment just replayed.
+    pub segment: usize,
+    pub total_segments: usize,
+    /// Records replayed so far, tombstones included.
+    pub records_replayed: usize,
+    /// Segment bytes read so far.
+    pub bytes_read: u64,
+}
+
+/// Call counts and cumulative latency of `get`, `set` and `delete`,
+/// collected when `StoreConfig::collect_timings` is on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpStats {
+    pub get_count: u64,
+    pub get_nanos: u64,
+    pub set_count: u64,
+    pub set_nanos: u64,
+    pub delete_count: u64,
+    pub delete_nanos: u64,
+}
+
+impl OpStats {
+    /// Mean `get` latency; zero before the first call.
+    pub fn avg_get_latency(&self) -> Duration {
+        average(self.get_nanos, self.get_count)
+    }
+
+    /// Mean `set` latency; zero before the first call.
+    pub fn avg_set_latency(&self) -> Duration {
+        average(self.set_nanos, self.set_count)
+    }
+
+    /// Mean `delete` latency; zero before the first call.
+    pub fn avg_delete_latency(&self) -> Duration {
+        average(self.delete_nanos, self.delete_count)
+    }
+}
+
+fn average(nanos: u64, count: u64) -> Duration {
+    Duration::from_nanos(nanos.checked_div(count).unwrap_or(0))
+}
+
+/// An operation timed by [`OpTimings`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    Get = 0,
+    Set = 1,
+    Delete = 2,
+}
+
+/// Shared accumulator behind [`OpStats`]. Atomic so `get`, which only has
+/// `&self`, can record into it.
+#[derive(Debug, Default)]
+pub(crate) struct OpTimings {
+    counts: [AtomicU64; 3],
+    nanos: [AtomicU64; 3],
+}
+
+impl OpTimings {
+    pub(crate) fn record(&self, op: Op, started: Instant) {
+        let elapsed = started.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+        self.counts[op as usize].fetch_add(1, Ordering::Relaxed);
+        self.nanos[op as usize].fetch_add(elapsed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> OpStats {
+        let load =
+            |counters: &[AtomicU64; 3], op: Op| counters[op as usize].load(Ordering::Relaxed);
+        OpStats {
+            get_count: load(&self.counts, Op::Get),
+            get_nanos: load(&self.nanos, Op::Get),
+            set_count: load(&self.counts, Op::Set),
+            set_nanos: load(&self.nanos, Op::Set),
+            delete_count: load(&self.counts, Op::Delete),
+            delete_nanos: load(&self.nanos, Op::Delete),
+        }
+    }
 }
 
 impl fmt::Display for StoreStats {
@@ -27,7 +179,91 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "  Keys: {}", self.num_keys)?;
         writeln!(f, "  Segments: {}", self.num_segments)?;
         writeln!(f, "  Total size: {:.2} MB", self.total_mb())?;
+        writeln!(f, "  On disk: {} bytes", self.on_disk_bytes)?;
+        writeln!(f, "  Write amplification: {:.2}", self.write_amplification)?;
+        writeln!(f, "  Space amplification: {:.2}", self.space_amplification)?;
+        writeln!(f, "  Tombstones: {}", self.tombstone_count)?;
+        writeln!(f, "  Index memory: {} bytes", self.index_memory_bytes)?;
         writeln!(f, "  Active segment: {}", self.active_segment_id)?;
-        write!(f, "  Oldest segment: {}", self.oldest_segment_id)
+        writeln!(f, "  Oldest segment: {}", self.oldest_segment_id)?;
+        write!(f, "  Active LSN: {}", self.active_lsn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum_merges_counters_and_segment_ids() {
+        let stats = [
+            StoreStats {
+                num_keys: 10,
+                num_segments: 2,
+                total_bytes: 1_000,
+                on_disk_bytes: 3_000,
+                write_amplification: 4.0,
+                space_amplification: 3.0,
+                tombstone_count: 1,
+                active_segment_id: 4,
+                oldest_segment_id: 3,
+                active_lsn: 40,
+                cache_hits: 5,
+                cache_misses: 1,
+                index_memory_bytes: 100,
+                eintr_retries: 0,
+                failed_syncs: 0,
+            },
+            StoreStats {
+                num_keys: 20,
+                num_segments: 3,
+                total_bytes: 2_000,
+                on_disk_bytes: 2_000,
+                write_amplification: 1.0,
+                space_amplification: 1.0,
+                tombstone_count: 0,
+                active_segment_id: 9,
+                oldest_segment_id: 7,
+                active_lsn: 90,
+                cache_hits: 0,
+                cache_misses: 2,
+                index_memory_bytes: 200,
+                eintr_retries: 0,
+                failed_syncs: 0,
+            },
+            StoreStats {
+                num_keys: 5,
+                num_segments: 1,
+                total_bytes: 500,
+                on_disk_bytes: 500,
+                write_amplification: 2.0,
+                space_amplification: 1.0,
+                tombstone_count: 2,
+                active_segment_id: 2,
+                oldest_segment_id: 2,
+                active_lsn: 20,
+                cache_hits: 1,
+                cache_misses: 0,
+                index_memory_bytes: 50,
+                eintr_retries: 0,
+                failed_syncs: 0,
+            },
+        ];
+
+        let total = StoreStats::sum(&stats);
+        assert_eq!(total.num_keys, 35);
+        assert_eq!(total.num_segments, 6);
+        assert_eq!(total.total_bytes, 3_500);
+        assert_eq!(total.on_disk_bytes, 5_500);
+        assert_eq!(total.tombstone_count, 3);
+        // (4_000 + 2_000 + 1_000) bytes written over 3_500 live bytes.
+        assert_eq!(total.write_amplification, 2.0);
+        assert_eq!(total.space_amplification, 5_500.0 / 3_500.0);
+        assert_eq!(total.active_segment_id, 9);
+        assert_eq!(total.oldest_segment_id, 2);
+        assert_eq!(total.active_lsn, 90);
+        assert_eq!(total.cache_hits, 6);
+        assert_eq!(total.cache_misses, 3);
+        assert_eq!(total.index_memory_bytes, 350);
     }
 }
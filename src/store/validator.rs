@@ -0,0 +1,31 @@
+//! Key validation and normalization hooks.
+
+use crate::store::error::{Result, StoreError};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Checks, and optionally rewrites, keys before they reach the store.
+///
+/// Returning `Cow::Owned` normalizes the key; returning an error rejects it.
+pub trait KeyValidator: Send + Sync + fmt::Debug {
+    fn validate<'a>(&self, key: &'a str) -> Result<Cow<'a, str>>;
+}
+
+/// Rejects empty keys and keys containing a newline.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultKeyValidator;
+
+impl KeyValidator for DefaultKeyValidator {
+    fn validate<'a>(&self, key: &'a str) -> Result<Cow<'a, str>> {
+        if key.is_empty() {
+            return Err(StoreError::InvalidKey("key must not be empty".to_string()));
+        }
+        if key.contains('\n') {
+            return Err(StoreError::InvalidKey(format!(
+                "key {:?} must not contain a newline",
+                key
+            )));
+        }
+        Ok(Cow::Borrowed(key))
+    }
+}
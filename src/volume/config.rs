@@ -1,12 +1,88 @@
 // src/volume/config.rs
 
+use crate::store::config::StoreConfig;
+use crate::volume::storage::HashAlgo;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::io;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// `volume_id` of a config file that doesn't set one.
+const DEFAULT_VOLUME_ID: &str = "vol-1";
+
+/// `config.example.toml`: every setting, at its default.
+const EXAMPLE_TOML: &str = include_str!("../../config.example.toml");
+
+/// Why a volume config file couldn't be loaded.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid volume config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid {field}: {message}")]
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+}
 
 #[derive(Clone)]
 pub struct VolumeConfig {
     pub volume_id: String,
     pub data_dir: String,
     pub bind_addr: SocketAddr,
+    /// Number of versions kept per blob, including the current one (1 = no versioning).
+    pub max_versions: usize,
+    /// Live bytes above which writes succeed but are flagged with a warning.
+    pub soft_limit_bytes: Option<u64>,
+    /// Live bytes above which writes are rejected.
+    pub hard_limit_bytes: Option<u64>,
+    /// Hash used for blob etags.
+    pub hash_algo: HashAlgo,
+    /// Secondary to connect to on startup and stream every write to.
+    pub replication_target: Option<SocketAddr>,
+    /// Address to accept a primary's replication connection on.
+    pub replication_listen_addr: Option<SocketAddr>,
+    /// Coordinator to send heartbeats to, e.g. `http://127.0.0.1:9000`.
+    pub coordinator_url: Option<String>,
+    /// URL the coordinator hands out for this volume. Defaults to
+    /// `http://{bind_addr}`.
+    pub public_url: Option<String>,
+    /// Time between heartbeats.
+    pub heartbeat_interval: Duration,
+    /// Base URLs of volumes holding copies of this one's blobs, asked for a
+    /// good copy when a read finds a local value damaged.
+    pub repair_urls: Vec<String>,
+    /// Largest request body to accept, in bytes. Read from the config file
+    /// but not enforced by the router yet.
+    pub max_blob_size: usize,
+    /// Longest blob key accepted, in bytes. `None` for no limit.
+    pub max_key_length: Option<usize>,
+    /// Bearer token shared by the cluster's volumes. Sent with repair
+    /// fetches; incoming requests aren't checked against it yet.
+    pub auth_token: Option<String>,
+    /// Requests per second to serve, across all clients. Not enforced yet.
+    pub rate_limit_rps: Option<u32>,
+    /// Origins allowed to call the API from a browser; `"*"` allows any.
+    /// Not enforced yet.
+    pub cors_allowed_origins: Vec<String>,
+    /// Time a request may take. Not enforced yet.
+    pub request_timeout: Option<Duration>,
+    /// Most verbose `log` level the volume binary emits.
+    pub log_level: LevelFilter,
+    /// Compact the store once when the server starts.
+    pub auto_compact_on_startup: bool,
+    /// Settings for the underlying store. Its `data_path` is ignored in
+    /// favour of `data_dir`.
+    pub store: StoreConfig,
 }
 
 impl VolumeConfig {
@@ -15,9 +91,53 @@ pub fn new(volume_id: impl Into<String>) -> Self {
             volume_id: volume_id.into(),
             data_dir: "data".to_string(),
             bind_addr: SocketAddr::from(([127, 0, 0, 1], 9002)),
+            max_versions: 1,
+            soft_limit_bytes: None,
+            hard_limit_bytes: None,
+            hash_algo: HashAlgo::default(),
+            replication_target: None,
+            replication_listen_addr: None,
+            coordinator_url: None,
+            public_url: None,
+            heartbeat_interval: Duration::from_secs(10),
+            repair_urls: Vec::new(),
+            // axum's own default body limit.
+            max_blob_size: 2 * 1024 * 1024,
+            max_key_length: None,
+            auth_token: None,
+            rate_limit_rps: None,
+            cors_allowed_origins: Vec::new(),
+            request_timeout: None,
+            log_level: LevelFilter::Info,
+            auto_compact_on_startup: false,
+            store: StoreConfig::default(),
         }
     }
 
+    /// Reads a TOML config file; see [`default_toml_str`](Self::default_toml_str)
+    /// for the schema. Settings it leaves out keep their defaults, and
+    /// unknown keys are rejected.
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml_str(&toml)
+    }
+
+    /// Parses a TOML config; see [`from_toml_file`](Self::from_toml_file).
+    pub fn from_toml_str(toml: &str) -> Result<Self, ConfigError> {
+        let file: VolumeConfigFile = toml::from_str(toml)?;
+        file.into_config()
+    }
+
+    /// The documented example config, `config.example.toml`, which lists
+    /// every setting at its default.
+    pub fn default_toml_str() -> &'static str {
+        EXAMPLE_TOML
+    }
+
     pub fn with_data_dir(mut self, dir: impl Into<String>) -> Self {
         self.data_dir = dir.into();
         self
@@ -27,4 +147,350 @@ pub fn with_bind_addr(mut self, addr: SocketAddr) -> Self {
         self.bind_addr = addr;
         self
     }
+
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = max_versions.max(1);
+        self
+    }
+
+    pub fn with_quota(
+        mut self,
+        soft_limit_bytes: Option<u64>,
+        hard_limit_bytes: Option<u64>,
+    ) -> Self {
+        self.soft_limit_bytes = soft_limit_bytes;
+        self.hard_limit_bytes = hard_limit_bytes;
+        self
+    }
+
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        self
+    }
+
+    pub fn with_store_config(mut self, store: StoreConfig) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn with_replication_target(mut self, addr: SocketAddr) -> Self {
+        self.replication_target = Some(addr);
+        self
+    }
+
+    pub fn with_replication_listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.replication_listen_addr = Some(addr);
+        self
+    }
+
+    pub fn with_coordinator_url(mut self, url: impl Into<String>) -> Self {
+        self.coordinator_url = Some(url.into());
+        self
+    }
+
+    pub fn with_public_url(mut self, url: impl Into<String>) -> Self {
+        self.public_url = Some(url.into());
+        self
+    }
+
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    pub fn with_repair_urls(mut self, urls: Vec<String>) -> Self {
+        self.repair_urls = urls;
+        self
+    }
+
+    pub fn with_max_blob_size(mut self, bytes: usize) -> Self {
+        self.max_blob_size = bytes;
+        self
+    }
+
+    pub fn with_max_key_length(mut self, bytes: usize) -> Self {
+        self.max_key_length = Some(bytes);
+        self
+    }
+
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    pub fn with_rate_limit(mut self, requests_per_sec: u32) -> Self {
+        self.rate_limit_rps = Some(requests_per_sec.max(1));
+        self
+    }
+
+    pub fn with_cors_allowed_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_allowed_origins = origins;
+        self
+    }
+
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_log_level(mut self, level: LevelFilter) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    pub fn with_auto_compact_on_startup(mut self, enabled: bool) -> Self {
+        self.auto_compact_on_startup = enabled;
+        self
+    }
+
+    /// URL the volume advertises to the coordinator.
+    pub fn advertised_url(&self) -> String {
+        self.public_url
+            .clone()
+            .unwrap_or_else(|| format!("http://{}", self.bind_addr))
+    }
+}
+
+/// The TOML form of [`VolumeConfig`]: plain values, checked and converted
+/// by [`into_config`](Self::into_config).
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct VolumeConfigFile {
+    volume_id: String,
+    data_dir: String,
+    bind_addr: SocketAddr,
+    max_blob_size: usize,
+    max_key_length: Option<usize>,
+    auth_token: Option<String>,
+    rate_limit_rps: Option<u32>,
+    cors_allowed_origins: Vec<String>,
+    request_timeout_ms: Option<u64>,
+    log_level: String,
+    coordinator_url: Option<String>,
+    auto_compact_on_startup: bool,
+    replication_target: Option<SocketAddr>,
+    replication_listen_addr: Option<SocketAddr>,
+    public_url: Option<String>,
+    heartbeat_interval_ms: u64,
+    repair_urls: Vec<String>,
+    max_versions: usize,
+    soft_limit_bytes: Option<u64>,
+    hard_limit_bytes: Option<u64>,
+    hash_algo: HashAlgo,
+}
+
+impl Default for VolumeConfigFile {
+    fn default() -> Self {
+        let config = VolumeConfig::new(DEFAULT_VOLUME_ID);
+        Self {
+            volume_id: config.volume_id,
+            data_dir: config.data_dir,
+            bind_addr: config.bind_addr,
+            max_blob_size: config.max_blob_size,
+            max_key_length: config.max_key_length,
+            auth_token: config.auth_token,
+            rate_limit_rps: config.rate_limit_rps,
+            cors_allowed_origins: config.cors_allowed_origins,
+            request_timeout_ms: config.request_timeout.map(|t| t.as_millis() as u64),
+            log_level: config.log_level.to_string().to_lowercase(),
+            coordinator_url: config.coordinator_url,
+            auto_compact_on_startup: config.auto_compact_on_startup,
+            replication_target: config.replication_target,
+            replication_listen_addr: config.replication_listen_addr,
+            public_url: config.public_url,
+            heartbeat_interval_ms: config.heartbeat_interval.as_millis() as u64,
+            repair_urls: config.repair_urls,
+            max_versions: config.max_versions,
+            soft_limit_bytes: config.soft_limit_bytes,
+            hard_limit_bytes: config.hard_limit_bytes,
+            hash_algo: config.hash_algo,
+        }
+    }
+}
+
+impl VolumeConfigFile {
+    fn into_config(self) -> Result<VolumeConfig, ConfigError> {
+        let invalid = |field, message: &str| ConfigError::Invalid {
+            field,
+            message: message.to_string(),
+        };
+        if self.volume_id.is_empty() {
+            return Err(invalid("volume_id", "must not be empty"));
+        }
+        if self.max_blob_size == 0 {
+            return Err(invalid("max_blob_size", "must be at least 1"));
+        }
+        if self.max_key_length == Some(0) {
+            return Err(invalid("max_key_length", "must be at least 1"));
+        }
+        if self.rate_limit_rps == Some(0) {
+            return Err(invalid("rate_limit_rps", "must be at least 1"));
+        }
+        if self.request_timeout_ms == Some(0) {
+            return Err(invalid("request_timeout_ms", "must be at least 1"));
+        }
+        if self.heartbeat_interval_ms == 0 {
+            return Err(invalid("heartbeat_interval_ms", "must be at least 1"));
+        }
+        let log_level = self.log_level.parse().map_err(|_| {
+            invalid(
+                "log_level",
+                &format!(
+                    "{:?} is not one of off, error, warn, info, debug or trace",
+                    self.log_level
+                ),
+            )
+        })?;
+        Ok(VolumeConfig {
+            volume_id: self.volume_id,
+            data_dir: self.data_dir,
+            bind_addr: self.bind_addr,
+            max_versions: self.max_versions.max(1),
+            soft_limit_bytes: self.soft_limit_bytes,
+            hard_limit_bytes: self.hard_limit_bytes,
+            hash_algo: self.hash_algo,
+            replication_target: self.replication_target,
+            replication_listen_addr: self.replication_listen_addr,
+            coordinator_url: self.coordinator_url,
+            public_url: self.public_url,
+            heartbeat_interval: Duration::from_millis(self.heartbeat_interval_ms),
+            repair_urls: self.repair_urls,
+            max_blob_size: self.max_blob_size,
+            max_key_length: self.max_key_length,
+            auth_token: self.auth_token,
+            rate_limit_rps: self.rate_limit_rps,
+            cors_allowed_origins: self.cors_allowed_origins,
+            request_timeout: self.request_timeout_ms.map(Duration::from_millis),
+            log_level,
+            auto_compact_on_startup: self.auto_compact_on_startup,
+            store: StoreConfig::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_toml_holds_the_defaults() {
+        let config = VolumeConfig::from_toml_str(VolumeConfig::default_toml_str()).unwrap();
+        assert_eq!(config.volume_id, "vol-1");
+        assert_eq!(config.data_dir, "data");
+        assert_eq!(config.bind_addr, SocketAddr::from(([127, 0, 0, 1], 9002)));
+        assert_eq!(config.max_blob_size, 2 * 1024 * 1024);
+        assert_eq!(config.max_key_length, None);
+        assert_eq!(config.auth_token, None);
+        assert_eq!(config.rate_limit_rps, None);
+        assert!(config.cors_allowed_origins.is_empty());
+        assert_eq!(config.request_timeout, None);
+        assert_eq!(config.log_level, LevelFilter::Info);
+        assert_eq!(config.coordinator_url, None);
+        assert!(!config.auto_compact_on_startup);
+        assert_eq!(config.replication_target, None);
+        assert_eq!(config.replication_listen_addr, None);
+        assert_eq!(config.public_url, None);
+        assert_eq!(config.heartbeat_interval, Duration::from_secs(10));
+        assert!(config.repair_urls.is_empty());
+        assert_eq!(config.max_versions, 1);
+        assert_eq!(config.soft_limit_bytes, None);
+        assert_eq!(config.hard_limit_bytes, None);
+        assert_eq!(config.hash_algo, HashAlgo::Crc32);
+
+        // An empty file means the same thing.
+        let empty = VolumeConfig::from_toml_str("").unwrap();
+        assert_eq!(empty.volume_id, config.volume_id);
+        assert_eq!(empty.bind_addr, config.bind_addr);
+        assert_eq!(empty.max_blob_size, config.max_blob_size);
+
+        // Optional settings are documented, commented out.
+        for key in [
+            "max_key_length",
+            "auth_token",
+            "rate_limit_rps",
+            "request_timeout_ms",
+            "coordinator_url",
+            "replication_target",
+        ] {
+            let line = format!("# {} = ", key);
+            assert!(EXAMPLE_TOML.contains(&line), "{} is not documented", key);
+        }
+    }
+
+    #[test]
+    fn test_from_toml_file_reads_settings_and_rejects_bad_ones() {
+        let dir = Path::new("tests_data/volume_config_toml");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("volume.toml");
+        std::fs::write(
+            &path,
+            r#"
+volume_id = "vol-7"
+bind_addr = "0.0.0.0:9100"
+max_key_length = 256
+auth_token = "s3cret"
+rate_limit_rps = 50
+cors_allowed_origins = ["https://app.example.com"]
+request_timeout_ms = 1500
+log_level = "debug"
+coordinator_url = "http://127.0.0.1:9000"
+auto_compact_on_startup = true
+replication_target = "127.0.0.1:9200"
+hash_algo = "sha256"
+"#,
+        )
+        .unwrap();
+
+        let config = VolumeConfig::from_toml_file(&path).unwrap();
+        assert_eq!(config.volume_id, "vol-7");
+        assert_eq!(config.bind_addr, SocketAddr::from(([0, 0, 0, 0], 9100)));
+        assert_eq!(config.max_key_length, Some(256));
+        assert_eq!(config.auth_token.as_deref(), Some("s3cret"));
+        assert_eq!(config.rate_limit_rps, Some(50));
+        assert_eq!(config.cors_allowed_origins, ["https://app.example.com"]);
+        assert_eq!(config.request_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(
+            config.coordinator_url.as_deref(),
+            Some("http://127.0.0.1:9000")
+        );
+        assert!(config.auto_compact_on_startup);
+        assert_eq!(
+            config.replication_target,
+            Some(SocketAddr::from(([127, 0, 0, 1], 9200)))
+        );
+        assert_eq!(config.hash_algo, HashAlgo::Sha256);
+        // Left out, so still the default.
+        assert_eq!(config.data_dir, "data");
+
+        assert!(matches!(
+            VolumeConfig::from_toml_file(dir.join("missing.toml")),
+            Err(ConfigError::Io { .. })
+        ));
+        assert!(matches!(
+            VolumeConfig::from_toml_str("bind_adr = \"127.0.0.1:1\""),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            VolumeConfig::from_toml_str("bind_addr = \"localhost\""),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            VolumeConfig::from_toml_str("log_level = \"loud\""),
+            Err(ConfigError::Invalid {
+                field: "log_level",
+                ..
+            })
+        ));
+        assert!(matches!(
+            VolumeConfig::from_toml_str("rate_limit_rps = 0"),
+            Err(ConfigError::Invalid {
+                field: "rate_limit_rps",
+                ..
+            })
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
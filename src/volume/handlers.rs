@@ -1,27 +1,279 @@
 //! HTTP handlers for volume blob operations.
 
-use crate::volume::storage::BlobStorage;
+use crate::common::PaginationCursor;
+use crate::store::error::{ErrorSeverity, StoreError};
+use crate::store::replication::ReplicationReceiver;
+use crate::volume::storage::{BlobMeta, BlobStorage, BulkDeleteResult, VerifiedRead};
 use axum::{
-    body::Bytes,
-    extract::{Path, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{Path, Query, Request, State},
+    http::HeaderMap,
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
-use serde::Serialize;
-use std::sync::{Arc, Mutex};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Page size of `GET /blobs?cursor=...` when no `limit` is given.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// The connection from this volume's primary, while one is open.
+pub type Upstream = Arc<Mutex<Option<Arc<ReplicationReceiver>>>>;
 
 /// Shared application state.
 #[derive(Clone)]
 pub struct AppState {
     /// Thread-safe blob storage instance.
     pub storage: Arc<Mutex<BlobStorage>>,
+    /// Set while a primary is replicating to this volume.
+    pub upstream: Upstream,
+}
+
+/// Prefix of the request and response headers carrying blob attributes.
+/// Header names are case-insensitive, so attribute names are lowercased.
+const ATTR_HEADER_PREFIX: &str = "x-blob-attr-";
+
+/// Header carrying the id of a request, set on every response.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// A failed request, rendered as
+/// `{"error": {"code": ..., "message": ..., "request_id": ...}}`.
+///
+/// The request id is filled in by the [`assign_request_id`] middleware.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    body: ErrorBody,
+    /// Seconds for `Retry-After`, when the client should back off.
+    retry_after: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ErrorBody {
+    /// Stable, machine-readable name of the failure, e.g. `not_found`.
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorEnvelope<'a> {
+    error: &'a ErrorBody,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorBody {
+                code,
+                message: message.into(),
+                request_id: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.body.code
+    }
+}
+
+impl From<&StoreError> for StatusCode {
+    fn from(err: &StoreError) -> Self {
+        match err {
+            StoreError::KeyNotFound(_) => StatusCode::NOT_FOUND,
+            StoreError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            StoreError::RangeNotSatisfiable { .. } => StatusCode::RANGE_NOT_SATISFIABLE,
+            StoreError::StoreFull => StatusCode::INSUFFICIENT_STORAGE,
+            StoreError::WriteThrottled { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            StoreError::InvalidKey(_) => StatusCode::BAD_REQUEST,
+            StoreError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            _ if err
+                .io_error()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                StatusCode::NOT_FOUND
+            },
+            _ => match err.severity() {
+                ErrorSeverity::Transient => StatusCode::SERVICE_UNAVAILABLE,
+                ErrorSeverity::Recoverable | ErrorSeverity::Fatal => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                },
+            },
+        }
+    }
+}
+
+impl From<&StoreError> for ApiError {
+    fn from(err: &StoreError) -> Self {
+        let status = StatusCode::from(err);
+        let code = match err {
+            StoreError::ChecksumMismatch { .. } => "checksum_mismatch",
+            StoreError::RangeNotSatisfiable { .. } => "range_not_satisfiable",
+            StoreError::StoreFull => "store_full",
+            StoreError::WriteThrottled { .. } => "write_throttled",
+            StoreError::InvalidKey(_) => "invalid_key",
+            StoreError::PreconditionFailed { .. } => "precondition_failed",
+            _ if status == StatusCode::NOT_FOUND => "not_found",
+            _ if status == StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+            _ => "internal",
+        };
+        let mut api_error = Self::new(status, code, err.to_string());
+        if let StoreError::WriteThrottled { suggested_delay_ms } = err {
+            // Retry-After is whole seconds; round up so clients never retry early.
+            api_error.retry_after = Some(suggested_delay_ms.div_ceil(1000).max(1));
+        }
+        api_error
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        Self::from(&err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(ErrorEnvelope { error: &self.body })).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+        }
+        // Left for `assign_request_id`, which re-renders it with the id.
+        response.extensions_mut().insert(self.body);
+        response
+    }
+}
+
+fn error_response(err: &StoreError) -> Response {
+    ApiError::from(err).into_response()
+}
+
+/// A process-unique request id: the server's start time and a counter.
+fn next_request_id() -> String {
+    static STARTED: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let started = *STARTED.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    });
+    format!(
+        "{:x}-{:06x}",
+        started,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Tags each request with an id, reusing the client's `x-request-id` if it
+/// sent one, echoes it in the response and adds it to error bodies.
+async fn assign_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map_or_else(next_request_id, str::to_string);
+
+    let mut response = next.run(request).await;
+    if let Some(mut body) = response.extensions_mut().remove::<ErrorBody>() {
+        body.request_id = Some(request_id.clone());
+        let (mut parts, _) = response.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        let json = serde_json::to_vec(&ErrorEnvelope { error: &body }).unwrap_or_default();
+        response = Response::from_parts(parts, Body::from(json));
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[derive(Deserialize)]
+struct GetBlobParams {
+    version: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ListBlobsParams {
+    #[serde(default)]
+    detailed: bool,
+    /// Page size; with `cursor`, switches the response to a paginated list.
+    limit: Option<usize>,
+    cursor: Option<String>,
+    /// Key prefix; only supported with `count_only`.
+    prefix: Option<String>,
+    /// Return a [`PrefixSummary`](crate::volume::PrefixSummary) of the
+    /// matching blobs instead of a list.
+    #[serde(default)]
+    count_only: bool,
+}
+
+#[derive(Deserialize)]
+struct BulkDeleteRequest {
+    keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BulkDeleteResponse {
+    deleted: usize,
+    not_found: usize,
+    details: BulkDeleteResult,
+}
+
+/// One entry of a `POST /blobs/batch` body.
+#[derive(Deserialize)]
+struct BatchPutEntry {
+    key: String,
+    value_b64: String,
+}
+
+#[derive(Deserialize)]
+struct CreateUploadRequest {
+    key: String,
 }
 
 #[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
+struct CreateUploadResponse {
+    upload_id: String,
+}
+
+#[derive(Serialize)]
+struct UploadPartResponse {
+    part: u32,
+    size: usize,
+}
+
+#[derive(Deserialize)]
+struct CatchupRequest {
+    #[serde(default)]
+    since_lsn: u64,
 }
 
 #[derive(Serialize)]
@@ -31,6 +283,8 @@ struct HealthResponse {
     keys: usize,
     segments: usize,
     total_mb: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota_used_pct: Option<f64>,
 }
 
 async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
@@ -43,43 +297,293 @@ async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
         keys: stats.num_keys,
         segments: stats.num_segments,
         total_mb: stats.total_mb(),
+        quota_used_pct: storage.quota_used_pct(),
     };
 
     (StatusCode::OK, Json(response))
 }
 
-async fn put_blob(State(state): State<AppState>, Path(key): Path<String>, body: Bytes) -> Response {
+/// Blob attributes from `X-Blob-Attr-<name>` headers, keyed by the
+/// lowercased `<name>`. `None` if the request has none.
+fn attrs_from_headers(headers: &HeaderMap) -> Result<Option<HashMap<String, String>>, ApiError> {
+    let mut attrs = HashMap::new();
+    for (name, value) in headers {
+        let Some(attr) = name.as_str().strip_prefix(ATTR_HEADER_PREFIX) else {
+            continue;
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| ApiError::bad_request(format!("Header {} is not visible ASCII", name)))?;
+        attrs.insert(attr.to_string(), value.to_string());
+    }
+    Ok((!attrs.is_empty()).then_some(attrs))
+}
+
+/// `X-Blob-Attr-<name>` headers for the attributes in `meta`.
+fn attr_headers(meta: &BlobMeta) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (attr, value) in meta.attrs.iter().flatten() {
+        let name = format!("{}{}", ATTR_HEADER_PREFIX, attr);
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+async fn put_blob(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let attrs = match attrs_from_headers(&headers) {
+        Ok(attrs) => attrs,
+        Err(e) => return e.into_response(),
+    };
+    // `If-Match: "<etag>"` or `*`; the quotes are optional.
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().trim_matches('"'));
     let mut storage = state.storage.lock().unwrap();
-    match storage.put(&key, &body) {
+    let result = match (if_match, attrs) {
+        (Some(expected), attrs) => storage.put_if_match(&key, &body, expected, attrs),
+        (None, Some(attrs)) => storage.put_with_attrs(&key, &body, attrs),
+        (None, None) => storage.put(&key, &body),
+    };
+    match result {
+        Ok(meta) if storage.soft_limit_exceeded() => (
+            StatusCode::CREATED,
+            [("X-Storage-Warning", "quota_soft_exceeded")],
+            Json(meta),
+        )
+            .into_response(),
         Ok(meta) => (StatusCode::CREATED, Json(meta)).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Stores every blob in the body with one write; see
+/// [`BlobStorage::put_many`].
+async fn put_blobs_batch(
+    State(state): State<AppState>,
+    Json(entries): Json<Vec<BatchPutEntry>>,
+) -> Response {
+    let mut decoded = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match BASE64.decode(&entry.value_b64) {
+            Ok(value) => decoded.push((entry.key, value)),
+            Err(e) => {
+                return ApiError::bad_request(format!(
+                    "value_b64 of {} is not valid base64: {}",
+                    entry.key, e
+                ))
+                .into_response()
+            },
+        }
+    }
+    let mut storage = state.storage.lock().unwrap();
+    match storage.put_many(decoded) {
+        Ok(metas) => (StatusCode::CREATED, Json(metas)).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn copy_blob(
+    State(state): State<AppState>,
+    Path((dst_key, src_key)): Path<(String, String)>,
+) -> Response {
+    let mut storage = state.storage.lock().unwrap();
+    match storage.copy(&src_key, &dst_key) {
+        Ok(meta) => (StatusCode::CREATED, Json(meta)).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Parses `Content-Range: bytes <first>-<last>/<total or *>` into the
+/// zero-based offset and length of the span. The total is not checked.
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let (range, _total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last): (u64, u64) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (first <= last).then(|| (first, last - first + 1))
+}
+
+/// Replaces the byte span named by `Content-Range` with the request body.
+async fn patch_blob(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let span = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range);
+    let offset = match span {
+        Some((offset, len)) if len == body.len() as u64 => offset,
+        _ => {
+            return ApiError::bad_request(
+                "Content-Range must be `bytes first-last/total` matching the body",
+            )
+            .into_response()
+        },
+    };
+    let mut storage = state.storage.lock().unwrap();
+    match storage.patch(&key, offset, &body) {
+        Ok(meta) => (StatusCode::OK, Json(meta)).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn create_upload(
+    State(state): State<AppState>,
+    Json(request): Json<CreateUploadRequest>,
+) -> Response {
+    let mut storage = state.storage.lock().unwrap();
+    match storage.create_upload(&request.key) {
+        Ok(upload_id) => (
+            StatusCode::CREATED,
+            Json(CreateUploadResponse { upload_id }),
         )
             .into_response(),
+        Err(e) => error_response(&e),
     }
 }
 
-async fn get_blob(State(state): State<AppState>, Path(key): Path<String>) -> Response {
-    let storage = state.storage.lock().unwrap();
-    match storage.get(&key) {
-        Ok(Some(data)) => (StatusCode::OK, data).into_response(),
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "Blob not found".to_string(),
+async fn upload_part(
+    State(state): State<AppState>,
+    Path((upload_id, part)): Path<(String, u32)>,
+    body: Bytes,
+) -> Response {
+    let mut storage = state.storage.lock().unwrap();
+    match storage.upload_part(&upload_id, part, &body) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(UploadPartResponse {
+                part,
+                size: body.len(),
             }),
         )
             .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn complete_upload(State(state): State<AppState>, Path(upload_id): Path<String>) -> Response {
+    let mut storage = state.storage.lock().unwrap();
+    match storage.complete_upload(&upload_id) {
+        Ok(meta) => (StatusCode::CREATED, Json(meta)).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Asks the connected primary to resend everything after `since_lsn`.
+async fn replication_catchup(
+    State(state): State<AppState>,
+    Json(request): Json<CatchupRequest>,
+) -> Response {
+    let upstream = state.upstream.lock().unwrap().clone();
+    let Some(receiver) = upstream else {
+        return ApiError::new(
+            StatusCode::CONFLICT,
+            "no_upstream",
+            "No primary is replicating to this volume",
+        )
+        .into_response();
+    };
+    match receiver.request_catchup(request.since_lsn) {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn get_blob(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(params): Query<GetBlobParams>,
+) -> Response {
+    let result = match params.version {
+        Some(version) => state
+            .storage
+            .lock()
+            .unwrap()
+            .get_version(&key, version)
+            .and_then(|data| data.ok_or_else(|| StoreError::KeyNotFound(key.clone()))),
+        None => read_current(&state, &key).await,
+    };
+    let storage = state.storage.lock().unwrap();
+    match result {
+        Ok(data) => match storage.head(&key) {
+            Ok(Some(meta)) if params.version.is_none() => (
+                StatusCode::OK,
+                attr_headers(&meta),
+                [(
+                    header::LAST_MODIFIED,
+                    httpdate::fmt_http_date(meta.modified_at),
+                )],
+                data,
+            )
+                .into_response(),
+            _ => (StatusCode::OK, data).into_response(),
+        },
+        Err(StoreError::KeyNotFound(_)) => ApiError::not_found("Blob not found").into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// The current value of `key`. With repair sources configured, the value on
+/// disk is verified so damage is healed here rather than served. Replicas are
+/// asked on a blocking thread, without holding the storage lock.
+async fn read_current(state: &AppState, key: &str) -> Result<Vec<u8>, StoreError> {
+    let not_found = || StoreError::KeyNotFound(key.to_string());
+    let (repair, urls) = {
+        let storage = state.storage.lock().unwrap();
+        if storage.repair_urls().is_empty() {
+            return storage.get_strict(key);
+        }
+        match storage.read_verified(key)? {
+            VerifiedRead::Intact(data) => return data.ok_or_else(not_found),
+            VerifiedRead::Damaged(repair) => (repair, storage.repair_urls().to_vec()),
+        }
+    };
+    let (repair, copy) = tokio::task::spawn_blocking(move || {
+        let copy = repair.fetch(&urls);
+        (repair, copy)
+    })
+    .await
+    .map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
+    state
+        .storage
+        .lock()
+        .unwrap()
+        .finish_repair(repair, copy)?
+        .ok_or_else(not_found)
+}
+
+async fn head_blob(State(state): State<AppState>, Path(key): Path<String>) -> Response {
+    let storage = state.storage.lock().unwrap();
+    match storage.head(&key) {
+        Ok(Some(meta)) => (
+            StatusCode::OK,
+            attr_headers(&meta),
+            [
+                (header::CONTENT_LENGTH, meta.size.to_string()),
+                (header::ETAG, format!("\"{}\"", meta.etag)),
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (
+                    header::LAST_MODIFIED,
+                    httpdate::fmt_http_date(meta.modified_at),
+                ),
+            ],
         )
             .into_response(),
+        Ok(None) => ApiError::not_found("Blob not found").into_response(),
+        Err(e) => error_response(&e),
     }
 }
 
@@ -87,39 +591,103 @@ async fn delete_blob(State(state): State<AppState>, Path(key): Path<String>) ->
     let mut storage = state.storage.lock().unwrap();
     match storage.delete(&key) {
         Ok(()) => StatusCode::NO_CONTENT.into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn bulk_delete_blobs(
+    State(state): State<AppState>,
+    Json(request): Json<BulkDeleteRequest>,
+) -> Response {
+    let mut storage = state.storage.lock().unwrap();
+    match storage.delete_many(&request.keys) {
+        Ok(details) => (
+            StatusCode::OK,
+            Json(BulkDeleteResponse {
+                deleted: details.deleted.len(),
+                not_found: details.not_found.len(),
+                details,
             }),
         )
             .into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn list_blobs(
+    State(state): State<AppState>,
+    Query(params): Query<ListBlobsParams>,
+) -> Response {
+    let storage = state.storage.lock().unwrap();
+    if params.count_only {
+        let prefix = params.prefix.as_deref().unwrap_or("");
+        return Json(storage.prefix_summary(prefix)).into_response();
+    }
+    if params.prefix.is_some() {
+        return ApiError::bad_request("prefix is only supported with count_only=true")
+            .into_response();
+    }
+    if params.limit.is_some() || params.cursor.is_some() {
+        let cursor = match params.cursor.as_deref().map(PaginationCursor::from_str) {
+            Some(Err(e)) => return ApiError::bad_request(e).into_response(),
+            Some(Ok(cursor)) => Some(cursor),
+            None => None,
+        };
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        return if params.detailed {
+            Json(storage.list_meta_paginated(cursor.as_ref(), limit)).into_response()
+        } else {
+            Json(storage.list_keys_paginated(cursor.as_ref(), limit)).into_response()
+        };
+    }
+    if params.detailed {
+        (StatusCode::OK, Json(storage.list_meta())).into_response()
+    } else {
+        (StatusCode::OK, Json(storage.list_keys())).into_response()
     }
 }
 
-async fn list_blobs(State(state): State<AppState>) -> impl IntoResponse {
+async fn list_versions(State(state): State<AppState>, Path(key): Path<String>) -> Response {
     let storage = state.storage.lock().unwrap();
-    let keys = storage.list_keys();
-    (StatusCode::OK, Json(keys))
+    let versions = storage.list_versions(&key);
+    if versions.is_empty() {
+        return ApiError::not_found("Blob not found").into_response();
+    }
+    (StatusCode::OK, Json(versions)).into_response()
 }
 
 /// Creates the HTTP router with all blob endpoints.
 pub fn create_router(storage: Arc<Mutex<BlobStorage>>) -> Router {
-    let state = AppState { storage };
+    create_router_with_upstream(storage, Upstream::default())
+}
+
+/// Like [`create_router`], for a volume that may receive replication.
+pub fn create_router_with_upstream(storage: Arc<Mutex<BlobStorage>>, upstream: Upstream) -> Router {
+    let state = AppState { storage, upstream };
 
     Router::new()
         .route("/", get(health_check))
         .route("/health", get(health_check))
-        .route("/blobs", get(list_blobs))
+        .route("/blobs", get(list_blobs).delete(bulk_delete_blobs))
+        .route("/blobs/batch", post(put_blobs_batch))
         .route("/blobs/:key", post(put_blob))
-        .route("/blobs/:key", get(get_blob))
+        .route("/blobs/:key", get(get_blob).head(head_blob))
         .route("/blobs/:key", delete(delete_blob))
+        .route("/blobs/:key", patch(patch_blob))
+        .route("/blobs/:key/versions", get(list_versions))
+        .route("/blobs/:key/copy-from/:src_key", post(copy_blob))
+        .route("/uploads", post(create_upload))
+        .route("/uploads/:id/parts/:n", put(upload_part))
+        .route("/uploads/:id/complete", post(complete_upload))
+        .route("/replication/catchup", post(replication_catchup))
+        .layer(middleware::from_fn(assign_request_id))
         .with_state(state)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::PaginatedList;
     use axum::body::Body;
     use axum::http::{Request, StatusCode as HttpStatus};
     use std::sync::{Arc, Mutex};
@@ -195,12 +763,59 @@ async fn test_put_and_get_blob() {
         let _ = std::fs::remove_dir_all("tests_data/handler_put_get");
     }
 
+    #[tokio::test]
+    async fn test_head_blob_returns_metadata_only() {
+        let storage = setup_test_storage("tests_data/handler_head");
+        let etag = {
+            let mut s = storage.lock().unwrap();
+            s.put("meta-key", b"hello world").unwrap().etag
+        };
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("HEAD")
+                    .uri("/blobs/meta-key")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), HttpStatus::OK);
+        let headers = response.headers().clone();
+        assert_eq!(headers[header::CONTENT_LENGTH], "11");
+        assert_eq!(headers[header::ETAG], format!("\"{}\"", etag));
+        assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 0);
+
+        let app = create_router(storage);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("HEAD")
+                    .uri("/blobs/missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_head");
+    }
+
     #[tokio::test]
     async fn test_get_not_found() {
         let storage = setup_test_storage("tests_data/handler_not_found");
         let app = create_router(storage);
 
         let response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .uri("/blobs/nonexistent")
@@ -211,6 +826,33 @@ async fn test_get_not_found() {
             .unwrap();
 
         assert_eq!(response.status(), HttpStatus::NOT_FOUND);
+        let request_id = response.headers()[REQUEST_ID_HEADER].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#""code":"not_found""#), "{}", body);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["error"]["message"], "Blob not found");
+        assert_eq!(error["error"]["request_id"], request_id.to_str().unwrap());
+
+        // A client-supplied id is kept.
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/nonexistent/versions")
+                    .header(REQUEST_ID_HEADER, "trace-42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "trace-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["request_id"], "trace-42");
 
         let _ = std::fs::remove_dir_all("tests_data/handler_not_found");
     }
@@ -256,4 +898,840 @@ async fn test_delete_blob() {
 
         let _ = std::fs::remove_dir_all("tests_data/handler_delete");
     }
+
+    #[tokio::test]
+    async fn test_blob_attrs_round_trip_through_headers() {
+        let storage = setup_test_storage("tests_data/handler_attrs");
+        let app = create_router(storage.clone());
+        let attrs = [
+            ("content-type", "image/png"),
+            ("author", "alice"),
+            ("camera", "X100V"),
+            ("license", "CC-BY-4.0"),
+            ("caption", "sunset over the bay"),
+        ];
+
+        let mut request = Request::builder().method("POST").uri("/blobs/photo");
+        for (name, value) in attrs {
+            request = request.header(format!("X-Blob-Attr-{}", name), value);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from("png bytes")).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/photo")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+        for (name, value) in attrs {
+            let header = format!("x-blob-attr-{}", name);
+            assert_eq!(response.headers()[header.as_str()], value);
+        }
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"png bytes");
+
+        // A plain re-PUT keeps the attributes; internal keys stay hidden.
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/blobs/photo")
+                    .body(Body::from("new bytes"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+        let s = storage.lock().unwrap();
+        let stored = s.get_attrs("photo").unwrap().unwrap();
+        assert_eq!(stored.len(), 5);
+        assert_eq!(stored["author"], "alice");
+        assert_eq!(s.list_keys(), ["photo"]);
+        drop(s);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_attrs");
+    }
+
+    #[tokio::test]
+    async fn test_list_blobs_count_only_by_prefix() {
+        let storage = setup_test_storage("tests_data/handler_count_only");
+        {
+            let mut s = storage.lock().unwrap();
+            for i in 0..5 {
+                s.put(&format!("photos/{}", i), &[0u8; 1000]).unwrap();
+            }
+            for i in 0..2 {
+                s.put(&format!("docs/{}", i), b"hello").unwrap();
+            }
+            s.put("logs/today", b"").unwrap();
+        }
+        let app = create_router(storage);
+
+        for (query, count, total_bytes) in [
+            ("prefix=photos/&count_only=true", 5, 5000),
+            ("prefix=docs/&count_only=true", 2, 10),
+            ("prefix=logs/&count_only=true", 1, 0),
+            ("count_only=true", 8, 5010),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/blobs?{}", query))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), HttpStatus::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let summary: crate::volume::PrefixSummary = serde_json::from_slice(&body).unwrap();
+            assert_eq!((summary.count, summary.total_bytes), (count, total_bytes));
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs?prefix=docs/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_count_only");
+    }
+
+    #[test]
+    fn test_store_error_status_mapping() {
+        let checksum = StoreError::ChecksumMismatch {
+            segment_id: 1,
+            offset: 0,
+        };
+        assert_eq!(
+            StatusCode::from(&checksum),
+            HttpStatus::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            error_response(&checksum).status(),
+            HttpStatus::UNPROCESSABLE_ENTITY
+        );
+
+        let not_found = StoreError::Io(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(StatusCode::from(&not_found), HttpStatus::NOT_FOUND);
+
+        let throttled = StoreError::WriteThrottled {
+            suggested_delay_ms: 100,
+        };
+        let response = error_response(&throttled);
+        assert_eq!(response.status(), HttpStatus::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "1");
+
+        let out_of_range = StoreError::RangeNotSatisfiable {
+            start: 8,
+            end: 12,
+            size: 10,
+        };
+        assert_eq!(
+            StatusCode::from(&out_of_range),
+            HttpStatus::RANGE_NOT_SATISFIABLE
+        );
+
+        let corrupted = StoreError::CorruptedData("bad".to_string());
+        assert_eq!(
+            StatusCode::from(&corrupted),
+            HttpStatus::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_blobs_detailed() {
+        let storage = setup_test_storage("tests_data/handler_list_detailed");
+        let etag = {
+            let mut s = storage.lock().unwrap();
+            s.put("alpha", b"12345").unwrap();
+            s.put("beta", b"1234567890").unwrap().etag
+        };
+
+        let app = create_router(storage);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs?detailed=true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metas: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metas.len(), 2);
+        assert_eq!(metas[0]["key"], "alpha");
+        assert_eq!(metas[0]["size"], 5);
+        assert_eq!(metas[1]["key"], "beta");
+        assert_eq!(metas[1]["size"], 10);
+        assert_eq!(metas[1]["etag"], etag);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_list_detailed");
+    }
+
+    #[tokio::test]
+    async fn test_list_blobs_paginated() {
+        let storage = setup_test_storage("tests_data/handler_list_paginated");
+        {
+            let mut s = storage.lock().unwrap();
+            for i in 0..200 {
+                s.put(&format!("blob-{:03}", i), b"x").unwrap();
+            }
+        }
+
+        let mut seen = Vec::new();
+        let mut uri = "/blobs?limit=50".to_string();
+        loop {
+            let response = create_router(storage.clone())
+                .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), HttpStatus::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let page: PaginatedList<String> = serde_json::from_slice(&body).unwrap();
+            assert!(page.items.len() <= 50);
+            assert_eq!(page.total_count, Some(200));
+            seen.extend(page.items);
+            match page.next_cursor {
+                Some(cursor) => {
+                    assert_eq!(cursor.volume_id.as_deref(), Some("test-vol"));
+                    uri = format!("/blobs?limit=50&cursor={}", cursor);
+                },
+                None => break,
+            }
+        }
+        let expected: Vec<String> = (0..200).map(|i| format!("blob-{:03}", i)).collect();
+        assert_eq!(seen, expected);
+
+        let response = create_router(storage)
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs?cursor=%21%21")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_list_paginated");
+    }
+
+    #[tokio::test]
+    async fn test_patch_blob_splices_range() {
+        let storage = setup_test_storage("tests_data/handler_patch");
+        storage.lock().unwrap().put("doc", b"0123456789").unwrap();
+
+        let patch = |range: &'static str, body: &'static str| {
+            Request::builder()
+                .method("PATCH")
+                .uri("/blobs/doc")
+                .header(header::CONTENT_RANGE, range)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let app = create_router(storage.clone());
+        let response = app.oneshot(patch("bytes 2-4/10", "abc")).await.unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/doc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"01abc56789");
+
+        let app = create_router(storage.clone());
+        let response = app.oneshot(patch("bytes 8-11/*", "wxyz")).await.unwrap();
+        assert_eq!(response.status(), HttpStatus::RANGE_NOT_SATISFIABLE);
+
+        let app = create_router(storage);
+        let response = app.oneshot(patch("bytes 2-4/10", "toolong")).await.unwrap();
+        assert_eq!(response.status(), HttpStatus::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_patch");
+    }
+
+    #[tokio::test]
+    async fn test_put_blob_if_match() {
+        let storage = setup_test_storage("tests_data/handler_if_match");
+        let etag = storage.lock().unwrap().put("doc", b"v1").unwrap().etag;
+
+        let put = |key: &str, if_match: &str, body: &'static [u8]| {
+            create_router(storage.clone()).oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/blobs/{}", key))
+                    .header("if-match", if_match)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+
+        // The etag from an earlier response, quoted as in the ETag header.
+        let response = put("doc", &format!("\"{}\"", etag), b"v2").await.unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+
+        // `etag` now names v1, which was replaced.
+        let response = put("doc", &etag, b"v3").await.unwrap();
+        assert_eq!(response.status(), HttpStatus::PRECONDITION_FAILED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "precondition_failed");
+        assert_eq!(
+            storage.lock().unwrap().get("doc").unwrap(),
+            Some(b"v2".to_vec())
+        );
+
+        // `*` only matches a blob that exists.
+        let response = put("new", "*", b"v1").await.unwrap();
+        assert_eq!(response.status(), HttpStatus::PRECONDITION_FAILED);
+        assert_eq!(storage.lock().unwrap().get("new").unwrap(), None);
+        let response = put("doc", "*", b"v4").await.unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+
+        {
+            let mut s = storage.lock().unwrap();
+            let current = s.head("doc").unwrap().unwrap().etag;
+            assert!(matches!(
+                s.put_if_match("doc", b"v5", "00000000", None),
+                Err(StoreError::PreconditionFailed { actual: Some(ref a), .. }) if *a == current
+            ));
+            assert_eq!(
+                s.put_if_match("doc", b"v5", &current, None).unwrap().size,
+                2
+            );
+        }
+
+        // Attribute headers are applied to a conditional write too.
+        let etag = storage.lock().unwrap().head("doc").unwrap().unwrap().etag;
+        let response = create_router(storage.clone())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/blobs/doc")
+                    .header("if-match", etag)
+                    .header("x-blob-attr-owner", "alice")
+                    .body(Body::from("v6"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+        let attrs = storage.lock().unwrap().get_attrs("doc").unwrap().unwrap();
+        assert_eq!(attrs["owner"], "alice");
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_if_match");
+    }
+
+    #[tokio::test]
+    async fn test_copy_blob_keeps_content_and_etag() {
+        let storage = setup_test_storage("tests_data/handler_copy");
+        let source = storage.lock().unwrap().put("src", b"copy me").unwrap();
+
+        let copy = |uri: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = create_router(storage.clone());
+        let response = app.oneshot(copy("/blobs/dst/copy-from/src")).await.unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let meta: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(meta["key"], "dst");
+        assert_eq!(meta["etag"], source.etag.as_str());
+
+        // The copy is independent of the source.
+        storage.lock().unwrap().delete("src").unwrap();
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/dst")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"copy me");
+
+        let app = create_router(storage);
+        let response = app.oneshot(copy("/blobs/dst/copy-from/src")).await.unwrap();
+        assert_eq!(response.status(), HttpStatus::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_copy");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_concatenates_parts() {
+        let storage = setup_test_storage("tests_data/handler_multipart");
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/uploads")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"key":"big"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let upload_id = json["upload_id"].as_str().unwrap().to_string();
+
+        // Parts may arrive out of order; they are joined by part number.
+        for (n, part) in [(2, "second-"), (1, "first-"), (3, "third")] {
+            let app = create_router(storage.clone());
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("PUT")
+                        .uri(format!("/uploads/{}/parts/{}", upload_id, n))
+                        .body(Body::from(part))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), HttpStatus::OK);
+        }
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/uploads/{}/complete", upload_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/big")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"first-second-third");
+        assert_eq!(storage.lock().unwrap().list_keys(), vec!["big".to_string()]);
+
+        // The upload is gone once completed.
+        let app = create_router(storage);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/uploads/{}/parts/4", upload_id))
+                    .body(Body::from("late"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_multipart");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_blobs() {
+        let storage = setup_test_storage("tests_data/handler_bulk_delete");
+        {
+            let mut s = storage.lock().unwrap();
+            for i in 0..20 {
+                s.put(&format!("key-{}", i), b"data").unwrap();
+            }
+        }
+
+        let keys: Vec<String> = (0..10)
+            .map(|i| format!("key-{}", i))
+            .chain((100..105).map(|i| format!("key-{}", i)))
+            .collect();
+        let body = serde_json::json!({ "keys": keys }).to_string();
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/blobs")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["deleted"], 10);
+        assert_eq!(json["not_found"], 5);
+
+        let app = create_router(storage);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut remaining: Vec<String> = serde_json::from_slice(&body).unwrap();
+        remaining.sort();
+        let mut expected: Vec<String> = (10..20).map(|i| format!("key-{}", i)).collect();
+        expected.sort();
+        assert_eq!(remaining, expected);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_bulk_delete");
+    }
+
+    #[tokio::test]
+    async fn test_put_blobs_batch() {
+        let storage = setup_test_storage("tests_data/handler_put_batch");
+        let body = serde_json::json!([
+            { "key": "a", "value_b64": BASE64.encode(b"alpha") },
+            { "key": "b", "value_b64": BASE64.encode(b"beta") },
+        ])
+        .to_string();
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/blobs/batch")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metas: Vec<BlobMeta> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metas.len(), 2);
+        assert_eq!(metas[1].key, "b");
+        assert_eq!(metas[1].size, 4);
+        assert_eq!(
+            storage.lock().unwrap().get("a").unwrap(),
+            Some(b"alpha".to_vec())
+        );
+
+        let body = serde_json::json!([{ "key": "c", "value_b64": "not base64!" }]).to_string();
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/blobs/batch")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::BAD_REQUEST);
+        assert_eq!(storage.lock().unwrap().get("c").unwrap(), None);
+
+        let _ = std::fs::remove_dir_all("tests_data/handler_put_batch");
+    }
+
+    #[tokio::test]
+    async fn test_blob_versions_are_retained() {
+        let path = "tests_data/handler_versions";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Mutex::new(
+            BlobStorage::new(path, "test-vol".to_string())
+                .unwrap()
+                .with_max_versions(3),
+        ));
+
+        {
+            let mut s = storage.lock().unwrap();
+            for i in 1..=5 {
+                s.put("doc", format!("v{}", i).as_bytes()).unwrap();
+            }
+            let versions: Vec<u64> = s.list_versions("doc").iter().map(|v| v.version).collect();
+            assert_eq!(versions, vec![3, 4, 5]);
+            assert_eq!(s.get_version("doc", 1).unwrap(), None);
+            assert_eq!(s.list_keys(), vec!["doc".to_string()]);
+        }
+
+        for version in 3..=5 {
+            let app = create_router(storage.clone());
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/blobs/doc?version={}", version))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), HttpStatus::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(&body[..], format!("v{}", version).as_bytes());
+        }
+
+        let app = create_router(storage);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/blobs/doc/versions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), HttpStatus::OK);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[tokio::test]
+    async fn test_reput_keeps_created_at_and_updates_modified_at() {
+        let path = "tests_data/handler_timestamps";
+        let storage = setup_test_storage(path);
+        let first = storage.lock().unwrap().put("doc", b"v1").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let second = storage.lock().unwrap().put("doc", b"v2").unwrap();
+
+        assert_eq!(second.created_at, first.created_at);
+        assert!(second.modified_at > first.modified_at);
+
+        let app = create_router(storage.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("HEAD")
+                    .uri("/blobs/doc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            httpdate::fmt_http_date(second.modified_at)
+        );
+
+        // Timestamps survive a reopen.
+        drop(storage);
+        let reopened = BlobStorage::new(path, "test-vol".to_string()).unwrap();
+        let meta = reopened.head("doc").unwrap().unwrap();
+        assert_eq!(meta.created_at, first.created_at);
+        assert_eq!(meta.modified_at, second.modified_at);
+        assert_eq!(reopened.list_keys(), vec!["doc".to_string()]);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_etag_length_follows_hash_algo() {
+        use crate::volume::storage::HashAlgo;
+
+        let path = "tests_data/handler_hash_algo";
+        let _ = std::fs::remove_dir_all(path);
+        let mut crc = BlobStorage::new(path, "test-vol".to_string()).unwrap();
+        let meta = crc.put("blob", b"payload").unwrap();
+        assert_eq!(meta.hash_algo, HashAlgo::Crc32);
+        assert_eq!(meta.etag.len(), 8);
+        drop(crc);
+
+        let mut sha = BlobStorage::new(path, "test-vol".to_string())
+            .unwrap()
+            .with_hash_algo(HashAlgo::Sha256);
+        let existing = sha.head("blob").unwrap().unwrap();
+        assert_eq!(existing.etag.len(), 64);
+        let meta = sha.put("other", b"payload").unwrap();
+        assert_eq!(meta.hash_algo, HashAlgo::Sha256);
+        assert_eq!(meta.etag, existing.etag);
+        assert!(meta.etag.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_reopen_reads_etag_and_size_from_sidecar() {
+        use crate::volume::storage::HashAlgo;
+        use crate::KVStore;
+
+        let path = "tests_data/handler_meta_sidecar";
+        let _ = std::fs::remove_dir_all(path);
+        let written = {
+            let mut storage = BlobStorage::new(path, "test-vol".to_string()).unwrap();
+            storage.put("doc", b"original").unwrap()
+        };
+        // Swap the value behind the volume's back: a reopen that re-hashed
+        // values would report the new bytes' etag.
+        {
+            let mut store = KVStore::open(path).unwrap();
+            store.set("doc", b"swapped!!").unwrap();
+            // A blob with only timestamps stored, as older versions wrote them.
+            store.set("legacy", b"old blob").unwrap();
+            store.set("__times:legacy", &[0u8; 16]).unwrap();
+        }
+
+        let reopened = BlobStorage::new(path, "test-vol".to_string()).unwrap();
+        let meta = reopened.head("doc").unwrap().unwrap();
+        assert_eq!(meta.etag, written.etag);
+        assert_eq!(meta.size, 8);
+        assert_eq!(meta.modified_at, written.modified_at);
+
+        let legacy = reopened.head("legacy").unwrap().unwrap();
+        assert_eq!(legacy.etag, HashAlgo::Crc32.etag(b"old blob"));
+        assert_eq!(legacy.size, 8);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_from_volume_config_applies_settings() {
+        use crate::store::config::StoreConfig;
+        use crate::volume::config::VolumeConfig;
+        use crate::volume::storage::HashAlgo;
+
+        let path = "tests_data/handler_volume_config";
+        let _ = std::fs::remove_dir_all(path);
+        let config = VolumeConfig::new("cfg-vol")
+            .with_data_dir(path)
+            .with_max_versions(2)
+            .with_hash_algo(HashAlgo::Sha256)
+            .with_store_config(StoreConfig {
+                max_segment_size: 4096,
+                ..StoreConfig::default()
+            });
+        let mut storage = BlobStorage::from_volume_config(&config).unwrap();
+        assert_eq!(storage.volume_id(), "cfg-vol");
+
+        storage.put("doc", b"v1").unwrap();
+        let meta = storage.put("doc", b"v2").unwrap();
+        assert_eq!(meta.etag.len(), 64);
+        assert_eq!(storage.list_versions("doc").len(), 2);
+        assert!(std::path::Path::new(path)
+            .join("segment-0000000001.dat")
+            .exists());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[tokio::test]
+    async fn test_hard_quota_returns_insufficient_storage() {
+        let path = "tests_data/handler_quota";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Mutex::new(
+            BlobStorage::new(path, "test-vol".to_string())
+                .unwrap()
+                .with_quota(Some(8 * 1024), Some(10 * 1024)),
+        ));
+        let blob = vec![7u8; 1024];
+
+        let mut statuses = Vec::new();
+        for i in 0..12 {
+            let app = create_router(storage.clone());
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/blobs/blob-{}", i))
+                        .body(Body::from(blob.clone()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            if i == 9 {
+                assert_eq!(
+                    response.headers()["X-Storage-Warning"],
+                    "quota_soft_exceeded"
+                );
+            }
+            statuses.push(response.status());
+        }
+
+        assert!(statuses[..10].iter().all(|s| *s == HttpStatus::CREATED));
+        assert_eq!(statuses[10], HttpStatus::INSUFFICIENT_STORAGE);
+        assert_eq!(statuses[11], HttpStatus::INSUFFICIENT_STORAGE);
+
+        let s = storage.lock().unwrap();
+        assert_eq!(s.list_keys().len(), 10);
+        for i in 0..10 {
+            assert_eq!(s.get(&format!("blob-{}", i)).unwrap(), Some(blob.clone()));
+        }
+        assert_eq!(s.quota_used_pct(), Some(100.0));
+        drop(s);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
 }
@@ -1,28 +1,62 @@
 // mini-kvstore-v2/src/volume/main.rs
 //! Volume binary entrypoint.
 
+use mini_kvstore_v2::common::logging::init_stderr_logger;
+use mini_kvstore_v2::volume::config::VolumeConfig;
 use mini_kvstore_v2::volume::server::start_volume_server;
 use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let volume_id = std::env::var("VOLUME_ID").unwrap_or_else(|_| "vol-1".to_string());
-    let data_dir =
-        std::env::var("DATA_DIR").unwrap_or_else(|_| format!("volume_data_{}", volume_id));
+    // A config file replaces VOLUME_ID, DATA_DIR and PORT; the settings
+    // below still override it.
+    let mut config = match std::env::var("CONFIG_FILE") {
+        Ok(path) => {
+            println!("Loading config from {}", path);
+            VolumeConfig::from_toml_file(path)?
+        },
+        Err(_) => {
+            let volume_id = std::env::var("VOLUME_ID").unwrap_or_else(|_| "vol-1".to_string());
+            let data_dir =
+                std::env::var("DATA_DIR").unwrap_or_else(|_| format!("volume_data_{}", volume_id));
+            let port: u16 = std::env::var("PORT")
+                .unwrap_or_else(|_| "9002".to_string())
+                .parse()
+                .unwrap_or(9002);
+            VolumeConfig::new(volume_id)
+                .with_data_dir(data_dir)
+                .with_bind_addr(SocketAddr::from(([127, 0, 0, 1], port)))
+        },
+    };
+    init_stderr_logger(config.log_level);
 
-    let port: u16 = std::env::var("PORT")
-        .unwrap_or_else(|_| "9002".to_string())
-        .parse()
-        .unwrap_or(9002);
+    println!("Starting volume server:");
+    println!("  volume_id = {}", config.volume_id);
+    println!("  data_dir  = {}", config.data_dir);
+    println!("  bind_addr = {}", config.bind_addr);
 
-    let bind_addr = SocketAddr::from(([127, 0, 0, 1], port));
+    if let Ok(target) = std::env::var("REPLICATION_TARGET") {
+        config = config.with_replication_target(target.parse()?);
+        println!("  replicating to {}", target);
+    }
+    if let Ok(listen) = std::env::var("REPLICATION_LISTEN") {
+        config = config.with_replication_listen_addr(listen.parse()?);
+        println!("  accepting replication on {}", listen);
+    }
 
-    println!("Starting volume server:");
-    println!("  volume_id = {}", volume_id);
-    println!("  data_dir  = {}", data_dir);
-    println!("  bind_addr = {}", bind_addr);
+    if let Ok(coordinator) = std::env::var("COORDINATOR_URL") {
+        println!("  heartbeats to {}", coordinator);
+        config = config.with_coordinator_url(coordinator);
+    }
+    if let Ok(urls) = std::env::var("REPAIR_URLS") {
+        println!("  repairing damaged blobs from {}", urls);
+        config = config.with_repair_urls(urls.split(',').map(str::to_string).collect());
+    }
+    if let Ok(public_url) = std::env::var("PUBLIC_URL") {
+        config = config.with_public_url(public_url);
+    }
 
-    start_volume_server(bind_addr).await?;
+    start_volume_server(config).await?;
 
     Ok(())
 }
@@ -3,4 +3,7 @@
 pub mod server;
 pub mod storage;
 
-pub use storage::BlobStorage;
+pub use server::{VolumeServer, VolumeServerHandle};
+pub use storage::{
+    BlobStorage, BulkDeleteResult, HashAlgo, PrefixSummary, Repair, VerifiedRead, VersionMeta,
+};
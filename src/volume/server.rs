@@ -1,16 +1,278 @@
-//! Volume module server endpoints (Axum skeleton).
-//! This module can be expanded to provide REST API endpoints for blob/volume management.
-
-use std::net::SocketAddr;
-
-/// Starts the volume server.
-/// This function serves as an entrypoint for a dedicated volume process.
-///
-/// Example usage:
-///    let addr = ([127,0,0,1], 9002).into();
-///    start_volume_server(addr);
-pub async fn start_volume_server(_bind_addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
-    // TODO: Add Axum/HTTP server implementation here.
-    println!("Volume server placeholder running at {:?}", _bind_addr);
+//! Volume server: blob storage behind the HTTP API, with optional
+//! primary-to-secondary replication.
+
+use crate::store::error::{Result as StoreResult, StoreError};
+use crate::store::replication::{ReplicationReceiver, ReplicationStream};
+use crate::volume::config::VolumeConfig;
+use crate::volume::handlers::{create_router_with_upstream, Upstream};
+use crate::volume::storage::BlobStorage;
+use axum::Router;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct VolumeServer {
+    config: VolumeConfig,
+    storage: Arc<Mutex<BlobStorage>>,
+    upstream: Upstream,
+    replication_addr: Option<SocketAddr>,
+}
+
+impl VolumeServer {
+    /// Opens the volume's storage. With `replication_target` set, connects to
+    /// the secondary and streams every write to it; with
+    /// `replication_listen_addr` set, accepts a primary's stream. With
+    /// `auto_compact_on_startup` set, compacts the store first.
+    pub fn new(config: VolumeConfig) -> StoreResult<Self> {
+        let mut storage = BlobStorage::from_volume_config(&config)?;
+        if config.auto_compact_on_startup {
+            storage.compact()?;
+        }
+        let stream = config
+            .replication_target
+            .map(ReplicationStream::connect)
+            .transpose()?
+            .map(Arc::new);
+        if let Some(stream) = &stream {
+            storage = storage.with_replication(stream.clone());
+        }
+        let storage = Arc::new(Mutex::new(storage));
+        if let Some(stream) = stream {
+            spawn_catchup_responder(stream, storage.clone());
+        }
+
+        if let Some(coordinator_url) = &config.coordinator_url {
+            spawn_heartbeat(
+                coordinator_url.trim_end_matches('/').to_string(),
+                config.advertised_url(),
+                config.heartbeat_interval,
+                Arc::downgrade(&storage),
+            );
+        }
+
+        let upstream = Upstream::default();
+        let replication_addr = match config.replication_listen_addr {
+            Some(addr) => Some(spawn_replication_listener(
+                addr,
+                storage.clone(),
+                upstream.clone(),
+            )?),
+            None => None,
+        };
+
+        Ok(Self {
+            config,
+            storage,
+            upstream,
+            replication_addr,
+        })
+    }
+
+    pub fn storage(&self) -> Arc<Mutex<BlobStorage>> {
+        self.storage.clone()
+    }
+
+    /// Address replication connections are accepted on, once bound.
+    pub fn replication_addr(&self) -> Option<SocketAddr> {
+        self.replication_addr
+    }
+
+    pub fn router(&self) -> Router {
+        create_router_with_upstream(self.storage.clone(), self.upstream.clone())
+    }
+
+    /// Serves the HTTP API on `bind_addr` until the process exits.
+    pub async fn serve(self) -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind(self.config.bind_addr).await?;
+        axum::serve(listener, self.router()).await
+    }
+
+    /// Starts a volume server for `data_dir` on a port of 127.0.0.1 picked
+    /// by the OS, on a thread with its own runtime, and returns its address.
+    /// The port is bound on return; use
+    /// [`wait_for_ready`](VolumeServerHandle::wait_for_ready) before the
+    /// first request.
+    pub fn spawn_on_random_port(
+        data_dir: impl AsRef<Path>,
+        volume_id: &str,
+    ) -> StoreResult<(VolumeServerHandle, SocketAddr)> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let server = VolumeServer::new(
+            VolumeConfig::new(volume_id)
+                .with_data_dir(data_dir.as_ref().to_string_lossy())
+                .with_bind_addr(addr),
+        )?;
+
+        let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+        let thread = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                axum::serve(listener, server.router())
+                    .with_graceful_shutdown(async {
+                        let _ = signal.await;
+                    })
+                    .await
+            })
+        });
+        let handle = VolumeServerHandle {
+            addr,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        };
+        Ok((handle, addr))
+    }
+}
+
+/// A volume server started by [`VolumeServer::spawn_on_random_port`].
+/// Dropping the handle shuts the server down, like
+/// [`shutdown`](Self::shutdown).
+pub struct VolumeServerHandle {
+    addr: SocketAddr,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl VolumeServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Polls `GET /health` until it answers 200, or fails with `TimedOut`
+    /// once `timeout` has passed.
+    pub fn wait_for_ready(&self, timeout: Duration) -> StoreResult<()> {
+        let url = format!("http://{}/health", self.addr);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let response = ureq::get(&url).timeout(Duration::from_millis(200)).call();
+            if response.is_ok_and(|r| r.status() == 200) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(StoreError::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "volume server at {} not ready after {:?}",
+                        self.addr, timeout
+                    ),
+                )));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Stops accepting connections, lets in-flight requests finish and
+    /// waits for the server thread, which closes the storage.
+    pub fn shutdown(mut self) -> StoreResult<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> StoreResult<()> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            thread
+                .join()
+                .map_err(|_| io::Error::other("volume server thread panicked"))??;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VolumeServerHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Answers the secondary's catchup requests until it disconnects.
+fn spawn_catchup_responder(stream: Arc<ReplicationStream>, storage: Arc<Mutex<BlobStorage>>) {
+    thread::spawn(move || loop {
+        match stream.next_catchup() {
+            Ok(Some(since_lsn)) => {
+                if let Err(e) = storage.lock().unwrap().resend_since(since_lsn) {
+                    log::warn!("replication catchup failed: {}", e);
+                    return;
+                }
+            },
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("replication connection lost: {}", e);
+                return;
+            },
+        }
+    });
+}
+
+/// Reports the volume's capacity to the coordinator every `interval`, until
+/// the storage is dropped. Failed heartbeats are logged and retried on the
+/// next tick.
+fn spawn_heartbeat(
+    coordinator_url: String,
+    public_url: String,
+    interval: std::time::Duration,
+    storage: Weak<Mutex<BlobStorage>>,
+) {
+    thread::spawn(move || loop {
+        let Some(storage) = storage.upgrade() else {
+            return;
+        };
+        let info = storage.lock().unwrap().volume_info(public_url.as_str());
+        drop(storage);
+        let url = format!("{}/volumes/{}/heartbeat", coordinator_url, info.id);
+        if let Err(e) = ureq::post(&url).send_json(&info) {
+            log::warn!("heartbeat to {} failed: {}", coordinator_url, e);
+        }
+        thread::sleep(interval);
+    });
+}
+
+/// Binds `addr` and applies the changes of each primary that connects, one
+/// connection at a time. Returns the bound address.
+fn spawn_replication_listener(
+    addr: SocketAddr,
+    storage: Arc<Mutex<BlobStorage>>,
+    upstream: Upstream,
+) -> StoreResult<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let receiver = Arc::new(ReplicationReceiver::new(stream));
+            *upstream.lock().unwrap() = Some(receiver.clone());
+            loop {
+                match receiver.recv() {
+                    Ok(Some(change)) => {
+                        if let Err(e) = storage.lock().unwrap().apply_change(&change) {
+                            log::warn!("failed to apply replicated change: {}", e);
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("replication connection lost: {}", e);
+                        break;
+                    },
+                }
+            }
+            *upstream.lock().unwrap() = None;
+        }
+    });
+    Ok(local_addr)
+}
+
+/// Starts a volume server for `config` and serves it.
+pub async fn start_volume_server(config: VolumeConfig) -> Result<(), Box<dyn std::error::Error>> {
+    VolumeServer::new(config)?.serve().await?;
     Ok(())
 }
@@ -1,8 +1,42 @@
-use crate::store::error::Result as StoreResult;
+use crate::common::{PaginatedList, PaginationCursor, VolumeInfo, VolumeStatus};
+use crate::store::batch::WriteBatch;
+use crate::store::compaction::CompactionStats;
+use crate::store::error::{Result as StoreResult, StoreError};
+use crate::store::replication::{ChangeRecord, ReplicationStream};
 use crate::store::stats::StoreStats;
+use crate::volume::config::VolumeConfig;
 use crate::KVStore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Hash used to compute blob etags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    /// CRC32, 8 hex chars. Cheap, but collides too easily to prove integrity.
+    #[default]
+    Crc32,
+    /// SHA-256, 64 hex chars.
+    Sha256,
+}
+
+impl HashAlgo {
+    /// Hex digest of `data`.
+    pub fn etag(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgo::Crc32 => format!("{:08x}", crc32fast::hash(data)),
+            HashAlgo::Sha256 => Sha256::digest(data)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlobMeta {
@@ -10,40 +44,961 @@ pub struct BlobMeta {
     pub etag: String,
     pub size: u64,
     pub volume_id: String,
+    /// Algorithm `etag` was computed with.
+    pub hash_algo: HashAlgo,
+    /// When the blob was first written. Kept across re-PUTs.
+    pub created_at: SystemTime,
+    /// When the current value was written.
+    pub modified_at: SystemTime,
+    /// User-defined attributes set with
+    /// [`put_with_attrs`](BlobStorage::put_with_attrs), if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<HashMap<String, String>>,
+}
+
+/// Outcome of [`BlobStorage::delete_many`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BulkDeleteResult {
+    pub deleted: Vec<String>,
+    pub not_found: Vec<String>,
+}
+
+/// Blobs under a key prefix; see [`BlobStorage::prefix_summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixSummary {
+    pub count: usize,
+    /// Sum of the blobs' sizes.
+    pub total_bytes: u64,
+}
+
+/// A historical (or the current) version of a blob.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionMeta {
+    pub version: u64,
+    pub size: u64,
+    pub is_latest: bool,
+}
+
+/// Outcome of [`BlobStorage::read_verified`].
+#[derive(Debug)]
+pub enum VerifiedRead {
+    /// The local copy checked out, or there is none.
+    Intact(Option<Vec<u8>>),
+    /// The local copy is damaged; fetch a good one and hand it to
+    /// [`BlobStorage::finish_repair`].
+    Damaged(Repair),
+}
+
+/// A blob whose local copy failed its checksum, and what a replica's copy
+/// has to match to replace it.
+#[derive(Debug)]
+pub struct Repair {
+    key: String,
+    etag: String,
+    hash_algo: HashAlgo,
+    auth_token: Option<String>,
+    error: StoreError,
+}
+
+impl Repair {
+    /// Fetches `GET {url}/blobs/{key}` from each of `replica_urls` in turn
+    /// and returns the first copy with the blob's etag. Blocks on the
+    /// network but needs no access to the storage, so callers can release
+    /// their lock first.
+    pub fn fetch(&self, replica_urls: &[String]) -> Option<Vec<u8>> {
+        for url in replica_urls {
+            let url = format!("{}/blobs/{}", url.trim_end_matches('/'), self.key);
+            let data = match fetch(&url, self.auth_token.as_deref()) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("repair fetch from {} failed: {}", url, e);
+                    continue;
+                },
+            };
+            if self.hash_algo.etag(&data) != self.etag {
+                log::warn!("repair copy from {} has the wrong etag", url);
+                continue;
+            }
+            return Some(data);
+        }
+        None
+    }
+}
+
+/// Prefix of the internal keys holding archived blob versions.
+const VERSION_PREFIX: &str = "__version:";
+
+fn version_key(key: &str, version: u64) -> String {
+    format!("{}{}:{}", VERSION_PREFIX, key, version)
+}
+
+/// Prefix of the internal keys holding a blob's created/modified timestamps,
+/// followed by its size and etag so opening the volume needn't re-hash
+/// every value.
+const TIMES_PREFIX: &str = "__times:";
+
+/// Encoded size of the timestamps: two `u64` millisecond counts. Entries
+/// written before sizes and etags were kept stop here.
+const TIMES_LEN: usize = 16;
+
+fn times_key(key: &str) -> String {
+    format!("{}{}", TIMES_PREFIX, key)
+}
+
+/// Prefix of the internal keys holding a blob's attributes as a JSON object.
+const ATTRS_PREFIX: &str = "__attrs:";
+
+fn attrs_key(key: &str) -> String {
+    format!("{}{}", ATTRS_PREFIX, key)
+}
+
+/// Prefix of the internal keys of multipart uploads: `__upload__:<id>` holds
+/// the target key and `__upload__:<id>:<n>` holds part `n`.
+const UPLOAD_PREFIX: &str = "__upload__:";
+
+fn upload_key(upload_id: &str) -> String {
+    format!("{}{}", UPLOAD_PREFIX, upload_id)
+}
+
+fn upload_part_key(upload_id: &str, part: u32) -> String {
+    format!("{}{}:{}", UPLOAD_PREFIX, upload_id, part)
+}
+
+fn is_internal_key(key: &str) -> bool {
+    key.starts_with(VERSION_PREFIX)
+        || key.starts_with(TIMES_PREFIX)
+        || key.starts_with(ATTRS_PREFIX)
+        || key.starts_with(UPLOAD_PREFIX)
+}
+
+/// The body of a `GET` to `url`.
+fn fetch(url: &str, auth_token: Option<&str>) -> std::io::Result<Vec<u8>> {
+    let mut request = ureq::get(url).timeout(Duration::from_secs(5));
+    if let Some(token) = auth_token {
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+    let response = request.call().map_err(std::io::Error::other)?;
+    let mut data = Vec::new();
+    response.into_reader().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn to_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn from_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+/// `created_at | modified_at | size | hash_algo | etag`: three `u64` LE,
+/// a hash byte (0 = CRC32, 1 = SHA-256), then the etag's hex digits.
+fn encode_times(meta: &BlobMeta) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(TIMES_LEN + 9 + meta.etag.len());
+    buf.extend_from_slice(&to_millis(meta.created_at).to_le_bytes());
+    buf.extend_from_slice(&to_millis(meta.modified_at).to_le_bytes());
+    buf.extend_from_slice(&meta.size.to_le_bytes());
+    buf.push(match meta.hash_algo {
+        HashAlgo::Crc32 => 0,
+        HashAlgo::Sha256 => 1,
+    });
+    buf.extend_from_slice(meta.etag.as_bytes());
+    buf
+}
+
+/// Decoded [`encode_times`] entry; `size_and_etag` is `None` for entries
+/// that only hold timestamps.
+struct StoredTimes {
+    created_at: SystemTime,
+    modified_at: SystemTime,
+    size_and_etag: Option<(u64, HashAlgo, String)>,
+}
+
+fn decode_times(buf: &[u8]) -> Option<StoredTimes> {
+    let read_u64 = |at: usize| Some(u64::from_le_bytes(buf.get(at..at + 8)?.try_into().ok()?));
+    let size_and_etag = || {
+        let algo = match *buf.get(TIMES_LEN + 8)? {
+            0 => HashAlgo::Crc32,
+            1 => HashAlgo::Sha256,
+            _ => return None,
+        };
+        let etag = std::str::from_utf8(&buf[TIMES_LEN + 9..]).ok()?;
+        Some((read_u64(TIMES_LEN)?, algo, etag.to_string()))
+    };
+    Some(StoredTimes {
+        created_at: from_millis(read_u64(0)?),
+        modified_at: from_millis(read_u64(8)?),
+        size_and_etag: size_and_etag(),
+    })
+}
+
+/// A secondary this volume streams its writes to.
+struct Replica {
+    stream: Arc<ReplicationStream>,
+    /// LSN of the last record sent.
+    sent_lsn: u64,
 }
 
 pub struct BlobStorage {
     store: KVStore,
     volume_id: String,
+    max_versions: usize,
+    soft_limit_bytes: Option<u64>,
+    hard_limit_bytes: Option<u64>,
+    hash_algo: HashAlgo,
+    /// Metadata of every user-visible blob, kept so HEAD and listings skip value reads.
+    meta: HashMap<String, BlobMeta>,
+    replica: Option<Replica>,
+    /// Volumes to fetch a good copy from when a local value is damaged.
+    repair_urls: Vec<String>,
+    /// Bearer token sent with repair fetches, for replicas that require one.
+    repair_auth_token: Option<String>,
+    /// Longest key `put` accepts, in bytes.
+    max_key_length: Option<usize>,
 }
 
 impl BlobStorage {
     pub fn new(data_dir: impl AsRef<Path>, volume_id: String) -> StoreResult<Self> {
-        let store = KVStore::open(data_dir)?;
-        Ok(BlobStorage { store, volume_id })
+        Self::from_store(KVStore::open(data_dir)?, volume_id, HashAlgo::default())
+    }
+
+    /// Opens the volume described by `config`: its data directory, store
+    /// settings, versioning, quota and etag hash. Replication is left to
+    /// [`VolumeServer`](crate::volume::VolumeServer).
+    pub fn from_volume_config(config: &VolumeConfig) -> StoreResult<Self> {
+        let store = KVStore::from_config(&config.store.clone().with_data_path(&config.data_dir))?;
+        Ok(
+            Self::from_store(store, config.volume_id.clone(), config.hash_algo)?
+                .with_max_versions(config.max_versions)
+                .with_quota(config.soft_limit_bytes, config.hard_limit_bytes)
+                .with_repair_urls(config.repair_urls.clone())
+                .with_repair_auth_token(config.auth_token.clone())
+                .with_max_key_length(config.max_key_length),
+        )
+    }
+
+    fn from_store(store: KVStore, volume_id: String, hash_algo: HashAlgo) -> StoreResult<Self> {
+        let mut storage = BlobStorage {
+            store,
+            volume_id,
+            max_versions: 1,
+            soft_limit_bytes: None,
+            hard_limit_bytes: None,
+            hash_algo,
+            meta: HashMap::new(),
+            replica: None,
+            repair_urls: Vec::new(),
+            repair_auth_token: None,
+            max_key_length: None,
+        };
+        storage.rebuild_meta()?;
+        Ok(storage)
+    }
+
+    /// Keeps up to `max_versions` versions per blob, the current one included.
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = max_versions.max(1);
+        self
+    }
+
+    /// Selects the etag hash and recomputes the etags of existing blobs.
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        for meta in self.meta.values_mut() {
+            if let Ok(Some(data)) = self.store.get(&meta.key) {
+                meta.etag = hash_algo.etag(&data);
+                meta.hash_algo = hash_algo;
+            }
+        }
+        self
+    }
+
+    /// Base URLs of the volumes [`get_with_repair`](Self::get_with_repair)
+    /// is pointed at by the HTTP API.
+    pub fn with_repair_urls(mut self, repair_urls: Vec<String>) -> Self {
+        self.repair_urls = repair_urls;
+        self
+    }
+
+    pub fn repair_urls(&self) -> &[String] {
+        &self.repair_urls
+    }
+
+    /// Sends `Authorization: Bearer <token>` with every repair fetch. The
+    /// volumes of a cluster share one `auth_token`, so this is the volume's own.
+    pub fn with_repair_auth_token(mut self, token: Option<String>) -> Self {
+        self.repair_auth_token = token;
+        self
+    }
+
+    /// Rejects writes to keys longer than `max_key_length` bytes with
+    /// `InvalidKey`; `None` accepts any length.
+    pub fn with_max_key_length(mut self, max_key_length: Option<usize>) -> Self {
+        self.max_key_length = max_key_length;
+        self
+    }
+
+    /// Fails with `InvalidKey` if `key` is over the length limit.
+    fn check_key_length(&self, key: &str) -> StoreResult<()> {
+        match self.max_key_length {
+            Some(max) if key.len() > max => Err(StoreError::InvalidKey(format!(
+                "key is {} bytes, over the {}-byte limit",
+                key.len(),
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compacts the underlying store.
+    pub fn compact(&mut self) -> StoreResult<CompactionStats> {
+        self.store.compact()
+    }
+
+    /// Streams every write from now on to the secondary behind `stream`.
+    pub fn with_replication(mut self, stream: Arc<ReplicationStream>) -> Self {
+        // `tail` returns records after the LSN it's given, so start just
+        // before the next write; older records are sent on catchup.
+        let sent_lsn = self.store.next_lsn() - 1;
+        self.replica = Some(Replica { stream, sent_lsn });
+        self
     }
 
+    /// Sets the soft (warn) and hard (reject) limits on live bytes.
+    pub fn with_quota(
+        mut self,
+        soft_limit_bytes: Option<u64>,
+        hard_limit_bytes: Option<u64>,
+    ) -> Self {
+        self.soft_limit_bytes = soft_limit_bytes;
+        self.hard_limit_bytes = hard_limit_bytes;
+        self
+    }
+
+    /// Stores `data` under `key`. Attributes set by an earlier
+    /// [`put_with_attrs`](Self::put_with_attrs) are kept.
     pub fn put(&mut self, key: &str, data: &[u8]) -> StoreResult<BlobMeta> {
-        let etag = format!("{:08x}", crc32fast::hash(data));
-        self.store.set(key, data)?;
-        Ok(BlobMeta {
-            key: key.to_string(),
-            etag,
-            size: data.len() as u64,
-            volume_id: self.volume_id.clone(),
-        })
+        self.put_inner(key, data, None)
+    }
+
+    /// Like [`put`](Self::put), but replaces the blob's attributes with
+    /// `attrs`; an empty map removes them.
+    pub fn put_with_attrs(
+        &mut self,
+        key: &str,
+        data: &[u8],
+        attrs: HashMap<String, String>,
+    ) -> StoreResult<BlobMeta> {
+        self.put_inner(key, data, Some(attrs))
+    }
+
+    /// The attributes stored with `key`, or `None` if it has none.
+    pub fn get_attrs(&self, key: &str) -> StoreResult<Option<HashMap<String, String>>> {
+        self.store
+            .get(&attrs_key(key))?
+            .map(|bytes| {
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| StoreError::CorruptedData(format!("Attributes of {}: {}", key, e)))
+            })
+            .transpose()
+    }
+
+    /// Like [`put`](Self::put), or [`put_with_attrs`](Self::put_with_attrs)
+    /// when `attrs` is given, but only if the blob exists with etag
+    /// `expected_etag`, or exists at all when `expected_etag` is `*`.
+    /// Otherwise fails with `PreconditionFailed`.
+    pub fn put_if_match(
+        &mut self,
+        key: &str,
+        data: &[u8],
+        expected_etag: &str,
+        attrs: Option<HashMap<String, String>>,
+    ) -> StoreResult<BlobMeta> {
+        self.check_if_match(key, expected_etag)?;
+        self.put_inner(key, data, attrs)
+    }
+
+    fn check_if_match(&self, key: &str, expected_etag: &str) -> StoreResult<()> {
+        let actual = self.meta.get(key).map(|meta| meta.etag.as_str());
+        match actual {
+            Some(etag) if expected_etag == "*" || etag == expected_etag => Ok(()),
+            _ => Err(StoreError::PreconditionFailed {
+                key: key.to_string(),
+                expected: expected_etag.to_string(),
+                actual: actual.map(str::to_string),
+            }),
+        }
+    }
+
+    /// Writes a blob; `attrs` of `None` leaves its attributes as they are.
+    fn put_inner(
+        &mut self,
+        key: &str,
+        data: &[u8],
+        attrs: Option<HashMap<String, String>>,
+    ) -> StoreResult<BlobMeta> {
+        self.check_key_length(key)?;
+        if let Some(hard) = self.hard_limit_bytes {
+            let current = self.used_bytes();
+            let replaced = self.store.get(key)?.map_or(0, |v| v.len() as u64);
+            if current - replaced + data.len() as u64 > hard {
+                return Err(StoreError::StoreFull);
+            }
+        }
+
+        if self.max_versions > 1 {
+            self.archive_current(key)?;
+        }
+        // Stored at millisecond precision, so round now to match what a reload sees.
+        let now = from_millis(to_millis(SystemTime::now()));
+        let created_at = self.meta.get(key).map_or(now, |m| m.created_at);
+        let mut batch = WriteBatch::new();
+        let attrs = match attrs {
+            Some(attrs) if attrs.is_empty() => {
+                batch.delete(attrs_key(key));
+                None
+            },
+            Some(attrs) => {
+                let json = serde_json::to_vec(&attrs)
+                    .map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
+                batch.set(attrs_key(key), json);
+                Some(attrs)
+            },
+            None => self.meta.get(key).and_then(|m| m.attrs.clone()),
+        };
+        let meta = self.make_meta(key, data, created_at, now, attrs);
+        batch
+            .set(key, data)
+            .set(times_key(key), encode_times(&meta));
+        self.store.write_batch(batch)?;
+        if self.soft_limit_exceeded() {
+            log::warn!(
+                "volume {} exceeded its soft quota ({} bytes)",
+                self.volume_id,
+                self.soft_limit_bytes.unwrap_or_default()
+            );
+        }
+        self.meta.insert(key.to_string(), meta.clone());
+        self.replicate();
+        Ok(meta)
+    }
+
+    /// Stores several blobs like [`put`](Self::put), with one
+    /// [`KVStore::set_many`] for all of them. The hard quota is checked for
+    /// the whole set up front, so either every blob is written or none is.
+    /// Returns the metadata of each entry, in order.
+    pub fn put_many(&mut self, entries: Vec<(String, Vec<u8>)>) -> StoreResult<Vec<BlobMeta>> {
+        for (key, _) in &entries {
+            self.check_key_length(key)?;
+        }
+        if let Some(hard) = self.hard_limit_bytes {
+            let mut sizes: HashMap<&str, u64> = HashMap::new();
+            for (key, data) in &entries {
+                sizes.insert(key, data.len() as u64);
+            }
+            let mut total = self.used_bytes();
+            for (key, size) in &sizes {
+                let replaced = self.store.get(key)?.map_or(0, |v| v.len() as u64);
+                total = total - replaced + size;
+            }
+            if total > hard {
+                return Err(StoreError::StoreFull);
+            }
+        }
+
+        if self.max_versions > 1 {
+            for (key, _) in &entries {
+                self.archive_current(key)?;
+            }
+        }
+        let now = from_millis(to_millis(SystemTime::now()));
+        let mut writes = Vec::with_capacity(entries.len() * 2);
+        let mut metas = Vec::with_capacity(entries.len());
+        for (key, data) in entries {
+            let created_at = self.meta.get(&key).map_or(now, |m| m.created_at);
+            let attrs = self.meta.get(&key).and_then(|m| m.attrs.clone());
+            let meta = self.make_meta(&key, &data, created_at, now, attrs);
+            writes.push((times_key(&key), encode_times(&meta)));
+            writes.push((key, data));
+            metas.push(meta);
+        }
+        self.store.set_many(writes)?;
+        for meta in &metas {
+            self.meta.insert(meta.key.clone(), meta.clone());
+        }
+        self.replicate();
+        Ok(metas)
+    }
+
+    /// Overwrites `bytes.len()` bytes of `key`'s value starting at `offset`
+    /// and stores the result like a [`put`](Self::put). The span must lie
+    /// within the current value.
+    pub fn patch(&mut self, key: &str, offset: u64, bytes: &[u8]) -> StoreResult<BlobMeta> {
+        let mut data = self.store.get_strict(key)?;
+        let end = offset + bytes.len() as u64;
+        if end > data.len() as u64 {
+            return Err(StoreError::RangeNotSatisfiable {
+                start: offset,
+                end,
+                size: data.len() as u64,
+            });
+        }
+        data[offset as usize..end as usize].copy_from_slice(bytes);
+        self.put(key, &data)
+    }
+
+    /// Stores the current value and attributes of `src_key` under
+    /// `dst_key` like a [`put`](Self::put), so the copy gets the same ETag.
+    /// A missing source is `KeyNotFound`.
+    pub fn copy(&mut self, src_key: &str, dst_key: &str) -> StoreResult<BlobMeta> {
+        let data = self.store.get_strict(src_key)?;
+        let attrs = self.get_attrs(src_key)?.unwrap_or_default();
+        self.put_with_attrs(dst_key, &data, attrs)
+    }
+
+    /// Starts a multipart upload that [`complete_upload`](Self::complete_upload)
+    /// will store under `key`, and returns its id.
+    pub fn create_upload(&mut self, key: &str) -> StoreResult<String> {
+        self.check_key_length(key)?;
+        let mut id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        while self.store.get(&upload_key(&format!("{:x}", id)))?.is_some() {
+            id += 1;
+        }
+        let upload_id = format!("{:x}", id);
+        self.store.set(&upload_key(&upload_id), key.as_bytes())?;
+        self.replicate();
+        Ok(upload_id)
+    }
+
+    /// Stores part `part` of an upload, replacing any earlier body for it.
+    pub fn upload_part(&mut self, upload_id: &str, part: u32, data: &[u8]) -> StoreResult<()> {
+        if self.store.get(&upload_key(upload_id))?.is_none() {
+            return Err(StoreError::KeyNotFound(upload_id.to_string()));
+        }
+        self.store.set(&upload_part_key(upload_id, part), data)?;
+        self.replicate();
+        Ok(())
+    }
+
+    /// Concatenates an upload's parts in part-number order, stores the result
+    /// like a [`put`](Self::put) and discards the upload.
+    pub fn complete_upload(&mut self, upload_id: &str) -> StoreResult<BlobMeta> {
+        let target = self
+            .store
+            .get(&upload_key(upload_id))?
+            .ok_or_else(|| StoreError::KeyNotFound(upload_id.to_string()))?;
+        let key = String::from_utf8(target)
+            .map_err(|_| StoreError::CorruptedData(format!("upload {} target key", upload_id)))?;
+
+        let parts = self.upload_parts(upload_id);
+        let mut data = Vec::new();
+        for part in &parts {
+            if let Some(bytes) = self.store.get(&upload_part_key(upload_id, *part))? {
+                data.extend_from_slice(&bytes);
+            }
+        }
+        let meta = self.put(&key, &data)?;
+
+        let mut batch = WriteBatch::new();
+        for part in parts {
+            batch.delete(upload_part_key(upload_id, part));
+        }
+        batch.delete(upload_key(upload_id));
+        self.store.write_batch(batch)?;
+        self.replicate();
+        Ok(meta)
+    }
+
+    /// Part numbers uploaded so far for `upload_id`, ascending.
+    fn upload_parts(&self, upload_id: &str) -> Vec<u32> {
+        let prefix = format!("{}:", upload_key(upload_id));
+        let mut parts: Vec<u32> = self
+            .store
+            .list_keys()
+            .iter()
+            .filter_map(|k| k.strip_prefix(&prefix)?.parse().ok())
+            .collect();
+        parts.sort_unstable();
+        parts
+    }
+
+    /// Returns a blob's metadata without reading its value.
+    pub fn head(&self, key: &str) -> StoreResult<Option<BlobMeta>> {
+        Ok(self.meta.get(key).cloned())
     }
 
     pub fn get(&self, key: &str) -> StoreResult<Option<Vec<u8>>> {
         self.store.get(key)
     }
 
+    /// Reads `key` from disk, verifying its checksum. If the local copy is
+    /// damaged, fetches `GET {url}/blobs/{key}` from each of `replica_urls`
+    /// in turn until one returns bytes with the blob's etag, stores them like
+    /// a [`put`](Self::put) and returns them. The `ChecksumMismatch` is
+    /// returned if no replica has a good copy. Requests carry the token set
+    /// by [`with_repair_auth_token`](Self::with_repair_auth_token), if any.
+    pub fn get_with_repair(
+        &mut self,
+        key: &str,
+        replica_urls: &[String],
+    ) -> StoreResult<Option<Vec<u8>>> {
+        match self.read_verified(key)? {
+            VerifiedRead::Intact(data) => Ok(data),
+            VerifiedRead::Damaged(repair) => {
+                let copy = repair.fetch(replica_urls);
+                self.finish_repair(repair, copy)
+            },
+        }
+    }
+
+    /// The first half of [`get_with_repair`](Self::get_with_repair): reads
+    /// `key` from disk, verifying its checksum, and describes the repair a
+    /// damaged copy needs. A damaged blob with no known etag can't be
+    /// repaired and is returned as the `ChecksumMismatch`.
+    pub fn read_verified(&self, key: &str) -> StoreResult<VerifiedRead> {
+        let error = match self.store.get_verified(key) {
+            Err(err @ StoreError::ChecksumMismatch { .. }) => err,
+            other => return other.map(VerifiedRead::Intact),
+        };
+        let Some(etag) = self.meta.get(key).map(|m| m.etag.clone()) else {
+            return Err(error);
+        };
+        Ok(VerifiedRead::Damaged(Repair {
+            key: key.to_string(),
+            etag,
+            hash_algo: self.hash_algo,
+            auth_token: self.repair_auth_token.clone(),
+            error,
+        }))
+    }
+
+    /// The second half of [`get_with_repair`](Self::get_with_repair): stores
+    /// `copy`, as fetched by [`Repair::fetch`], like a [`put`](Self::put)
+    /// and returns it. Without a copy the `ChecksumMismatch` is returned. If
+    /// the blob was rewritten while the copy was fetched, the copy is stale
+    /// and the current value is returned instead.
+    pub fn finish_repair(
+        &mut self,
+        repair: Repair,
+        copy: Option<Vec<u8>>,
+    ) -> StoreResult<Option<Vec<u8>>> {
+        let Some(data) = copy else {
+            return Err(repair.error);
+        };
+        if self.meta.get(&repair.key).map(|m| m.etag.as_str()) != Some(repair.etag.as_str()) {
+            return self.store.get(&repair.key);
+        }
+        self.put(&repair.key, &data)?;
+        Ok(Some(data))
+    }
+
+    /// Like [`get`](Self::get), but a missing blob is `KeyNotFound`.
+    pub fn get_strict(&self, key: &str) -> StoreResult<Vec<u8>> {
+        self.store.get_strict(key)
+    }
+
     pub fn delete(&mut self, key: &str) -> StoreResult<()> {
-        self.store.delete(key)
+        let mut batch = WriteBatch::new();
+        batch
+            .delete(key)
+            .delete(times_key(key))
+            .delete(attrs_key(key));
+        self.store.write_batch(batch)?;
+        self.meta.remove(key);
+        self.replicate();
+        Ok(())
+    }
+
+    /// Deletes several blobs, writing all tombstones in one batch.
+    pub fn delete_many(&mut self, keys: &[String]) -> StoreResult<BulkDeleteResult> {
+        let mut result = BulkDeleteResult::default();
+        let mut batch = WriteBatch::new();
+        for key in keys {
+            if result.deleted.contains(key) || result.not_found.contains(key) {
+                continue;
+            }
+            if self.meta.contains_key(key) {
+                batch
+                    .delete(key.as_str())
+                    .delete(times_key(key))
+                    .delete(attrs_key(key));
+                result.deleted.push(key.clone());
+            } else {
+                result.not_found.push(key.clone());
+            }
+        }
+
+        self.store.write_batch(batch)?;
+        for key in &result.deleted {
+            self.meta.remove(key);
+        }
+        self.replicate();
+        Ok(result)
     }
 
     pub fn list_keys(&self) -> Vec<String> {
-        self.store.list_keys()
+        self.store
+            .list_keys()
+            .into_iter()
+            .filter(|k| !is_internal_key(k))
+            .collect()
+    }
+
+    /// Cached metadata for every blob, sorted by key. Reads no values.
+    pub fn list_meta(&self) -> Vec<BlobMeta> {
+        let mut metas: Vec<BlobMeta> = self.meta.values().cloned().collect();
+        metas.sort_by(|a, b| a.key.cmp(&b.key));
+        metas
+    }
+
+    /// How many blobs have keys starting with `prefix`, and their total
+    /// size, from the metadata cache. Internal bookkeeping keys, which the
+    /// store's [`scan_count`](KVStore::scan_count) would also match for an
+    /// empty prefix, are not counted.
+    pub fn prefix_summary(&self, prefix: &str) -> PrefixSummary {
+        self.meta
+            .values()
+            .filter(|meta| meta.key.starts_with(prefix))
+            .fold(PrefixSummary::default(), |acc, meta| PrefixSummary {
+                count: acc.count + 1,
+                total_bytes: acc.total_bytes + meta.size,
+            })
+    }
+
+    /// Up to `page_size` blob keys following `after`, in key order.
+    pub fn list_keys_paginated(
+        &self,
+        after: Option<&PaginationCursor>,
+        page_size: usize,
+    ) -> PaginatedList<String> {
+        let mut keys: Vec<String> = self.meta.keys().cloned().collect();
+        keys.sort_unstable();
+        self.stamp_cursor(PaginatedList::page(keys, after, page_size, |k| k))
+    }
+
+    /// Metadata counterpart of [`list_keys_paginated`](Self::list_keys_paginated).
+    pub fn list_meta_paginated(
+        &self,
+        after: Option<&PaginationCursor>,
+        page_size: usize,
+    ) -> PaginatedList<BlobMeta> {
+        let page = PaginatedList::page(self.list_meta(), after, page_size, |m| &m.key);
+        self.stamp_cursor(page)
+    }
+
+    fn stamp_cursor<T>(&self, mut page: PaginatedList<T>) -> PaginatedList<T> {
+        if let Some(cursor) = page.next_cursor.as_mut() {
+            cursor.volume_id = Some(self.volume_id.clone());
+        }
+        page
+    }
+
+    /// Reads a specific version of a blob, current or archived.
+    pub fn get_version(&self, key: &str, version: u64) -> StoreResult<Option<Vec<u8>>> {
+        if self.store.get(key)?.is_some() && version == self.current_version(key) {
+            return self.store.get(key);
+        }
+        self.store.get(&version_key(key, version))
+    }
+
+    /// Lists all retained versions of a blob, oldest first.
+    pub fn list_versions(&self, key: &str) -> Vec<VersionMeta> {
+        let mut versions: Vec<VersionMeta> = self
+            .archived_versions(key)
+            .into_iter()
+            .filter_map(|v| {
+                let data = self.store.get(&version_key(key, v)).ok()??;
+                Some(VersionMeta {
+                    version: v,
+                    size: data.len() as u64,
+                    is_latest: false,
+                })
+            })
+            .collect();
+
+        if let Ok(Some(data)) = self.store.get(key) {
+            versions.push(VersionMeta {
+                version: self.current_version(key),
+                size: data.len() as u64,
+                is_latest: true,
+            });
+        }
+        versions
+    }
+
+    fn make_meta(
+        &self,
+        key: &str,
+        data: &[u8],
+        created_at: SystemTime,
+        modified_at: SystemTime,
+        attrs: Option<HashMap<String, String>>,
+    ) -> BlobMeta {
+        BlobMeta {
+            key: key.to_string(),
+            etag: self.hash_algo.etag(data),
+            size: data.len() as u64,
+            volume_id: self.volume_id.clone(),
+            hash_algo: self.hash_algo,
+            created_at,
+            modified_at,
+            attrs,
+        }
+    }
+
+    /// Reloads the metadata cache, reading and hashing only the values of
+    /// blobs whose size and etag weren't stored with their timestamps.
+    fn rebuild_meta(&mut self) -> StoreResult<()> {
+        self.meta.clear();
+        for key in self.list_keys() {
+            self.refresh_meta(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the cached metadata of one blob from its bookkeeping keys.
+    fn refresh_meta(&mut self, key: &str) -> StoreResult<()> {
+        if !self.store.contains_key(key)? {
+            self.meta.remove(key);
+            return Ok(());
+        }
+        let times = self
+            .store
+            .get(&times_key(key))?
+            .and_then(|buf| decode_times(&buf));
+        let attrs = self.get_attrs(key)?;
+        let meta = match times {
+            Some(StoredTimes {
+                created_at,
+                modified_at,
+                size_and_etag: Some((size, hash_algo, etag)),
+            }) if hash_algo == self.hash_algo => BlobMeta {
+                key: key.to_string(),
+                etag,
+                size,
+                volume_id: self.volume_id.clone(),
+                hash_algo,
+                created_at,
+                modified_at,
+                attrs,
+            },
+            _ => {
+                // Blobs written before timestamps were tracked report the epoch.
+                let (created_at, modified_at) =
+                    times.map_or((UNIX_EPOCH, UNIX_EPOCH), |t| (t.created_at, t.modified_at));
+                let data = self.store.get_strict(key)?;
+                self.make_meta(key, &data, created_at, modified_at, attrs)
+            },
+        };
+        self.meta.insert(key.to_string(), meta);
+        Ok(())
+    }
+
+    /// Applies a change streamed from a primary volume.
+    pub fn apply_change(&mut self, change: &ChangeRecord) -> StoreResult<()> {
+        self.store.apply_change(change)?;
+        let blob = change
+            .key
+            .strip_prefix(TIMES_PREFIX)
+            .or_else(|| change.key.strip_prefix(ATTRS_PREFIX))
+            .unwrap_or(&change.key);
+        if !is_internal_key(blob) {
+            self.refresh_meta(blob)?;
+        }
+        Ok(())
+    }
+
+    /// Resends every change after `since_lsn` to the secondary, for a
+    /// catchup it requested.
+    pub fn resend_since(&mut self, since_lsn: u64) -> StoreResult<()> {
+        let Some(replica) = &mut self.replica else {
+            return Ok(());
+        };
+        let last = replica.stream.send_since(&self.store, since_lsn)?;
+        replica.sent_lsn = replica.sent_lsn.max(last);
+        Ok(())
+    }
+
+    /// Sends writes the secondary hasn't seen yet. A failed send is logged
+    /// and stops replication rather than failing the write, which has
+    /// already been applied locally.
+    fn replicate(&mut self) {
+        let Some(replica) = &mut self.replica else {
+            return;
+        };
+        match replica.stream.send_since(&self.store, replica.sent_lsn) {
+            Ok(last) => replica.sent_lsn = last,
+            Err(e) => {
+                log::warn!("volume {} stopped replicating: {}", self.volume_id, e);
+                self.replica = None;
+            },
+        }
+    }
+
+    /// Archived version numbers for `key`, ascending.
+    fn archived_versions(&self, key: &str) -> Vec<u64> {
+        let prefix = format!("{}{}:", VERSION_PREFIX, key);
+        let mut versions: Vec<u64> = self
+            .store
+            .list_keys()
+            .iter()
+            .filter_map(|k| k.strip_prefix(&prefix)?.parse().ok())
+            .collect();
+        versions.sort_unstable();
+        versions
+    }
+
+    /// The version number of the value currently stored under `key`.
+    fn current_version(&self, key: &str) -> u64 {
+        self.archived_versions(key).last().map_or(1, |v| v + 1)
+    }
+
+    /// Moves the current value (if any) into the archive and prunes old versions.
+    fn archive_current(&mut self, key: &str) -> StoreResult<()> {
+        let Some(old) = self.store.get(key)? else {
+            return Ok(());
+        };
+        let version = self.current_version(key);
+        self.store.set(&version_key(key, version), &old)?;
+
+        let archived = self.archived_versions(key);
+        let excess = archived.len().saturating_sub(self.max_versions - 1);
+        for v in &archived[..excess] {
+            self.store.delete(&version_key(key, *v))?;
+        }
+        Ok(())
+    }
+
+    /// Whether live bytes are above the soft limit.
+    pub fn soft_limit_exceeded(&self) -> bool {
+        self.soft_limit_bytes
+            .is_some_and(|soft| self.used_bytes() > soft)
+    }
+
+    /// Percentage of the quota in use, relative to the hard limit when set.
+    pub fn quota_used_pct(&self) -> Option<f64> {
+        let limit = self.hard_limit_bytes.or(self.soft_limit_bytes)?;
+        if limit == 0 {
+            return Some(100.0);
+        }
+        Some(self.used_bytes() as f64 / limit as f64 * 100.0)
+    }
+
+    /// Bytes counted against the quota: blob data and archived versions, but
+    /// not the timestamp bookkeeping.
+    fn used_bytes(&self) -> u64 {
+        let times_bytes: u64 = self
+            .store
+            .list_keys()
+            .iter()
+            .filter(|k| k.starts_with(TIMES_PREFIX))
+            .filter_map(|k| self.store.get(k).ok().flatten())
+            .map(|v| v.len() as u64)
+            .sum();
+        self.stats().total_bytes.saturating_sub(times_bytes)
     }
 
     pub fn volume_id(&self) -> &str {
@@ -53,4 +1008,18 @@ pub fn volume_id(&self) -> &str {
     pub fn stats(&self) -> StoreStats {
         self.store.stats()
     }
+
+    /// Capacity report for the coordinator, as reachable at `url`. The
+    /// heartbeat time is left for the coordinator to stamp.
+    pub fn volume_info(&self, url: impl Into<String>) -> VolumeInfo {
+        VolumeInfo {
+            id: self.volume_id.clone(),
+            url: url.into(),
+            status: VolumeStatus::Online,
+            capacity_bytes: self.hard_limit_bytes.unwrap_or(0),
+            used_bytes: self.stats().total_bytes,
+            num_keys: self.meta.len(),
+            last_heartbeat_secs: 0,
+        }
+    }
 }
@@ -0,0 +1,60 @@
+#![cfg(feature = "async")]
+
+use mini_kvstore_v2::{KVStore, KVStoreActor};
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_sets_through_one_handle() {
+    let test_dir = "test_actor_db";
+    setup_test_dir(test_dir);
+
+    let (handle, actor) = KVStoreActor::spawn(KVStore::open(test_dir).unwrap());
+
+    let mut tasks = Vec::new();
+    for t in 0..16 {
+        let handle = handle.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..25 {
+                handle
+                    .set(format!("task_{}_key_{}", t, i), format!("v{}", i))
+                    .await
+                    .unwrap();
+                // Every task also races on one shared key; the last write wins.
+                handle
+                    .set(format!("shared_{}", i), format!("task_{}", t))
+                    .await
+                    .unwrap();
+            }
+            handle
+                .set(format!("task_{}_key_0", t), "final")
+                .await
+                .unwrap();
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    for t in 0..16 {
+        assert_eq!(
+            handle.get(format!("task_{}_key_0", t)).await.unwrap(),
+            Some(b"final".to_vec())
+        );
+        for i in 1..25 {
+            assert_eq!(
+                handle.get(format!("task_{}_key_{}", t, i)).await.unwrap(),
+                Some(format!("v{}", i).into_bytes())
+            );
+        }
+    }
+    handle.compact().await.unwrap();
+    handle.delete("shared_0").await.unwrap();
+
+    drop(handle);
+    let store = actor.join().unwrap();
+    assert_eq!(store.list_keys().len(), 16 * 25 + 24);
+    assert!(store.get("shared_1").unwrap().is_some());
+
+    cleanup_test_dir(test_dir);
+}
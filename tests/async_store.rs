@@ -0,0 +1,44 @@
+#![cfg(feature = "async")]
+
+use mini_kvstore_v2::AsyncKVStore;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_async_sets_and_gets() {
+    let test_dir = "test_async_store_db";
+    setup_test_dir(test_dir);
+
+    let store = AsyncKVStore::open(test_dir).unwrap();
+
+    let mut tasks = Vec::new();
+    for t in 0..8 {
+        let store = store.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..50 {
+                let key = format!("task_{}_key_{}", t, i);
+                store
+                    .set(key.clone(), format!("value_{}", i))
+                    .await
+                    .unwrap();
+                let value = store.get(key).await.unwrap();
+                assert_eq!(value, Some(format!("value_{}", i).into_bytes()));
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    for t in 0..8 {
+        for i in 0..50 {
+            let value = store.get(format!("task_{}_key_{}", t, i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{}", i).into_bytes()));
+        }
+    }
+
+    store.delete("task_0_key_0").await.unwrap();
+    assert_eq!(store.get("task_0_key_0").await.unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
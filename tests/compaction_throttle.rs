@@ -0,0 +1,38 @@
+#![cfg(feature = "heavy-tests")]
+
+use mini_kvstore_v2::KVStore;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+#[test]
+fn throttled_compaction_of_100mb_stays_near_limit() {
+    let test_dir = "test_compaction_throttle_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    let value = vec![0xabu8; 1024 * 1024];
+    for i in 0..100 {
+        store.set(&format!("blob_{:03}", i), &value).unwrap();
+    }
+
+    let limit = 50 * 1024 * 1024;
+    let started = std::time::Instant::now();
+    let stats = store.compact_with_throttle(limit).unwrap();
+    let wall = started.elapsed().as_secs_f64();
+
+    assert!(stats.bytes_written >= 100 * 1024 * 1024);
+    let observed = stats.bytes_written as f64 / wall;
+    assert!(
+        (observed - limit as f64).abs() <= limit as f64 * 0.2,
+        "observed {:.0} B/s, limit {} B/s",
+        observed,
+        limit
+    );
+    assert!(stats.avg_write_rate_bps as f64 <= limit as f64 * 1.2);
+
+    drop(store);
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.list_keys().len(), 100);
+
+    cleanup_test_dir(test_dir);
+}
@@ -0,0 +1,85 @@
+use mini_kvstore_v2::coord::handlers::create_router;
+use mini_kvstore_v2::coord::{BalancerKind, Coordinator, RegisteredVolume};
+use mini_kvstore_v2::volume::config::VolumeConfig;
+use mini_kvstore_v2::volume::VolumeServer;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+/// Polls the coordinator until `volume_id` satisfies `pred` or `timeout` passes.
+async fn wait_for_volume(
+    coordinator: &Arc<Mutex<Coordinator>>,
+    volume_id: &str,
+    timeout: Duration,
+    pred: impl Fn(&RegisteredVolume) -> bool,
+) -> Option<RegisteredVolume> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(volume) = coordinator.lock().unwrap().volume(volume_id) {
+            if pred(volume) {
+                return Some(volume.clone());
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    None
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn heartbeat_reports_used_bytes() {
+    let coord_dir = "test_coord_heartbeat_db";
+    let volume_dir = "test_coord_heartbeat_volume_db";
+    setup_test_dir(coord_dir);
+    setup_test_dir(volume_dir);
+
+    let coordinator = Arc::new(Mutex::new(
+        Coordinator::open(coord_dir, BalancerKind::LeastUsed).unwrap(),
+    ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let coord_url = format!("http://{}", listener.local_addr().unwrap());
+    let app = create_router(coordinator.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    coordinator
+        .lock()
+        .unwrap()
+        .register("vol-hb", "http://127.0.0.1:9102", 1)
+        .unwrap();
+    let server = VolumeServer::new(
+        VolumeConfig::new("vol-hb")
+            .with_data_dir(volume_dir)
+            .with_quota(None, Some(1 << 20))
+            .with_public_url("http://127.0.0.1:9102")
+            .with_coordinator_url(coord_url)
+            .with_heartbeat_interval(Duration::from_millis(20)),
+    )
+    .unwrap();
+
+    let first = wait_for_volume(&coordinator, "vol-hb", Duration::from_secs(5), |v| {
+        v.info.last_heartbeat_secs > 0
+    })
+    .await
+    .expect("no heartbeat received");
+    assert_eq!(first.info.capacity_bytes, 1 << 20);
+
+    server
+        .storage()
+        .lock()
+        .unwrap()
+        .put("blob", &[7u8; 4096])
+        .unwrap();
+
+    let after = wait_for_volume(&coordinator, "vol-hb", Duration::from_secs(5), |v| {
+        v.info.used_bytes > first.info.used_bytes
+    })
+    .await
+    .expect("used_bytes never increased");
+    assert_eq!(after.info.num_keys, first.info.num_keys + 1);
+    assert_eq!(after.info.url, "http://127.0.0.1:9102");
+
+    drop(server);
+    drop(coordinator);
+    cleanup_test_dir(coord_dir);
+    cleanup_test_dir(volume_dir);
+}
@@ -0,0 +1,153 @@
+use mini_kvstore_v2::{Index, KVStore, StoreConfig, StoreError};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+/// Tracks live heap bytes so the index estimate can be checked against reality.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::SeqCst);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Tests in this file run on parallel threads; the allocation counter is
+/// process-wide, so measurements must not overlap.
+static MEASURE: Mutex<()> = Mutex::new(());
+
+#[test]
+fn memory_estimate_tracks_actual_allocation() {
+    let _guard = MEASURE.lock().unwrap();
+
+    for (count, key_len) in [(1_000, 16), (50_000, 32), (200_000, 8)] {
+        let keys: Vec<Vec<u8>> = (0..count)
+            .map(|i| format!("{:0width$}", i, width = key_len).into_bytes())
+            .collect();
+
+        let before = ALLOCATED.load(Ordering::SeqCst);
+        let mut index = Index::new();
+        for (i, key) in keys.iter().enumerate() {
+            index.insert(key.clone(), 1, i as u64 * 64, 64);
+        }
+        let actual = ALLOCATED.load(Ordering::SeqCst) - before;
+        let estimate = index.memory_estimate_bytes();
+
+        let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+        assert!(
+            error <= 0.2,
+            "{} keys of {} bytes: estimate {} vs actual {}",
+            count,
+            key_len,
+            estimate,
+            actual
+        );
+        drop(index);
+    }
+}
+
+#[test]
+fn set_rejects_new_keys_past_index_memory_cap() {
+    let _guard = MEASURE.lock().unwrap();
+    let test_dir = "test_index_memory_cap_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_index_memory_bytes: Some(16 * 1024),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+
+    let mut accepted = 0;
+    let err = loop {
+        match store.set(&format!("key_{:06}", accepted), b"v") {
+            Ok(()) => accepted += 1,
+            Err(e) => break e,
+        }
+        assert!(accepted < 10_000, "cap never enforced");
+    };
+    assert!(matches!(err, StoreError::StoreFull));
+    assert!(accepted > 0);
+    assert!(store.stats().index_memory_bytes >= 16 * 1024);
+
+    // Overwrites don't grow the index, so they still go through.
+    store.set("key_000000", b"updated").unwrap();
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn set_many_rejects_batches_past_index_memory_cap() {
+    let _guard = MEASURE.lock().unwrap();
+    let test_dir = "test_index_memory_cap_batch_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_index_memory_bytes: Some(16 * 1024),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+
+    let batch = |start: usize, count: usize| {
+        (start..start + count)
+            .map(|i| (format!("key_{:06}", i), b"v".to_vec()))
+            .collect::<Vec<_>>()
+    };
+    store.set_many(batch(0, 100)).unwrap();
+
+    let err = store.set_many(batch(100, 1_000)).unwrap_err();
+    assert!(matches!(err, StoreError::StoreFull));
+    // Nothing from the refused batch was written.
+    assert_eq!(store.get("key_000100").unwrap(), None);
+    assert!(store.stats().index_memory_bytes <= 16 * 1024);
+
+    // Overwrites don't grow the index, so they still go through.
+    store.set_many(batch(0, 100)).unwrap();
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn shrink_to_fit_releases_capacity_after_deletes() {
+    let _guard = MEASURE.lock().unwrap();
+    let test_dir = "test_shrink_to_fit_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..5_000 {
+        store.set(&format!("key_{:05}", i), &[0u8; 32]).unwrap();
+    }
+    for i in 10..5_000 {
+        store.delete(&format!("key_{:05}", i)).unwrap();
+    }
+    let before = store.memory_estimate();
+    store.shrink_to_fit();
+    let after = store.memory_estimate();
+
+    assert!(after < before / 10, "{} -> {}", before, after);
+    assert!(after >= 10 * (9 + 32), "live data still counted");
+    assert_eq!(store.get("key_00003").unwrap(), Some(vec![0u8; 32]));
+
+    cleanup_test_dir(test_dir);
+}
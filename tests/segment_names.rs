@@ -0,0 +1,106 @@
+use mini_kvstore_v2::{migrate_segment_names, parse_segment_file_name, KVStore, StoreConfig};
+use std::path::Path;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+/// Segment file names in `dir`, sorted.
+fn segment_names(dir: &str) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| parse_segment_file_name(name).is_some())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Two sealed segments holding `a`, `b` (overwritten) and `c`.
+fn write_two_segments(config: &StoreConfig) {
+    let mut store = KVStore::from_config(config).unwrap();
+    store.set("a", b"1").unwrap();
+    store.set("b", b"old").unwrap();
+    store.reset_active_segment().unwrap();
+    store.set("b", b"2").unwrap();
+    store.set("c", b"3").unwrap();
+    store.close().unwrap();
+}
+
+fn assert_replayed(dir: &str) {
+    let store = KVStore::open_read_only(dir).unwrap();
+    assert_eq!(store.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get("b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get("c").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn legacy_names_replay_and_migrate() {
+    let test_dir = "test_segment_names_legacy_db";
+    setup_test_dir(test_dir);
+    write_two_segments(&StoreConfig {
+        data_path: test_dir.into(),
+        ..StoreConfig::default()
+    });
+    assert_eq!(
+        segment_names(test_dir),
+        ["segment-0000000001.dat", "segment-0000000002.dat"]
+    );
+
+    // Rename the segments the way older versions named them.
+    for id in [1, 2] {
+        let dir = Path::new(test_dir);
+        std::fs::rename(
+            dir.join(format!("segment-{:010}.dat", id)),
+            dir.join(format!("segment-{}.dat", id)),
+        )
+        .unwrap();
+    }
+    assert_replayed(test_dir);
+
+    assert_eq!(migrate_segment_names(Path::new(test_dir)).unwrap(), 2);
+    assert_eq!(
+        segment_names(test_dir),
+        ["segment-0000000001.dat", "segment-0000000002.dat"]
+    );
+    assert_eq!(migrate_segment_names(Path::new(test_dir)).unwrap(), 0);
+    assert_replayed(test_dir);
+
+    // A writable open picks up where the migrated segments leave off.
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("d", b"4").unwrap();
+    assert_eq!(store.active_segment_id(), 3);
+    assert!(Path::new(test_dir).join("segment-0000000003.dat").exists());
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn timestamped_names_replay() {
+    let test_dir = "test_segment_names_timestamp_db";
+    setup_test_dir(test_dir);
+    write_two_segments(&StoreConfig {
+        data_path: test_dir.into(),
+        timestamp_segment_names: true,
+        ..StoreConfig::default()
+    });
+
+    let names = segment_names(test_dir);
+    assert_eq!(names.len(), 2);
+    for (name, id) in names.iter().zip(["0000000001", "0000000002"]) {
+        let ts = name
+            .strip_prefix(&format!("segment-{}-", id))
+            .and_then(|rest| rest.strip_suffix(".dat"))
+            .unwrap_or_else(|| panic!("unexpected segment name {}", name));
+        assert!(ts.parse::<u64>().unwrap() > 0);
+    }
+    assert_replayed(test_dir);
+
+    // Timestamped names are already current; there is nothing to migrate.
+    assert_eq!(migrate_segment_names(Path::new(test_dir)).unwrap(), 0);
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.compact().unwrap();
+    assert_eq!(store.get("b").unwrap(), Some(b"2".to_vec()));
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
@@ -0,0 +1,47 @@
+use mini_kvstore_v2::{FsyncPolicy, StoreConfig, StoreStats};
+
+#[test]
+fn store_stats_round_trip() {
+    let stats = StoreStats {
+        num_keys: 42,
+        num_segments: 3,
+        total_bytes: 4096,
+        on_disk_bytes: 8192,
+        write_amplification: 2.5,
+        space_amplification: 2.0,
+        tombstone_count: 4,
+        active_segment_id: 7,
+        oldest_segment_id: 2,
+        active_lsn: 7 << 32,
+        cache_hits: 10,
+        cache_misses: 3,
+        index_memory_bytes: 1024,
+        eintr_retries: 2,
+        failed_syncs: 1,
+    };
+
+    let json = serde_json::to_string(&stats).unwrap();
+    let decoded: StoreStats = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, stats);
+}
+
+#[test]
+fn store_config_round_trip() {
+    let config = StoreConfig {
+        fsync_policy: FsyncPolicy::Interval,
+        max_segment_size: 4096,
+        data_path: "custom/path".into(),
+        ..StoreConfig::default()
+    };
+
+    let json = config.to_json_str().unwrap();
+    let decoded = StoreConfig::from_json_str(&json).unwrap();
+    assert_eq!(decoded.summary(), config.summary());
+
+    let partial = StoreConfig::from_json_str(r#"{"fsync_policy":"Never"}"#).unwrap();
+    assert!(matches!(partial.fsync_policy, FsyncPolicy::Never));
+    assert_eq!(
+        partial.max_segment_size,
+        StoreConfig::default().max_segment_size
+    );
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "async")]
+
+use mini_kvstore_v2::SharedKVStore;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn readers_run_alongside_writes_and_compaction() {
+    let test_dir = "test_shared_store_db";
+    setup_test_dir(test_dir);
+
+    let store = SharedKVStore::open(test_dir).unwrap();
+    for i in 0..100 {
+        store.set(&format!("key_{}", i), b"old").await.unwrap();
+    }
+
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        tasks.push(tokio::spawn(async move {
+            for round in 0..20 {
+                for i in 0..100 {
+                    let value = store.get(&format!("key_{}", i)).await.unwrap().unwrap();
+                    assert!(value == b"old" || value == b"new", "round {}", round);
+                }
+            }
+        }));
+    }
+    for i in 0..100 {
+        store.set(&format!("key_{}", i), b"new").await.unwrap();
+    }
+    store.compact().await.unwrap();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    for i in 0..100 {
+        let value = store.get(&format!("key_{}", i)).await.unwrap();
+        assert_eq!(value, Some(b"new".to_vec()));
+    }
+    store.delete("key_0").await.unwrap();
+    assert_eq!(store.get("key_0").await.unwrap(), None);
+    store.compact().await.unwrap();
+    assert_eq!(store.get("key_1").await.unwrap(), Some(b"new".to_vec()));
+
+    drop(store);
+    cleanup_test_dir(test_dir);
+}
@@ -1,4 +1,11 @@
-use mini_kvstore_v2::KVStore;
+use mini_kvstore_v2::{
+    lsn, lsn_position, CachePolicy, ChangeRecord, CheckpointInfo, CompactionJournal, Compression,
+    DefaultKeyValidator, FsyncPolicy, JournalAction, JsonCodec, KVStore, KeyGuard, Manifest,
+    OpStats, ReplicationRecord, Segment, SegmentState, StoreConfig, StoreError, TypedKVStore,
+    Verification,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 mod common;
 use common::{cleanup_test_dir, setup_test_dir};
 
@@ -33,3 +40,2430 @@ fn compaction_after_many_updates() {
 
     cleanup_test_dir(test_dir);
 }
+
+#[test]
+fn compacted_data_survives_reopen() {
+    let test_dir = "test_compact_reopen_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for round in 0..5 {
+        for i in 0..200 {
+            store
+                .set(
+                    &format!("key_{}", i),
+                    format!("value_{}_{}", i, round).as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+    store.delete("key_0").unwrap();
+
+    // ~5 KB of live records at 20 KB/s: throttled, but quick.
+    let stats = store.compact_with_throttle(20 * 1024).unwrap();
+    assert!(stats.bytes_written < stats.bytes_read);
+    assert!(stats.avg_write_rate_bps <= 20 * 1024 * 12 / 10);
+    assert_eq!(store.stats().num_segments, 1);
+
+    store.set("after", b"compaction").unwrap();
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("key_0").unwrap(), None);
+    for i in 1..200 {
+        assert_eq!(
+            store.get(&format!("key_{}", i)).unwrap(),
+            Some(format!("value_{}_4", i).into_bytes())
+        );
+    }
+    assert_eq!(store.get("after").unwrap(), Some(b"compaction".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compaction_estimate_reports_dead_bytes() {
+    let test_dir = "test_compaction_estimate_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for round in 0..5 {
+        let value = format!("value_{}", round);
+        store.set("key", value.as_bytes()).unwrap();
+    }
+    store.delete("gone").unwrap();
+
+    let est = store.compaction_estimate();
+    assert!(est.dead_bytes > 0, "overwrites should leave dead bytes");
+    assert!(est.live_bytes > 0);
+    assert_eq!(est.estimated_segments_after, 1);
+    assert_eq!(est.records_before, 6);
+    assert_eq!(est.tombstones_before, 1);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compaction_estimate_sizes_compressed_records_as_stored() {
+    let test_dir = "test_compaction_estimate_zstd_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        compression: Some(Compression::Zstd { level: 3 }),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    for round in 0..5u8 {
+        store.set("key", &[round; 100 * 1024]).unwrap();
+    }
+
+    let est = store.compaction_estimate();
+    assert!(est.dead_bytes > 0, "overwrites should leave dead bytes");
+    // The live record is compressed on disk, far below the value's length.
+    assert!(est.live_bytes > 0 && est.live_bytes < 1024);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn non_utf8_key_on_disk_replays_as_binary_key() {
+    let test_dir = "test_invalid_utf8_key_db";
+    setup_test_dir(test_dir);
+
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        store.set("ok", b"v").unwrap();
+    }
+
+    // Append a set record whose key bytes are not valid UTF-8 to the sealed
+    // segment; appending drops its footer.
+    let key = [0xff, 0xfe];
+    Segment::open(std::path::Path::new(test_dir), 1)
+        .unwrap()
+        .append(&key, b"x")
+        .unwrap();
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get_bytes_key(&key).unwrap(), Some(b"x".to_vec()));
+    assert_eq!(store.get("ok").unwrap(), Some(b"v".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn binary_keys_survive_reopen_and_compaction() {
+    let test_dir = "test_binary_keys_db";
+    setup_test_dir(test_dir);
+
+    let keys: [&[u8]; 4] = [
+        b"\0",
+        b"a\0b",
+        &[0xff, 0xfe, 0x80],
+        &[0xc3, 0x28, 0x00, 0xff],
+    ];
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            store.set_bytes_key(key, &[i as u8]).unwrap();
+        }
+        store.set_bytes_key(keys[0], b"overwritten").unwrap();
+        store.delete_bytes_key(keys[1]).unwrap();
+        assert_eq!(
+            store.get_bytes_key(keys[0]).unwrap(),
+            Some(b"overwritten".to_vec())
+        );
+        assert_eq!(store.get_bytes_key(&[0xff]).unwrap(), None);
+    }
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.compact().unwrap();
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(
+        store.get_bytes_key(keys[0]).unwrap(),
+        Some(b"overwritten".to_vec())
+    );
+    assert_eq!(store.get_bytes_key(keys[1]).unwrap(), None);
+    assert_eq!(store.get_bytes_key(keys[2]).unwrap(), Some(vec![2]));
+    assert_eq!(store.get_bytes_key(keys[3]).unwrap(), Some(vec![3]));
+    let mut listed = store.list_bytes_keys();
+    listed.sort();
+    assert_eq!(
+        listed,
+        vec![
+            b"\0".to_vec(),
+            vec![0xc3, 0x28, 0x00, 0xff],
+            vec![0xff, 0xfe, 0x80]
+        ]
+    );
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn max_total_bytes_evicts_least_recently_written() {
+    let test_dir = "test_max_total_bytes_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_total_bytes: Some(1000),
+        ..StoreConfig::default()
+    };
+    let value = [7u8; 100];
+    let mut evicted = Vec::new();
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        for i in 0..10 {
+            evicted.extend(
+                store
+                    .set_with_eviction(&format!("key_{}", i), &value)
+                    .unwrap(),
+            );
+        }
+        assert!(evicted.is_empty());
+
+        // Rewriting key_0 makes key_1 the oldest.
+        store.set("key_0", &value).unwrap();
+        for i in 10..13 {
+            evicted.extend(
+                store
+                    .set_with_eviction(&format!("key_{}", i), &value)
+                    .unwrap(),
+            );
+        }
+    }
+    assert_eq!(
+        evicted,
+        vec![b"key_1".to_vec(), b"key_2".to_vec(), b"key_3".to_vec()]
+    );
+
+    // Recency survives a reopen: the next write evicts key_4.
+    let mut store = KVStore::from_config(&config).unwrap();
+    let evicted = store.set_with_eviction("key_13", &value).unwrap();
+    assert_eq!(evicted, vec![b"key_4".to_vec()]);
+    assert_eq!(store.get("key_4").unwrap(), None);
+    for key in ["key_0", "key_5", "key_12", "key_13"] {
+        assert_eq!(store.get(key).unwrap(), Some(value.to_vec()));
+    }
+    assert_eq!(store.stats().total_bytes, 1000);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn set_many_evicts_older_keys_but_not_its_own() {
+    let test_dir = "test_set_many_eviction_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_total_bytes: Some(1000),
+        ..StoreConfig::default()
+    };
+    let value = [7u8; 100];
+    let mut store = KVStore::from_config(&config).unwrap();
+    for i in 0..10 {
+        store.set(&format!("key_{}", i), &value).unwrap();
+    }
+    store
+        .set_many((10..13).map(|i| (format!("key_{}", i), value.to_vec())))
+        .unwrap();
+
+    for key in ["key_0", "key_1", "key_2"] {
+        assert_eq!(store.get(key).unwrap(), None);
+    }
+    for key in ["key_3", "key_10", "key_11", "key_12"] {
+        assert_eq!(store.get(key).unwrap(), Some(value.to_vec()));
+    }
+    assert_eq!(store.stats().total_bytes, 1000);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn preallocated_segments_replay_correctly() {
+    let test_dir = "test_preallocate_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        preallocate_segment_bytes: Some(1024 * 1024),
+        ..StoreConfig::default()
+    };
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        for i in 0..50 {
+            store
+                .set(&format!("key_{}", i), format!("value_{}", i).as_bytes())
+                .unwrap();
+        }
+        store.delete("key_0").unwrap();
+    }
+
+    let store = KVStore::from_config(&config).unwrap();
+    assert_eq!(store.get("key_0").unwrap(), None);
+    for i in 1..50 {
+        assert_eq!(
+            store.get(&format!("key_{}", i)).unwrap(),
+            Some(format!("value_{}", i).into_bytes())
+        );
+    }
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn key_validator_rejects_empty_keys() {
+    let test_dir = "test_key_validator_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        key_validator: Some(Arc::new(DefaultKeyValidator)),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+
+    assert!(matches!(
+        store.set("", b"v"),
+        Err(StoreError::InvalidKey(_))
+    ));
+    assert!(matches!(store.get(""), Err(StoreError::InvalidKey(_))));
+    assert!(matches!(
+        store.delete("a\nb"),
+        Err(StoreError::InvalidKey(_))
+    ));
+    assert!(store.list_keys().is_empty());
+
+    store.set("fine", b"v").unwrap();
+    assert_eq!(store.get("fine").unwrap(), Some(b"v".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn delete_batch_counts_present_keys() {
+    let test_dir = "test_delete_batch_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..5 {
+        store.set(&format!("key_{}", i), b"v").unwrap();
+    }
+
+    let removed = store
+        .delete_batch(&["key_0", "key_2", "missing", "key_4", "key_0"])
+        .unwrap();
+    assert_eq!(removed, 3);
+    assert_eq!(store.get("key_0").unwrap(), None);
+    assert_eq!(store.get("key_1").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(store.list_keys().len(), 2);
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    let mut keys = store.list_keys();
+    keys.sort();
+    assert_eq!(keys, vec!["key_1".to_string(), "key_3".to_string()]);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn rename_moves_value_and_survives_reopen() {
+    let test_dir = "test_rename_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("user:alice", b"profile").unwrap();
+    assert!(store.rename("user:alice", "user:alicia").unwrap());
+    assert_eq!(store.get("user:alice").unwrap(), None);
+    assert_eq!(store.get("user:alicia").unwrap(), Some(b"profile".to_vec()));
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.list_keys(), vec!["user:alicia".to_string()]);
+    assert_eq!(store.get("user:alicia").unwrap(), Some(b"profile".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn rename_of_absent_key_writes_nothing() {
+    let test_dir = "test_rename_absent_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("other", b"v").unwrap();
+    let lsn = store.next_lsn();
+    assert!(!store.rename("missing", "other").unwrap());
+    assert_eq!(store.next_lsn(), lsn);
+    assert_eq!(store.get("other").unwrap(), Some(b"v".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn rename_overwrites_existing_destination() {
+    let test_dir = "test_rename_overwrite_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("from", b"new").unwrap();
+    store.set("to", b"old").unwrap();
+    assert!(store.rename("from", "to").unwrap());
+    assert_eq!(store.get("to").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(store.get("from").unwrap(), None);
+    assert_eq!(store.list_keys().len(), 1);
+
+    // Renaming a key onto itself keeps it.
+    assert!(store.rename("to", "to").unwrap());
+    assert_eq!(store.get("to").unwrap(), Some(b"new".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn scan_count_and_value_bytes_by_prefix() {
+    let test_dir = "test_scan_count_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..10 {
+        store.set(&format!("user:{}", i), &[b'u'; 7]).unwrap();
+    }
+    for i in 0..4 {
+        store.set(&format!("order:{}", i), &[b'o'; 100]).unwrap();
+    }
+    for i in 0..3 {
+        store.set(&format!("session:{}", i), b"").unwrap();
+    }
+    // Overwritten and deleted keys count as they are now.
+    store.set("user:0", &[b'u'; 20]).unwrap();
+    store.delete("order:3").unwrap();
+
+    assert_eq!(store.scan_count("user:"), 10);
+    assert_eq!(store.value_bytes_for_prefix("user:"), 9 * 7 + 20);
+    assert_eq!(store.scan_count("order:"), 3);
+    assert_eq!(store.value_bytes_for_prefix("order:"), 300);
+    assert_eq!(store.scan_count("session:"), 3);
+    assert_eq!(store.value_bytes_for_prefix("session:"), 0);
+    assert_eq!(store.scan_count(""), 16);
+    assert_eq!(store.scan_count("nothing:"), 0);
+    assert_eq!(store.value_bytes_for_prefix("nothing:"), 0);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn set_many_writes_all_entries_faster_than_single_sets() {
+    let single_dir = "test_set_many_single_db";
+    let batch_dir = "test_set_many_batch_db";
+    setup_test_dir(single_dir);
+    setup_test_dir(batch_dir);
+    let entries: Vec<(String, Vec<u8>)> = (0..1_000)
+        .map(|i| (format!("key_{:04}", i), format!("value_{}", i).into_bytes()))
+        .collect();
+
+    // With fsync off, single sets and set_many differ only in how they
+    // append, so compare that alone rather than 1000 fsyncs against one.
+    let open = |dir: &str| {
+        KVStore::from_config(&StoreConfig {
+            data_path: dir.into(),
+            fsync_policy: FsyncPolicy::Never,
+            ..StoreConfig::default()
+        })
+        .unwrap()
+    };
+    // Best of a few rounds, so a scheduling hiccup in one doesn't decide it.
+    let mut single = open(single_dir);
+    let mut batched = open(batch_dir);
+    let (mut single_elapsed, mut batch_elapsed) = (Duration::MAX, Duration::MAX);
+    for _ in 0..3 {
+        let started = Instant::now();
+        for (key, value) in &entries {
+            single.set(key, value).unwrap();
+        }
+        single_elapsed = single_elapsed.min(started.elapsed());
+
+        let batch = entries.clone();
+        let started = Instant::now();
+        assert_eq!(batched.set_many(batch).unwrap(), 1_000);
+        batch_elapsed = batch_elapsed.min(started.elapsed());
+    }
+    assert!(
+        batch_elapsed < single_elapsed,
+        "1000 sets: {:?}, set_many: {:?}",
+        single_elapsed,
+        batch_elapsed
+    );
+    drop(batched);
+
+    let reopened = KVStore::open(batch_dir).unwrap();
+    assert_eq!(reopened.list_keys().len(), 1_000);
+    for (key, value) in entries.iter().step_by(97) {
+        assert_eq!(reopened.get(key).unwrap().as_ref(), Some(value));
+    }
+    drop(single);
+    cleanup_test_dir(single_dir);
+    cleanup_test_dir(batch_dir);
+}
+
+#[test]
+fn bulk_load_sorted_keys() {
+    let test_dir = "test_bulk_load_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("existing", b"kept").unwrap();
+
+    let stats = store
+        .bulk_load((0..100_000).map(|i| (format!("key_{:06}", i), format!("v{}", i).into_bytes())))
+        .unwrap();
+    assert_eq!(stats.records, 100_000);
+
+    assert_eq!(store.get("existing").unwrap(), Some(b"kept".to_vec()));
+    for i in (0..100_000).step_by(997) {
+        assert_eq!(
+            store.get(&format!("key_{:06}", i)).unwrap(),
+            Some(format!("v{}", i).into_bytes())
+        );
+    }
+    assert_eq!(store.list_keys().len(), 100_001);
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.list_keys().len(), 100_001);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn bulk_load_rejects_unsorted_input() {
+    let test_dir = "test_bulk_load_unsorted_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    let input = vec![
+        ("a".to_string(), b"1".to_vec()),
+        ("c".to_string(), b"2".to_vec()),
+        ("b".to_string(), b"3".to_vec()),
+    ];
+    assert!(matches!(
+        store.bulk_load(input),
+        Err(StoreError::UnsortedInput { .. })
+    ));
+    assert!(store.list_keys().is_empty());
+
+    // Nothing from the aborted load survives a reopen either.
+    store.set("d", b"4").unwrap();
+    drop(store);
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.list_keys(), vec!["d".to_string()]);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn bulk_load_discards_everything_on_a_rejected_key() {
+    let test_dir = "test_bulk_load_invalid_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        key_validator: Some(Arc::new(DefaultKeyValidator)),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    let input = vec![
+        ("a".to_string(), b"1".to_vec()),
+        ("b\n".to_string(), b"2".to_vec()),
+    ];
+    assert!(matches!(
+        store.bulk_load(input),
+        Err(StoreError::InvalidKey(_))
+    ));
+    assert_eq!(store.get("a").unwrap(), None);
+    drop(store);
+
+    let store = KVStore::from_config(&config).unwrap();
+    assert_eq!(store.get("a").unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn bulk_load_applies_the_index_cap_and_eviction() {
+    let test_dir = "test_bulk_load_limits_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_index_memory_bytes: Some(16 * 1024),
+        max_total_bytes: Some(1000),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    let keys = |count: usize| (0..count).map(|i| (format!("key_{:06}", i), vec![7u8; 100]));
+
+    assert!(matches!(
+        store.bulk_load(keys(1_000)),
+        Err(StoreError::StoreFull)
+    ));
+    assert!(store.list_keys().is_empty());
+
+    // Older keys make room for the load; the loaded ones stay.
+    for i in 0..5 {
+        store.set(&format!("old_{}", i), &[1u8; 100]).unwrap();
+    }
+    store.bulk_load(keys(8)).unwrap();
+    assert_eq!(store.get("old_0").unwrap(), None);
+    assert_eq!(store.get("old_2").unwrap(), None);
+    assert!(store.get("old_3").unwrap().is_some());
+    assert!(store.get("key_000000").unwrap().is_some());
+    assert_eq!(store.stats().total_bytes, 1000);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn set_is_throttled_near_segment_limit() {
+    let test_dir = "test_write_throttle_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_segment_size: 1000,
+        max_records_per_segment: Some(4),
+        enable_write_throttling: true,
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+
+    // A rotation with little dead data doesn't call for a compaction.
+    for i in 0..4 {
+        store.set(&format!("key{}", i), b"v").unwrap();
+    }
+    assert_eq!(store.segment_ids().len(), 2);
+    assert!(!store.pending_compaction());
+
+    // Overwrites kill most of the log; the next rotation notices.
+    for _ in 0..4 {
+        store.set("key0", b"w").unwrap();
+    }
+    assert!(store.pending_compaction());
+
+    // 13-byte header + 4 + 880 = 897 bytes, under 90% of the segment.
+    store.set("fill", &[0u8; 880]).unwrap();
+    store.set("a", b"1").unwrap();
+    // Now past 90% with a compaction pending.
+    match store.set("b", b"2") {
+        Err(StoreError::WriteThrottled { suggested_delay_ms }) => assert!(suggested_delay_ms > 0),
+        other => panic!("expected WriteThrottled, got {:?}", other),
+    }
+    assert_eq!(store.get("b").unwrap(), None);
+
+    store.compact().unwrap();
+    assert!(!store.pending_compaction());
+    store.set("b", b"2").unwrap();
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn from_config_applies_custom_settings() {
+    let test_dir = "test_from_config_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_segment_size: 4096,
+        ..StoreConfig::default()
+    };
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        assert_eq!(store.config().max_segment_size, 4096);
+        assert_eq!(store.base_dir(), std::path::PathBuf::from(test_dir));
+
+        // Filling a 4 KiB segment starts a new one; a default 16 MiB one
+        // would take it all.
+        store.set("fill", &[0u8; 3800]).unwrap();
+        assert_eq!(store.segment_ids().len(), 1);
+        store.set("more", &[0u8; 300]).unwrap();
+        assert_eq!(store.segment_ids().len(), 2);
+        store.set("next", b"v").unwrap();
+        assert_eq!(
+            store.locate("next").unwrap().0 as u64,
+            store.active_segment_id()
+        );
+    }
+
+    // `open` is `from_config` with the defaults and the given path.
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.config().data_path_str(), test_dir);
+    assert_eq!(
+        store.config().max_segment_size,
+        StoreConfig::default().max_segment_size
+    );
+    let segments = store.segment_ids().len();
+    store.set("big", &[0u8; 8000]).unwrap();
+    assert_eq!(store.segment_ids().len(), segments);
+    assert_eq!(store.get("fill").unwrap(), Some(vec![0u8; 3800]));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn fsync_policy_always_syncs_every_write() {
+    let test_dir = "test_fsync_always_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert!(matches!(store.config().fsync_policy, FsyncPolicy::Always));
+    store.set("a", b"1").unwrap();
+    store.delete("a").unwrap();
+    assert_eq!(store.inline_fsyncs(), 2);
+    // A batch is synced once, however many records it holds.
+    store
+        .set_many((0..10).map(|i| (format!("k{}", i), b"v".to_vec())))
+        .unwrap();
+    assert_eq!(store.inline_fsyncs(), 3);
+    store
+        .bulk_load((0..10).map(|i| (format!("load{}", i), b"v".to_vec())))
+        .unwrap();
+    assert_eq!(store.inline_fsyncs(), 4);
+    drop(store);
+    cleanup_test_dir(test_dir);
+
+    setup_test_dir(test_dir);
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        fsync_policy: FsyncPolicy::Never,
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    store.set("a", b"1").unwrap();
+    store.set_many([("b".to_string(), b"2".to_vec())]).unwrap();
+    assert_eq!(store.inline_fsyncs(), 0);
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn data_path_with_spaces_and_symbols() {
+    let test_dir = "test data_path #1 (ü & co)";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig::default().with_data_path(test_dir);
+    assert_eq!(config.data_path, std::path::Path::new(test_dir));
+    assert_eq!(config.data_path_str(), test_dir);
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        store.set("key", b"value").unwrap();
+    }
+    assert!(std::path::Path::new(test_dir)
+        .join("segment-0000000001.dat")
+        .exists());
+    let store = KVStore::from_config(&config).unwrap();
+    assert_eq!(store.get("key").unwrap(), Some(b"value".to_vec()));
+
+    drop(store);
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn first_and_last_key_span_live_keys() {
+    let test_dir = "test_first_last_key_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.first_key(), None);
+    assert_eq!(store.last_key(), None);
+
+    for key in ["mango", "apple", "zucchini", "banana", "Zebra"] {
+        store.set(key, b"v").unwrap();
+    }
+    assert_eq!(store.first_key().as_deref(), Some("Zebra"));
+    assert_eq!(store.last_key().as_deref(), Some("zucchini"));
+
+    store.delete("Zebra").unwrap();
+    store.delete("zucchini").unwrap();
+    assert_eq!(store.first_key().as_deref(), Some("apple"));
+    assert_eq!(store.last_key().as_deref(), Some("mango"));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn get_strict_reports_missing_keys() {
+    let test_dir = "test_get_strict_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    match store.get_strict("never") {
+        Err(StoreError::KeyNotFound(key)) => assert_eq!(key, "never"),
+        other => panic!("expected KeyNotFound, got {:?}", other),
+    }
+
+    store.set("gone", b"v").unwrap();
+    assert_eq!(store.get_strict("gone").unwrap(), b"v".to_vec());
+    store.delete("gone").unwrap();
+    assert!(matches!(
+        store.get_strict("gone"),
+        Err(StoreError::KeyNotFound(key)) if key == "gone"
+    ));
+    assert_eq!(store.get("gone").unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn add_and_members_with_prefix() {
+    let test_dir = "test_set_members_db";
+    setup_test_dir(test_dir);
+
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        for member in ["tags:rust", "tags:db", "tags:kv", "users:ann"] {
+            store.add(member).unwrap();
+        }
+        store.add("tags:db").unwrap();
+        store.delete("tags:kv").unwrap();
+        assert_eq!(store.get("tags:rust").unwrap(), Some(Vec::new()));
+    }
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(
+        store.members_with_prefix("tags:"),
+        vec!["tags:db".to_string(), "tags:rust".to_string()]
+    );
+    assert_eq!(
+        store.members_with_prefix("users:"),
+        vec!["users:ann".to_string()]
+    );
+    assert!(store.members_with_prefix("groups:").is_empty());
+    assert_eq!(store.members_with_prefix("").len(), 3);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn verify_uses_footer_of_sealed_segments() {
+    let test_dir = "test_verify_footer_db";
+    setup_test_dir(test_dir);
+
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        for i in 0..50 {
+            store.set(&format!("key_{}", i), b"sealed value").unwrap();
+        }
+    }
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("fresh", b"active value").unwrap();
+
+    // Segment 1 was sealed on close; the active segment has no footer yet.
+    assert_eq!(
+        store.verify().unwrap(),
+        vec![(1, Verification::Footer), (2, Verification::Records(1))]
+    );
+    drop(store);
+
+    // Flip a value byte in the first record of segment 1.
+    let path = std::path::Path::new(test_dir).join("segment-0000000001.dat");
+    let mut data = std::fs::read(&path).unwrap();
+    data[13 + "key_0".len()] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+
+    let mut segment = Segment::open(std::path::Path::new(test_dir), 1).unwrap();
+    assert!(matches!(
+        segment.verify(),
+        Err(StoreError::ChecksumMismatch {
+            segment_id: 1,
+            offset: 0
+        })
+    ));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn checkpoint_seals_the_active_segment() {
+    let test_dir = "test_checkpoint_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..10 {
+        store.set(&format!("key_{}", i), b"before").unwrap();
+    }
+    let sealed_id = store.active_segment_id();
+    let info = store.checkpoint().unwrap();
+    assert_eq!(info.segment_id as u64, sealed_id);
+    assert_eq!(info.lsn, lsn(sealed_id, info.byte_offset));
+    assert_eq!(store.active_segment_id(), sealed_id + 1);
+
+    store.set("after", b"checkpoint").unwrap();
+    store.set("key_0", b"overwritten").unwrap();
+
+    let mut sealed = Segment::open(std::path::Path::new(test_dir), info.segment_id).unwrap();
+    assert_eq!(sealed.len(), info.byte_offset);
+    assert_eq!(sealed.verify().unwrap(), Verification::Footer);
+    assert_eq!(sealed.scan_from_offset(0).unwrap().count(), 10);
+
+    let saved: CheckpointInfo = serde_json::from_slice(
+        &std::fs::read(std::path::Path::new(test_dir).join("CHECKPOINT")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(saved, info);
+
+    drop(store);
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("key_0").unwrap(), Some(b"overwritten".to_vec()));
+    assert_eq!(store.get("after").unwrap(), Some(b"checkpoint".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn open_errors_name_the_failing_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_dir = "test_io_path_context_db";
+    setup_test_dir(test_dir);
+    std::fs::create_dir_all(test_dir).unwrap();
+    std::fs::set_permissions(test_dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Permission bits don't apply to root, so there is nothing to observe.
+    let probe = std::path::Path::new(test_dir).join("probe");
+    if std::fs::File::create(&probe).is_ok() {
+        std::fs::set_permissions(test_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        cleanup_test_dir(test_dir);
+        return;
+    }
+
+    let err = KVStore::open(test_dir).unwrap_err();
+    std::fs::set_permissions(test_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(matches!(err, StoreError::IoWithPath { .. }), "{:?}", err);
+    assert!(err.to_string().contains(test_dir), "{}", err);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn op_stats_time_each_operation() {
+    let test_dir = "test_op_stats_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::from_config(&StoreConfig {
+        data_path: test_dir.into(),
+        collect_timings: true,
+        ..StoreConfig::default()
+    })
+    .unwrap();
+    const N: u64 = 25;
+    for i in 0..N {
+        store.set(&format!("key_{}", i), b"value").unwrap();
+    }
+    store.get("key_0").unwrap();
+    store.delete("key_1").unwrap();
+
+    let stats = store.op_stats();
+    assert_eq!(stats.set_count, N);
+    assert!(stats.set_nanos > 0);
+    assert!(stats.avg_set_latency() > std::time::Duration::ZERO);
+    assert_eq!(stats.get_count, 1);
+    assert_eq!(stats.delete_count, 1);
+    drop(store);
+
+    // Off by default.
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("key_0", b"again").unwrap();
+    assert_eq!(store.op_stats(), OpStats::default());
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compact_prefix_only_rewrites_segments_holding_the_prefix() {
+    let test_dir = "test_compact_prefix_db";
+    setup_test_dir(test_dir);
+    let segment_size = |id: u64| {
+        std::fs::metadata(format!("{}/segment-{:010}.dat", test_dir, id)).map(|m| m.len())
+    };
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..20 {
+        store.set(&format!("keep:{}", i), b"kept value").unwrap();
+    }
+    store.checkpoint().unwrap();
+    for i in 0..200 {
+        store.set(&format!("tenant:{}", i), &[7u8; 256]).unwrap();
+    }
+    store.checkpoint().unwrap();
+    for i in 0..20 {
+        store.set(&format!("other:{}", i), b"other value").unwrap();
+    }
+    store.checkpoint().unwrap();
+    assert_eq!(store.segment_ids(), vec![1, 2, 3, 4]);
+    let untouched = (segment_size(1).unwrap(), segment_size(3).unwrap());
+
+    assert_eq!(store.delete_prefix("tenant:").unwrap(), 200);
+    // Written next to the tombstones, and must survive the rewrite.
+    store.set("tenant:new", b"fresh").unwrap();
+    let active_bytes = segment_size(4).unwrap();
+    store.compact_prefix("tenant:").unwrap();
+
+    // Segment 2 only held dead tenant records and is gone; the sealed active
+    // segment shrank to the new key; 1 and 3 never had tenant keys.
+    assert_eq!(store.segment_ids(), vec![1, 3, 4, 5]);
+    assert!(segment_size(2).is_err());
+    assert!(segment_size(4).unwrap() < active_bytes / 10);
+    assert_eq!(
+        (segment_size(1).unwrap(), segment_size(3).unwrap()),
+        untouched
+    );
+    assert_eq!(store.stats().tombstone_count, 0);
+
+    drop(store);
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("tenant:7").unwrap(), None);
+    assert_eq!(store.get("tenant:new").unwrap(), Some(b"fresh".to_vec()));
+    assert_eq!(store.get("keep:3").unwrap(), Some(b"kept value".to_vec()));
+    assert_eq!(store.get("other:3").unwrap(), Some(b"other value".to_vec()));
+    assert_eq!(store.list_keys().len(), 41);
+
+    cleanup_test_dir(test_dir);
+}
+
+/// Read the counter under `key`, write back one more and return what was read.
+fn increment(guard: &KeyGuard<'_>) -> u64 {
+    let current = guard
+        .get()
+        .unwrap()
+        .map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+        .unwrap_or(0);
+    guard.set(&(current + 1).to_le_bytes()).unwrap();
+    current
+}
+
+#[test]
+fn lock_key_isolates_read_modify_write_per_key() {
+    let test_dir = "test_lock_key_distinct_db";
+    setup_test_dir(test_dir);
+    let store = Arc::new(Mutex::new(KVStore::open(test_dir).unwrap()));
+
+    let handles: Vec<_> = (0..100)
+        .map(|i| {
+            let store = Arc::clone(&store);
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    let guard = KVStore::lock_key(&store, &format!("counter:{}", i));
+                    increment(&guard);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let store = store.lock().unwrap();
+    for i in 0..100 {
+        assert_eq!(
+            store.get(&format!("counter:{}", i)).unwrap(),
+            Some(20u64.to_le_bytes().to_vec())
+        );
+    }
+    drop(store);
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn lock_key_serializes_writers_of_the_same_key() {
+    let test_dir = "test_lock_key_shared_db";
+    setup_test_dir(test_dir);
+    let store = Arc::new(Mutex::new(KVStore::open(test_dir).unwrap()));
+
+    let handles: Vec<_> = (0..16)
+        .map(|_| {
+            let store = Arc::clone(&store);
+            std::thread::spawn(move || {
+                (0..50)
+                    .map(|_| increment(&KVStore::lock_key(&store, "shared")))
+                    .collect::<Vec<u64>>()
+            })
+        })
+        .collect();
+    let mut seen: Vec<u64> = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+
+    // Every increment saw the previous one's write: no value was read twice.
+    seen.sort_unstable();
+    assert_eq!(seen, (0..800).collect::<Vec<u64>>());
+    assert_eq!(
+        store.lock().unwrap().get("shared").unwrap(),
+        Some(800u64.to_le_bytes().to_vec())
+    );
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn index_snapshot_locates_keys() {
+    let test_dir = "test_index_snapshot_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("a", b"first").unwrap();
+    store.set("b", b"second").unwrap();
+    store.set("c", b"gone").unwrap();
+    store.delete("c").unwrap();
+    store.checkpoint().unwrap();
+    store.set("a", b"moved").unwrap();
+
+    let record =
+        |key: &str, value: &[u8]| Segment::record_size(key.len() as u64, value.len() as u64);
+    assert_eq!(
+        store.index_snapshot(),
+        vec![
+            ("a".to_string(), 2, 0, record("a", b"moved")),
+            (
+                "b".to_string(),
+                1,
+                record("a", b"first"),
+                record("b", b"second")
+            ),
+        ]
+    );
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn get_reader_streams_large_values() {
+    use std::io::Read;
+
+    let test_dir = "test_get_reader_db";
+    setup_test_dir(test_dir);
+
+    // Pseudo-random bytes don't compress, so this one is stored raw.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let big: Vec<u8> = (0..3 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let text = "compressible ".repeat(4096).into_bytes();
+    let mut store = KVStore::from_config(&StoreConfig {
+        data_path: test_dir.into(),
+        compression: Some(Compression::Zstd { level: 3 }),
+        ..StoreConfig::default()
+    })
+    .unwrap();
+    store.set("big", &big).unwrap();
+    store.set("text", &text).unwrap();
+    assert_eq!(
+        store.describe_key("big").unwrap().unwrap().value_len,
+        big.len() as u64
+    );
+    assert!(store.describe_key("text").unwrap().unwrap().value_len < text.len() as u64);
+
+    for (key, expected) in [("big", &big), ("text", &text)] {
+        let mut reader = store.get_reader(key).unwrap().unwrap();
+        let mut read = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = reader.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            read.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(read.len(), expected.len(), "{}", key);
+        assert!(read == *expected, "{} differs", key);
+    }
+
+    store.delete("big").unwrap();
+    assert!(store.get_reader("big").unwrap().is_none());
+    assert!(store.get_reader("missing").unwrap().is_none());
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn describe_key_reads_record_header() {
+    let test_dir = "test_describe_key_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("first", b"x").unwrap();
+    store.set("key", b"hello world").unwrap();
+
+    let active = *store.segment_ids().last().unwrap() as usize;
+    let desc = store.describe_key("key").unwrap().unwrap();
+    assert_eq!(desc.key, "key");
+    assert_eq!(desc.segment_id, active);
+    assert_eq!(desc.offset, Segment::record_size(5, 1));
+    assert_eq!(desc.value_len, b"hello world".len() as u64);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(b"key");
+    hasher.update(b"hello world");
+    assert_eq!(desc.checksum, hasher.finalize());
+    assert!(!desc.is_tombstone);
+    assert!(desc.last_modified_secs.is_some());
+
+    store.delete("key").unwrap();
+    assert_eq!(store.describe_key("key").unwrap(), None);
+    assert_eq!(store.describe_key("missing").unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn block_cache_serves_repeated_disk_reads() {
+    let test_dir = "test_block_cache_hits_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..100 {
+        store.set(&format!("key{:03}", i), &[b'v'; 100]).unwrap();
+    }
+    assert_eq!(
+        (store.stats().cache_hits, store.stats().cache_misses),
+        (0, 0)
+    );
+
+    store.describe_key("key000").unwrap().unwrap();
+    assert_eq!(
+        (store.stats().cache_hits, store.stats().cache_misses),
+        (0, 1)
+    );
+    // Same block, now cached.
+    store.describe_key("key001").unwrap().unwrap();
+    assert_eq!(
+        (store.stats().cache_hits, store.stats().cache_misses),
+        (1, 1)
+    );
+
+    // Replication reads go through the same cache.
+    let changes = store.tail(0).unwrap().count();
+    assert_eq!(changes, 100);
+    let hits = store.stats().cache_hits;
+    assert_eq!(store.tail(0).unwrap().count(), 100);
+    assert!(store.stats().cache_hits > hits);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn cache_policy_decides_which_block_is_evicted() {
+    // Reads block A three times and B once, then C, which evicts one of
+    // them from a two-block cache; a final read of A shows which.
+    let hits_with = |policy: CachePolicy, test_dir: &str| {
+        setup_test_dir(test_dir);
+        let config = StoreConfig {
+            data_path: test_dir.into(),
+            block_cache_bytes: 2 * 4096,
+            cache_policy: policy,
+            ..StoreConfig::default()
+        };
+        let mut store = KVStore::from_config(&config).unwrap();
+        for i in 0..200 {
+            store.set(&format!("key{:03}", i), &[b'v'; 100]).unwrap();
+        }
+        // The first key whose header lies wholly inside each block.
+        let in_block = |block: u64| {
+            (0..200)
+                .map(|i| format!("key{:03}", i))
+                .find(|key| {
+                    let (_, offset, _) = store.locate(key).unwrap();
+                    offset / 4096 == block && offset % 4096 <= 4096 - 13
+                })
+                .unwrap()
+        };
+        let (a, b, c) = (in_block(0), in_block(1), in_block(2));
+        for key in [&a, &a, &a, &b, &c, &a] {
+            store.describe_key(key).unwrap().unwrap();
+        }
+        let hits = store.stats().cache_hits;
+        drop(store);
+        cleanup_test_dir(test_dir);
+        hits
+    };
+
+    // LRU evicts A, read longest ago; LFU evicts B, read least often.
+    assert_eq!(hits_with(CachePolicy::Lru, "test_cache_policy_lru_db"), 2);
+    assert_eq!(hits_with(CachePolicy::Lfu, "test_cache_policy_lfu_db"), 3);
+}
+
+#[test]
+fn locate_reports_segment_of_key() {
+    let test_dir = "test_locate_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("old", b"sealed").unwrap();
+    store.checkpoint().unwrap();
+    store.set("new", b"active").unwrap();
+    store.set("gone", b"x").unwrap();
+    store.delete("gone").unwrap();
+
+    let segments = store.segment_ids();
+    let (old_seg, old_offset, old_len) = store.locate("old").unwrap();
+    let (new_seg, new_offset, new_len) = store.locate("new").unwrap();
+    assert!(segments.contains(&(old_seg as u64)));
+    assert!(segments.contains(&(new_seg as u64)));
+    assert!(old_seg < new_seg);
+    assert_eq!((old_offset, new_offset), (0, 0));
+    assert_eq!(old_len, Segment::record_size(3, 6));
+    assert_eq!(new_len, Segment::record_size(3, 6));
+    assert_eq!(store.locate("gone"), None);
+    assert_eq!(store.locate("missing"), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn display_summarizes_the_store() {
+    let test_dir = "test_display_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("a", &[0u8; 1024 * 1024]).unwrap();
+    store.checkpoint().unwrap();
+    store.set("b", b"small").unwrap();
+
+    let line = store.to_string();
+    assert!(!line.contains('\n'), "{}", line);
+    assert_eq!(
+        line,
+        format!(
+            "KVStore {{ dir: \"{}\", keys: 2, segments: 2 (active: segment-0000000002.dat), size: 1.00 MB }}",
+            test_dir
+        )
+    );
+    assert_eq!(format!("{:?}", store), line);
+
+    let report = format!("{:#}", store);
+    assert!(report.starts_with(&format!("KVStore at {}\n", test_dir)));
+    assert!(report.contains("Store Statistics:"));
+    assert!(report.contains("  Keys: 2"));
+    assert!(report.contains("  Segments: 2"));
+    assert!(report.contains("  Active segment: 2"));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn iter_segment_reads_a_single_segment() {
+    let test_dir = "test_iter_segment_db";
+    setup_test_dir(test_dir);
+
+    // Five 24-byte records fill a segment.
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_segment_size: 120,
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    for seg in 1..=3 {
+        for i in 0..5 {
+            store.set(&format!("seg{}:{}", seg, i), b"value").unwrap();
+        }
+    }
+    store.delete("seg1:4").unwrap();
+    assert_eq!(store.segment_ids(), vec![1, 2, 3, 4]);
+
+    let records: Vec<_> = store
+        .iter_segment(1)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let keys: Vec<&str> = records.iter().map(|(_, key, _)| key.as_str()).collect();
+    assert_eq!(keys, ["seg1:0", "seg1:1", "seg1:2", "seg1:3", "seg1:4"]);
+    assert_eq!(records[0].0, 0);
+    assert!(records.iter().all(|(_, _, value)| value.is_some()));
+
+    // The active segment is readable too.
+    let active: Vec<_> = store
+        .iter_segment(4)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(active, [(0, "seg1:4".to_string(), None)]);
+    assert_eq!(store.iter_segment(3).unwrap().count(), 5);
+    assert!(matches!(
+        store.iter_segment(7),
+        Err(StoreError::SegmentNotFound(7))
+    ));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn open_reports_whether_shutdown_was_clean() {
+    let test_dir = "test_clean_shutdown_db";
+    setup_test_dir(test_dir);
+    let marker = std::path::Path::new(test_dir).join("CLEAN_SHUTDOWN");
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert!(
+        store.was_clean_shutdown(),
+        "a new store has nothing to recover"
+    );
+    store.set("key", b"value").unwrap();
+    drop(store);
+    assert!(marker.exists());
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert!(store.was_clean_shutdown());
+    // Removed while open, so a crash from here on is detected.
+    assert!(!marker.exists());
+    store.close().unwrap();
+    assert!(marker.exists());
+
+    // Simulate a crash: the marker never got written.
+    std::fs::remove_file(&marker).unwrap();
+    let store = KVStore::open(test_dir).unwrap();
+    assert!(!store.was_clean_shutdown());
+    assert_eq!(store.get("key").unwrap(), Some(b"value".to_vec()));
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn dir_and_file_modes_apply_on_creation() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_dir = "test_dir_mode_db";
+    // `open` has to create the directory for the mode to apply.
+    cleanup_test_dir(test_dir);
+
+    let mut store = KVStore::from_config(&StoreConfig {
+        data_path: test_dir.into(),
+        dir_mode: Some(0o700),
+        file_mode: Some(0o600),
+        ..StoreConfig::default()
+    })
+    .unwrap();
+    store.set("key", b"value").unwrap();
+
+    let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(test_dir), 0o700);
+    assert_eq!(mode(&format!("{}/segment-0000000001.dat", test_dir)), 0o600);
+
+    drop(store);
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn export_segments_as_ndjson() {
+    use base64::Engine as _;
+
+    let test_dir = "test_export_ndjson_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..45 {
+        store
+            .set(&format!("key_{}", i), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    drop(store);
+
+    // Second segment, including tombstones.
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..5 {
+        store.delete(&format!("key_{}", i)).unwrap();
+    }
+
+    let output = format!("{}/export.ndjson", test_dir);
+    assert_eq!(store.export_all_segments_ndjson(&output).unwrap(), 50);
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let lines: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 50);
+
+    for (i, record) in lines[..45].iter().enumerate() {
+        assert_eq!(record["op"], "set");
+        assert_eq!(record["key"], format!("key_{}", i));
+        let value = base64::engine::general_purpose::STANDARD
+            .decode(record["value_b64"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(value, format!("value_{}", i).into_bytes());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(format!("key_{}", i).as_bytes());
+        crc.update(&value);
+        assert_eq!(record["checksum"], format!("0x{:08x}", crc.finalize()));
+    }
+    for (i, record) in lines[45..].iter().enumerate() {
+        assert_eq!(record["op"], "del");
+        assert_eq!(record["key"], format!("key_{}", i));
+        assert!(record.get("value_b64").is_none());
+    }
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compressed_records_need_the_matching_compressor() {
+    let test_dir = "test_compressor_db";
+    setup_test_dir(test_dir);
+
+    let zstd = StoreConfig {
+        data_path: test_dir.into(),
+        compression: Some(Compression::Zstd { level: 3 }),
+        ..StoreConfig::default()
+    };
+    {
+        let mut store = KVStore::from_config(&zstd).unwrap();
+        for i in 0..20 {
+            store
+                .set(
+                    &format!("key_{}", i),
+                    "compressible ".repeat(200).as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+
+    let null = StoreConfig {
+        compression: None,
+        ..zstd.clone()
+    };
+    match KVStore::from_config(&null) {
+        Err(StoreError::ChecksumMismatch { segment_id, offset }) => {
+            assert_eq!(segment_id, 1);
+            assert_eq!(offset, 0);
+        },
+        other => panic!("expected ChecksumMismatch, got {:?}", other.map(|_| ())),
+    }
+
+    let store = KVStore::from_config(&zstd).unwrap();
+    for i in 0..20 {
+        assert_eq!(
+            store.get(&format!("key_{}", i)).unwrap(),
+            Some("compressible ".repeat(200).into_bytes())
+        );
+    }
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn large_values_are_compressed_on_disk() {
+    let test_dir = "test_compression_flag_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        compression: Some(Compression::Zstd { level: 3 }),
+        ..StoreConfig::default()
+    };
+    let big = b"mini-kvstore ".repeat(1024 * 1024 / 13);
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        store.set("small", b"below the threshold").unwrap();
+        store.set("big", &big).unwrap();
+        assert_eq!(store.get("big").unwrap(), Some(big.clone()));
+    }
+
+    let on_disk: u64 = std::fs::read_dir(test_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum();
+    assert!(
+        on_disk < big.len() as u64 / 10,
+        "{} bytes on disk for a {} byte value",
+        on_disk,
+        big.len()
+    );
+
+    // Flagged and unflagged records in the same segment both read back.
+    let store = KVStore::from_config(&config).unwrap();
+    assert_eq!(store.get("big").unwrap(), Some(big));
+    assert_eq!(
+        store.get("small").unwrap(),
+        Some(b"below the threshold".to_vec())
+    );
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn merge_segments_keeps_latest_values() {
+    let test_dir = "test_merge_segments_db";
+    setup_test_dir(test_dir);
+    let dir = std::path::Path::new(test_dir);
+
+    {
+        let mut seg0 = Segment::open(dir, 0).unwrap();
+        seg0.append(b"shared", b"old").unwrap();
+        seg0.append(b"only_0", b"zero").unwrap();
+        seg0.append(b"deleted", b"gone soon").unwrap();
+        let mut seg1 = Segment::open(dir, 1).unwrap();
+        seg1.append(b"shared", b"new").unwrap();
+        seg1.append(b"only_1", b"one").unwrap();
+        seg1.append_tombstone(b"deleted").unwrap();
+    }
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.merge_segments(0, 1).unwrap();
+    assert!(!dir.join("segment-0000000000.dat").exists());
+
+    let mut merged = Segment::open(dir, 1).unwrap();
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some((key, value, next)) = merged.read_record_at(offset).unwrap() {
+        records.push((key, value));
+        offset = next;
+    }
+    assert_eq!(
+        records,
+        vec![
+            ("deleted".to_string(), None),
+            ("only_0".to_string(), Some(b"zero".to_vec())),
+            ("only_1".to_string(), Some(b"one".to_vec())),
+            ("shared".to_string(), Some(b"new".to_vec())),
+        ]
+    );
+
+    assert_eq!(store.get("shared").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(store.get("deleted").unwrap(), None);
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("shared").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(store.get("only_0").unwrap(), Some(b"zero".to_vec()));
+    assert_eq!(store.get("only_1").unwrap(), Some(b"one".to_vec()));
+    assert_eq!(store.get("deleted").unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn merge_segments_skips_records_overwritten_in_between() {
+    let test_dir = "test_merge_non_adjacent_db";
+    setup_test_dir(test_dir);
+    let dir = std::path::Path::new(test_dir);
+
+    {
+        Segment::open(dir, 0).unwrap().append(b"k", b"v0").unwrap();
+        Segment::open(dir, 1).unwrap().append(b"k", b"v1").unwrap();
+        Segment::open(dir, 2)
+            .unwrap()
+            .append(b"other", b"v2")
+            .unwrap();
+    }
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.merge_segments(0, 2).unwrap();
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("k").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(store.get("other").unwrap(), Some(b"v2".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn second_open_of_locked_dir_fails() {
+    let test_dir = "test_lock_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.set("key", b"value").unwrap();
+    assert!(matches!(
+        KVStore::open(test_dir),
+        Err(StoreError::AlreadyLocked(_))
+    ));
+
+    let mut reader = KVStore::open_read_only(test_dir).unwrap();
+    assert_eq!(reader.get("key").unwrap(), Some(b"value".to_vec()));
+    assert!(matches!(reader.set("key", b"x"), Err(StoreError::ReadOnly)));
+
+    drop(store);
+    KVStore::open(test_dir).unwrap();
+
+    cleanup_test_dir(test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_open_works_on_read_only_directory() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_dir = "test_read_only_dir_db";
+    setup_test_dir(test_dir);
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        store.set("sealed", b"old").unwrap();
+        store.checkpoint().unwrap();
+        store.set("tail", b"new").unwrap();
+    }
+    let listing = || {
+        let mut names: Vec<_> = std::fs::read_dir(test_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        names
+    };
+    let before = listing();
+    let manifest = std::fs::read(std::path::Path::new(test_dir).join("MANIFEST")).unwrap();
+    let set_mode = |mode: u32| {
+        for entry in std::fs::read_dir(test_dir).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o666)).unwrap();
+        }
+        std::fs::set_permissions(test_dir, std::fs::Permissions::from_mode(mode)).unwrap();
+    };
+    set_mode(0o555);
+
+    let result = std::panic::catch_unwind(|| {
+        let mut store = KVStore::from_config(&StoreConfig {
+            data_path: test_dir.into(),
+            read_only: true,
+            ..StoreConfig::default()
+        })
+        .unwrap();
+        assert_eq!(store.get("sealed").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(store.get("tail").unwrap(), Some(b"new".to_vec()));
+        assert!(matches!(store.set("k", b"v"), Err(StoreError::ReadOnly)));
+        assert_eq!(store.verify().unwrap().len(), store.segment_ids().len());
+        let first = store.segment_ids()[0] as usize;
+        assert_eq!(store.iter_segment(first).unwrap().count(), 1);
+        assert!(matches!(store.compact(), Err(StoreError::ReadOnly)));
+        assert!(matches!(
+            store.compact_with_throttle(1024),
+            Err(StoreError::ReadOnly)
+        ));
+        drop(store);
+    });
+    set_mode(0o755);
+    result.unwrap();
+    // Nothing was created, removed or rewritten, even where permissions
+    // don't apply.
+    assert_eq!(listing(), before);
+    assert_eq!(
+        std::fs::read(std::path::Path::new(test_dir).join("MANIFEST")).unwrap(),
+        manifest
+    );
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn open_repairs_corrupted_manifest_from_directory() {
+    let test_dir = "test_manifest_repair_db";
+    setup_test_dir(test_dir);
+    let dir = std::path::Path::new(test_dir);
+
+    {
+        let mut store = KVStore::open(test_dir).unwrap();
+        store.set("a", b"1").unwrap();
+        store.reset_active_segment().unwrap();
+        store.set("b", b"2").unwrap();
+    }
+    std::fs::write(dir.join("MANIFEST"), b"{ not json").unwrap();
+
+    {
+        let store = KVStore::open(test_dir).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get("b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.manifest().live_segment_ids(), vec![1, 2, 3]);
+    }
+    let repaired = Manifest::load(dir).unwrap().unwrap();
+    assert_eq!(repaired.active_id, 3);
+    assert_eq!(repaired.entry(3).unwrap().state, SegmentState::Active);
+    assert_eq!(repaired.entry(1).unwrap().state, SegmentState::Immutable);
+
+    // A manifest that lists a missing segment and omits a present one.
+    let mut stale = repaired.clone();
+    stale.remove(1);
+    stale.segments[0].id = 99;
+    stale.save(dir).unwrap();
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get("b").unwrap(), Some(b"2".to_vec()));
+    assert!(store.manifest().entry(99).is_none());
+    assert!(store.manifest().entry(1).is_some());
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compaction_updates_manifest() {
+    let test_dir = "test_manifest_compaction_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..10 {
+        store.set("key", format!("v{}", i).as_bytes()).unwrap();
+        store.reset_active_segment().unwrap();
+    }
+    store.compact().unwrap();
+
+    let manifest = Manifest::load(std::path::Path::new(test_dir))
+        .unwrap()
+        .unwrap();
+    assert_eq!(manifest, *store.manifest());
+    assert_eq!(manifest.live_segment_ids(), vec![manifest.active_id]);
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("key").unwrap(), Some(b"v9".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn disk_usage_counts_overwritten_records() {
+    let test_dir = "test_disk_usage_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..50 {
+        store.set("key", format!("value_{}", i).as_bytes()).unwrap();
+    }
+
+    let live = store.stats().total_bytes;
+    let disk = store.disk_usage().unwrap();
+    assert!(disk > live, "disk {} <= live {}", disk, live);
+    assert_eq!(
+        disk,
+        std::fs::metadata(store.base_dir().join("segment-0000000001.dat"))
+            .unwrap()
+            .len()
+    );
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn substores_are_independent() {
+    let test_dir = "test_substore_db";
+    setup_test_dir(test_dir);
+
+    let mut parent = KVStore::open(test_dir).unwrap();
+    parent.set("shared", b"parent").unwrap();
+    {
+        let mut users = parent.open_substore("users").unwrap();
+        let mut orders = parent.open_substore("orders").unwrap();
+        users.set("shared", b"users").unwrap();
+        users.set("alice", b"1").unwrap();
+        orders.set("shared", b"orders").unwrap();
+
+        assert_eq!(users.get("shared").unwrap(), Some(b"users".to_vec()));
+        assert_eq!(orders.get("shared").unwrap(), Some(b"orders".to_vec()));
+        assert_eq!(orders.get("alice").unwrap(), None);
+
+        // Each substore holds its own lock.
+        assert!(matches!(
+            parent.open_substore("users"),
+            Err(StoreError::AlreadyLocked(_))
+        ));
+    }
+    std::fs::create_dir_all(std::path::Path::new(test_dir).join("not_a_store")).unwrap();
+    assert!(parent.open_substore("../escape").is_err());
+
+    assert_eq!(parent.get("shared").unwrap(), Some(b"parent".to_vec()));
+    assert_eq!(parent.list_keys(), vec!["shared".to_string()]);
+    assert_eq!(parent.list_substores().unwrap(), vec!["orders", "users"]);
+
+    let users = parent.open_substore("users").unwrap();
+    assert_eq!(users.get("alice").unwrap(), Some(b"1".to_vec()));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn segment_ids_track_active_segment() {
+    let test_dir = "test_segment_ids_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.segment_ids(), vec![1]);
+    assert_eq!(store.active_segment_id(), 1);
+
+    store.set("a", b"1").unwrap();
+    store.reset_active_segment().unwrap();
+    store.set("b", b"2").unwrap();
+    store.reset_active_segment().unwrap();
+
+    assert_eq!(store.segment_ids(), vec![1, 2, 3]);
+    assert_eq!(store.active_segment_id(), 3);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn typed_store_round_trips_json_values() {
+    let test_dir = "test_typed_store_db";
+    setup_test_dir(test_dir);
+
+    let mut store: TypedKVStore<JsonCodec<String, serde_json::Value>> =
+        TypedKVStore::open(test_dir).unwrap();
+    let alice = serde_json::json!({ "name": "Alice", "age": 30, "tags": ["admin"] });
+    store.set(&"user:1".to_string(), &alice).unwrap();
+    store
+        .set(&"user:2".to_string(), &serde_json::json!(null))
+        .unwrap();
+
+    assert_eq!(store.get(&"user:1".to_string()).unwrap(), Some(alice));
+    assert_eq!(
+        store.get(&"user:2".to_string()).unwrap(),
+        Some(serde_json::Value::Null)
+    );
+    assert_eq!(store.get(&"user:3".to_string()).unwrap(), None);
+
+    let mut keys = store.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["user:1".to_string(), "user:2".to_string()]);
+
+    store.delete(&"user:2".to_string()).unwrap();
+    assert_eq!(store.get(&"user:2".to_string()).unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn records_since_replicates_into_fresh_store() {
+    let primary_dir = "test_replication_primary_db";
+    let standby_dir = "test_replication_standby_db";
+    setup_test_dir(primary_dir);
+    setup_test_dir(standby_dir);
+
+    let mut primary = KVStore::open(primary_dir).unwrap();
+    let mut standby = KVStore::open(standby_dir).unwrap();
+    for i in 0..20 {
+        primary
+            .set(&format!("key_{}", i % 7), format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    primary.delete("key_3").unwrap();
+    primary.reset_active_segment().unwrap();
+    primary.set("after_roll", b"x").unwrap();
+
+    let mut position = (0, 0);
+    for record in primary.records_since(0, 0).unwrap() {
+        let record = record.unwrap();
+        standby.apply_record(&record).unwrap();
+        position = (record.segment_id, record.offset + record.len);
+    }
+
+    // Resuming from the last position only picks up newer writes.
+    primary.set("later", b"y").unwrap();
+    let tail: Vec<_> = primary
+        .records_since(position.0, position.1)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(tail.len(), 1);
+    assert_eq!(tail[0].key, b"later");
+    standby.apply_record(&tail[0]).unwrap();
+
+    let mut keys = primary.list_keys();
+    keys.sort();
+    let mut standby_keys = standby.list_keys();
+    standby_keys.sort();
+    assert_eq!(keys, standby_keys);
+    for key in &keys {
+        assert_eq!(primary.get(key).unwrap(), standby.get(key).unwrap());
+    }
+    assert_eq!(standby.get("key_3").unwrap(), None);
+
+    cleanup_test_dir(primary_dir);
+    cleanup_test_dir(standby_dir);
+}
+
+#[test]
+fn apply_record_set_then_tombstone_deletes() {
+    let test_dir = "test_apply_record_db";
+    setup_test_dir(test_dir);
+
+    let set = ReplicationRecord {
+        segment_id: 1,
+        offset: 0,
+        len: 24,
+        key: b"key".to_vec(),
+        value: Some(b"value".to_vec()),
+    };
+    let tombstone = ReplicationRecord {
+        segment_id: 1,
+        offset: 24,
+        len: 16,
+        key: b"key".to_vec(),
+        value: None,
+    };
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    store.apply_record(&set).unwrap();
+    assert_eq!(store.get("key").unwrap(), Some(b"value".to_vec()));
+    store.apply_record(&tombstone).unwrap();
+    assert_eq!(store.get("key").unwrap(), None);
+
+    // Re-applying the tail of the stream changes nothing and writes nothing.
+    let disk = store.disk_usage().unwrap();
+    store.apply_record(&tombstone).unwrap();
+    assert_eq!(store.disk_usage().unwrap(), disk);
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.get("key").unwrap(), None);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn tail_returns_changes_after_lsn() {
+    let test_dir = "test_tail_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..10 {
+        store.set(&format!("key_{}", i), b"v").unwrap();
+    }
+    store.reset_active_segment().unwrap();
+    store.delete("key_0").unwrap();
+
+    let all: Vec<ChangeRecord> = store.tail(0).unwrap().collect();
+    assert_eq!(all.len(), 11);
+    assert!(all.windows(2).all(|w| w[0].lsn < w[1].lsn));
+    assert_eq!(lsn_position(all[10].lsn), (2, 0));
+    assert_eq!(all[10].value, None);
+
+    let since = all[4].lsn;
+    let rest: Vec<ChangeRecord> = store.tail(since).unwrap().collect();
+    assert_eq!(rest, all[5..].to_vec());
+    assert_eq!(store.tail(all[10].lsn).unwrap().count(), 0);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn amplification_drops_after_compaction() {
+    let test_dir = "test_amplification_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for round in 0..10 {
+        for i in 0..100 {
+            let value = format!("{:0>100}", round);
+            store
+                .set(&format!("key_{:03}", i), value.as_bytes())
+                .unwrap();
+        }
+    }
+    store.delete("key_000").unwrap();
+
+    let before = store.stats();
+    assert_eq!(before.tombstone_count, 1);
+    assert_eq!(before.on_disk_bytes, store.disk_usage().unwrap());
+    assert!(
+        before.space_amplification > 5.0,
+        "space amplification {}",
+        before.space_amplification
+    );
+
+    store.compact().unwrap();
+    let after = store.stats();
+    assert_eq!(after.tombstone_count, 0);
+    assert!(
+        after.space_amplification < 1.5,
+        "space amplification {}",
+        after.space_amplification
+    );
+    assert!(after.write_amplification > before.write_amplification);
+    assert!(after.to_string().contains("Space amplification"));
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn tombstone_count_tracks_deletes_until_compaction() {
+    let test_dir = "test_tombstone_count_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for i in 0..20 {
+        store.set(&format!("key{}", i), b"value").unwrap();
+    }
+    for i in 0..7 {
+        store.delete(&format!("key{}", i)).unwrap();
+    }
+    assert_eq!(store.stats().tombstone_count, 7);
+
+    // The count is rebuilt from the segments on open.
+    drop(store);
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.stats().tombstone_count, 7);
+
+    store.compact().unwrap();
+    assert_eq!(store.stats().tombstone_count, 0);
+    assert_eq!(store.stats().num_keys, 13);
+    drop(store);
+    assert_eq!(KVStore::open(test_dir).unwrap().stats().tombstone_count, 0);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn stats_cached_matches_stats() {
+    let test_dir = "test_stats_cached_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.stats_cached(), store.stats());
+    for round in 0..3 {
+        for i in 0..50 {
+            let value = format!("{}-{}", round, i);
+            store
+                .set(&format!("key_{:02}", i), value.as_bytes())
+                .unwrap();
+        }
+        store.delete(&format!("key_{:02}", round)).unwrap();
+        store.reset_active_segment().unwrap();
+        assert_eq!(store.stats_cached(), store.stats());
+    }
+    store.set("tail", b"in the active segment").unwrap();
+    assert_eq!(store.stats_cached(), store.stats());
+
+    store.merge_segments(1, 2).unwrap();
+    assert_eq!(store.stats_cached(), store.stats());
+    store.compact().unwrap();
+    let cached = store.stats_cached();
+    assert_eq!(cached, store.stats());
+    assert_eq!(cached.num_keys, 50);
+    drop(store);
+
+    // Sizes are seeded from the segment files on reopen.
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.stats_cached(), store.stats());
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn stats_track_oldest_segment_and_lsn() {
+    let test_dir = "test_oldest_segment_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for round in 0..5 {
+        store.set("key", format!("v{}", round).as_bytes()).unwrap();
+        if round < 4 {
+            store.reset_active_segment().unwrap();
+        }
+    }
+    assert_eq!(store.segment_ids(), vec![1, 2, 3, 4, 5]);
+    let stats = store.stats();
+    assert_eq!(stats.oldest_segment_id, 1);
+    assert_eq!(stats.active_segment_id, 5);
+    assert_eq!(stats.active_lsn, store.next_lsn());
+
+    // Compaction rewrites the live records into segment 6, which stays
+    // active, and retires 1-5.
+    store.compact().unwrap();
+    let stats = store.stats();
+    assert_eq!(stats.oldest_segment_id, 6);
+    assert_eq!(stats.active_segment_id, 6);
+    assert!(stats.active_lsn > lsn(6, 0));
+    drop(store);
+
+    let store = KVStore::open(test_dir).unwrap();
+    assert_eq!(store.stats().oldest_segment_id, 6);
+    assert_eq!(store.stats().active_lsn, lsn(7, 0));
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn export_then_import_into_fresh_store() {
+    let source_dir = "test_export_source_db";
+    let target_dir = "test_export_target_db";
+    setup_test_dir(source_dir);
+    setup_test_dir(target_dir);
+    let export_path = std::path::Path::new(source_dir).join("backup.kvx");
+
+    let mut source = KVStore::open(source_dir).unwrap();
+    for i in 0..100 {
+        source
+            .set(&format!("key_{:03}", i), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    source.set("key_000", b"overwritten").unwrap();
+    source.delete("key_001").unwrap();
+    source.set("empty", b"").unwrap();
+    source
+        .set_bytes_key(b"bin\xff\x00key", &[0, 1, 2, 255])
+        .unwrap();
+    assert_eq!(source.export(&export_path).unwrap(), 101);
+
+    let mut target = KVStore::open(target_dir).unwrap();
+    assert_eq!(target.import(&export_path).unwrap(), 101);
+    let sorted_keys = |store: &KVStore| {
+        let mut keys = store.list_bytes_keys();
+        keys.sort();
+        keys
+    };
+    assert_eq!(sorted_keys(&target), sorted_keys(&source));
+    for key in sorted_keys(&source) {
+        assert_eq!(
+            target.get_bytes_key(&key).unwrap(),
+            source.get_bytes_key(&key).unwrap()
+        );
+    }
+    assert_eq!(target.get("key_001").unwrap(), None);
+    assert_eq!(
+        target.get_bytes_key(b"bin\xff\x00key").unwrap(),
+        Some(vec![0, 1, 2, 255])
+    );
+
+    // Anything that isn't an export is refused before touching the store.
+    let bogus = std::path::Path::new(target_dir).join("bogus");
+    std::fs::write(&bogus, b"not an export").unwrap();
+    assert!(matches!(
+        target.import(&bogus),
+        Err(StoreError::CorruptedData(_))
+    ));
+
+    drop(source);
+    drop(target);
+    cleanup_test_dir(source_dir);
+    cleanup_test_dir(target_dir);
+}
+
+#[test]
+fn rotates_by_record_and_tombstone_count() {
+    let test_dir = "test_rotate_by_count_db";
+    setup_test_dir(test_dir);
+
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        max_records_per_segment: Some(10),
+        ..StoreConfig::default()
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    for i in 0..35 {
+        store.set(&format!("key_{:02}", i), b"small").unwrap();
+    }
+
+    let states: Vec<_> = store
+        .manifest()
+        .segments
+        .iter()
+        .map(|entry| (entry.id, entry.state))
+        .collect();
+    assert_eq!(
+        states,
+        vec![
+            (1, SegmentState::Immutable),
+            (2, SegmentState::Immutable),
+            (3, SegmentState::Immutable),
+            (4, SegmentState::Active),
+        ]
+    );
+    for (id, expected) in [(1, 10), (2, 10), (3, 10), (4, 5)] {
+        assert_eq!(store.iter_segment(id).unwrap().count(), expected);
+    }
+    drop(store);
+
+    // Deletes rotate on their own limit, well before the record limit.
+    let config = StoreConfig {
+        max_records_per_segment: None,
+        max_tombstones_before_rotation: Some(3),
+        ..config
+    };
+    let mut store = KVStore::from_config(&config).unwrap();
+    let active = store.active_segment_id();
+    store.delete("key_00").unwrap();
+    store.delete("key_01").unwrap();
+    store.set("key_00", b"back").unwrap();
+    assert_eq!(store.active_segment_id(), active);
+    store.delete("key_02").unwrap();
+    assert_eq!(store.active_segment_id(), active + 1);
+    assert_eq!(store.get("key_00").unwrap(), Some(b"back".to_vec()));
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn open_with_progress_reports_each_segment() {
+    let test_dir = "test_replay_progress_db";
+    setup_test_dir(test_dir);
+
+    let mut store = KVStore::open(test_dir).unwrap();
+    for segment in 0..4 {
+        for i in 0..=segment {
+            store
+                .set(&format!("key_{}_{}", segment, i), b"value")
+                .unwrap();
+        }
+        store.reset_active_segment().unwrap();
+    }
+    drop(store);
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = reports.clone();
+    let store = KVStore::open_with_progress(
+        test_dir,
+        Some(Box::new(move |progress| {
+            sink.lock().unwrap().push(progress)
+        })),
+    )
+    .unwrap();
+    assert_eq!(store.stats().num_keys, 10);
+
+    let reports = reports.lock().unwrap();
+    // Four segments of records plus the empty one left active by the last reset.
+    assert_eq!(reports.len(), 5);
+    for (i, report) in reports.iter().enumerate() {
+        assert_eq!(report.segment, i + 1);
+        assert_eq!(report.total_segments, 5);
+    }
+    assert!(reports
+        .windows(2)
+        .all(|w| w[0].records_replayed <= w[1].records_replayed
+            && w[0].bytes_read <= w[1].bytes_read));
+    assert_eq!(
+        reports
+            .iter()
+            .map(|r| r.records_replayed)
+            .collect::<Vec<_>>(),
+        vec![1, 3, 6, 10, 10]
+    );
+    assert_eq!(reports[4].bytes_read, store.disk_usage().unwrap());
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn aligned_records_keep_offsets_on_8_byte_boundaries() {
+    let test_dir = "test_aligned_records_db";
+    setup_test_dir(test_dir);
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        align_records: true,
+        ..StoreConfig::default()
+    };
+
+    let assert_aligned = |store: &KVStore| {
+        for (key, _, offset, len) in store.index_snapshot() {
+            assert_eq!(offset % 8, 0, "{} at offset {}", key, offset);
+            assert_eq!(len % 8, 0, "{} is {} bytes", key, len);
+        }
+    };
+    {
+        let mut store = KVStore::from_config(&config).unwrap();
+        for i in 0..30 {
+            store.set(&format!("key{}", i), &vec![b'x'; i]).unwrap();
+        }
+        store
+            .set_many((0..10).map(|i| (format!("batch{}", i), vec![b'y'; i * 3])))
+            .unwrap();
+        for i in 0..10 {
+            store.delete(&format!("key{}", i)).unwrap();
+        }
+        assert_aligned(&store);
+        store.compact().unwrap();
+        assert_aligned(&store);
+    }
+
+    // Replay walks over the padding and lands on every record.
+    let store = KVStore::from_config(&config).unwrap();
+    assert_aligned(&store);
+    assert_eq!(store.get("key3").unwrap(), None);
+    assert_eq!(store.get("key29").unwrap(), Some(vec![b'x'; 29]));
+    assert_eq!(store.get("batch7").unwrap(), Some(vec![b'y'; 21]));
+    assert_eq!(store.get_verified("key17").unwrap(), Some(vec![b'x'; 17]));
+    drop(store);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compaction_journal_logs_one_write_per_live_key() {
+    let test_dir = "test_compaction_journal_db";
+    setup_test_dir(test_dir);
+    let config = StoreConfig {
+        data_path: test_dir.into(),
+        enable_compaction_journal: true,
+        ..StoreConfig::default()
+    };
+
+    let mut store = KVStore::from_config(&config).unwrap();
+    for round in 0..5 {
+        for i in 0..50 {
+            store
+                .set(&format!("key{}", i), format!("v{}", round).as_bytes())
+                .unwrap();
+        }
+    }
+    store.set("gone", b"soon deleted").unwrap();
+    store.delete("gone").unwrap();
+    let segments_before = store.segment_ids().len();
+    store.compact().unwrap();
+    drop(store);
+
+    let mut journal = CompactionJournal::open(std::path::Path::new(test_dir)).unwrap();
+    let entries = journal.entries().unwrap();
+    let written: Vec<_> = entries
+        .iter()
+        .filter(|e| e.action == JournalAction::WriteKey)
+        .collect();
+    assert_eq!(written.len(), 50);
+    assert!(written.iter().all(|e| e.key.is_some() && e.bytes > 0));
+
+    let summary = journal.summarize().unwrap();
+    assert_eq!(summary.keys_written, 50);
+    assert_eq!(summary.tombstones_skipped, 1);
+    assert_eq!(summary.segments_read, segments_before);
+    assert_eq!(summary.segments_deleted, segments_before);
+    assert!(summary.first_ts <= summary.last_ts);
+
+    // Each line is a standalone JSON object.
+    let log = std::fs::read_to_string(journal.path()).unwrap();
+    assert_eq!(log.matches("\"action\":\"write_key\"").count(), 50);
+
+    cleanup_test_dir(test_dir);
+}
+
+#[test]
+fn compaction_journal_writes_segment_actions_through() {
+    let test_dir = "test_compaction_journal_sync_db";
+    setup_test_dir(test_dir);
+
+    let mut journal = CompactionJournal::open(std::path::Path::new(test_dir)).unwrap();
+    journal
+        .record(JournalAction::WriteKey, 2, Some(b"k"), 10)
+        .unwrap();
+    journal
+        .record(JournalAction::DeleteOldSegment, 1, None, 100)
+        .unwrap();
+
+    // Without a flush, both lines are already in the file.
+    let log = std::fs::read_to_string(journal.path()).unwrap();
+    assert_eq!(log.lines().count(), 2);
+    assert!(log.contains("\"action\":\"delete_old_segment\""));
+
+    cleanup_test_dir(test_dir);
+}
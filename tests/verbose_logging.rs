@@ -0,0 +1,84 @@
+use log::{Level, Log, Metadata, Record};
+use mini_kvstore_v2::{KVStore, StoreConfig};
+use std::sync::Mutex;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+/// Keeps every record so the test can inspect what the store logged.
+struct CapturingLogger {
+    records: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+fn take_records() -> Vec<(Level, String)> {
+    std::mem::take(&mut *LOGGER.records.lock().unwrap())
+}
+
+#[test]
+fn verbose_logging_controls_operation_logs() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let test_dir = "test_verbose_logging_db";
+    setup_test_dir(test_dir);
+    let config = |verbose_logging| StoreConfig {
+        data_path: test_dir.into(),
+        verbose_logging,
+        ..StoreConfig::default()
+    };
+
+    {
+        let mut quiet = KVStore::from_config(&config(false)).unwrap();
+        quiet.set("quiet", b"12345").unwrap();
+        quiet.get("quiet").unwrap();
+    }
+    assert_eq!(take_records(), vec![]);
+
+    let mut store = KVStore::from_config(&config(true)).unwrap();
+    store.set("alpha", b"12345").unwrap();
+    store.get("alpha").unwrap();
+    store.delete("alpha").unwrap();
+    store.reset_active_segment().unwrap();
+    store.compact().unwrap();
+
+    let records = take_records();
+    let logged = |level: Level, prefix: &str| {
+        records
+            .iter()
+            .any(|(l, msg)| *l == level && msg.starts_with(prefix))
+    };
+    assert!(
+        logged(Level::Debug, "set key=alpha bytes=5"),
+        "{:?}",
+        records
+    );
+    assert!(
+        logged(Level::Trace, "get key=alpha bytes=5"),
+        "{:?}",
+        records
+    );
+    assert!(logged(Level::Debug, "delete key=alpha"), "{:?}", records);
+    assert!(logged(Level::Debug, "rotated to segment"), "{:?}", records);
+    assert!(logged(Level::Debug, "compacted"), "{:?}", records);
+
+    drop(store);
+    cleanup_test_dir(test_dir);
+}
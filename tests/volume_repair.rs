@@ -0,0 +1,187 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use mini_kvstore_v2::volume::handlers::create_router;
+use mini_kvstore_v2::volume::{BlobStorage, VolumeServer};
+use mini_kvstore_v2::StoreError;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+const GOOD: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+/// Flips a byte of `value` in whichever segment file of `dir` holds it.
+fn corrupt_on_disk(dir: &str, value: &[u8]) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "dat") {
+            continue;
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        if let Some(pos) = bytes.windows(value.len()).position(|w| w == value) {
+            bytes[pos] ^= 0xff;
+            fs::write(&path, bytes).unwrap();
+            return;
+        }
+    }
+    panic!("value not found in any segment of {}", dir);
+}
+
+#[test]
+fn damaged_blob_is_repaired_from_a_replica() {
+    let local_dir = "test_repair_local_db";
+    let replica_dir = "test_repair_replica_db";
+    setup_test_dir(local_dir);
+    setup_test_dir(replica_dir);
+
+    {
+        let mut replica = BlobStorage::new(replica_dir, "vol-b".to_string()).unwrap();
+        replica.put("doc", GOOD).unwrap();
+        replica.put("other", b"not the same bytes").unwrap();
+    }
+    let (replica, addr) = VolumeServer::spawn_on_random_port(replica_dir, "vol-b").unwrap();
+    replica.wait_for_ready(Duration::from_secs(5)).unwrap();
+    // The first source is unreachable, so repair has to fall through to the replica.
+    let urls = ["http://127.0.0.1:1".to_string(), format!("http://{}", addr)];
+
+    let mut local = BlobStorage::new(local_dir, "vol-a".to_string()).unwrap();
+    local.put("doc", GOOD).unwrap();
+    local.put("other", b"local bytes").unwrap();
+    corrupt_on_disk(local_dir, GOOD);
+
+    // The in-memory copy hides the damage; a verified read finds it.
+    assert_eq!(local.get("doc").unwrap(), Some(GOOD.to_vec()));
+    assert!(matches!(
+        local.get_with_repair("doc", &[]),
+        Err(StoreError::ChecksumMismatch { .. })
+    ));
+
+    // A replica whose copy has the wrong etag is not trusted.
+    corrupt_on_disk(local_dir, b"local bytes");
+    assert!(matches!(
+        local.get_with_repair("other", &urls[1..]),
+        Err(StoreError::ChecksumMismatch { .. })
+    ));
+
+    assert_eq!(
+        local.get_with_repair("doc", &urls).unwrap(),
+        Some(GOOD.to_vec())
+    );
+    // Healed locally: a verified read no longer needs the replica.
+    assert_eq!(
+        local.get_with_repair("doc", &[]).unwrap(),
+        Some(GOOD.to_vec())
+    );
+    drop(local);
+
+    replica.shutdown().unwrap();
+    cleanup_test_dir(local_dir);
+    cleanup_test_dir(replica_dir);
+}
+
+/// Serves `GOOD` for one request that carries `Authorization: Bearer
+/// <token>`, answering anything else with 401 like an authenticated volume,
+/// after waiting `delay`. Returns the base URL and whether the request was
+/// authorized.
+fn serve_once_with_auth(
+    token: &'static str,
+    delay: Duration,
+) -> (String, thread::JoinHandle<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let expected = format!("authorization: bearer {}", token);
+        let mut authorized = false;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            authorized |= line.trim().eq_ignore_ascii_case(&expected);
+        }
+        thread::sleep(delay);
+        let mut stream = stream;
+        let response = if authorized {
+            let mut response =
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", GOOD.len()).into_bytes();
+            response.extend_from_slice(GOOD);
+            response
+        } else {
+            b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_vec()
+        };
+        stream.write_all(&response).unwrap();
+        authorized
+    });
+    (url, handle)
+}
+
+#[test]
+fn repair_sends_the_auth_token() {
+    let local_dir = "test_repair_auth_db";
+    setup_test_dir(local_dir);
+
+    let mut local = BlobStorage::new(local_dir, "vol-a".to_string())
+        .unwrap()
+        .with_repair_auth_token(Some("s3cret".to_string()));
+    local.put("doc", GOOD).unwrap();
+    corrupt_on_disk(local_dir, GOOD);
+
+    let (url, replica) = serve_once_with_auth("s3cret", Duration::ZERO);
+    assert_eq!(
+        local.get_with_repair("doc", &[url]).unwrap(),
+        Some(GOOD.to_vec())
+    );
+    assert!(replica.join().unwrap());
+    drop(local);
+
+    cleanup_test_dir(local_dir);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn slow_repair_does_not_hold_up_other_requests() {
+    let local_dir = "test_repair_slow_db";
+    setup_test_dir(local_dir);
+
+    let (url, replica) = serve_once_with_auth("s3cret", Duration::from_secs(1));
+    let mut local = BlobStorage::new(local_dir, "vol-a".to_string())
+        .unwrap()
+        .with_repair_urls(vec![url])
+        .with_repair_auth_token(Some("s3cret".to_string()));
+    local.put("doc", GOOD).unwrap();
+    local.put("other", b"intact").unwrap();
+    corrupt_on_disk(local_dir, GOOD);
+    let app = create_router(Arc::new(Mutex::new(local)));
+    let get = |key: &str| {
+        Request::get(format!("/blobs/{}", key))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let repair = tokio::spawn(app.clone().oneshot(get("doc")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The replica is still sleeping, yet the volume keeps serving.
+    let started = Instant::now();
+    let response = app.oneshot(get("other")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert!(!repair.is_finished());
+
+    let response = repair.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], GOOD);
+    assert!(replica.join().unwrap());
+
+    cleanup_test_dir(local_dir);
+}
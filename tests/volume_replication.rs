@@ -0,0 +1,120 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use mini_kvstore_v2::volume::config::VolumeConfig;
+use mini_kvstore_v2::volume::{BlobStorage, VolumeServer};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+/// Polls `storage` for `key` until it holds `expected` or `timeout` passes.
+fn wait_for(
+    storage: &Arc<Mutex<BlobStorage>>,
+    key: &str,
+    expected: &[u8],
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if storage.lock().unwrap().get(key).unwrap().as_deref() == Some(expected) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    false
+}
+
+#[tokio::test]
+async fn primary_writes_reach_secondary() {
+    let primary_dir = "test_volume_primary_db";
+    let secondary_dir = "test_volume_secondary_db";
+    setup_test_dir(primary_dir);
+    setup_test_dir(secondary_dir);
+
+    // Written before replication starts, so only a catchup delivers it.
+    BlobStorage::new(primary_dir, "primary".to_string())
+        .unwrap()
+        .put("early", b"before")
+        .unwrap();
+
+    let any_port: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let secondary = thread::spawn(move || {
+        VolumeServer::new(
+            VolumeConfig::new("secondary")
+                .with_data_dir(secondary_dir)
+                .with_replication_listen_addr(any_port),
+        )
+        .unwrap()
+    })
+    .join()
+    .unwrap();
+    let target = secondary.replication_addr().unwrap();
+    let primary = thread::spawn(move || {
+        VolumeServer::new(
+            VolumeConfig::new("primary")
+                .with_data_dir(primary_dir)
+                .with_replication_target(target),
+        )
+        .unwrap()
+    })
+    .join()
+    .unwrap();
+
+    primary
+        .storage()
+        .lock()
+        .unwrap()
+        .put("live", b"hot")
+        .unwrap();
+    let replica = secondary.storage();
+    assert!(wait_for(
+        &replica,
+        "live",
+        b"hot",
+        Duration::from_millis(100)
+    ));
+    assert_eq!(
+        replica.lock().unwrap().head("live").unwrap().unwrap().size,
+        3
+    );
+    assert_eq!(replica.lock().unwrap().get("early").unwrap(), None);
+
+    let response = secondary
+        .router()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/replication/catchup")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"since_lsn":0}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert!(wait_for(
+        &replica,
+        "early",
+        b"before",
+        Duration::from_secs(1)
+    ));
+
+    primary.storage().lock().unwrap().delete("live").unwrap();
+    let deadline = Instant::now() + Duration::from_millis(100);
+    while replica.lock().unwrap().get("live").unwrap().is_some() {
+        assert!(Instant::now() < deadline, "delete was not replicated");
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert_eq!(
+        replica.lock().unwrap().list_keys(),
+        vec!["early".to_string()]
+    );
+
+    drop(primary);
+    drop(secondary);
+    cleanup_test_dir(primary_dir);
+    cleanup_test_dir(secondary_dir);
+}
@@ -0,0 +1,45 @@
+use mini_kvstore_v2::volume::VolumeServer;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+mod common;
+use common::{cleanup_test_dir, setup_test_dir};
+
+#[test]
+fn random_port_server_serves_and_shuts_down() {
+    let test_dir = "test_volume_server_random_port_db";
+    setup_test_dir(test_dir);
+
+    let (server, addr) = VolumeServer::spawn_on_random_port(test_dir, "vol-rand").unwrap();
+    assert_ne!(addr.port(), 0);
+    assert_eq!(server.addr(), addr);
+    server.wait_for_ready(Duration::from_secs(5)).unwrap();
+
+    let health = ureq::get(&format!("http://{}/health", addr))
+        .call()
+        .unwrap();
+    assert_eq!(health.status(), 200);
+    let body: serde_json::Value = health.into_json().unwrap();
+    assert_eq!(body["volume_id"], "vol-rand");
+
+    ureq::post(&format!("http://{}/blobs/greeting", addr))
+        .send_bytes(b"hello")
+        .unwrap();
+
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(TcpStream::connect(addr).is_err());
+
+    // The storage was closed with the server, so the directory reopens.
+    let (server, addr) = VolumeServer::spawn_on_random_port(test_dir, "vol-rand").unwrap();
+    server.wait_for_ready(Duration::from_secs(5)).unwrap();
+    let blob = ureq::get(&format!("http://{}/blobs/greeting", addr))
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert_eq!(blob, "hello");
+    drop(server);
+
+    cleanup_test_dir(test_dir);
+}